@@ -0,0 +1,8 @@
+#![no_main]
+
+use corelib::device::xiaomi::components::network::fuzz_dhcp_reply;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = fuzz_dhcp_reply(data);
+});
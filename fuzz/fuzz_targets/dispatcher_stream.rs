@@ -0,0 +1,68 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use corelib::device::xiaomi::packet::{
+    dispatcher::split_frames,
+    v2::layer1::{L1DataType, L1Packet},
+};
+use libfuzzer_sys::fuzz_target;
+
+// 模拟蓝牙上来的字节流：真包、垃圾、被截断的包随便混，还会被任意切块
+#[derive(Debug, Arbitrary)]
+enum Piece {
+    Frame { kind: u8, seq: u8, payload: Vec<u8> },
+    Garbage(Vec<u8>),
+    Truncated { payload: Vec<u8>, keep: u8 },
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    pieces: Vec<Piece>,
+    chunk_sizes: Vec<u8>,
+}
+
+fn frame_bytes(kind: u8, seq: u8, payload: Vec<u8>) -> Vec<u8> {
+    let kind = match kind % 4 {
+        0 => L1DataType::Nak,
+        1 => L1DataType::Ack,
+        2 => L1DataType::Cmd,
+        _ => L1DataType::Data,
+    };
+    L1Packet::new(kind, false, seq, payload).to_bytes()
+}
+
+fuzz_target!(|input: Input| {
+    let mut stream = Vec::new();
+    for piece in input.pieces {
+        match piece {
+            Piece::Frame { kind, seq, payload } => {
+                stream.extend(frame_bytes(kind, seq, payload));
+            }
+            Piece::Garbage(bytes) => stream.extend(bytes),
+            Piece::Truncated { payload, keep } => {
+                let bytes = frame_bytes(3, 0, payload);
+                let keep = usize::from(keep).min(bytes.len());
+                stream.extend_from_slice(&bytes[..keep]);
+            }
+        }
+    }
+
+    let mut buffer = Vec::new();
+    let mut offset = 0;
+    let mut sizes = input.chunk_sizes.iter().cycle();
+    while offset < stream.len() {
+        let size = sizes
+            .next()
+            .map_or(stream.len(), |s| usize::from(*s).max(1));
+        let end = (offset + size).min(stream.len());
+        buffer.extend_from_slice(&stream[offset..end]);
+        offset = end;
+
+        for frame in split_frames(&mut buffer) {
+            // 切出来的帧一定得是能解的完整 L1 包
+            L1Packet::from_bytes(&frame).expect("split_frames yielded an undecodable frame");
+        }
+        // 缓冲最多只留一个没收齐的包
+        assert!(buffer.len() <= 8 + 64512);
+    }
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+use corelib::device::xiaomi::packet::v2::layer1cmd::L1CmdPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Some(cmd) = L1CmdPacket::from_payload_bytes(data) {
+        let _ = cmd.get_version();
+        let _ = cmd.get_mps();
+        let _ = cmd.get_tx_win();
+        let _ = cmd.get_send_timeout();
+        let _ = cmd.get_device_type();
+        let _ = cmd.get_device_name();
+        let _ = cmd.get_os_version();
+        let _ = L1CmdPacket::from_payload_bytes(&cmd.to_payload_bytes());
+    }
+});
@@ -0,0 +1,14 @@
+#![no_main]
+
+use corelib::device::xiaomi::packet::v2::layer1::L1Packet;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(pkt) = L1Packet::from_bytes(data) {
+        // 解出来的包重新编码后必须还能原样解回去
+        let bytes = pkt.to_bytes();
+        let again = L1Packet::from_bytes(&bytes).expect("re-encoded L1 packet must decode");
+        assert_eq!(again.payload, pkt.payload);
+        assert_eq!(again.seq, pkt.seq);
+    }
+});
@@ -0,0 +1,25 @@
+#![no_main]
+
+use corelib::crypto::aesctr::aes128_ctr_crypt;
+use corelib::device::xiaomi::packet::v2::layer2::{L2Cipher, L2Packet};
+use libfuzzer_sys::fuzz_target;
+
+// 固定密钥的 CTR，走一遍 WriteEnc 的解密分支
+struct FixedKeyCipher;
+
+const KEY: [u8; 16] = [0x11; 16];
+
+impl L2Cipher for FixedKeyCipher {
+    fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, ()> {
+        Ok(aes128_ctr_crypt(&KEY, &KEY, plaintext))
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+        Ok(aes128_ctr_crypt(&KEY, &KEY, ciphertext))
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let _ = L2Packet::from_bytes(data, None);
+    let _ = L2Packet::from_bytes(data, Some(&FixedKeyCipher));
+});
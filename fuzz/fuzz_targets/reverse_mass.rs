@@ -0,0 +1,27 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use corelib::device::xiaomi::packet::mass::ReverseMassPacket;
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    streaming: bool,
+    packets: Vec<Vec<u8>>,
+}
+
+fuzz_target!(|input: Input| {
+    let mut rmp = if input.streaming {
+        ReverseMassPacket::new_streaming()
+    } else {
+        ReverseMassPacket::new()
+    };
+    for packet in input.packets {
+        if rmp.handle_packet(packet).is_err() {
+            break;
+        }
+        let _ = rmp.take_ready_chunks();
+    }
+    let _ = rmp.complete();
+    let _ = rmp.file(true);
+});
@@ -1,3 +1,15 @@
 pub const XIAOMI_BLE_V2_SERVICE_UUID: &str = "fe95";
 pub const XIAOMI_BLE_V2_TX_UUID: &str = "005f";
 pub const XIAOMI_BLE_V2_RX_UUID: &str = "005e";
+
+/// 真 SPP 连上后要先发的这么一段，不发手表不理人（TCP 转发不用）
+pub const XIAOMI_SPP_HELLO: &[u8] = &[
+    0xba, 0xdc, 0xfe, 0x00, 0xc0, 0x03, 0x00, 0x00, 0x01, 0x00, 0xef,
+];
+
+/// 固件安装请求里报的版本号。包里的真实版本没解析，填个占位
+pub const XIAOMI_FIRMWARE_PLACEHOLDER_VERSION: &str = "99.99.99";
+
+/// 蓝牙联网里假网关应答 DHCP 用的端口
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
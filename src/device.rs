@@ -33,34 +33,58 @@ use crate::device::xiaomi::components::network::NetworkComponent;
 use crate::device::xiaomi::components::network::NetworkSystem;
 use crate::device::xiaomi::components::{
     auth::{AuthComponent, AuthSystem},
+    capability::{DeviceCapabilities, probe_capabilities},
     info::{InfoComponent, InfoSystem},
     install::{InstallComponent, InstallSystem},
     mass::{MassComponent, MassSystem},
     media::{MediaComponent, MediaSystem},
+    notification::{NotificationComponent, NotificationSystem},
     report::ReportSystem,
     resource::{ResourceComponent, ResourceSystem},
     sync::{SyncComponent, SyncSystem},
     thirdparty_app::{ThirdpartyAppComponent, ThirdpartyAppSystem},
     watchface::{WatchfaceComponent, WatchfaceSystem},
 };
-use crate::device::xiaomi::config::XiaomiDeviceConfig;
+use crate::device::xiaomi::config::{BlePacing, ChannelCrypto, QosProfile, XiaomiDeviceConfig};
+use crate::device::xiaomi::packet::{
+    cipher,
+    raw_pb::{self, RawWearPacket},
+    v2::layer2::L2Channel,
+};
+use crate::device::xiaomi::sar::{DeviceLinkInfo, DrainReport, SarController};
 use crate::device::xiaomi::r#type::ConnectType;
 use crate::device::xiaomi::{SendError, XiaomiDevice, cleanup_cached_state};
 use crate::ecs::Component;
-use anyhow::{Context, bail};
+use anyhow::{Context, anyhow, bail};
 use serde::{Deserialize, Serialize};
 use std::future::Future;
+use std::time::Duration;
 use tokio::runtime::Handle;
 
+pub mod connect;
 pub mod data;
+pub mod diagnostic;
+pub mod fitness;
+pub mod handle;
 pub mod install;
+pub mod notification;
+pub mod post_connect;
 pub mod resource;
+pub mod setup;
+pub mod storage;
 pub mod sync;
 pub mod thirdparty_app;
 pub mod vivo;
 pub mod watchface;
 pub mod xiaomi;
 
+pub use connect::{RetryPolicy, XiaomiConnectParams, connect_with_retry};
+pub use diagnostic::diagnostic_dump;
+pub use handle::{DeviceError, DeviceHandle};
+pub use post_connect::{PostConnectOptions, PostConnectStep, SyncReport, post_connect_sync};
+pub use setup::{DeviceSetup, SetupError, SetupReport};
+pub use storage::{FreeSpacePolicy, FreedReport, free_space};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DeviceKind {
     Xiaomi,
@@ -104,6 +128,271 @@ pub struct DeviceConnectionInfo {
     pub addr: String,
     #[serde(default)]
     pub kind: DeviceKind,
+    /// 仅小米设备有，连接时探测出来的能力
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<DeviceCapabilities>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSummary {
+    pub addr: String,
+    pub name: String,
+    pub kind: DeviceKind,
+    /// 仅小米设备有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connect_type: Option<ConnectType>,
+    pub authed: bool,
+    /// 电量百分比，还没拿到过设备状态时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub battery: Option<i32>,
+    /// 小米 BLE 连接开了按间隔限速时才有
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ble_pacing: Option<BlePacing>,
+}
+
+/// 列出当前所有已连接设备的概要信息，给设备选择器之类的UI用
+pub async fn list_connected() -> Vec<DeviceSummary> {
+    crate::ecs::with_rt_read(|rt| {
+        let mut summaries: Vec<DeviceSummary> = rt
+            .device_ids()
+            .filter_map(|id| {
+                rt.with_device_ref(id, |world, entity| {
+                    let device = world.get::<Device>(entity)?;
+                    let (authed, battery) = match device.kind() {
+                        DeviceKind::Xiaomi => (
+                            world
+                                .get::<AuthComponent>(entity)
+                                .is_some_and(|auth| auth.is_authed),
+                            world
+                                .get::<InfoComponent>(entity)
+                                .and_then(|info| info.battery())
+                                .map(|battery| battery.capacity as i32),
+                        ),
+                        DeviceKind::Vivo => (
+                            world
+                                .get::<VivoAuthComponent>(entity)
+                                .is_some_and(|auth| auth.is_authed),
+                            world
+                                .get::<VivoInfoComponent>(entity)
+                                .and_then(|info| info.status.as_ref())
+                                .map(|status| status.battery.capacity),
+                        ),
+                    };
+                    let xiaomi = world.get::<XiaomiDevice>(entity);
+                    let connect_type = xiaomi.map(|dev| dev.connect_type);
+                    let ble_pacing = xiaomi
+                        .filter(|dev| dev.connect_type == ConnectType::BLE)
+                        .and_then(|dev| dev.config.transport.ble_pacing());
+                    Some(DeviceSummary {
+                        addr: device.addr().to_string(),
+                        name: device.name().to_string(),
+                        kind: device.kind(),
+                        connect_type,
+                        authed,
+                        battery,
+                        ble_pacing,
+                    })
+                })
+                .flatten()
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.addr.cmp(&b.addr));
+        summaries
+    })
+    .await
+}
+
+/// 宿主切后台前调用：等该设备发送队列里的东西发完并被确认，最多等 `timeout`。
+/// 链路已经判死会立刻返回，不会白等
+pub async fn flush_device(addr: String, timeout: Duration) -> anyhow::Result<DrainReport> {
+    let kind = crate::ecs::with_rt_read({
+        let addr = addr.clone();
+        move |rt| {
+            rt.component_ref::<Device>(&addr)
+                .map(|device| device.kind())
+        }
+    })
+    .await
+    .with_context(|| format!("Device {addr} not found"))?;
+
+    match kind {
+        DeviceKind::Xiaomi => SarController::drain(addr, timeout).await,
+        DeviceKind::Vivo => bail!("flush_device is not supported for Vivo devices yet"),
+    }
+}
+
+/// 握手阶段手表报上来的链路参数（mps、版本、设备名等），诊断页用
+pub async fn link_info(addr: String) -> anyhow::Result<Option<DeviceLinkInfo>> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<XiaomiDevice>(&addr)
+            .map(|dev| dev.sar.lock().link_info().cloned())
+            .with_context(|| format!("Device {addr} is not a connected Xiaomi device"))
+    })
+    .await
+}
+
+/// 设备当前生效的配置（连接时传进来的那份），只读
+pub async fn get_device_config(addr: String) -> anyhow::Result<XiaomiDeviceConfig> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<XiaomiDevice>(&addr)
+            .map(|dev| dev.config.clone())
+            .with_context(|| format!("Device {addr} is not a connected Xiaomi device"))
+    })
+    .await
+}
+
+/// 会话中途切 QoS 预设，比如刷固件前切 Throughput，刷完切回 Latency。
+/// 连接时单独传的参数（`tx_win_overrun_allowance` 之类）以单独传的为准
+pub async fn set_qos(addr: String, profile: QosProfile) -> anyhow::Result<()> {
+    crate::ecs::with_rt_mut_labeled("set_qos", move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            world
+                .get_mut::<XiaomiDevice>(entity)
+                .map(|mut dev| dev.set_qos(profile))
+        })
+        .flatten()
+        .with_context(|| format!("Device {addr} is not a connected Xiaomi device"))
+    })
+    .await
+}
+
+/// 换设备的 authkey（用户把 key 输错了改一下），不用整个断开重连。
+/// key 变了会踢掉缓存的 L2 cipher 并清掉认证状态，之后重新跑一遍认证就会用新 key 派生
+pub async fn update_authkey(addr: String, authkey: String) -> anyhow::Result<()> {
+    crate::ecs::with_rt_mut_labeled("update_authkey", move |rt| {
+        let changed = rt
+            .with_device_mut(&addr, |world, entity| {
+                world
+                    .get_mut::<AuthSystem>(entity)
+                    .map(|mut auth| auth.set_authkey(authkey))
+            })
+            .flatten()
+            .with_context(|| format!("Device {addr} is not a connected Xiaomi device"))??;
+        if changed {
+            cipher::remove_l2_cipher(&addr);
+            log::info!("[update_authkey] authkey of {addr} changed, cipher evicted");
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// 接下来发的 Pb 包会不会加密：要求认证已经完成、通道策略允许，cipher 还没建的话顺手建好。
+/// 发凭据之类的敏感内容前先问一句，false 就别发，不然会静默退回明文
+pub async fn l2_encryption_ready(addr: String) -> anyhow::Result<bool> {
+    let sar_version = crate::ecs::with_rt_read({
+        let addr = addr.clone();
+        move |rt| {
+            rt.with_device_ref(&addr, |world, entity| {
+                let dev = world.get::<XiaomiDevice>(entity)?;
+                let authed = world
+                    .get::<AuthComponent>(entity)
+                    .is_some_and(|auth| auth.is_authed);
+                let allowed = dev.config.channel_crypto.get(L2Channel::Pb) != ChannelCrypto::Never;
+                Some((authed && allowed).then_some(dev.sar_version))
+            })
+            .flatten()
+        }
+    })
+    .await
+    .with_context(|| format!("Device {addr} is not a connected Xiaomi device"))?;
+    let Some(sar_version) = sar_version else {
+        return Ok(false);
+    };
+    Ok(cipher::ensure_l2_cipher(&addr, sar_version).await.is_some())
+}
+
+/// 发一个原始 WearPacket，`payload_bytes` 是已经带 tag 的 protobuf 字段，会接在 type/id 后面加密入队。
+/// 不稳定 API，给宿主试验未公开的 PB 类型用
+pub async fn send_raw_wear_packet(
+    addr: String,
+    r#type: i32,
+    id: u32,
+    payload_bytes: Vec<u8>,
+) -> anyhow::Result<()> {
+    let raw = raw_pb::build_envelope(r#type, id, &payload_bytes);
+    crate::ecs::with_rt_mut_labeled("send_raw_wear_packet", move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            let dev = world
+                .get::<XiaomiDevice>(entity)
+                .ok_or_else(|| anyhow!("Device {addr} is not a Xiaomi device"))?;
+            let bytes = cipher::encode_raw_pb_bytes(dev, raw, "send_raw_wear_packet")
+                .ok_or_else(|| anyhow!("Pb channel encryption policy rejected the packet"))?;
+            dev.sar.lock().enqueue(bytes);
+            Ok(())
+        })
+        .with_context(|| format!("Device {addr} not found"))?
+    })
+    .await
+}
+
+/// 订阅没被内置 System 认领的 WearPacket，`type_filter` 为 None 表示全部。
+/// 不稳定 API：以后内置支持了某个类型，它就不会再出现在这里
+pub fn subscribe_wear_packets(
+    addr: &str,
+    type_filter: Option<i32>,
+) -> tokio::sync::broadcast::Receiver<RawWearPacket> {
+    raw_pb::subscribe(addr, type_filter)
+}
+
+/// 重新跑一遍小米设备的联网协议栈初始化。
+/// 连接时那次 ensure_runtime 失败只会打个 warn，宿主可以在之后调用这个补救，不用整个重连
+#[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+pub async fn restart_network_stack(addr: String, handle: Handle) -> anyhow::Result<()> {
+    crate::ecs::with_rt_mut_labeled("restart_network_stack", move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            let config = world
+                .get::<NetworkComponent>(entity)
+                .map(|comp| comp.config().clone())
+                .ok_or_else(|| anyhow!("Device {addr} has no network component"))?;
+            let mut sys = world
+                .get_mut::<NetworkSystem>(entity)
+                .ok_or_else(|| anyhow!("Device {addr} has no network system"))?;
+            sys.restart_stack(handle, config)?;
+            // 协议栈重建后再告诉手表一次联网可用
+            sys.force_sync_network_status()
+        })
+        .with_context(|| format!("Device {addr} not found"))?
+    })
+    .await
+}
+
+/// 宿主重建了 tokio runtime 之后调一下：协议栈还活着就不动，任务跟着老 runtime
+/// 没了的话在新的 `handle` 上重建并重新同步联网状态。返回是否真的重建了
+#[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+pub async fn ensure_network_stack(addr: String, handle: Handle) -> anyhow::Result<bool> {
+    crate::ecs::with_rt_mut_labeled("ensure_network_stack", move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            let config = world
+                .get::<NetworkComponent>(entity)
+                .map(|comp| comp.config().clone())
+                .ok_or_else(|| anyhow!("Device {addr} has no network component"))?;
+            let mut sys = world
+                .get_mut::<NetworkSystem>(entity)
+                .ok_or_else(|| anyhow!("Device {addr} has no network system"))?;
+            if sys.is_running() {
+                return Ok(false);
+            }
+            sys.ensure_runtime(handle, config)?;
+            sys.force_sync_network_status()?;
+            Ok(true)
+        })
+        .with_context(|| format!("Device {addr} not found"))?
+    })
+    .await
+}
+
+/// 联网代理最近连不上的目标（拒绝/不可达/超时），给诊断页面用
+#[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+pub async fn network_connect_failures(
+    addr: String,
+) -> anyhow::Result<Vec<crate::device::xiaomi::components::network::FailedSession>> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<NetworkSystem>(&addr)
+            .map(|sys| sys.connect_failures())
+            .with_context(|| format!("Device {addr} has no network system"))
+    })
+    .await
 }
 
 pub fn cleanup_device_state(kind: DeviceKind, addr: &str) {
@@ -125,6 +414,7 @@ pub async fn create_device<F, Fut>(
     transport_chunk_size_spp: Option<usize>,
     transport_chunk_size_ble: Option<usize>,
     force_android: bool,
+    config: XiaomiDeviceConfig,
     sender: F,
 ) -> anyhow::Result<DeviceConnectionInfo>
 where
@@ -138,128 +428,224 @@ where
             )
         }
         DeviceKind::Xiaomi => {
-            let device_id_for_auth = addr.clone();
-            #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
-            let device_id_for_network = addr.clone();
-            let addr_for_entity = addr.clone();
-            let name_for_entity = name.clone();
-            let tk_handle_clone = tk_handle.clone();
-
-            cleanup_device_state(device_kind, &addr);
-
-            crate::ecs::with_rt_mut(move |rt| {
-                let mut device_config = XiaomiDeviceConfig::default();
-                if let Some(allowance) = tx_win_overrun_allowance {
-                    device_config.sar.tx_win_overrun_allowance = allowance.min(16);
-                }
-                if let Some(chunk_size_spp) = transport_chunk_size_spp {
-                    device_config.transport.chunk_size_spp = chunk_size_spp.max(1);
-                }
-                if let Some(chunk_size_ble) = transport_chunk_size_ble {
-                    device_config.transport.chunk_size_ble = chunk_size_ble.max(1);
-                }
-                #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
-                let network_config = device_config.network.clone();
-                let authkey_for_component = authkey.clone();
-                let dev = XiaomiDevice::new(
-                    tk_handle_clone.clone(),
-                    name_for_entity.clone(),
-                    addr_for_entity.clone(),
-                    authkey,
-                    sar_version,
-                    connect_type,
-                    force_android,
-                    device_config,
-                    sender,
-                );
-                let device_id = dev.addr().to_string();
-                let entity = rt.spawn_device(
-                    device_id.clone(),
-                    (
-                        dev,
-                        Device::new(
-                            name_for_entity.clone(),
-                            addr_for_entity.clone(),
-                            device_kind,
-                        ),
-                    ),
-                );
-                let mut entity_ref = rt.world_mut().entity_mut(entity);
-                entity_ref.insert((
-                    AuthComponent::new(authkey_for_component),
-                    AuthSystem::new(device_id.clone()),
-                    InstallComponent::new(),
-                    InstallSystem::new(device_id.clone()),
-                    MassComponent::new(),
-                    MassSystem::new(device_id.clone()),
-                    MediaComponent::default(),
-                    MediaSystem::new(device_id.clone()),
-                    InfoComponent::new(),
-                    InfoSystem::new(device_id.clone()),
-                    ReportSystem::new(device_id.clone()),
-                ));
-                entity_ref.insert((
-                    ThirdpartyAppComponent::new(),
-                    ThirdpartyAppSystem::new(device_id.clone()),
-                    ResourceComponent::new(),
-                    ResourceSystem::new(device_id.clone()),
-                    WatchfaceComponent::new(),
-                    WatchfaceSystem::new(device_id.clone()),
-                    SyncComponent::new(),
-                    SyncSystem::new(device_id.clone()),
-                ));
-                #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
-                {
-                    let network_config_for_runtime = network_config.clone();
-                    entity_ref.insert((
-                        NetworkComponent::new(network_config),
-                        NetworkSystem::new(device_id.clone()),
-                    ));
-                    if let Some(mut sys) = rt.world_mut().get_mut::<NetworkSystem>(entity) {
-                        if let Err(err) =
-                            sys.ensure_runtime(tk_handle_clone.clone(), network_config_for_runtime)
-                        {
-                            log::warn!("[XiaomiDevice] failed to start network stack: {err:?}");
-                        }
-                    }
-                }
+            spawn_xiaomi_device(XiaomiConnectParams {
+                tk_handle,
+                name: name.clone(),
+                addr: addr.clone(),
+                authkey,
+                sar_version,
+                connect_type,
+                tx_win_overrun_allowance,
+                transport_chunk_size_spp,
+                transport_chunk_size_ble,
+                force_android,
+                config,
+                sender,
             })
             .await;
 
-            let auth_rx = crate::ecs::with_rt_mut(move |rt| {
-                rt.with_device_mut(&device_id_for_auth, |world, entity| {
-                    let mut auth_system = world
-                        .get_mut::<AuthSystem>(entity)
-                        .expect("AuthSystem missing");
-                    auth_system.prepare_auth().map(Some)
-                })
-                .unwrap_or_else(|| Ok(None))
-            })
-            .await?;
-
-            if let Some(rx) = auth_rx {
+            if let Some(rx) = start_xiaomi_auth(addr.clone(), false).await? {
                 let auth_result = rx.await.context("Auth await response not received")?;
                 auth_result?;
             }
 
-            #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
-            // 在Auth完成后同步网络状态以确保蓝牙联网可用
-            crate::ecs::with_rt_mut(move |rt| {
-                rt.with_device_mut(&device_id_for_network, |world, entity| {
-                    let mut sys = world
-                        .get_mut::<NetworkSystem>(entity)
-                        .expect("NetworkSystem missing");
-                    let _ = sys.sync_network_status();
-                });
-            })
-            .await;
+            Ok(finish_xiaomi_connect(name, addr).await)
+        }
+    }
+}
 
-            Ok(DeviceConnectionInfo {
-                name: name.clone(),
-                addr: addr.clone(),
-                kind: DeviceKind::Xiaomi,
-            })
+/// 建好小米设备的实体和各个 System，不做认证
+pub(crate) async fn spawn_xiaomi_device<F, Fut>(params: XiaomiConnectParams<F>)
+where
+    F: Fn(Vec<Vec<u8>>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), SendError>> + Send + 'static,
+{
+    let XiaomiConnectParams {
+        tk_handle,
+        name,
+        addr,
+        authkey,
+        sar_version,
+        connect_type,
+        tx_win_overrun_allowance,
+        transport_chunk_size_spp,
+        transport_chunk_size_ble,
+        force_android,
+        config,
+        sender,
+    } = params;
+
+    cleanup_device_state(DeviceKind::Xiaomi, &addr);
+
+    crate::ecs::with_rt_mut(move |rt| {
+        // 调用方按机型给的配置打底，单独传的参数再覆盖上去
+        let mut device_config = config;
+        if let Some(allowance) = tx_win_overrun_allowance {
+            device_config.sar.tx_win_overrun_allowance = allowance.min(16);
+        }
+        if let Some(chunk_size_spp) = transport_chunk_size_spp {
+            device_config.transport.chunk_size_spp = chunk_size_spp.max(1);
+        }
+        if let Some(chunk_size_ble) = transport_chunk_size_ble {
+            device_config.transport.chunk_size_ble = chunk_size_ble.max(1);
+        }
+        let battery_history_len = device_config.info.battery_history_len;
+        let authkey_for_component = authkey.clone();
+        // SAR 先不握手：设备秒回的 L1StartRsp 要能找到实体
+        let dev = XiaomiDevice::new_deferred(
+            tk_handle.clone(),
+            name.clone(),
+            addr.clone(),
+            authkey,
+            sar_version,
+            connect_type,
+            force_android,
+            device_config,
+            sender,
+        );
+        // 网络缓冲要用套过 QoS 预设的
+        #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+        let network_config = dev.config.network.clone();
+        let device_id = dev.addr().to_string();
+        let entity = rt.spawn_device(
+            device_id.clone(),
+            (
+                dev,
+                Device::new(name.clone(), addr.clone(), DeviceKind::Xiaomi),
+            ),
+        );
+        let mut entity_ref = rt.world_mut().entity_mut(entity);
+        entity_ref.insert((
+            AuthComponent::new(authkey_for_component),
+            AuthSystem::new(device_id.clone()),
+            InstallComponent::new(),
+            InstallSystem::new(device_id.clone()),
+            MassComponent::new(),
+            MassSystem::new(device_id.clone()),
+            MediaComponent::default(),
+            MediaSystem::new(device_id.clone()),
+            InfoComponent::with_battery_history(battery_history_len),
+            InfoSystem::new(device_id.clone()),
+            ReportSystem::new(device_id.clone()),
+        ));
+        entity_ref.insert((
+            ThirdpartyAppComponent::new(),
+            ThirdpartyAppSystem::new(device_id.clone()),
+            ResourceComponent::new(),
+            ResourceSystem::new(device_id.clone()),
+            WatchfaceComponent::new(),
+            WatchfaceSystem::new(device_id.clone()),
+            SyncComponent::new(),
+            SyncSystem::new(device_id.clone()),
+            NotificationComponent::new(),
+            NotificationSystem::new(device_id.clone()),
+        ));
+        #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+        {
+            let network_config_for_runtime = network_config.clone();
+            entity_ref.insert((
+                NetworkComponent::new(network_config),
+                NetworkSystem::new(device_id.clone()),
+            ));
+            if let Some(mut sys) = rt.world_mut().get_mut::<NetworkSystem>(entity) {
+                if let Err(err) = sys.ensure_runtime(tk_handle.clone(), network_config_for_runtime)
+                {
+                    log::warn!("[XiaomiDevice] failed to start network stack: {err:?}");
+                }
+            }
         }
+        if let Some(dev) = rt.world_mut().get::<XiaomiDevice>(entity) {
+            dev.sar.lock().start();
+        }
+    })
+    .await;
+}
+
+/// 回放和测试用的小米设备的 authkey
+pub(crate) const MOCK_XIAOMI_AUTHKEY: &str = "00112233445566778899aabbccddeeff";
+
+/// 不接真实传输的小米设备实体，参数全用默认的，发出去的帧交给 `sender`。回放和测试用
+pub(crate) async fn spawn_mock_xiaomi_with_sender<F, Fut>(
+    addr: &str,
+    config: XiaomiDeviceConfig,
+    sender: F,
+) where
+    F: Fn(Vec<Vec<u8>>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), SendError>> + Send + 'static,
+{
+    spawn_xiaomi_device(XiaomiConnectParams {
+        tk_handle: Handle::current(),
+        name: "mock".to_string(),
+        addr: addr.to_string(),
+        authkey: MOCK_XIAOMI_AUTHKEY.to_string(),
+        sar_version: 2,
+        connect_type: ConnectType::TCP,
+        tx_win_overrun_allowance: None,
+        transport_chunk_size_spp: None,
+        transport_chunk_size_ble: None,
+        force_android: false,
+        config,
+        sender,
+    })
+    .await;
+}
+
+/// 同上，发送永远成功
+#[cfg(test)]
+pub(crate) async fn spawn_mock_xiaomi(addr: &str, config: XiaomiDeviceConfig) {
+    spawn_mock_xiaomi_with_sender(addr, config, |_frames: Vec<Vec<u8>>| async {
+        Ok::<(), SendError>(())
+    })
+    .await;
+}
+
+/// 发出认证第一步，返回等认证结果的 rx；设备已经不在了返回 None。
+/// `retry` 为 true 时换新 nonce 重发，上一轮晚到的回包会被丢掉
+pub(crate) async fn start_xiaomi_auth(
+    addr: String,
+    retry: bool,
+) -> anyhow::Result<Option<tokio::sync::oneshot::Receiver<anyhow::Result<()>>>> {
+    crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            let mut auth_system = world
+                .get_mut::<AuthSystem>(entity)
+                .expect("AuthSystem missing");
+            if retry {
+                auth_system.retry_auth().map(Some)
+            } else {
+                auth_system.prepare_auth().map(Some)
+            }
+        })
+        .unwrap_or_else(|| Ok(None))
+    })
+    .await
+}
+
+/// 认证完成后的收尾：同步网络状态、探测能力
+pub(crate) async fn finish_xiaomi_connect(name: String, addr: String) -> DeviceConnectionInfo {
+    #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+    {
+        let device_id_for_network = addr.clone();
+        // 在Auth完成后同步网络状态以确保蓝牙联网可用
+        crate::ecs::with_rt_mut(move |rt| {
+            rt.with_device_mut(&device_id_for_network, |world, entity| {
+                let mut sys = world
+                    .get_mut::<NetworkSystem>(entity)
+                    .expect("NetworkSystem missing");
+                let _ = sys.sync_network_status();
+            });
+        })
+        .await;
+    }
+
+    // 宿主拿到连接结果时就能知道哪些功能可用
+    let capabilities = probe_capabilities(addr.clone()).await;
+
+    DeviceConnectionInfo {
+        name,
+        addr,
+        kind: DeviceKind::Xiaomi,
+        capabilities: Some(capabilities),
     }
 }
 
@@ -347,5 +733,6 @@ where
         name,
         addr,
         kind: DeviceKind::Vivo,
+        capabilities: None,
     })
 }
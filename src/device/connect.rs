@@ -0,0 +1,298 @@
+//! 带重试的小米设备连接。BLE 不稳的时候第一个 AppVerify 经常丢，
+//! 官方 App 会默默重发，这里也一样：超时就在同一个实体上重发认证，authkey 错了直接放弃
+
+use std::future::Future;
+
+use anyhow::anyhow;
+use tokio::runtime::Handle;
+
+use super::{DeviceConnectionInfo, finish_xiaomi_connect, spawn_xiaomi_device, start_xiaomi_auth};
+use crate::asyncrt::{Duration, sleep, timeout};
+use crate::device::xiaomi::{
+    SendError, components::auth::is_auth_key_error, config::XiaomiDeviceConfig, r#type::ConnectType,
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+/// create_device 的那一串参数
+pub struct XiaomiConnectParams<F> {
+    pub tk_handle: Handle,
+    pub name: String,
+    pub addr: String,
+    pub authkey: String,
+    pub sar_version: u32,
+    pub connect_type: ConnectType,
+    pub tx_win_overrun_allowance: Option<u8>,
+    pub transport_chunk_size_spp: Option<usize>,
+    pub transport_chunk_size_ble: Option<usize>,
+    pub force_android: bool,
+    pub config: XiaomiDeviceConfig,
+    pub sender: F,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    /// 第 n 次重试前等 base_backoff * 2^(n-1)
+    pub base_backoff: Duration,
+    /// 整个连接流程的上限，UI 的转圈不会超过这个时间
+    pub total_deadline: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(500),
+            total_deadline: Duration::from_secs(20),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, retry: u32) -> Duration {
+        self.base_backoff
+            .saturating_mul(1u32 << retry.saturating_sub(1).min(16))
+    }
+}
+
+/// 建实体 + 认证，认证超时会在同一个实体上重发，authkey 错误立刻失败。
+/// 最终失败时错误里会带上每一次尝试的结果
+pub async fn connect_with_retry<F, Fut>(
+    params: XiaomiConnectParams<F>,
+    policy: RetryPolicy,
+) -> anyhow::Result<DeviceConnectionInfo>
+where
+    F: Fn(Vec<Vec<u8>>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), SendError>> + Send + 'static,
+{
+    let name = params.name.clone();
+    let addr = params.addr.clone();
+    spawn_xiaomi_device(params).await;
+
+    run_auth_attempts(&policy, |attempt| {
+        let addr = addr.clone();
+        async move {
+            match start_xiaomi_auth(addr.clone(), attempt > 1).await? {
+                Some(rx) => rx
+                    .await
+                    .map_err(|_| anyhow!("Auth await response not received"))?,
+                None => Err(anyhow!("Device {addr} not found")),
+            }
+        }
+    })
+    .await?;
+
+    Ok(finish_xiaomi_connect(name, addr).await)
+}
+
+/// 重试循环本体，`attempt` 收到的是从 1 开始的尝试序号
+async fn run_auth_attempts<A, AFut>(policy: &RetryPolicy, mut attempt: A) -> anyhow::Result<()>
+where
+    A: FnMut(u32) -> AFut,
+    AFut: Future<Output = anyhow::Result<()>>,
+{
+    let started = Instant::now();
+    let max_attempts = policy.max_attempts.max(1);
+    let mut outcomes: Vec<String> = Vec::new();
+
+    for n in 1..=max_attempts {
+        let remaining = policy.total_deadline.saturating_sub(started.elapsed());
+        if remaining.is_zero() {
+            outcomes.push(format!("attempt {n}: skipped, deadline reached"));
+            break;
+        }
+        // 剩下的时间平均分给剩下的几次，免得第一次就把时间吃光
+        let per_attempt = remaining / (max_attempts - n + 1);
+
+        match timeout(per_attempt, attempt(n)).await {
+            Ok(Ok(())) => {
+                if n > 1 {
+                    log::info!("[Connect] auth succeeded on attempt {n}");
+                }
+                return Ok(());
+            }
+            Ok(Err(err)) if is_auth_key_error(&err) => {
+                outcomes.push(format!("attempt {n}: {err}"));
+                return Err(err.context(summarize(&outcomes)));
+            }
+            Ok(Err(err)) => {
+                log::warn!("[Connect] auth attempt {n} failed: {err:?}");
+                outcomes.push(format!("attempt {n}: {err}"));
+            }
+            Err(_) => {
+                log::warn!(
+                    "[Connect] auth attempt {n} timed out after {}ms",
+                    per_attempt.as_millis()
+                );
+                outcomes.push(format!(
+                    "attempt {n}: timed out after {}ms",
+                    per_attempt.as_millis()
+                ));
+            }
+        }
+
+        if n < max_attempts {
+            let remaining = policy.total_deadline.saturating_sub(started.elapsed());
+            sleep(policy.backoff(n).min(remaining)).await;
+        }
+    }
+
+    Err(anyhow!("{}", summarize(&outcomes)))
+}
+
+fn summarize(outcomes: &[String]) -> String {
+    format!("auth failed ({})", outcomes.join("; "))
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::components::auth::AuthError;
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(10),
+            total_deadline: Duration::from_millis(600),
+        }
+    }
+
+    fn block_on<T>(fut: impl Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new().unwrap().block_on(fut)
+    }
+
+    #[test]
+    fn retries_when_first_verify_is_dropped() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let result = block_on(run_auth_attempts(&policy(), |n| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if n == 1 {
+                    // 手表的 AuthDeviceVerify 丢了，永远等不到
+                    std::future::pending::<()>().await;
+                }
+                Ok(())
+            }
+        }));
+
+        assert!(result.is_ok());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn mock_watch_dropping_the_first_verify_still_connects() {
+        use crate::device::MOCK_XIAOMI_AUTHKEY;
+        use crate::device::xiaomi::components::auth::{
+            AuthComponent, AuthSystem, mock_device_confirm, mock_device_verify,
+        };
+        use crate::device::xiaomi::system::L2PbExt;
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:connect-drop-first-verify";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (connected, nonces_seen, authed) = rt.block_on(async {
+            // 手表：第一轮 AppVerify 当没收到，之后每一轮都正常签名、确认
+            let watch = tokio::spawn(async move {
+                let mut seen: Vec<Vec<u8>> = Vec::new();
+                loop {
+                    let nonce = crate::device::xiaomi::with_component_mut::<AuthComponent, _, _>(
+                        addr,
+                        |auth| auth.random_bytes.clone(),
+                    )
+                    .await
+                    .filter(|nonce| nonce.len() == 16 && !seen.contains(nonce));
+                    if let Some(nonce) = nonce {
+                        seen.push(nonce.clone());
+                        if seen.len() > 1 {
+                            crate::ecs::with_rt_mut(move |rt| {
+                                rt.with_device_mut(addr, |world, entity| {
+                                    let mut auth = world.get_mut::<AuthSystem>(entity).unwrap();
+                                    auth.on_pb_packet(mock_device_verify(
+                                        MOCK_XIAOMI_AUTHKEY,
+                                        &nonce,
+                                        [0x3c; 16],
+                                    ));
+                                    auth.on_pb_packet(mock_device_confirm());
+                                });
+                            })
+                            .await;
+                        }
+                    }
+                    if seen.len() > 1 {
+                        return seen.len();
+                    }
+                    sleep(Duration::from_millis(5)).await;
+                }
+            });
+
+            let params = XiaomiConnectParams {
+                tk_handle: Handle::current(),
+                name: "mock".to_string(),
+                addr: addr.to_string(),
+                authkey: MOCK_XIAOMI_AUTHKEY.to_string(),
+                sar_version: 2,
+                connect_type: ConnectType::TCP,
+                tx_win_overrun_allowance: None,
+                transport_chunk_size_spp: None,
+                transport_chunk_size_ble: None,
+                force_android: false,
+                config: XiaomiDeviceConfig::default(),
+                sender: |_frames: Vec<Vec<u8>>| async { Ok::<(), SendError>(()) },
+            };
+            let policy = RetryPolicy {
+                max_attempts: 3,
+                base_backoff: Duration::from_millis(10),
+                total_deadline: Duration::from_secs(3),
+            };
+            let connected = connect_with_retry(params, policy).await;
+            let nonces_seen = watch.await.unwrap();
+            let authed =
+                crate::device::xiaomi::with_component_mut::<AuthComponent, _, _>(addr, |auth| {
+                    auth.is_authed
+                })
+                .await;
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            (connected, nonces_seen, authed)
+        });
+        crate::device::xiaomi::cleanup_cached_state(addr);
+
+        assert!(connected.is_ok(), "{connected:?}");
+        assert_eq!(nonces_seen, 2);
+        assert_eq!(authed, Some(true));
+    }
+
+    #[test]
+    fn wrong_key_aborts_immediately() {
+        let calls = Arc::new(AtomicU32::new(0));
+        let err = block_on(run_auth_attempts(&policy(), |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            async { Err(AuthError::HmacMismatch.into()) }
+        }))
+        .unwrap_err();
+
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(is_auth_key_error(&err));
+    }
+
+    #[test]
+    fn respects_total_deadline() {
+        let started = Instant::now();
+        let err = block_on(run_auth_attempts(&policy(), |_| {
+            std::future::pending::<anyhow::Result<()>>()
+        }))
+        .unwrap_err();
+
+        assert!(started.elapsed() < Duration::from_millis(900));
+        let msg = err.to_string();
+        assert!(msg.contains("attempt 1: timed out"), "{msg}");
+        assert!(msg.contains("attempt 3"), "{msg}");
+    }
+}
@@ -8,7 +8,10 @@ use crate::{
         vivo::components::info::{
             InfoComponent as VivoInfoComponent, InfoSystem as VivoInfoSystem,
         },
-        xiaomi::components::info::InfoSystem as XiaomiInfoSystem,
+        xiaomi::components::info::{
+            BatterySample, DeviceSnapshot, InfoComponent as XiaomiInfoComponent,
+            InfoSystem as XiaomiInfoSystem,
+        },
     },
 };
 
@@ -96,9 +99,22 @@ pub async fn request_device_data_json(
     }
 }
 
+/// 设备详情页用：info / status / storage 一次拿齐，三个请求并发
+pub async fn request_device_snapshot(addr: String) -> anyhow::Result<DeviceSnapshot> {
+    match device_kind(&addr).await? {
+        DeviceKind::Xiaomi => {
+            let snapshot = with_xiaomi_info_system(addr, |sys| sys.request_full_snapshot()).await?;
+            snapshot.await
+        }
+        DeviceKind::Vivo => Err(anyhow_site!(
+            "Device snapshot is not supported for Vivo devices yet"
+        )),
+    }
+}
+
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
@@ -194,8 +210,8 @@ async fn await_slot<T>(
 }
 
 pub async fn read_cached_vivo_device_info(addr: String) -> Option<DeviceInfoData> {
-    crate::ecs::with_rt_mut(move |rt| {
-        rt.with_device_mut(&addr, |world, entity| {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.with_device_ref(&addr, |world, entity| {
             world
                 .get::<VivoInfoComponent>(entity)
                 .and_then(|comp| comp.info.clone())
@@ -204,3 +220,13 @@ pub async fn read_cached_vivo_device_info(addr: String) -> Option<DeviceInfoData
     })
     .await
 }
+
+/// 读取小米设备记录的电量历史，需要在配置里打开 battery_history_len
+pub async fn read_battery_history(addr: String, since: u64) -> anyhow::Result<Vec<BatterySample>> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<XiaomiInfoComponent>(&addr)
+            .map(|info| info.battery_history(since))
+            .ok_or_else(|| anyhow_site!("Xiaomi info component not found"))
+    })
+    .await
+}
@@ -0,0 +1,182 @@
+//! 一次性导出某台设备的协议状态，给用户贴到 issue 里用。
+//! 全是现成 getter 拼出来的，密钥只说有没有，不带内容（AuthComponent 序列化时本来就脱敏）
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::device::Device;
+use crate::device::xiaomi::{
+    XiaomiDevice,
+    components::{
+        auth::AuthComponent, info::InfoComponent, install::InstallComponent, mass::MassComponent,
+        media::MediaComponent, notification::NotificationComponent, resource::ResourceComponent,
+        sync::SyncComponent, thirdparty_app::ThirdpartyAppComponent, watchface::WatchfaceComponent,
+    },
+    packet::{cipher, dispatcher},
+};
+use crate::ecs::{Component, Entity, World};
+
+/// 导出格式的版本，字段有破坏性调整时加一
+pub const DIAGNOSTIC_DUMP_VERSION: u32 = 1;
+
+/// 设备不在时报错；Vivo 设备只有基础信息
+pub async fn diagnostic_dump(device_id: String) -> anyhow::Result<Value> {
+    let mut dump = crate::ecs::with_rt_read({
+        let device_id = device_id.clone();
+        move |rt| {
+            rt.with_device_ref(&device_id, |world, entity| {
+                dump_entity(world, entity, &device_id)
+            })
+        }
+    })
+    .await
+    .with_context(|| format!("Device {device_id} not found"))?;
+
+    if let Value::Object(map) = &mut dump {
+        map.insert(
+            "runtime".to_string(),
+            serde_json::to_value(crate::ecs::runtime_metrics()).unwrap_or(Value::Null),
+        );
+    }
+    Ok(dump)
+}
+
+fn dump_entity(world: &World, entity: Entity, device_id: &str) -> Value {
+    let mut dump = Map::new();
+    dump.insert("version".to_string(), json!(DIAGNOSTIC_DUMP_VERSION));
+    dump.insert("device_id".to_string(), json!(device_id));
+    dump.insert("device".to_string(), to_value(world.get::<Device>(entity)));
+    dump.insert(
+        "kind".to_string(),
+        to_value(world.get::<Device>(entity).map(|d| d.kind()).as_ref()),
+    );
+
+    let Some(dev) = world.get::<XiaomiDevice>(entity) else {
+        return Value::Object(dump);
+    };
+    dump.insert(
+        "entity".to_string(),
+        crate::ecs::graph::snapshot_entity_details(dev),
+    );
+
+    let mut components = Map::new();
+    add_component::<AuthComponent>(world, entity, &mut components);
+    add_component::<InstallComponent>(world, entity, &mut components);
+    add_component::<MassComponent>(world, entity, &mut components);
+    add_component::<MediaComponent>(world, entity, &mut components);
+    add_component::<InfoComponent>(world, entity, &mut components);
+    add_component::<ThirdpartyAppComponent>(world, entity, &mut components);
+    add_component::<ResourceComponent>(world, entity, &mut components);
+    add_component::<WatchfaceComponent>(world, entity, &mut components);
+    add_component::<SyncComponent>(world, entity, &mut components);
+    add_component::<NotificationComponent>(world, entity, &mut components);
+    dump.insert("components".to_string(), Value::Object(components));
+
+    {
+        let sar = dev.sar.lock();
+        dump.insert(
+            "sar".to_string(),
+            json!({
+                "link_state": sar.link_state(),
+                "tx_window": sar.tx_window_size(),
+                "raw_tx_window": sar.raw_tx_window_size(),
+                "send_timeout_ms": sar.send_timeout_ms(),
+                "since_last_inbound_ms": sar.since_last_inbound().map(|d| d.as_millis() as u64),
+                "link_info": sar.link_info(),
+            }),
+        );
+    }
+    dump.insert(
+        "dispatcher".to_string(),
+        to_value(dispatcher::dispatcher_stats(device_id).as_ref()),
+    );
+    dump.insert(
+        "cipher".to_string(),
+        json!({
+            "l2_cipher_present": cipher::get_l2_cipher(device_id).is_some(),
+            "l2_encryption_ready": dev.l2_encryption_ready(),
+        }),
+    );
+    dump.insert(
+        "transport_profiler".to_string(),
+        to_value(Some(&dev.transport_profiler.status())),
+    );
+    dump.insert("network".to_string(), network_dump(world, entity));
+
+    Value::Object(dump)
+}
+
+fn add_component<T: Component + Serialize>(
+    world: &World,
+    entity: Entity,
+    out: &mut Map<String, Value>,
+) {
+    if let Some(comp) = world.get::<T>(entity) {
+        let name = std::any::type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default();
+        out.insert(name.to_string(), to_value(Some(comp)));
+    }
+}
+
+fn to_value<T: Serialize>(value: Option<&T>) -> Value {
+    value
+        .and_then(|v| serde_json::to_value(v).ok())
+        .unwrap_or(Value::Null)
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+fn network_dump(world: &World, entity: Entity) -> Value {
+    use crate::device::xiaomi::components::network::NetworkSystem;
+
+    let Some(sys) = world.get::<NetworkSystem>(entity) else {
+        return Value::Null;
+    };
+    let running = sys.is_stack_running();
+    json!({
+        "stack_running": running,
+        "speed": running.then(|| sys.get_speed()),
+        "last_synced_capability": sys.last_synced_capability(),
+        "connect_failures": sys.connect_failures(),
+    })
+}
+
+#[cfg(not(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack")))]
+fn network_dump(_world: &World, _entity: Entity) -> Value {
+    Value::Null
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::config::XiaomiDeviceConfig;
+    use crate::device::{MOCK_XIAOMI_AUTHKEY, spawn_mock_xiaomi};
+
+    #[test]
+    fn dump_has_every_section_and_no_authkey() {
+        crate::ecs::init_runtime_default();
+        let addr = "test:diag-dump";
+        let authkey = MOCK_XIAOMI_AUTHKEY;
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        assert!(rt.block_on(diagnostic_dump(addr.to_string())).is_err());
+
+        let dump = rt.block_on(async {
+            spawn_mock_xiaomi(addr, XiaomiDeviceConfig::default()).await;
+            let dump = diagnostic_dump(addr.to_string()).await.unwrap();
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            dump
+        });
+        crate::device::xiaomi::cleanup_cached_state(addr);
+
+        for section in ["entity", "components", "sar", "cipher", "runtime"] {
+            assert!(!dump[section].is_null(), "missing {section}");
+        }
+        assert_eq!(dump["cipher"]["l2_cipher_present"], false);
+        assert_eq!(dump["cipher"]["l2_encryption_ready"], false);
+        assert!(dump["components"]["AuthComponent"].is_object());
+        assert!(!dump.to_string().contains(authkey));
+    }
+}
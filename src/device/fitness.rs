@@ -0,0 +1,35 @@
+//! 手表推过来的运动/健康记录文件。目前只把 FileFitness 通道上收到的整个文件原样交出去，
+//! 记录格式的解析留给宿主
+
+use serde::Serialize;
+
+use crate::device::xiaomi::components::mass::incoming::{
+    DEFAULT_INCOMING_CAPACITY, IncomingFile, IncomingReceiver, subscribe_with,
+};
+use crate::device::xiaomi::packet::v2::layer2::L2Channel;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FitnessRecord {
+    pub addr: String,
+    pub file_name: String,
+    pub data: Vec<u8>,
+}
+
+fn to_record(file: &IncomingFile) -> FitnessRecord {
+    FitnessRecord {
+        addr: file.addr.clone(),
+        file_name: file.file_name.clone(),
+        data: file.data.clone(),
+    }
+}
+
+/// 设备不在线也可以先订阅，重连之后收到的记录照样进同一个接收端。
+/// 用法和 `incoming_files` 一样：`while let Some(record) = rx.next().await`
+pub fn records(addr: &str) -> IncomingReceiver<FitnessRecord> {
+    subscribe_with(
+        addr,
+        &[L2Channel::FileFitness],
+        DEFAULT_INCOMING_CAPACITY,
+        to_record,
+    )
+}
@@ -0,0 +1,292 @@
+//! 按地址拿到的设备句柄，把 `with_rt_mut` → 找实体 → 取组件那一套样板包起来：
+//!
+//! ```ignore
+//! let handle = DeviceHandle::find(addr).await?;
+//! let battery = handle.info().battery().await?;
+//! ```
+//!
+//! 只是薄薄一层，底下的 `with_rt_*` 和各个 System 照常能用。
+//! 句柄本身不持有实体，设备断开后再调用会拿到 `DeviceError::NotFound`
+
+use std::sync::Arc;
+
+use pb::xiaomi::protocol::{self, DeviceInfo, DeviceStatus, device_status::Battery};
+
+use crate::device::xiaomi::components::{
+    info::{DeviceSnapshot, InfoSystem},
+    install::{
+        InstallOptions, InstallOutcome, InstallProgressReceiver, InstallSystem, progress_channel,
+    },
+    mass::SendMassCallbackData,
+    shared::await_response,
+};
+use crate::device::xiaomi::packet::{cipher, mass::MassDataType};
+use crate::device::{Device, DeviceKind};
+use crate::ecs::{Component, Entity, World};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceError {
+    /// 这个地址上没有已连接的设备
+    NotFound(String),
+    /// 设备在，但不是这个操作要求的类型（比如对 Vivo 设备调小米专有接口）
+    WrongKind { addr: String, kind: DeviceKind },
+    /// 实体上没挂这个组件，一般是还没初始化完
+    MissingComponent {
+        addr: String,
+        component: &'static str,
+    },
+}
+
+impl std::fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound(addr) => write!(f, "Device {addr} not found"),
+            Self::WrongKind { addr, kind } => {
+                write!(f, "Device {addr} is a {kind:?} device, not supported here")
+            }
+            Self::MissingComponent { addr, component } => {
+                write!(f, "Device {addr} has no {component}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DeviceError {}
+
+fn missing<T>(addr: &str) -> DeviceError {
+    DeviceError::MissingComponent {
+        addr: addr.to_string(),
+        component: std::any::type_name::<T>()
+            .rsplit("::")
+            .next()
+            .unwrap_or_default(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceHandle {
+    addr: String,
+}
+
+impl DeviceHandle {
+    /// 不检查设备在不在，第一次调用时才知道
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into() }
+    }
+
+    /// 确认设备已连接再给句柄
+    pub async fn find(addr: impl Into<String>) -> anyhow::Result<Self> {
+        let handle = Self::new(addr);
+        handle.kind().await?;
+        Ok(handle)
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    pub async fn kind(&self) -> anyhow::Result<DeviceKind> {
+        self.read::<Device, _, _>(|device| device.kind()).await
+    }
+
+    /// 在 ECS 线程上拿到设备实体跑 `f`，其它方法都是基于这个
+    pub async fn with<F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut World, Entity) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let addr = self.addr.clone();
+        crate::ecs::with_rt_mut_labeled("device_handle::with", move |rt| {
+            rt.with_device_mut(&addr, f)
+                .ok_or_else(|| DeviceError::NotFound(addr).into())
+        })
+        .await
+    }
+
+    /// 改某个组件（System 也是组件）
+    pub async fn with_component<T, F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        T: Component,
+        F: FnOnce(&mut T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let addr = self.addr.clone();
+        self.with(move |world, entity| {
+            let mut comp = world
+                .get_mut::<T>(entity)
+                .ok_or_else(|| missing::<T>(&addr))?;
+            Ok::<_, anyhow::Error>(f(&mut *comp))
+        })
+        .await?
+    }
+
+    pub async fn read<T, F, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        T: Component,
+        F: FnOnce(&T) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let addr = self.addr.clone();
+        crate::ecs::with_rt_read(move |rt| {
+            if rt.component_ref::<Device>(&addr).is_none() {
+                return Err(DeviceError::NotFound(addr).into());
+            }
+            match rt.component_ref::<T>(&addr) {
+                Some(comp) => Ok(f(comp)),
+                None => Err(missing::<T>(&addr).into()),
+            }
+        })
+        .await
+    }
+
+    async fn require_xiaomi(&self) -> anyhow::Result<()> {
+        match self.kind().await? {
+            DeviceKind::Xiaomi => Ok(()),
+            kind => Err(DeviceError::WrongKind {
+                addr: self.addr.clone(),
+                kind,
+            }
+            .into()),
+        }
+    }
+
+    pub fn info(&self) -> InfoHandle<'_> {
+        InfoHandle { device: self }
+    }
+
+    /// 小米设备安装，等到设备回安装结果才返回
+    pub async fn install(
+        &self,
+        r#type: MassDataType,
+        file_data: Vec<u8>,
+        package_name: Option<String>,
+        options: InstallOptions,
+        progress_cb: Arc<dyn Fn(SendMassCallbackData) + Send + Sync>,
+    ) -> anyhow::Result<InstallOutcome> {
+        self.require_xiaomi().await?;
+        let addr = self.addr.clone();
+        // 安装 future 在 wasm 上不是 Send，这里直接走 with_rt_mut，不套 with()
+        let install = crate::ecs::with_rt_mut_labeled("device_handle::install", move |rt| {
+            rt.with_device_mut(&addr, |world, entity| {
+                let mut sys = world
+                    .get_mut::<InstallSystem>(entity)
+                    .ok_or_else(|| missing::<InstallSystem>(&addr))?;
+                sys.send_install_request_with_options(
+                    r#type,
+                    file_data,
+                    package_name.as_deref(),
+                    progress_cb,
+                    None,
+                    options,
+                )
+            })
+            .ok_or_else(|| anyhow::Error::from(DeviceError::NotFound(addr.clone())))?
+        })
+        .await?;
+        install.await
+    }
+
+    /// 同 install，进度从返回的接收端里取。安装要 await 返回的 future 才会开始
+    pub fn install_with_progress(
+        &self,
+        r#type: MassDataType,
+        file_data: Vec<u8>,
+        package_name: Option<String>,
+        options: InstallOptions,
+    ) -> (
+        InstallProgressReceiver,
+        impl Future<Output = anyhow::Result<InstallOutcome>> + '_,
+    ) {
+        let (progress_cb, progress) = progress_channel();
+        let install = self.install(r#type, file_data, package_name, options, progress_cb);
+        (progress, install)
+    }
+
+    /// 发一个 Pb 包，按 Pb 通道的加密策略编码
+    pub async fn send_pb(&self, packet: protocol::WearPacket) -> anyhow::Result<()> {
+        self.require_xiaomi().await?;
+        cipher::enqueue_pb_packet_async(&self.addr, packet, "DeviceHandle::send_pb").await
+    }
+}
+
+/// `handle.info()` 拿到的，全部会真的去问设备；只要缓存用 `DeviceHandle::read::<InfoComponent, ..>`
+pub struct InfoHandle<'a> {
+    device: &'a DeviceHandle,
+}
+
+impl InfoHandle<'_> {
+    async fn request<F, Rx, R>(&self, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut InfoSystem) -> Rx + Send + 'static,
+        Rx: std::future::Future<Output = anyhow::Result<R>> + Send + 'static,
+        R: Send + 'static,
+    {
+        self.device.require_xiaomi().await?;
+        let rx = self
+            .device
+            .with_component::<InfoSystem, _, _>(move |sys| f(sys))
+            .await?;
+        rx.await
+    }
+
+    pub async fn device_info(&self) -> anyhow::Result<DeviceInfo> {
+        self.request(|sys| {
+            await_response(
+                sys.request_device_info(),
+                "Device info response not received",
+            )
+        })
+        .await
+    }
+
+    pub async fn status(&self) -> anyhow::Result<DeviceStatus> {
+        self.request(|sys| {
+            await_response(
+                sys.request_device_status(),
+                "Device status response not received",
+            )
+        })
+        .await
+    }
+
+    /// 设备状态里没带电量时返回 None
+    pub async fn battery(&self) -> anyhow::Result<Option<Battery>> {
+        Ok(self.status().await?.battery)
+    }
+
+    pub async fn storage(&self) -> anyhow::Result<protocol::StorageInfo> {
+        self.request(|sys| {
+            await_response(
+                sys.request_device_storage(),
+                "Device storage info response not received",
+            )
+        })
+        .await
+    }
+
+    pub async fn snapshot(&self) -> anyhow::Result<DeviceSnapshot> {
+        self.request(|sys| sys.request_full_snapshot()).await
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_device_is_not_found() {
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let handle = DeviceHandle::new("test:handle-missing");
+
+        let err = rt.block_on(handle.info().battery()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<DeviceError>(),
+            Some(&DeviceError::NotFound("test:handle-missing".to_string()))
+        );
+        assert!(
+            rt.block_on(DeviceHandle::find("test:handle-missing"))
+                .is_err()
+        );
+    }
+}
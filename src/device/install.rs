@@ -16,6 +16,11 @@ use crate::{
             },
             quickapp_manifest::parse_vivo_quick_app_manifest,
         },
+        xiaomi::{
+            config::ResConfig,
+            packet::mass::MassDataType,
+            resutils::{self, FileType, PackageInfo},
+        },
     },
 };
 
@@ -293,6 +298,149 @@ pub async fn install_vivo_quick_app_local(
     Ok(())
 }
 
+/// 文件内容认不出能装的类型
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileInstallError {
+    Unsupported {
+        detected: FileType,
+        /// abp 包里写的资源文件，普通文件是 None
+        inner: Option<FileType>,
+    },
+}
+
+impl std::fmt::Display for FileInstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unsupported {
+                detected,
+                inner: None,
+            } => write!(
+                f,
+                "unsupported file: detected {detected:?}, expected a watchface, firmware, quick app or abp package"
+            ),
+            Self::Unsupported {
+                detected,
+                inner: Some(inner),
+            } => write!(
+                f,
+                "unsupported file: {detected:?} package contains {inner:?}, expected a watchface, firmware or quick app"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FileInstallError {}
+
+/// `plan_file_install` 的结果：真正要传的内容和从里面读出来的元数据
+#[derive(Debug, Clone)]
+pub struct FileInstallPlan {
+    pub data_type: MassDataType,
+    /// abp 包是拆出来的那个文件
+    pub payload: Vec<u8>,
+    pub info: PackageInfo,
+}
+
+/// 认类型、拆 abp、校验，再把安装要的元数据（表盘 id、快应用包名/版本）读出来。
+/// 只认小米这边能装的三种：表盘、固件、快应用
+pub fn plan_file_install(data: Vec<u8>, config: &ResConfig) -> anyhow::Result<FileInstallPlan> {
+    let detected = resutils::get_file_type(&data);
+    let (data_type, payload) = match detected {
+        FileType::Abp => {
+            let (data_type, payload) = resutils::unpack_abp_package(&data)?;
+            if !matches!(
+                data_type,
+                MassDataType::Watchface | MassDataType::Firmware | MassDataType::ThirdPartyApp
+            ) {
+                return Err(FileInstallError::Unsupported {
+                    detected,
+                    inner: Some(resutils::get_file_type(&payload)),
+                }
+                .into());
+            }
+            (data_type, payload)
+        }
+        // toolkit 打出来的 rpk 里不一定有 get_file_type 认的字样，能读出 manifest 也算快应用
+        FileType::Zip if resutils::parse_quickapp_manifest(&data).is_ok() => {
+            (MassDataType::ThirdPartyApp, data)
+        }
+        other => match MassDataType::try_from(other) {
+            Ok(data_type) => (data_type, data),
+            Err(_) => {
+                return Err(FileInstallError::Unsupported {
+                    detected,
+                    inner: None,
+                }
+                .into());
+            }
+        },
+    };
+    let info = resutils::validate_package(&payload, data_type, config)?;
+    if data_type == MassDataType::ThirdPartyApp && info.package_name.is_none() {
+        bail_site!("quick app package has no manifest, cannot tell its package name");
+    }
+    Ok(FileInstallPlan {
+        data_type,
+        payload,
+        info,
+    })
+}
+
+/// 用户选了个文件直接装：读文件、认类型、读元数据，再走对应的小米安装流程。
+/// Vivo 的安装参数和流程都不一样，还是用上面的 `install_vivo_*`
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn install_file(
+    addr: String,
+    path: &std::path::Path,
+    progress_cb: Arc<
+        dyn Fn(crate::device::xiaomi::components::mass::SendMassCallbackData) + Send + Sync,
+    >,
+) -> anyhow::Result<crate::device::xiaomi::components::install::InstallOutcome> {
+    use crate::device::{
+        handle::DeviceHandle,
+        xiaomi::{self, components::install::InstallOptions},
+    };
+
+    if device_kind(&addr).await? != DeviceKind::Xiaomi {
+        bail_site!("install_file only supports Xiaomi devices, use install_vivo_* for vivo");
+    }
+    let data = tokio::fs::read(path)
+        .await
+        .map_err(|err| anyhow_site!("failed to read {}: {}", path.display(), err))?;
+
+    let res_config = xiaomi::with_device_ref(&addr, |dev| dev.config.res.clone())
+        .await
+        .ok_or_else(|| anyhow_site!("Device not found"))?;
+    // 拆 abp、解 zip 读 manifest 都是 CPU 活，大文件别卡住 async 线程
+    let plan = tokio::task::spawn_blocking(move || plan_file_install(data, &res_config))
+        .await
+        .map_err(|err| anyhow_site!("plan_file_install task failed: {}", err))?
+        .map_err(|err| err.context(format!("cannot install {}", path.display())))?;
+
+    log::info!(
+        "[Install] {} -> {}: {} ({} bytes, id={:?}, package={:?}, version_code={:?})",
+        path.display(),
+        addr,
+        plan.data_type,
+        plan.info.size,
+        plan.info.id,
+        plan.info.package_name,
+        plan.info.version_code
+    );
+    DeviceHandle::new(addr)
+        .install(
+            plan.data_type,
+            plan.payload,
+            plan.info.package_name,
+            // plan_file_install 已经校验过了
+            InstallOptions {
+                prevalidated: true,
+                ..Default::default()
+            },
+            progress_cb,
+        )
+        .await
+}
+
 /// 计算字节流的 MD5 hex（小写，32 字符），与 jadx `wAppBean.getFileMd5()` 在云端
 /// 接到的格式对齐。
 fn compute_file_md5_hex(bytes: &[u8]) -> String {
@@ -310,7 +458,7 @@ fn compute_file_md5_hex(bytes: &[u8]) -> String {
 
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
@@ -369,3 +517,72 @@ where
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn zip_with_files(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn rpk() -> Vec<u8> {
+        zip_with_files(&[(
+            "manifest.json",
+            br#"{"package":"com.example.watch.todo","versionName":"1.2.0","versionCode":12}"#,
+        )])
+    }
+
+    #[test]
+    fn plans_each_supported_type_and_rejects_text() {
+        let config = ResConfig {
+            watchface_id_offset: 4,
+            watchface_id_field_len: 24,
+            ..ResConfig::default()
+        };
+
+        let mut face = b"\x5a\xa5\x34\x12".to_vec();
+        face.extend_from_slice(b"123456789012");
+        face.resize(4 + 24, 0);
+        let plan = plan_file_install(face, &config).unwrap();
+        assert_eq!(plan.data_type, MassDataType::Watchface);
+        assert_eq!(plan.info.id.as_deref(), Some("123456789012"));
+
+        let firmware = zip_with_files(&[("vela_ap.bin", &vec![0u8; resutils::MIN_FIRMWARE_SIZE])]);
+        let plan = plan_file_install(firmware, &config).unwrap();
+        assert_eq!(plan.data_type, MassDataType::Firmware);
+
+        let plan = plan_file_install(rpk(), &config).unwrap();
+        assert_eq!(plan.data_type, MassDataType::ThirdPartyApp);
+        assert_eq!(
+            plan.info.package_name.as_deref(),
+            Some("com.example.watch.todo")
+        );
+        assert_eq!(plan.info.version_code, Some(12));
+
+        let abp = zip_with_files(&[
+            ("abp.json", br#"{"file":"app.rpk","type":"quickapp"}"#),
+            ("app.rpk", &rpk()),
+        ]);
+        let plan = plan_file_install(abp, &config).unwrap();
+        assert_eq!(plan.data_type, MassDataType::ThirdPartyApp);
+        assert_eq!(plan.payload, rpk());
+
+        let err = plan_file_install(b"just some notes".to_vec(), &config).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<FileInstallError>(),
+            Some(&FileInstallError::Unsupported {
+                detected: FileType::Text,
+                inner: None,
+            })
+        );
+    }
+}
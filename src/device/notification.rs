@@ -0,0 +1,67 @@
+use crate::{
+    anyhow_site,
+    device::xiaomi::components::notification::{
+        IconStatus, NotificationComponent, NotificationFilter, NotificationImportance,
+        NotificationSystem,
+    },
+};
+
+/// 换掉设备的通知转发规则，立即生效
+pub async fn set_notification_filter(
+    addr: String,
+    filter: NotificationFilter,
+) -> anyhow::Result<()> {
+    crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            world
+                .get_mut::<NotificationComponent>(entity)
+                .map(|mut comp| comp.set_filter(filter))
+                .ok_or_else(|| anyhow_site!("Xiaomi notification component not found"))
+        })
+        .ok_or_else(|| anyhow_site!("Device not found"))?
+    })
+    .await
+}
+
+pub async fn notification_filter(addr: String) -> anyhow::Result<NotificationFilter> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<NotificationComponent>(&addr)
+            .map(|comp| comp.filter().clone())
+            .ok_or_else(|| anyhow_site!("Xiaomi notification component not found"))
+    })
+    .await
+}
+
+/// 宿主转发一条手机通知前先问这里，false 就别发了
+pub async fn should_mirror(
+    addr: String,
+    package_name: String,
+    importance: NotificationImportance,
+) -> anyhow::Result<bool> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<NotificationComponent>(&addr)
+            .map(|comp| comp.filter().allows(&package_name, importance))
+            .ok_or_else(|| anyhow_site!("Xiaomi notification component not found"))
+    })
+    .await
+}
+
+/// 推通知前确保手表上有这个包的图标，手表已经有（或者缓存里记着有）返回 `Present`；
+/// 没有的话给了 `icon_png` 就传上去，没给返回 `NotificationError::IconMissingLocally`
+pub async fn ensure_icon(
+    addr: String,
+    package_name: String,
+    icon_png: Option<Vec<u8>>,
+) -> anyhow::Result<IconStatus> {
+    let icon = crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            world
+                .get_mut::<NotificationSystem>(entity)
+                .ok_or_else(|| anyhow_site!("Xiaomi notification system not found"))?
+                .ensure_icon(&package_name, icon_png)
+        })
+        .ok_or_else(|| anyhow_site!("Device not found"))?
+    })
+    .await?;
+    icon.await
+}
@@ -0,0 +1,370 @@
+//! 连上之后的整套同步：先要设备状态，再并发拿设备信息、对时、设语言，最后刷资源列表。
+//! 和 `DeviceSetup` 不一样，某一步失败不会拦住后面的，每步的结果都收进 `SyncReport`，
+//! 每步结束时发一个 `CoreEvent::PostConnectStep`
+
+use std::future::Future;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+use crate::asyncrt::{Duration, timeout};
+use crate::events::{CoreEvent, PostConnectStepFinished};
+use crate::models::sync::TimeSyncProps;
+
+use super::data::DeviceDataType;
+
+const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum PostConnectStep {
+    DeviceStatus,
+    DeviceInfo,
+    SyncTime,
+    SetLanguage,
+    WatchfaceList,
+    QuickAppList,
+}
+
+impl PostConnectStep {
+    /// 按执行顺序排
+    pub const ALL: [PostConnectStep; 6] = [
+        PostConnectStep::DeviceStatus,
+        PostConnectStep::DeviceInfo,
+        PostConnectStep::SyncTime,
+        PostConnectStep::SetLanguage,
+        PostConnectStep::WatchfaceList,
+        PostConnectStep::QuickAppList,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PostConnectStep::DeviceStatus => "device_status",
+            PostConnectStep::DeviceInfo => "device_info",
+            PostConnectStep::SyncTime => "sync_time",
+            PostConnectStep::SetLanguage => "set_language",
+            PostConnectStep::WatchfaceList => "watchface_list",
+            PostConnectStep::QuickAppList => "quick_app_list",
+        }
+    }
+}
+
+impl std::fmt::Display for PostConnectStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Default)]
+pub struct PostConnectOptions {
+    /// None 就不对时
+    pub time: Option<TimeSyncProps>,
+    /// `en-US` / `en_US` 都行，None 就不设语言。不认识的 locale 照发，同 `sync::set_language`
+    pub locale: Option<String>,
+    pub skip: Vec<PostConnectStep>,
+    /// 每步单独计时，None 用 10 秒
+    pub step_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct StepReport {
+    pub step: PostConnectStep,
+    pub elapsed_ms: u64,
+    /// None 表示成功
+    pub error: Option<String>,
+}
+
+impl StepReport {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct SyncReport {
+    /// 跑过的步骤，按 `PostConnectStep::ALL` 的顺序
+    pub steps: Vec<StepReport>,
+    /// 被跳过的（显式 skip，或者没给对时参数 / locale）
+    pub skipped: Vec<PostConnectStep>,
+}
+
+impl SyncReport {
+    pub fn all_ok(&self) -> bool {
+        self.steps.iter().all(StepReport::is_ok)
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &StepReport> {
+        self.steps.iter().filter(|step| !step.is_ok())
+    }
+
+    pub fn get(&self, step: PostConnectStep) -> Option<&StepReport> {
+        self.steps.iter().find(|report| report.step == step)
+    }
+}
+
+/// 连接完成后调用一次，不会因为某一步失败提前返回
+pub async fn post_connect_sync(addr: String, options: PostConnectOptions) -> SyncReport {
+    let PostConnectOptions {
+        time,
+        locale,
+        skip,
+        step_timeout,
+    } = options;
+    let limit = step_timeout.unwrap_or(DEFAULT_STEP_TIMEOUT);
+    let enabled = |step: PostConnectStep| !skip.contains(&step);
+
+    // 状态先单独要，顺便确认链路是通的
+    let status = run_step(
+        &addr,
+        PostConnectStep::DeviceStatus,
+        limit,
+        enabled(PostConnectStep::DeviceStatus)
+            .then(|| request_data(addr.clone(), DeviceDataType::Status)),
+    )
+    .await;
+
+    // 这三个互不依赖，SAR 自己会排队，一起发
+    let (info, time_sync, language) = tokio::join!(
+        run_step(
+            &addr,
+            PostConnectStep::DeviceInfo,
+            limit,
+            enabled(PostConnectStep::DeviceInfo)
+                .then(|| request_data(addr.clone(), DeviceDataType::Info)),
+        ),
+        run_step(
+            &addr,
+            PostConnectStep::SyncTime,
+            limit,
+            time.filter(|_| enabled(PostConnectStep::SyncTime))
+                .map(|props| super::sync::sync_time(addr.clone(), props)),
+        ),
+        run_step(
+            &addr,
+            PostConnectStep::SetLanguage,
+            limit,
+            locale
+                .filter(|_| enabled(PostConnectStep::SetLanguage))
+                .map(|locale| super::sync::set_language(addr.clone(), locale, false)),
+        ),
+    );
+
+    // 资源列表回包大，放最后
+    let (watchfaces, quick_apps) = tokio::join!(
+        run_step(
+            &addr,
+            PostConnectStep::WatchfaceList,
+            limit,
+            enabled(PostConnectStep::WatchfaceList).then(|| async {
+                super::resource::request_watchface_list_json(addr.clone())
+                    .await
+                    .map(drop)
+            }),
+        ),
+        run_step(
+            &addr,
+            PostConnectStep::QuickAppList,
+            limit,
+            enabled(PostConnectStep::QuickAppList).then(|| async {
+                super::resource::request_quick_app_list_json(addr.clone())
+                    .await
+                    .map(drop)
+            }),
+        ),
+    );
+
+    let mut report = SyncReport::default();
+    let results = [status, info, time_sync, language, watchfaces, quick_apps];
+    for (step, result) in PostConnectStep::ALL.into_iter().zip(results) {
+        match result {
+            Some(step_report) => report.steps.push(step_report),
+            None => report.skipped.push(step),
+        }
+    }
+    log::info!(
+        "[PostConnect] {addr}: {} ok, {} failed, {} skipped",
+        report.steps.len() - report.failed().count(),
+        report.failed().count(),
+        report.skipped.len()
+    );
+    report
+}
+
+async fn request_data(addr: String, data_type: DeviceDataType) -> anyhow::Result<()> {
+    super::data::request_device_data_json(addr, data_type)
+        .await
+        .map(drop)
+}
+
+/// `fut` 为 None 表示这步被跳过
+async fn run_step<Fut>(
+    addr: &str,
+    step: PostConnectStep,
+    limit: Duration,
+    fut: Option<Fut>,
+) -> Option<StepReport>
+where
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let fut = fut?;
+    let started_at = Instant::now();
+    let result = match timeout(limit, fut).await {
+        Ok(result) => result,
+        Err(_) => Err(crate::anyhow_site!(
+            "timed out after {}ms",
+            limit.as_millis()
+        )),
+    };
+    let error = result.err().map(|err| {
+        log::warn!("[PostConnect] {addr} step `{step}` failed: {err:?}");
+        format!("{err:#}")
+    });
+    crate::events::emit(CoreEvent::PostConnectStep(PostConnectStepFinished {
+        device_addr: addr.to_string(),
+        step,
+        error: error.clone(),
+    }));
+    Some(StepReport {
+        step,
+        elapsed_ms: started_at
+            .elapsed()
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX),
+        error,
+    })
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::spawn_mock_xiaomi;
+    use crate::device::xiaomi::{
+        cleanup_cached_state, components::info::InfoSystem, config::XiaomiDeviceConfig,
+        system::L2PbExt,
+    };
+    use crate::models::sync::{Date, Time, TimeZone};
+    use pb::xiaomi::protocol;
+    use tokio::sync::broadcast::error::RecvError;
+
+    fn system_packet(
+        id: protocol::system::SystemId,
+        payload: protocol::system::Payload,
+    ) -> protocol::WearPacket {
+        protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::System as i32,
+            id: id as u32,
+            payload: Some(protocol::wear_packet::Payload::System(protocol::System {
+                payload: Some(payload),
+            })),
+        }
+    }
+
+    fn time_props() -> TimeSyncProps {
+        TimeSyncProps {
+            date: Date {
+                year: 2025,
+                month: 1,
+                day: 2,
+            },
+            time: Time {
+                hour: 3,
+                minute: 4,
+                second: 5,
+                millisecond: 0,
+            },
+            timezone: TimeZone {
+                offset: 64,
+                dst_offset: 0,
+                id: "Asia/Shanghai".to_string(),
+            },
+            is_12_hour_format: false,
+        }
+    }
+
+    #[test]
+    fn one_failing_step_does_not_stop_the_rest() {
+        crate::ecs::init_runtime_default();
+        let addr = "test:post-connect-sync";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let report = rt.block_on(async {
+            spawn_mock_xiaomi(addr, XiaomiDeviceConfig::default()).await;
+            let mut events = crate::events::subscribe();
+            // 假手表每轮都会发 DeviceStateChanged，边跑边收，只挑步骤事件；落后了就接着收
+            let collector = tokio::spawn(async move {
+                let mut finished = Vec::new();
+                while finished.len() < 5 {
+                    match events.recv().await {
+                        Ok(CoreEvent::PostConnectStep(done)) if done.device_addr == addr => {
+                            finished.push(done.step)
+                        }
+                        Ok(_) | Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+                finished
+            });
+
+            // 假手表：状态和设备信息有问必答，表盘列表永远不回
+            let watch = tokio::spawn(async move {
+                loop {
+                    crate::ecs::with_rt_mut(move |rt| {
+                        rt.with_device_mut(addr, |world, entity| {
+                            if let Some(mut sys) = world.get_mut::<InfoSystem>(entity) {
+                                sys.on_pb_packet(system_packet(
+                                    protocol::system::SystemId::GetDeviceStatus,
+                                    protocol::system::Payload::DeviceStatus(Default::default()),
+                                ));
+                                sys.on_pb_packet(system_packet(
+                                    protocol::system::SystemId::GetDeviceInfo,
+                                    protocol::system::Payload::DeviceInfo(Default::default()),
+                                ));
+                            }
+                        });
+                    })
+                    .await;
+                    // 每轮都会发 DeviceStateChanged，别太勤把事件总线挤爆
+                    crate::asyncrt::sleep(Duration::from_millis(50)).await;
+                }
+            });
+
+            let report = post_connect_sync(
+                addr.to_string(),
+                PostConnectOptions {
+                    time: Some(time_props()),
+                    locale: Some("en-US".to_string()),
+                    skip: vec![PostConnectStep::QuickAppList],
+                    step_timeout: Some(Duration::from_millis(300)),
+                },
+            )
+            .await;
+            watch.abort();
+
+            let finished = timeout(Duration::from_secs(1), collector)
+                .await
+                .expect("step events not all received")
+                .unwrap();
+            assert_eq!(finished.len(), 5, "{finished:?}");
+            assert_eq!(finished[0], PostConnectStep::DeviceStatus);
+            assert_eq!(finished[4], PostConnectStep::WatchfaceList);
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            report
+        });
+        cleanup_cached_state(addr);
+
+        assert_eq!(report.skipped, [PostConnectStep::QuickAppList]);
+        let ran: Vec<_> = report.steps.iter().map(|step| step.step).collect();
+        assert_eq!(ran, &PostConnectStep::ALL[..5]);
+        let failed: Vec<_> = report.failed().map(|step| step.step).collect();
+        assert_eq!(failed, [PostConnectStep::WatchfaceList]);
+        assert!(
+            report
+                .get(PostConnectStep::WatchfaceList)
+                .and_then(|step| step.error.as_deref())
+                .is_some_and(|err| err.contains("timed out"))
+        );
+        assert!(!report.all_ok());
+    }
+}
@@ -53,7 +53,7 @@ pub async fn request_dial_free_storage_json(addr: String) -> anyhow::Result<serd
 
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
@@ -0,0 +1,218 @@
+//! 连接后的初始化流程：对时、语言、权限、应用状态握手之类的，按固定顺序一步步来，
+//! 每步有自己的超时，哪步失败就停在哪并告诉调用方
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::asyncrt::{Duration, timeout};
+use crate::models::sync::TimeSyncProps;
+
+#[cfg(target_arch = "wasm32")]
+type StepFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>>>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+type StepFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+type StepFn = Box<dyn FnOnce(String) -> StepFuture + Send>;
+
+const DEFAULT_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct SetupStep {
+    name: String,
+    timeout: Option<Duration>,
+    run: StepFn,
+}
+
+/// 有序的初始化步骤列表，用 builder 拼好后 run
+pub struct DeviceSetup {
+    addr: String,
+    default_timeout: Duration,
+    steps: Vec<SetupStep>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SetupReport {
+    pub completed: Vec<String>,
+}
+
+/// 哪一步失败了，前面成功的步骤也带上
+#[derive(Debug)]
+pub struct SetupError {
+    pub step: String,
+    pub index: usize,
+    pub completed: Vec<String>,
+    pub source: anyhow::Error,
+}
+
+impl std::fmt::Display for SetupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "device setup failed at step {} `{}`: {:#}",
+            self.index + 1,
+            self.step,
+            self.source
+        )
+    }
+}
+
+impl std::error::Error for SetupError {}
+
+impl DeviceSetup {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            default_timeout: DEFAULT_STEP_TIMEOUT,
+            steps: Vec::new(),
+        }
+    }
+
+    /// 没单独指定超时的步骤用这个
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = timeout;
+        self
+    }
+
+    /// 追加一步，`f` 拿到的是设备地址
+    pub fn step<F, Fut>(self, name: impl Into<String>, f: F) -> Self
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.push(name.into(), None, f)
+    }
+
+    pub fn step_with_timeout<F, Fut>(self, name: impl Into<String>, timeout: Duration, f: F) -> Self
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.push(name.into(), Some(timeout), f)
+    }
+
+    pub fn sync_time(self, props: TimeSyncProps) -> Self {
+        self.step("sync_time", move |addr| super::sync::sync_time(addr, props))
+    }
+
+    pub fn set_language(self, locale: impl Into<String>) -> Self {
+        let locale = locale.into();
+        self.step("set_language", move |addr| {
+            super::sync::set_language(addr, locale, false)
+        })
+    }
+
+    fn push<F, Fut>(mut self, name: String, timeout: Option<Duration>, f: F) -> Self
+    where
+        F: FnOnce(String) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        self.steps.push(SetupStep {
+            name,
+            timeout,
+            run: Box::new(move |addr| Box::pin(f(addr))),
+        });
+        self
+    }
+
+    /// 按顺序跑，遇到失败或超时立刻停下
+    pub async fn run(self) -> Result<SetupReport, SetupError> {
+        let mut completed = Vec::with_capacity(self.steps.len());
+        for (index, step) in self.steps.into_iter().enumerate() {
+            let limit = step.timeout.unwrap_or(self.default_timeout);
+            log::debug!(
+                "[DeviceSetup] {} step {}: {}",
+                self.addr,
+                index + 1,
+                step.name
+            );
+
+            let result = match timeout(limit, (step.run)(self.addr.clone())).await {
+                Ok(result) => result,
+                Err(_) => Err(anyhow::anyhow!("timed out after {}ms", limit.as_millis())),
+            };
+            if let Err(source) = result {
+                log::warn!(
+                    "[DeviceSetup] {} aborted at `{}`: {source:?}",
+                    self.addr,
+                    step.name
+                );
+                return Err(SetupError {
+                    step: step.name,
+                    index,
+                    completed,
+                    source,
+                });
+            }
+            completed.push(step.name);
+        }
+        Ok(SetupReport { completed })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    fn block_on<T>(fut: impl Future<Output = T>) -> T {
+        tokio::runtime::Runtime::new().unwrap().block_on(fut)
+    }
+
+    fn recording_step(
+        log: &Arc<Mutex<Vec<String>>>,
+        name: &'static str,
+    ) -> impl FnOnce(String) -> std::future::Ready<anyhow::Result<()>> + Send + 'static {
+        let log = log.clone();
+        move |addr| {
+            log.lock().push(format!("{addr}:{name}"));
+            std::future::ready(Ok(()))
+        }
+    }
+
+    #[test]
+    fn runs_steps_in_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let report = block_on(
+            DeviceSetup::new("dev")
+                .step("time", recording_step(&log, "time"))
+                .step("language", recording_step(&log, "language"))
+                .step("app_status", recording_step(&log, "app_status"))
+                .run(),
+        )
+        .unwrap();
+
+        assert_eq!(report.completed, ["time", "language", "app_status"]);
+        assert_eq!(*log.lock(), ["dev:time", "dev:language", "dev:app_status"]);
+    }
+
+    #[test]
+    fn stops_at_failed_or_slow_step() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let err = block_on(
+            DeviceSetup::new("dev")
+                .default_timeout(Duration::from_millis(50))
+                .step("time", recording_step(&log, "time"))
+                .step("permission", |_| std::future::pending())
+                .step("language", recording_step(&log, "language"))
+                .run(),
+        )
+        .unwrap_err();
+
+        assert_eq!(err.step, "permission");
+        assert_eq!(err.index, 1);
+        assert_eq!(err.completed, ["time"]);
+        assert!(err.to_string().contains("timed out"), "{err}");
+        // 后面的步骤不会再跑
+        assert_eq!(*log.lock(), ["dev:time"]);
+
+        let err = block_on(
+            DeviceSetup::new("dev")
+                .step("time", |_| async { Err(anyhow::anyhow!("rejected")) })
+                .run(),
+        )
+        .unwrap_err();
+        assert_eq!(err.index, 0);
+        assert!(err.completed.is_empty());
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::HashSet;
+
+use pb::xiaomi::protocol;
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::{
+    anyhow_site,
+    asyncrt::{Duration, sleep},
+    bail_site,
+    device::{
+        Device, DeviceKind,
+        xiaomi::components::{
+            info::InfoSystem as XiaomiInfoSystem,
+            resource::ResourceSystem as XiaomiResourceSystem,
+            thirdparty_app::{AppInfo as XiaomiAppInfo, ThirdpartyAppSystem},
+            watchface::WatchfaceSystem,
+        },
+    },
+};
+
+// 卸载后设备更新列表要一点时间，确认几次还在就当失败
+const REMOVAL_CONFIRM_ATTEMPTS: u32 = 5;
+const REMOVAL_CONFIRM_INTERVAL: Duration = Duration::from_millis(800);
+
+/// 腾空间时哪些东西能删
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FreeSpacePolicy {
+    /// 表盘删完还不够时要不要动快应用
+    #[serde(default)]
+    pub include_apps: bool,
+    /// 绝对不能删的表盘 ID / 应用包名
+    #[serde(default)]
+    pub protect: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemovableKind {
+    Watchface,
+    QuickApp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RemovableItem {
+    pub kind: RemovableKind,
+    /// 表盘 ID 或应用包名
+    pub id: String,
+    #[serde(skip)]
+    fingerprint: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FreedReport {
+    pub needed_bytes: u64,
+    pub free_before: u64,
+    pub free_after: u64,
+    pub satisfied: bool,
+    pub removed: Vec<RemovableItem>,
+    /// 发了卸载但设备没删掉的
+    pub failed: Vec<RemovableItem>,
+}
+
+/// 空间不够装东西时一键清理：先删非当前的旧表盘，策略允许的话再删快应用，
+/// 每删一个重新查一次存储，够了就停。当前表盘和 `protect` 里的东西永远不碰
+pub async fn free_space(
+    addr: String,
+    needed_bytes: u64,
+    policy: FreeSpacePolicy,
+) -> anyhow::Result<FreedReport> {
+    match device_kind(&addr).await? {
+        DeviceKind::Xiaomi => {}
+        DeviceKind::Vivo => bail_site!("free_space is not supported for Vivo devices yet"),
+    }
+
+    let watchfaces = list_watchfaces(&addr).await?;
+    let apps = if policy.include_apps {
+        list_quick_apps(&addr).await?
+    } else {
+        Vec::new()
+    };
+
+    let candidates = select_candidates(&watchfaces, &apps, &policy);
+    log::info!(
+        "[Storage] need {} bytes on {}, {} removable candidates",
+        needed_bytes,
+        addr,
+        candidates.len()
+    );
+    free_space_with(&mut XiaomiSpaceOps { addr }, needed_bytes, candidates).await
+}
+
+/// 按删除顺序排好的候选：表盘在前（设备列表按安装先后排，旧的在前），应用在后
+fn select_candidates(
+    watchfaces: &[protocol::WatchFaceItem],
+    apps: &[protocol::AppItem],
+    policy: &FreeSpacePolicy,
+) -> Vec<RemovableItem> {
+    let protect: HashSet<&str> = policy.protect.iter().map(String::as_str).collect();
+
+    let faces = watchfaces
+        .iter()
+        .filter(|face| !face.is_current && face.can_remove)
+        .filter(|face| !protect.contains(face.id.as_str()))
+        .map(|face| RemovableItem {
+            kind: RemovableKind::Watchface,
+            id: face.id.clone(),
+            fingerprint: Vec::new(),
+        });
+    let apps = apps
+        .iter()
+        .filter(|_| policy.include_apps)
+        .filter(|app| !protect.contains(app.package_name.as_str()))
+        .map(|app| RemovableItem {
+            kind: RemovableKind::QuickApp,
+            id: app.package_name.clone(),
+            fingerprint: app.fingerprint.clone(),
+        });
+    faces.chain(apps).collect()
+}
+
+/// free_space 的设备操作，抽出来方便测试
+trait SpaceOps {
+    async fn free_bytes(&mut self) -> anyhow::Result<u64>;
+    /// 发卸载并等设备确认删掉
+    async fn remove(&mut self, item: &RemovableItem) -> anyhow::Result<()>;
+}
+
+async fn free_space_with<O: SpaceOps>(
+    ops: &mut O,
+    needed_bytes: u64,
+    candidates: Vec<RemovableItem>,
+) -> anyhow::Result<FreedReport> {
+    let free_before = ops.free_bytes().await?;
+    let mut free = free_before;
+    let mut removed = Vec::new();
+    let mut failed = Vec::new();
+
+    for item in candidates {
+        if free >= needed_bytes {
+            break;
+        }
+        match ops.remove(&item).await {
+            Ok(()) => {
+                removed.push(item);
+                free = ops.free_bytes().await?;
+            }
+            Err(err) => {
+                log::warn!(
+                    "[Storage] failed to remove {:?} {}: {err:?}",
+                    item.kind,
+                    item.id
+                );
+                failed.push(item);
+            }
+        }
+    }
+
+    Ok(FreedReport {
+        needed_bytes,
+        free_before,
+        free_after: free,
+        satisfied: free >= needed_bytes,
+        removed,
+        failed,
+    })
+}
+
+struct XiaomiSpaceOps {
+    addr: String,
+}
+
+impl SpaceOps for XiaomiSpaceOps {
+    async fn free_bytes(&mut self) -> anyhow::Result<u64> {
+        let storage = request(
+            &self.addr,
+            |sys: &mut XiaomiInfoSystem| sys.request_device_storage(),
+            "Device storage info response not received",
+        )
+        .await?;
+        Ok(storage.total.saturating_sub(storage.used))
+    }
+
+    async fn remove(&mut self, item: &RemovableItem) -> anyhow::Result<()> {
+        match item.kind {
+            RemovableKind::Watchface => {
+                let id = item.id.clone();
+                with_xiaomi(&self.addr, move |sys: &mut WatchfaceSystem| {
+                    sys.uninstall_watchface(&id)
+                })
+                .await?;
+            }
+            RemovableKind::QuickApp => {
+                let info = XiaomiAppInfo {
+                    package_name: item.id.clone(),
+                    fingerprint: item.fingerprint.clone(),
+                };
+                with_xiaomi(&self.addr, move |sys: &mut ThirdpartyAppSystem| {
+                    sys.uninstall_app(&info)
+                })
+                .await?;
+            }
+        }
+
+        // 卸载没有单独的回包，重新拉列表确认真的没了
+        for _ in 0..REMOVAL_CONFIRM_ATTEMPTS {
+            sleep(REMOVAL_CONFIRM_INTERVAL).await;
+            let still_there = match item.kind {
+                RemovableKind::Watchface => list_watchfaces(&self.addr)
+                    .await?
+                    .iter()
+                    .any(|face| face.id == item.id),
+                RemovableKind::QuickApp => list_quick_apps(&self.addr)
+                    .await?
+                    .iter()
+                    .any(|app| app.package_name == item.id),
+            };
+            if !still_there {
+                return Ok(());
+            }
+        }
+        bail_site!("device still lists {} after uninstall", item.id)
+    }
+}
+
+async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
+    let addr_owned = addr.to_string();
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<Device>(&addr_owned)
+            .map(|device| device.kind())
+            .ok_or_else(|| anyhow_site!("Device not found"))
+    })
+    .await
+}
+
+async fn with_xiaomi<S, F, R>(addr: &str, f: F) -> anyhow::Result<R>
+where
+    S: crate::ecs::Component,
+    F: FnOnce(&mut S) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let addr = addr.to_string();
+    crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            let mut system = world
+                .get_mut::<S>(entity)
+                .ok_or_else(|| anyhow_site!("Xiaomi system not found"))?;
+            Ok(f(&mut system))
+        })
+        .ok_or_else(|| anyhow_site!("Device not found"))?
+    })
+    .await
+}
+
+/// 发请求并等回包，列表和存储查询都走这里
+async fn request<S, F, T>(addr: &str, f: F, missing_msg: &'static str) -> anyhow::Result<T>
+where
+    S: crate::ecs::Component,
+    F: FnOnce(&mut S) -> oneshot::Receiver<anyhow::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    let rx = with_xiaomi(addr, f).await?;
+    rx.await.map_err(|_| anyhow_site!("{missing_msg}"))?
+}
+
+async fn list_watchfaces(addr: &str) -> anyhow::Result<Vec<protocol::WatchFaceItem>> {
+    request(
+        addr,
+        |sys: &mut XiaomiResourceSystem| sys.request_watchface_list(),
+        "Watchface list response not received",
+    )
+    .await
+}
+
+async fn list_quick_apps(addr: &str) -> anyhow::Result<Vec<protocol::AppItem>> {
+    request(
+        addr,
+        |sys: &mut XiaomiResourceSystem| sys.request_quick_app_list(),
+        "Quick app list response not received",
+    )
+    .await
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct MockOps {
+        free: u64,
+        sizes: HashMap<String, u64>,
+        broken: HashSet<String>,
+        removed: Vec<String>,
+    }
+
+    impl MockOps {
+        fn new(free: u64, sizes: &[(&str, u64)]) -> Self {
+            Self {
+                free,
+                sizes: sizes
+                    .iter()
+                    .map(|(id, size)| (id.to_string(), *size))
+                    .collect(),
+                broken: HashSet::new(),
+                removed: Vec::new(),
+            }
+        }
+    }
+
+    impl SpaceOps for MockOps {
+        async fn free_bytes(&mut self) -> anyhow::Result<u64> {
+            Ok(self.free)
+        }
+
+        async fn remove(&mut self, item: &RemovableItem) -> anyhow::Result<()> {
+            if self.broken.contains(&item.id) {
+                bail_site!("uninstall ignored");
+            }
+            self.free += self.sizes.get(&item.id).copied().unwrap_or_default();
+            self.removed.push(item.id.clone());
+            Ok(())
+        }
+    }
+
+    fn face(id: &str, is_current: bool) -> protocol::WatchFaceItem {
+        protocol::WatchFaceItem {
+            id: id.to_string(),
+            is_current,
+            can_remove: true,
+            ..Default::default()
+        }
+    }
+
+    fn app(package_name: &str) -> protocol::AppItem {
+        protocol::AppItem {
+            package_name: package_name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn ids(items: &[RemovableItem]) -> Vec<&str> {
+        items.iter().map(|item| item.id.as_str()).collect()
+    }
+
+    #[test]
+    fn candidates_skip_active_and_protected() {
+        let faces = [
+            face("old", false),
+            face("active", true),
+            face("kept", false),
+        ];
+        let apps = [app("com.example.a"), app("com.example.keep")];
+        let mut policy = FreeSpacePolicy {
+            include_apps: false,
+            protect: vec!["kept".to_string(), "com.example.keep".to_string()],
+        };
+
+        assert_eq!(ids(&select_candidates(&faces, &apps, &policy)), ["old"]);
+
+        policy.include_apps = true;
+        assert_eq!(
+            ids(&select_candidates(&faces, &apps, &policy)),
+            ["old", "com.example.a"]
+        );
+    }
+
+    #[test]
+    fn stops_once_enough_space_is_free() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let faces = [face("a", false), face("b", false), face("c", false)];
+        let candidates = select_candidates(&faces, &[], &FreeSpacePolicy::default());
+        let mut ops = MockOps::new(100, &[("a", 300), ("b", 300), ("c", 300)]);
+
+        let report = rt
+            .block_on(free_space_with(&mut ops, 600, candidates))
+            .unwrap();
+
+        assert!(report.satisfied);
+        assert_eq!(ops.removed, ["a", "b"]);
+        assert_eq!(report.free_after, 700);
+    }
+
+    #[test]
+    fn reports_shortfall_when_candidates_run_out() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let faces = [face("a", false), face("b", false)];
+        let candidates = select_candidates(&faces, &[], &FreeSpacePolicy::default());
+        let mut ops = MockOps::new(0, &[("a", 100), ("b", 100)]);
+        ops.broken.insert("a".to_string());
+
+        let report = rt
+            .block_on(free_space_with(&mut ops, 1_000, candidates))
+            .unwrap();
+
+        assert!(!report.satisfied);
+        assert_eq!(ids(&report.removed), ["b"]);
+        assert_eq!(ids(&report.failed), ["a"]);
+        assert_eq!(report.free_after, 100);
+    }
+
+    #[test]
+    fn removes_nothing_when_already_enough() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let candidates = select_candidates(&[face("a", false)], &[], &FreeSpacePolicy::default());
+        let mut ops = MockOps::new(500, &[("a", 100)]);
+
+        let report = rt
+            .block_on(free_space_with(&mut ops, 500, candidates))
+            .unwrap();
+
+        assert!(report.satisfied);
+        assert!(ops.removed.is_empty());
+    }
+}
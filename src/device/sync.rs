@@ -1,31 +1,37 @@
 use crate::{
     anyhow_site,
     device::{
-        Device, DeviceKind, vivo::components::sync::SyncSystem as VivoSyncSystem,
-        xiaomi::components::sync::SyncSystem as XiaomiSyncSystem,
+        Device, DeviceKind,
+        vivo::components::sync::SyncSystem as VivoSyncSystem,
+        xiaomi::components::sync::{
+            SyncComponent as XiaomiSyncComponent, SyncSystem as XiaomiSyncSystem,
+        },
     },
     models::sync::TimeSyncProps,
 };
 
 pub async fn sync_time(addr: String, props: TimeSyncProps) -> anyhow::Result<()> {
     match device_kind(&addr).await? {
-        DeviceKind::Xiaomi => {
-            with_xiaomi_sync_system(addr, move |sys| {
-                sys.sync_time(props);
-                Ok(())
-            })
-            .await
-        }
+        DeviceKind::Xiaomi => with_xiaomi_sync_system(addr, move |sys| sys.sync_time(props)).await,
         DeviceKind::Vivo => with_vivo_sync_system(addr, move |sys| sys.sync_time(props)).await,
     }
 }
 
-pub async fn set_language(addr: String, locale: String) -> anyhow::Result<()> {
+/// 小米设备会先换分隔符、按支持列表校验，见 `SyncSystem::set_language`；`strict` 对 Vivo 不起作用
+pub async fn set_language(addr: String, locale: String, strict: bool) -> anyhow::Result<()> {
     match device_kind(&addr).await? {
         DeviceKind::Xiaomi => {
-            with_xiaomi_sync_system(addr, move |sys| {
-                sys.set_language(locale);
-                Ok(())
+            crate::ecs::with_rt_mut(move |rt| {
+                rt.with_device_mut(&addr, |world, entity| {
+                    let supported = world
+                        .get::<XiaomiSyncComponent>(entity)
+                        .and_then(|comp| comp.supported_locales().map(<[String]>::to_vec));
+                    let mut system = world
+                        .get_mut::<XiaomiSyncSystem>(entity)
+                        .ok_or_else(|| anyhow_site!("Xiaomi sync system not found"))?;
+                    system.set_language(locale, supported.as_deref(), strict)
+                })
+                .ok_or_else(|| anyhow_site!("Device not found"))?
             })
             .await
         }
@@ -33,9 +39,34 @@ pub async fn set_language(addr: String, locale: String) -> anyhow::Result<()> {
     }
 }
 
+/// 缓存在 SyncComponent 里的手表支持 locale，None 表示还不知道。
+/// 目前协议里没有能列举 locale 的请求，只能靠宿主用 `set_supported_locales` 告诉我们
+pub async fn get_supported_locales(addr: String) -> anyhow::Result<Option<Vec<String>>> {
+    crate::ecs::with_rt_read(move |rt| {
+        rt.component_ref::<XiaomiSyncComponent>(&addr)
+            .map(|comp| comp.supported_locales().map(<[String]>::to_vec))
+            .ok_or_else(|| anyhow_site!("Xiaomi sync component not found"))
+    })
+    .await
+}
+
+/// 记下手表支持的 locale（固件写法，比如 `en_US`），之后 `set_language` 按它校验
+pub async fn set_supported_locales(addr: String, locales: Vec<String>) -> anyhow::Result<()> {
+    crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&addr, |world, entity| {
+            world
+                .get_mut::<XiaomiSyncComponent>(entity)
+                .map(|mut comp| comp.set_supported_locales(locales))
+                .ok_or_else(|| anyhow_site!("Xiaomi sync component not found"))
+        })
+        .ok_or_else(|| anyhow_site!("Device not found"))?
+    })
+    .await
+}
+
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
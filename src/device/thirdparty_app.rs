@@ -106,7 +106,7 @@ async fn xiaomi_app_info(addr: &str, package_name: &str) -> anyhow::Result<Xiaom
 
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
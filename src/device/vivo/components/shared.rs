@@ -8,6 +8,7 @@ use crate::{
     ecs::access::with_device_component_mut,
 };
 
+/// 和小米那边的 `xiaomi::components::shared::RequestSlot` 一样，说明看那边
 pub struct RequestSlot<T> {
     waiters: Mutex<Vec<oneshot::Sender<Result<T>>>>,
 }
@@ -22,6 +23,8 @@ impl<T> RequestSlot<T> {
     pub fn prepare(&mut self) -> (oneshot::Receiver<Result<T>>, bool) {
         let (tx, rx) = oneshot::channel();
         let mut waiters = self.waiters.lock();
+        // 同小米那边，先清掉超时放弃的
+        waiters.retain(|tx| !tx.is_closed());
         let should_enqueue = waiters.is_empty();
         waiters.push(tx);
         (rx, should_enqueue)
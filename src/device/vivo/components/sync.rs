@@ -163,45 +163,9 @@ fn build_vivo_time_sync(props: &TimeSyncProps) -> anyhow::Result<VivoTimeSync> {
 }
 
 fn validate_time_parts(props: &TimeSyncProps) -> anyhow::Result<()> {
-    if props.date.month == 0 || props.date.month > 12 {
-        return Err(anyhow_site!(
-            "invalid vivo sync month: {}",
-            props.date.month
-        ));
-    }
-    let max_day = days_in_month(props.date.year as i32, props.date.month);
-    if props.date.day == 0 || props.date.day > max_day {
-        return Err(anyhow_site!("invalid vivo sync day: {}", props.date.day));
-    }
-    if props.time.hour > 23 || props.time.minute > 59 || props.time.second > 59 {
-        return Err(anyhow_site!(
-            "invalid vivo sync time: {:02}:{:02}:{:02}",
-            props.time.hour,
-            props.time.minute,
-            props.time.second
-        ));
-    }
-    if props.time.millisecond > 999 {
-        return Err(anyhow_site!(
-            "invalid vivo sync millisecond: {}",
-            props.time.millisecond
-        ));
-    }
-    Ok(())
-}
-
-fn days_in_month(year: i32, month: u32) -> u32 {
-    match month {
-        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
-        4 | 6 | 9 | 11 => 30,
-        2 if is_leap_year(year) => 29,
-        2 => 28,
-        _ => 0,
-    }
-}
-
-fn is_leap_year(year: i32) -> bool {
-    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    props
+        .validate()
+        .map_err(|err| anyhow_site!("invalid vivo sync time: {err}"))
 }
 
 fn days_from_civil(year: i32, month: u32, day: u32) -> i64 {
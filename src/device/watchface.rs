@@ -14,7 +14,7 @@ use crate::{
         },
         xiaomi::components::{
             mass::{SendMassCallbackData, send_file_for_owner_with_known_slice_length},
-            watchface::WatchfaceSystem as XiaomiWatchfaceSystem,
+            watchface::{WatchfaceInfo, WatchfaceSystem as XiaomiWatchfaceSystem},
         },
         xiaomi::packet::mass::MassDataType,
     },
@@ -42,6 +42,20 @@ pub async fn set_current(addr: String, watchface_id: String) -> anyhow::Result<(
     Ok(())
 }
 
+/// 当前正在用的表盘，小米这边每次都会刷新一次已安装列表
+pub async fn get_current(addr: String) -> anyhow::Result<WatchfaceInfo> {
+    match device_kind(&addr).await? {
+        DeviceKind::Xiaomi => {
+            let rx = with_xiaomi_watchface_system(addr, |sys| Ok(sys.request_current())).await?;
+            rx.await
+                .map_err(|_| anyhow_site!("Current watchface response not received"))?
+        }
+        DeviceKind::Vivo => {
+            bail_site!("Current watchface query is not supported for Vivo devices yet")
+        }
+    }
+}
+
 pub async fn uninstall(addr: String, watchface_id: String) -> anyhow::Result<()> {
     match device_kind(&addr).await? {
         DeviceKind::Xiaomi => {
@@ -147,7 +161,7 @@ pub async fn install_local_zip_vivo(
 
 async fn device_kind(addr: &str) -> anyhow::Result<DeviceKind> {
     let addr_owned = addr.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
+    crate::ecs::with_rt_read(move |rt| {
         rt.component_ref::<Device>(&addr_owned)
             .map(|device| device.kind())
             .ok_or_else(|| anyhow_site!("Device not found"))
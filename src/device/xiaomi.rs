@@ -6,22 +6,27 @@ use std::time::Instant;
 use web_time::Instant;
 
 use crate::{
-    asyncrt::universal_block_on,
+    asyncrt::{Duration, sleep, universal_block_on},
     device::{
         Device, DeviceKind,
         xiaomi::{
-            config::XiaomiDeviceConfig,
-            packet::{cipher, dispatcher},
+            config::{BlePacing, ChannelCrypto, QosProfile, XiaomiDeviceConfig},
+            packet::{
+                cipher, dispatcher, raw_pb, read,
+                v2::{layer1::L1Packet, layer2::L2Channel},
+            },
             r#type::ConnectType,
         },
     },
-    ecs::Component,
+    ecs::{Component, runtime::Runtime},
 };
+use bytes::Bytes;
 use parking_lot::Mutex as ParkingMutex;
 use tokio::runtime::Handle;
 use tokio::sync::Mutex as AsyncMutex;
 use transport_profiler::TransportProfilerHandle;
 
+pub mod clock;
 pub mod components;
 pub mod config;
 pub mod packet;
@@ -37,8 +42,40 @@ pub enum SendError {
     Io(String),
 }
 
+/// SAR 交给发送端的一段数据，发送端靠它认帧边界（见 `TransportConfig::align_frames`）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendUnit {
+    /// 一个完整的 L1 帧
+    Frame(Bytes),
+    /// 不是 L1 帧的裸字节（SPP Hello 之类），原样发
+    Raw(Bytes),
+}
+
+impl SendUnit {
+    pub fn is_frame(&self) -> bool {
+        matches!(self, Self::Frame(_))
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            Self::Frame(bytes) | Self::Raw(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for SendUnit {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Frame(bytes) | Self::Raw(bytes) => bytes,
+        }
+    }
+}
+
 type SendFuture = Pin<Box<dyn Future<Output = Result<(), SendError>> + Send>>;
-type SendFn = Arc<dyn Fn(Vec<Vec<u8>>) -> SendFuture + Send + Sync>;
+/// SAR -> 发送端，按帧给
+type SendFn = Arc<dyn Fn(Vec<SendUnit>) -> SendFuture + Send + Sync>;
+/// 发送端 -> 宿主，已经切好的片
+type RawSendFn = Arc<dyn Fn(Vec<Vec<u8>>) -> SendFuture + Send + Sync>;
 const SPP_STREAM_SEND_COALESCE_CAP: usize = 60 * 1024;
 
 #[derive(Component, serde::Serialize)]
@@ -46,7 +83,7 @@ pub struct XiaomiDevice {
     #[serde(flatten)]
     device: Device,
     pub sar_version: u32,          // SAR 协议版本，对应SPP v?
-    pub connect_type: ConnectType, // 连接类型，SPP or BLE
+    pub connect_type: ConnectType, // 连接类型，SPP / BLE / TCP
     pub force_android: bool, // 安卓人安卓代码安卓生态安卓手表安卓设备安卓pb 在连接设备时强制使用ANDROID作为设备类型
     #[serde(skip_serializing)]
     sender: SendFn,
@@ -55,15 +92,119 @@ pub struct XiaomiDevice {
     #[serde(skip_serializing)]
     pub sar: ParkingMutex<sar::SarController>,
     pub config: XiaomiDeviceConfig,
+    /// 建设备时调用方给的配置（套预设之前），中途换 QoS 预设时靠它分辨哪些字段是单独改过的
+    #[serde(skip_serializing)]
+    requested_config: XiaomiDeviceConfig,
 }
 
 pub fn cleanup_cached_state(device_id: &str) {
     cipher::remove_l2_cipher(device_id);
     dispatcher::clear_recv_buffer(device_id);
+    dispatcher::clear_dispatcher_stats(device_id);
+    read::clear_pending_reads(device_id);
+    raw_pb::clear_subscribers(device_id);
+    dispatcher::set_crc_verification(device_id, true);
+}
+
+/// 一个 runtime 任务里直接拿到 `&mut XiaomiDevice`，设备不在（或者不是小米设备）返回 None。
+/// 任务里只带一份 owner 和 `f`，热路径用这个，别再自己拼 with_device_mut + get_mut
+pub async fn with_device_mut<R, F>(owner: &str, f: F) -> Option<R>
+where
+    F: FnOnce(&mut XiaomiDevice) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    with_component_mut::<XiaomiDevice, _, _>(owner, f).await
+}
+
+/// 同 `with_device_mut`，拿同一个设备实体上的别的组件
+pub async fn with_component_mut<T, R, F>(owner: &str, f: F) -> Option<R>
+where
+    T: Component,
+    F: FnOnce(&mut T) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    crate::ecs::with_rt_mut(device_job::<T, _, _>(owner.to_string(), f)).await
+}
+
+/// 只读版本，走 `with_rt_read`
+pub async fn with_device_ref<R, F>(owner: &str, f: F) -> Option<R>
+where
+    F: FnOnce(&XiaomiDevice) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let owner = owner.to_string();
+    crate::ecs::with_rt_read(move |rt| {
+        rt.with_device_ref(&owner, |world, entity| {
+            world.get::<XiaomiDevice>(entity).map(f)
+        })
+        .flatten()
+    })
+    .await
+}
+
+fn device_job<T, R, F>(owner: String, f: F) -> impl FnOnce(&mut Runtime) -> Option<R>
+where
+    T: Component,
+    F: FnOnce(&mut T) -> R,
+{
+    move |rt| {
+        rt.with_device_mut(&owner, |world, entity| {
+            world.get_mut::<T>(entity).map(|mut comp| f(&mut comp))
+        })
+        .flatten()
+    }
+}
+
+/// BLE 始终校验 L1 CRC，可靠的流式传输才看 `SarConfig.verify_crc`
+fn verifies_crc(config: &XiaomiDeviceConfig, connect_type: ConnectType) -> bool {
+    config.sar.verify_crc || !connect_type.is_stream()
 }
 
 impl XiaomiDevice {
+    /// 构造完 SAR 立刻开始握手，见 `SarController::new`
     pub fn new<F, Fut>(
+        tk_handle: Handle,
+        name: String,
+        addr: String,
+        authkey: String,
+        sar_version: u32,
+        connect_type: ConnectType,
+        force_android: bool,
+        config: XiaomiDeviceConfig,
+        sender: F,
+    ) -> Self
+    where
+        F: Fn(Vec<Vec<u8>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), SendError>> + Send + 'static,
+    {
+        let dev = Self::new_deferred(
+            tk_handle,
+            name,
+            addr,
+            authkey,
+            sar_version,
+            connect_type,
+            force_android,
+            config,
+            sender,
+        );
+        dev.sar.lock().start();
+        dev
+    }
+
+    /// 套用 QoS 预设：SAR 参数立刻生效，MASS 参数从下一次传输开始生效。
+    /// 建设备时单独给的字段（连接参数里的 `tx_win_overrun_allowance` 之类）不会被预设盖掉
+    pub fn set_qos(&mut self, profile: QosProfile) {
+        profile.apply_over(&mut self.config, &self.requested_config);
+        self.sar.lock().update_config(&self.config.sar);
+        dispatcher::set_crc_verification(
+            self.addr(),
+            verifies_crc(&self.config, self.connect_type),
+        );
+    }
+
+    /// SAR 先不握手，实体进了 runtime 之后再 `sar.lock().start()`
+    pub fn new_deferred<F, Fut>(
         tk_handle: Handle,
         name: String,
         addr: String,
@@ -78,50 +219,71 @@ impl XiaomiDevice {
         F: Fn(Vec<Vec<u8>>) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Result<(), SendError>> + Send + 'static,
     {
+        // 预设在这里套上，单独改过的字段盖在上面
+        let requested_config = config.clone();
+        let config = config.resolved();
         let transport_profiler = TransportProfilerHandle::new();
         // 包装线程安全Sender
-        let raw_sender: SendFn = Arc::new(move |data: Vec<Vec<u8>>| Box::pin(sender(data)));
-        // 上锁防止串串包
-        let send_lock = Arc::new(AsyncMutex::new(()));
+        let raw_sender: RawSendFn = Arc::new(move |data: Vec<Vec<u8>>| Box::pin(sender(data)));
         let transport_config = config.transport.clone();
+        // 上锁防止串串包；BLE 限速的窗口也放锁里，所有流量共用一个节拍
+        let pacer = transport_config
+            .ble_pacing()
+            .filter(|_| matches!(connect_type, ConnectType::BLE))
+            .map(IntervalPacer::new);
+        let send_lock = Arc::new(AsyncMutex::new(pacer));
         let sender: SendFn = {
             let raw_sender = raw_sender.clone();
             let send_lock = send_lock.clone();
             let profiler = transport_profiler.clone();
-            Arc::new(move |data: Vec<Vec<u8>>| {
+            #[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+            let record_id = addr.clone();
+            Arc::new(move |data: Vec<SendUnit>| {
+                #[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+                sar::record::capture(&record_id, sar::record::Direction::Out, &data);
                 let raw_sender = raw_sender.clone();
                 let send_lock = send_lock.clone();
                 let profiler = profiler.clone();
                 let chunk_size_ble = transport_config.chunk_size_ble;
                 let chunk_size_spp = transport_config.chunk_size_spp;
+                let ble_chunk_delay = Duration::from_millis(transport_config.ble_chunk_delay_ms);
+                let align_frames = transport_config.align_frames;
                 Box::pin(async move {
-                    let _guard = send_lock.lock().await;
+                    let mut pacer = send_lock.lock().await;
 
-                    let chunk_size_max = if connect_type == ConnectType::SPP {
+                    let chunk_size_max = if connect_type.is_stream() {
                         chunk_size_spp.max(SPP_STREAM_SEND_COALESCE_CAP)
                     } else {
                         chunk_size_ble
                     };
 
-                    let mut chunks = Vec::new();
-                    for packet in data {
-                        if packet.len() <= chunk_size_max {
-                            chunks.push(packet);
-                        } else {
-                            chunks
-                                .extend(packet.chunks(chunk_size_max).map(|chunk| chunk.to_vec()));
-                        }
-                    }
-                    let packet_count = chunks.len() as u32;
-                    let total_bytes = chunks.iter().map(|chunk| chunk.len() as u64).sum::<u64>();
+                    let writes = chunk_units(data, chunk_size_max, align_frames);
+                    let chunk_count = writes.iter().map(Vec::len).sum::<usize>();
+                    let packet_count = chunk_count as u32;
+                    let total_bytes = writes
+                        .iter()
+                        .flatten()
+                        .map(|chunk| chunk.len() as u64)
+                        .sum::<u64>();
                     let started_at = Instant::now();
-                    let result = raw_sender(chunks).await;
+                    // pacer 只有 BLE 才会有
+                    let paced = pacer.is_some()
+                        || (matches!(connect_type, ConnectType::BLE)
+                            && !ble_chunk_delay.is_zero()
+                            && chunk_count > 1);
+                    let result = if paced {
+                        // 本来就一片一写，帧天然从片头开始
+                        let chunks = writes.into_iter().flatten().collect();
+                        send_paced(&raw_sender, chunks, ble_chunk_delay, pacer.as_mut()).await
+                    } else {
+                        send_writes(&raw_sender, writes).await
+                    };
                     profiler.record(
                         "transport",
-                        if connect_type == ConnectType::BLE {
-                            "send_batch_ble"
-                        } else {
-                            "send_batch_spp"
+                        match connect_type {
+                            ConnectType::BLE => "send_batch_ble",
+                            ConnectType::SPP => "send_batch_spp",
+                            ConnectType::TCP => "send_batch_tcp",
                         },
                         Some(
                             started_at
@@ -135,8 +297,8 @@ impl XiaomiDevice {
                         None,
                         Some(result.is_ok()),
                         Some(format!(
-                            "chunk_size_max={},connect_type={:?}",
-                            chunk_size_max, connect_type
+                            "chunk_size_max={},connect_type={:?},paced={},align_frames={}",
+                            chunk_size_max, connect_type, paced, align_frames
                         )),
                     );
                     result
@@ -145,19 +307,20 @@ impl XiaomiDevice {
         };
 
         // 不知道为什么傻逼小米针对SPP连接要发这么一个神秘Hello
-        if connect_type == ConnectType::SPP {
+        if connect_type.requires_spp_hello() {
             universal_block_on(|| async {
-                sender(vec![
-                    crate::tools::hex_stream_to_bytes("badcfe00c00300000100ef").unwrap(),
-                ])
+                sender(vec![SendUnit::Raw(Bytes::from_static(
+                    crate::constants::XIAOMI_SPP_HELLO,
+                ))])
                 .await
                 .unwrap();
             });
         }
 
         let base = Device::new(name, addr, DeviceKind::Xiaomi);
+        dispatcher::set_crc_verification(base.addr(), verifies_crc(&config, connect_type));
         // 创建 SAR 控制器，并传入设备名以便定时任务访问
-        let sar = sar::SarController::new(
+        let sar = sar::SarController::new_deferred(
             tk_handle.clone(),
             sender.clone(),
             base.addr().to_string(),
@@ -174,12 +337,13 @@ impl XiaomiDevice {
             transport_profiler,
             sar: ParkingMutex::new(sar),
             config,
+            requested_config,
         };
         dev
     }
 
     pub async fn send_data(&self, data: Vec<u8>) -> Result<(), SendError> {
-        (self.sender)(vec![data]).await
+        (self.sender)(vec![SendUnit::Raw(data.into())]).await
     }
 
     pub fn base(&self) -> &Device {
@@ -197,4 +361,453 @@ impl XiaomiDevice {
     pub fn addr(&self) -> &str {
         self.device.addr()
     }
+
+    /// 现在发 Pb 包会不会加密：通道策略不是 Never 且 L2 cipher 已经注册。
+    /// cipher 是第一次发包时才按认证派生的 key 懒建的，认证状态在 AuthComponent 上这里看不到，
+    /// 发凭据之类的敏感内容前用 `device::l2_encryption_ready`，它会连认证一起判断
+    pub fn l2_encryption_ready(&self) -> bool {
+        self.config.channel_crypto.get(L2Channel::Pb) != ChannelCrypto::Never
+            && cipher::get_l2_cipher(self.addr()).is_some()
+    }
+}
+
+/// 按传输层的片长切好，返回每次交给宿主的一批片。
+/// 平时整批一次写；`align` 时每个 L1 帧单独一批，片长至少装得下帧头，
+/// 帧头不会被拆到两次写里，也不会接在上一帧的尾巴后面
+fn chunk_units(units: Vec<SendUnit>, chunk_size: usize, align: bool) -> Vec<Vec<Vec<u8>>> {
+    let chunk_size = if align {
+        chunk_size.max(L1Packet::HEADER_LEN)
+    } else {
+        chunk_size
+    };
+    let mut writes: Vec<Vec<Vec<u8>>> = vec![Vec::new()];
+    for unit in units {
+        if align && unit.is_frame() && writes.last().is_some_and(|w| !w.is_empty()) {
+            writes.push(Vec::new());
+        }
+        let bytes = unit.into_bytes();
+        let write = writes.last_mut().unwrap();
+        if bytes.len() <= chunk_size {
+            write.push(bytes.to_vec());
+        } else {
+            write.extend(bytes.chunks(chunk_size).map(<[u8]>::to_vec));
+        }
+    }
+    writes
+}
+
+/// 一批一批交给传输层，前一批写完才写下一批
+async fn send_writes(raw_sender: &RawSendFn, writes: Vec<Vec<Vec<u8>>>) -> Result<(), SendError> {
+    for chunks in writes {
+        raw_sender(chunks).await?;
+    }
+    Ok(())
+}
+
+/// 一片一片交给传输层，中间歇一会，给慢吞吞的 BLE 栈喘口气
+async fn send_paced(
+    raw_sender: &RawSendFn,
+    chunks: Vec<Vec<u8>>,
+    delay: Duration,
+    mut pacer: Option<&mut IntervalPacer>,
+) -> Result<(), SendError> {
+    for (idx, chunk) in chunks.into_iter().enumerate() {
+        if idx > 0 && !delay.is_zero() {
+            sleep(delay).await;
+        }
+        if let Some(pacer) = pacer.as_deref_mut() {
+            pacer.wait_turn().await;
+        }
+        raw_sender(vec![chunk]).await?;
+    }
+    Ok(())
+}
+
+/// 按连接间隔限速：一个间隔里写满 `max_writes` 片就睡到下个间隔开头再写
+struct IntervalPacer {
+    max_writes: u32,
+    interval: Duration,
+    window_start: Option<Instant>,
+    writes: u32,
+}
+
+impl IntervalPacer {
+    fn new(pacing: BlePacing) -> Self {
+        Self {
+            max_writes: pacing.max_writes_per_interval,
+            interval: Duration::from_millis(pacing.interval_ms),
+            window_start: None,
+            writes: 0,
+        }
+    }
+
+    async fn wait_turn(&mut self) {
+        let now = Instant::now();
+        let start = match self.window_start {
+            Some(start) if now.duration_since(start) < self.interval => start,
+            // 闲了超过一个间隔，从现在重新数
+            _ => {
+                self.window_start = Some(now);
+                self.writes = 0;
+                now
+            }
+        };
+        if self.writes >= self.max_writes {
+            let next = start + self.interval;
+            sleep(next.saturating_duration_since(now)).await;
+            self.window_start = Some(next);
+            self.writes = 0;
+        }
+        self.writes += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn encryption_ready_follows_auth_and_policy() {
+        use crate::device::xiaomi::components::auth::AuthComponent;
+        use crate::device::{l2_encryption_ready, spawn_mock_xiaomi};
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:l2-encryption-ready";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let dev_ready = || {
+            crate::ecs::with_rt_read(move |rt| {
+                rt.component_ref::<XiaomiDevice>(addr)
+                    .map(|dev| dev.l2_encryption_ready())
+            })
+        };
+        rt.block_on(async {
+            spawn_mock_xiaomi(addr, XiaomiDeviceConfig::default()).await;
+            assert!(!l2_encryption_ready(addr.to_string()).await.unwrap());
+            assert_eq!(dev_ready().await, Some(false));
+
+            // 认证完成，key 已经派生好，cipher 还没建
+            crate::ecs::with_rt_mut(move |rt| {
+                rt.with_device_mut(addr, |world, entity| {
+                    let mut auth = world.get_mut::<AuthComponent>(entity).unwrap();
+                    auth.is_authed = true;
+                    auth.enc_key = vec![0x11; 16];
+                    auth.dec_key = vec![0x22; 16];
+                });
+            })
+            .await;
+            assert!(l2_encryption_ready(addr.to_string()).await.unwrap());
+            assert_eq!(dev_ready().await, Some(true));
+
+            // Pb 被配成明文的话 cipher 在也不算
+            crate::ecs::with_rt_mut(move |rt| {
+                if let Some(mut dev) = rt.component_mut::<XiaomiDevice>(addr) {
+                    dev.config
+                        .channel_crypto
+                        .set(L2Channel::Pb, ChannelCrypto::Never);
+                }
+            })
+            .await;
+            assert!(!l2_encryption_ready(addr.to_string()).await.unwrap());
+            assert_eq!(dev_ready().await, Some(false));
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            assert!(l2_encryption_ready(addr.to_string()).await.is_err());
+        });
+        cleanup_cached_state(addr);
+    }
+
+    #[test]
+    fn ble_chunk_delay_sends_one_chunk_per_write() {
+        let _lock = sar::tests::SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writes: Arc<Mutex<Vec<usize>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let mut config = XiaomiDeviceConfig::default();
+        config.transport.chunk_size_ble = 8;
+        config.transport.ble_chunk_delay_ms = 2;
+
+        let dev = rt.block_on(async {
+            let writes = writes.clone();
+            XiaomiDevice::new(
+                Handle::current(),
+                "mock".to_string(),
+                "test:ble-paced".to_string(),
+                String::new(),
+                2,
+                ConnectType::BLE,
+                false,
+                config,
+                move |frames: Vec<Vec<u8>>| {
+                    let writes = writes.clone();
+                    async move {
+                        writes.lock().push(frames.len());
+                        Ok(())
+                    }
+                },
+            )
+        });
+        rt.block_on(async {
+            dev.send_data(vec![0u8; 40]).await.unwrap();
+        });
+
+        let writes = writes.lock();
+        assert!(writes.len() >= 5);
+        assert!(writes.iter().all(|&count| count == 1));
+        drop(dev);
+    }
+
+    fn test_frames() -> Vec<Vec<u8>> {
+        use crate::device::xiaomi::packet::v2::layer1::L1DataType;
+
+        [0usize, 3, 17, 40, 250]
+            .into_iter()
+            .enumerate()
+            .map(|(seq, len)| {
+                L1Packet::new(L1DataType::Data, false, seq as u8, vec![0x5a; len]).to_bytes()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aligned_frames_never_split_header() {
+        let frames = test_frames();
+        let units = |frames: &[Vec<u8>]| {
+            let mut units = vec![SendUnit::Raw(Bytes::from_static(b"hello"))];
+            units.extend(frames.iter().map(|f| SendUnit::Frame(f.clone().into())));
+            units
+        };
+
+        for chunk_size in [5, 8, 13, 20, 244] {
+            let writes = chunk_units(units(&frames), chunk_size, true);
+            // Hello 自己一批，之后一帧一批
+            assert_eq!(writes.len(), frames.len() + 1);
+            assert_eq!(writes[0], vec![b"hello".to_vec()]);
+            for (write, frame) in writes[1..].iter().zip(&frames) {
+                assert!(
+                    write[0].len() >= L1Packet::HEADER_LEN,
+                    "chunk_size={chunk_size}"
+                );
+                assert_eq!(&write.concat(), frame);
+            }
+        }
+
+        // 不对齐时整批一次写，片小于帧头就会把帧头拆开
+        let flat = chunk_units(units(&frames), 5, false);
+        assert_eq!(flat.len(), 1);
+        assert_eq!(flat[0][1].len(), 5);
+    }
+
+    #[test]
+    fn aligned_ble_sender_writes_one_frame_per_call() {
+        let _lock = sar::tests::SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let calls: Arc<Mutex<Vec<Vec<Vec<u8>>>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let mut config = XiaomiDeviceConfig::default();
+        config.transport.chunk_size_ble = 20;
+        config.transport.align_frames = true;
+
+        let dev = rt.block_on(async {
+            let calls = calls.clone();
+            XiaomiDevice::new_deferred(
+                Handle::current(),
+                "mock".to_string(),
+                "test:ble-aligned".to_string(),
+                String::new(),
+                2,
+                ConnectType::BLE,
+                false,
+                config,
+                move |chunks: Vec<Vec<u8>>| {
+                    let calls = calls.clone();
+                    async move {
+                        calls.lock().push(chunks);
+                        Ok(())
+                    }
+                },
+            )
+        });
+        let frames = test_frames();
+        let units = frames
+            .iter()
+            .map(|f| SendUnit::Frame(f.clone().into()))
+            .collect();
+        rt.block_on((dev.sender)(units)).unwrap();
+
+        let calls = calls.lock();
+        assert_eq!(calls.len(), frames.len());
+        for (chunks, frame) in calls.iter().zip(&frames) {
+            assert_eq!(&chunks[0][..2], &L1Packet::MAGIC.to_le_bytes());
+            assert!(chunks.iter().all(|chunk| chunk.len() <= 20));
+            assert_eq!(&chunks.concat(), frame);
+        }
+        drop(dev);
+    }
+
+    /// 每次 write 记下时间和片数
+    fn timed_writes(
+        connect_type: ConnectType,
+        config: XiaomiDeviceConfig,
+        addr: &str,
+    ) -> Vec<(Instant, usize)> {
+        let _lock = sar::tests::SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let writes: Arc<Mutex<Vec<(Instant, usize)>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let dev = rt.block_on(async {
+            let writes = writes.clone();
+            XiaomiDevice::new(
+                Handle::current(),
+                "mock".to_string(),
+                addr.to_string(),
+                String::new(),
+                2,
+                connect_type,
+                false,
+                config,
+                move |frames: Vec<Vec<u8>>| {
+                    let writes = writes.clone();
+                    async move {
+                        writes.lock().push((Instant::now(), frames.len()));
+                        Ok(())
+                    }
+                },
+            )
+        });
+        rt.block_on(async {
+            dev.send_data(vec![0u8; 40]).await.unwrap();
+        });
+        drop(dev);
+        let writes = writes.lock().clone();
+        writes
+    }
+
+    fn pacing_config() -> XiaomiDeviceConfig {
+        let mut config = XiaomiDeviceConfig::default();
+        config.transport.chunk_size_ble = 8;
+        config.transport.ble_max_writes_per_interval = 2;
+        config.transport.ble_interval_ms = 40;
+        config
+    }
+
+    #[test]
+    fn ble_pacing_waits_for_next_interval() {
+        let interval = Duration::from_millis(40);
+        let writes = timed_writes(ConnectType::BLE, pacing_config(), "test:ble-interval");
+
+        // L1StartReq 加一个 40 字节的数据帧，切 8 字节一片远不止 4 片
+        assert!(writes.len() >= 4);
+        assert!(writes.iter().all(|&(_, count)| count == 1));
+        // 开头两片连着写，第三片要等到下个间隔
+        assert!(writes[1].0 - writes[0].0 < interval / 2);
+        assert!(writes[2].0 - writes[0].0 >= interval - Duration::from_millis(2));
+        let windows = writes.len().div_ceil(2) as u32 - 1;
+        let span = writes.last().unwrap().0 - writes[0].0;
+        assert!(span >= (interval - Duration::from_millis(2)) * windows);
+    }
+
+    #[test]
+    fn spp_ignores_ble_pacing() {
+        let mut config = pacing_config();
+        config.transport.ble_max_writes_per_interval = 1;
+        config.transport.ble_interval_ms = 500;
+        let writes = timed_writes(ConnectType::SPP, config, "test:spp-interval");
+
+        // 至少有 Hello 和数据帧（L1StartReq 不一定赶得上），真被限速的话第二次写就得等半秒
+        assert!(writes.len() >= 2);
+        let span = writes.last().unwrap().0 - writes[0].0;
+        assert!(span < Duration::from_millis(250), "{span:?}");
+    }
+
+    #[test]
+    fn tcp_transport_skips_spp_hello() {
+        let _lock = sar::tests::SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let sent: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+        let hello = crate::constants::XIAOMI_SPP_HELLO.to_vec();
+
+        let dev = rt.block_on(async {
+            let sent = sent.clone();
+            XiaomiDevice::new(
+                Handle::current(),
+                "mock".to_string(),
+                "test:tcp".to_string(),
+                String::new(),
+                2,
+                ConnectType::TCP,
+                false,
+                XiaomiDeviceConfig::default(),
+                move |frames: Vec<Vec<u8>>| {
+                    let sent = sent.clone();
+                    async move {
+                        sent.lock().extend(frames);
+                        Ok(())
+                    }
+                },
+            )
+        });
+        rt.block_on(async { crate::asyncrt::sleep(std::time::Duration::from_millis(50)).await });
+
+        let sent = sent.lock();
+        // L1StartReq 应该照常发出去，但不能有 SPP Hello
+        assert!(!sent.is_empty());
+        assert!(sent.iter().all(|frame| frame != &hello));
+        drop(dev);
+    }
+
+    #[test]
+    fn instant_l1start_rsp_applies_negotiated_window() {
+        use crate::device::spawn_mock_xiaomi_with_sender;
+        use crate::device::xiaomi::packet::v2::layer1::{L1DataType, L1Packet};
+        use crate::device::xiaomi::packet::v2::layer1cmd::{CmdCode, L1CmdBuilder, L1CmdPacket};
+
+        let _lock = sar::tests::SAR_TEST_LOCK.lock();
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let addr = "test:instant-start-rsp";
+
+        rt.block_on(async {
+            let handle = Handle::current();
+            // 假手表：一看到 L1StartReq 就把 Rsp 塞回 dispatcher，不给实体挂载留时间
+            let sender = {
+                let handle = handle.clone();
+                move |frames: Vec<Vec<u8>>| {
+                    for frame in &frames {
+                        let is_start_req = L1Packet::from_bytes(frame)
+                            .ok()
+                            .filter(|pkt| pkt.pkt_type == L1DataType::Cmd)
+                            .and_then(|pkt| L1CmdPacket::from_payload_bytes(&pkt.payload))
+                            .is_some_and(|cmd| cmd.cmd == CmdCode::CmdL1startReq);
+                        if is_start_req {
+                            let rsp = L1CmdBuilder::new()
+                                .cmd(CmdCode::CmdL1startRsp)
+                                .version(1, 0, 0)
+                                .tx_win(8)
+                                .build()
+                                .unwrap();
+                            let pkt =
+                                L1Packet::new(L1DataType::Cmd, false, 0, rsp.to_payload_bytes());
+                            dispatcher::on_packet(handle.clone(), addr.to_string(), pkt.to_bytes());
+                        }
+                    }
+                    async { Ok::<(), SendError>(()) }
+                }
+            };
+
+            spawn_mock_xiaomi_with_sender(addr, XiaomiDeviceConfig::default(), sender).await;
+            crate::asyncrt::sleep(std::time::Duration::from_millis(200)).await;
+
+            let tx_win = crate::ecs::with_rt_read(move |rt| {
+                rt.component_ref::<XiaomiDevice>(addr)
+                    .and_then(|dev| dev.sar.lock().link_info().and_then(|info| info.tx_win))
+            })
+            .await;
+            assert_eq!(tx_win, Some(8));
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+        });
+        cleanup_cached_state(addr);
+    }
 }
@@ -0,0 +1,218 @@
+//! SAR / MASS 计时用的时钟。平时就是系统时间；测试里换成 `MockClock` 手动拨，
+//! 重传超时、ACK 卡顿这类用例不用真的干等
+
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use tokio::sync::oneshot;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(target_arch = "wasm32")]
+use web_time::{Duration, Instant};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type ClockSleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+#[cfg(target_arch = "wasm32")]
+pub type ClockSleep = Pin<Box<dyn Future<Output = ()>>>;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration) -> ClockSleep;
+}
+
+/// 默认时钟，行为和直接用 `Instant::now` / `asyncrt::sleep` 完全一样
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> ClockSleep {
+        Box::pin(crate::asyncrt::sleep(duration))
+    }
+}
+
+/// 配置里存的时钟句柄，clone 出来的都是同一个时钟
+#[derive(Clone)]
+pub struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub fn new(clock: impl Clock + 'static) -> Self {
+        Self(Arc::new(clock))
+    }
+
+    /// 从 `since` 到现在过了多久，`since` 比现在还晚时为 0
+    pub fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// 同 `asyncrt::timeout`，超时返回 None
+    pub async fn timeout<F: Future>(&self, duration: Duration, fut: F) -> Option<F::Output> {
+        let sleep = self.sleep(duration);
+        tokio::select! {
+            biased;
+            out = fut => Some(out),
+            _ = sleep => None,
+        }
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        Self::new(SystemClock)
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Debug for SharedClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedClock")
+    }
+}
+
+struct MockState {
+    now: Instant,
+    sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+}
+
+/// 手动拨的时钟：不 `advance` 时间就不走，sleep 到点才醒
+#[derive(Clone)]
+pub struct MockClock {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(MockState {
+                now: Instant::now(),
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn shared(&self) -> SharedClock {
+        SharedClock::new(self.clone())
+    }
+
+    /// 往前拨 `by`，到点的 sleep 全部叫醒
+    pub fn advance(&self, by: Duration) {
+        let due = {
+            let mut state = self.state.lock();
+            state.now += by;
+            let now = state.now;
+            let (due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut state.sleepers)
+                .into_iter()
+                .partition(|(at, _)| *at <= now);
+            state.sleepers = pending;
+            due
+        };
+        for (_, tx) in due {
+            let _ = tx.send(());
+        }
+    }
+
+    /// 还在等的 sleep 个数，已经被丢掉的不算
+    pub fn pending_sleeps(&self) -> usize {
+        self.state
+            .lock()
+            .sleepers
+            .iter()
+            .filter(|(_, tx)| !tx.is_closed())
+            .count()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.state.lock().now
+    }
+
+    fn sleep(&self, duration: Duration) -> ClockSleep {
+        if duration.is_zero() {
+            return Box::pin(async {});
+        }
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock();
+            state.sleepers.retain(|(_, tx)| !tx.is_closed());
+            let at = state.now + duration;
+            state.sleepers.push((at, tx));
+        }
+        Box::pin(async move {
+            // 时钟没了就再也没人拨，一直睡下去
+            if rx.await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_sleep_wakes_only_when_advanced_past_deadline() {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let clock = MockClock::new();
+        let shared = clock.shared();
+        let start = shared.now();
+
+        let sleeper = rt.spawn({
+            let shared = shared.clone();
+            async move { shared.sleep(Duration::from_secs(10)).await }
+        });
+        rt.block_on(async {
+            while clock.pending_sleeps() == 0 {
+                tokio::task::yield_now().await;
+            }
+        });
+
+        clock.advance(Duration::from_secs(9));
+        assert_eq!(shared.elapsed(start), Duration::from_secs(9));
+        assert_eq!(clock.pending_sleeps(), 1);
+        assert!(!sleeper.is_finished());
+
+        clock.advance(Duration::from_secs(1));
+        rt.block_on(sleeper).unwrap();
+        assert_eq!(clock.pending_sleeps(), 0);
+
+        // 超时也跟着假时间走
+        let waiter = rt.spawn({
+            let shared = shared.clone();
+            async move {
+                shared
+                    .timeout(Duration::from_millis(500), std::future::pending::<()>())
+                    .await
+            }
+        });
+        rt.block_on(async {
+            while clock.pending_sleeps() == 0 {
+                tokio::task::yield_now().await;
+            }
+        });
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(rt.block_on(waiter).unwrap(), None);
+    }
+}
@@ -0,0 +1,128 @@
+//! 连上并认证完成后跑一次能力探测，让宿主提前知道哪些功能在这块手表上能用，
+//! 而不是用到的时候才失败。只看本地已有的状态，不为探测单独发请求拖慢连接
+
+use crate::device::xiaomi::XiaomiDevice;
+
+use super::info::InfoComponent;
+
+/// 只放连接时确实能知道的东西。压缩、Lyra、Ota 通道、表盘上限这些目前没有协议能问，
+/// 猜出来的值还不如不报，宿主用到时自己看结果。MASS 加不加密是宿主自己的配置，
+/// 看 `XiaomiDeviceConfig::encrypts_mass`
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceCapabilities {
+    pub network_proxy: bool,
+    pub firmware_version: Option<String>,
+}
+
+/// 探测拿到的原始结果，全是本地已有的状态，不额外发请求
+#[derive(Debug, Clone, Default)]
+pub struct CapabilityProbe {
+    pub sar_version: u32,
+    pub network_stack_running: bool,
+    /// 之前的设备信息请求拿到过才有
+    pub firmware_version: Option<String>,
+}
+
+impl DeviceCapabilities {
+    pub fn from_probe(probe: &CapabilityProbe) -> Self {
+        // Network 走 L2 的通道，SAR v1 没有 L2
+        let has_l2 = probe.sar_version >= 2;
+
+        Self {
+            network_proxy: has_l2 && probe.network_stack_running,
+            firmware_version: probe
+                .firmware_version
+                .clone()
+                .filter(|version| !version.is_empty()),
+        }
+    }
+}
+
+/// 跑一遍探测，结果存进 InfoComponent 并返回。要在认证完成后调用
+pub async fn probe_capabilities(owner_id: String) -> DeviceCapabilities {
+    let capabilities = crate::ecs::with_rt_mut_labeled("capability::probe", {
+        let owner_id = owner_id.clone();
+        move |rt| {
+            rt.with_device_mut(&owner_id, |world, entity| {
+                let probe = CapabilityProbe {
+                    sar_version: world
+                        .get::<XiaomiDevice>(entity)
+                        .map(|dev| dev.sar_version)
+                        .unwrap_or_default(),
+                    network_stack_running: network_stack_running(world, entity),
+                    firmware_version: world
+                        .get::<InfoComponent>(entity)
+                        .map(|info| info.firmware_version().to_string()),
+                };
+                let capabilities = DeviceCapabilities::from_probe(&probe);
+                if let Some(mut info) = world.get_mut::<InfoComponent>(entity) {
+                    info.set_capabilities(capabilities.clone());
+                }
+                capabilities
+            })
+            .unwrap_or_default()
+        }
+    })
+    .await;
+    log::info!("[Capability] {owner_id}: {capabilities:?}");
+    capabilities
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
+fn network_stack_running(world: &bevy_ecs::world::World, entity: bevy_ecs::entity::Entity) -> bool {
+    world
+        .get::<super::network::NetworkSystem>(entity)
+        .is_some_and(|sys| sys.is_stack_running())
+}
+
+#[cfg(not(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack")))]
+fn network_stack_running(
+    _world: &bevy_ecs::world::World,
+    _entity: bevy_ecs::entity::Entity,
+) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probe(sar_version: u32, firmware: Option<&str>, network: bool) -> CapabilityProbe {
+        CapabilityProbe {
+            sar_version,
+            network_stack_running: network,
+            firmware_version: firmware.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn full_v2_device() {
+        let caps = DeviceCapabilities::from_probe(&probe(2, Some("2.1.5"), true));
+        assert_eq!(
+            caps,
+            DeviceCapabilities {
+                network_proxy: true,
+                firmware_version: Some("2.1.5".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn v1_device_has_no_l2_features() {
+        let caps = DeviceCapabilities::from_probe(&probe(1, Some("1.0.3"), true));
+        assert!(!caps.network_proxy);
+        assert_eq!(caps.firmware_version.as_deref(), Some("1.0.3"));
+    }
+
+    #[test]
+    fn inconclusive_probe_stays_conservative() {
+        // 还没问过设备信息，网络栈也没起来
+        let caps = DeviceCapabilities::from_probe(&probe(2, None, false));
+        assert_eq!(caps, DeviceCapabilities::default());
+
+        let caps = DeviceCapabilities::from_probe(&probe(2, Some(""), true));
+        assert!(caps.network_proxy);
+        assert!(caps.firmware_version.is_none());
+    }
+}
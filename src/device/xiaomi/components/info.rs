@@ -1,11 +1,22 @@
-use pb::xiaomi::protocol::{self, DeviceInfo, DeviceStatus, device_status::Battery};
+use pb::xiaomi::protocol::{
+    self, DeviceInfo, DeviceStatus,
+    device_status::{Battery, battery::ChargeStatus},
+};
+use std::collections::VecDeque;
+use std::future::Future;
 use tokio::sync::oneshot;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+use web_time::{SystemTime, UNIX_EPOCH};
+
 use crate::{
-    device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet},
+    device::xiaomi::system::{L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
     ecs::{Component, access::with_device_component_mut},
 };
 
+use super::capability::DeviceCapabilities;
 use super::shared::{HasOwnerId, RequestSlot, SystemRequestExt, await_response};
 use crate::anyhow_site;
 
@@ -57,6 +68,33 @@ impl InfoSystem {
         .await
     }
 
+    /// 一次把 info / status / storage 三个都要回来，三个请求同时发出去
+    pub async fn get_full_snapshot(&mut self) -> anyhow::Result<DeviceSnapshot> {
+        self.request_full_snapshot().await
+    }
+
+    /// 同 get_full_snapshot，但请求在调用时就发出去了，返回的 future 不再借用 self，
+    /// 方便在 ECS 闭包里发完请求、出来再等
+    pub fn request_full_snapshot(
+        &mut self,
+    ) -> impl Future<Output = anyhow::Result<DeviceSnapshot>> + Send + use<> {
+        let info_rx = self.request_device_info();
+        let status_rx = self.request_device_status();
+        let storage_rx = self.request_device_storage();
+        async move {
+            let (info, status, storage) = tokio::join!(
+                await_response(info_rx, "Device info response not received"),
+                await_response(status_rx, "Device status response not received"),
+                await_response(storage_rx, "Device storage info response not received"),
+            );
+            Ok(DeviceSnapshot {
+                info: info?,
+                status: status?,
+                storage: storage?,
+            })
+        }
+    }
+
     pub fn request_device_info(&mut self) -> oneshot::Receiver<anyhow::Result<DeviceInfo>> {
         let (rx, should_enqueue) = self.device_info_wait.prepare();
         if should_enqueue {
@@ -114,6 +152,7 @@ impl L2PbExt for InfoSystem {
             if let Some(sys_payload) = sys.payload {
                 match sys_payload {
                     pb::xiaomi::protocol::system::Payload::DeviceInfo(dev_info) => {
+                        mark_pb_consumed();
                         let dev_info_for_slot = dev_info.clone();
                         let model = dev_info.model.clone();
                         let serial_number = dev_info.serial_number.clone();
@@ -148,11 +187,13 @@ impl L2PbExt for InfoSystem {
                         }
                     }
                     pb::xiaomi::protocol::system::Payload::DeviceStatus(dev_status) => {
+                        mark_pb_consumed();
                         let dev_status_for_slot = dev_status.clone();
                         let battery = dev_status.battery;
                         let update_res = with_device_component_mut::<InfoComponent, _, _>(
                             self.owner_id.clone(),
                             move |comp| {
+                                comp.record_battery_sample(&battery);
                                 comp.battery = Some(battery);
                             },
                         );
@@ -176,6 +217,7 @@ impl L2PbExt for InfoSystem {
                         }
                     }
                     pb::xiaomi::protocol::system::Payload::StorageInfo(storage) => {
+                        mark_pb_consumed();
                         let storage_for_slot = storage.clone();
                         let total = storage.total;
                         let used = storage.used;
@@ -212,12 +254,27 @@ impl L2PbExt for InfoSystem {
     }
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DeviceSnapshot {
+    pub info: DeviceInfo,
+    pub status: DeviceStatus,
+    pub storage: protocol::StorageInfo,
+}
+
 #[derive(serde::Serialize)]
 pub struct StorageInfo {
     pub total: u64,
     pub free: u64,
 }
 
+#[derive(Debug, Clone, Copy, serde::Serialize)]
+pub struct BatterySample {
+    /// unix 毫秒时间戳
+    pub timestamp: u64,
+    pub level: u32,
+    pub charging: bool,
+}
+
 #[derive(Component, serde::Serialize)]
 pub struct InfoComponent {
     //codename: String,
@@ -227,10 +284,21 @@ pub struct InfoComponent {
     product_device: String,
     battery: Option<Battery>,
     storage: StorageInfo,
+    /// 认证后探测出来的能力，探测完之前为 None
+    capabilities: Option<DeviceCapabilities>,
+    #[serde(skip_serializing)]
+    battery_history: VecDeque<BatterySample>,
+    #[serde(skip_serializing)]
+    battery_history_len: usize,
 }
 
 impl InfoComponent {
     pub fn new() -> Self {
+        Self::with_battery_history(0)
+    }
+
+    /// capacity 为 0 时不记录电量历史
+    pub fn with_battery_history(capacity: usize) -> Self {
         Self {
             //codename: "".to_string(),
             model: "".to_string(),
@@ -239,7 +307,37 @@ impl InfoComponent {
             product_device: "".to_string(),
             battery: None,
             storage: StorageInfo { total: 0, free: 0 },
+            capabilities: None,
+            battery_history: VecDeque::with_capacity(capacity),
+            battery_history_len: capacity,
+        }
+    }
+
+    fn record_battery_sample(&mut self, battery: &Battery) {
+        if self.battery_history_len == 0 {
+            return;
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        if self.battery_history.len() >= self.battery_history_len {
+            self.battery_history.pop_front();
         }
+        self.battery_history.push_back(BatterySample {
+            timestamp,
+            level: battery.capacity as u32,
+            charging: battery_is_charging(battery),
+        });
+    }
+
+    /// 返回 since（unix 毫秒）之后的电量采样，按时间先后排列
+    pub fn battery_history(&self, since: u64) -> Vec<BatterySample> {
+        self.battery_history
+            .iter()
+            .filter(|sample| sample.timestamp >= since)
+            .copied()
+            .collect()
     }
 
     pub fn model(&self) -> &str {
@@ -265,4 +363,135 @@ impl InfoComponent {
     pub fn storage(&self) -> &StorageInfo {
         &self.storage
     }
+
+    pub fn capabilities(&self) -> Option<&DeviceCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    pub(crate) fn set_capabilities(&mut self, capabilities: DeviceCapabilities) {
+        self.capabilities = Some(capabilities);
+    }
+}
+
+// 充满了也还插着充电器，但不算在充
+fn battery_is_charging(battery: &Battery) -> bool {
+    battery.charge_status() == ChargeStatus::Charging
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::{spawn_mock_xiaomi, xiaomi::config::XiaomiDeviceConfig};
+
+    fn system_packet(
+        id: protocol::system::SystemId,
+        payload: protocol::system::Payload,
+    ) -> protocol::WearPacket {
+        protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::System as i32,
+            id: id as u32,
+            payload: Some(protocol::wear_packet::Payload::System(protocol::System {
+                payload: Some(payload),
+            })),
+        }
+    }
+
+    #[test]
+    fn battery_history_reads_typed_charge_status() {
+        let mut comp = InfoComponent::with_battery_history(4);
+        for (capacity, status) in [
+            (40, ChargeStatus::Charging),
+            (100, ChargeStatus::Full),
+            (99, ChargeStatus::NotCharging),
+        ] {
+            let mut battery = Battery {
+                capacity,
+                ..Default::default()
+            };
+            battery.set_charge_status(status);
+            comp.record_battery_sample(&battery);
+        }
+        comp.record_battery_sample(&Battery::default());
+
+        let charging: Vec<(u32, bool)> = comp
+            .battery_history(0)
+            .iter()
+            .map(|sample| (sample.level, sample.charging))
+            .collect();
+        assert_eq!(
+            charging,
+            vec![(40, true), (100, false), (99, false), (0, false)]
+        );
+    }
+
+    #[test]
+    fn concurrent_status_callers_share_one_response() {
+        let id = "test:info-status-shared";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+
+        // 保活和 UI 刷新几乎同时要设备状态
+        let mut sys = InfoSystem::new(id.to_string());
+        let keepalive = sys.request_device_status();
+        let ui_refresh = sys.request_device_status();
+
+        let mut status = DeviceStatus::default();
+        status.battery.capacity = 42;
+        sys.on_pb_packet(system_packet(
+            protocol::system::SystemId::GetDeviceStatus,
+            protocol::system::Payload::DeviceStatus(status),
+        ));
+
+        let (a, b) = rt.block_on(async {
+            tokio::join!(
+                await_response(keepalive, "keepalive status"),
+                await_response(ui_refresh, "ui status"),
+            )
+        });
+        assert_eq!(a.unwrap().battery.capacity, 42);
+        assert_eq!(b.unwrap().battery.capacity, 42);
+
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
+
+    #[test]
+    fn full_snapshot_waits_for_all_three() {
+        let id = "test:info-snapshot";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+
+        let mut sys = InfoSystem::new(id.to_string());
+        let snapshot = sys.request_full_snapshot();
+
+        // 回包顺序和请求顺序无关
+        sys.on_pb_packet(system_packet(
+            protocol::system::SystemId::GetStorageInfo,
+            protocol::system::Payload::StorageInfo(protocol::StorageInfo {
+                total: 1000,
+                used: 400,
+                ..Default::default()
+            }),
+        ));
+        sys.on_pb_packet(system_packet(
+            protocol::system::SystemId::GetDeviceStatus,
+            protocol::system::Payload::DeviceStatus(Default::default()),
+        ));
+        sys.on_pb_packet(system_packet(
+            protocol::system::SystemId::GetDeviceInfo,
+            protocol::system::Payload::DeviceInfo(protocol::DeviceInfo {
+                firmware_version: "1.2.3".to_string(),
+                ..Default::default()
+            }),
+        ));
+
+        let snapshot = rt.block_on(snapshot).unwrap();
+        assert_eq!(snapshot.info.firmware_version, "1.2.3");
+        assert_eq!(snapshot.storage.total, 1000);
+
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
 }
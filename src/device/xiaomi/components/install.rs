@@ -6,42 +6,428 @@ use std::sync::Arc;
 use crate::{anyhow_site, bail_site};
 use anyhow::{Context, Result};
 use pb::xiaomi::protocol::{self, WearPacket};
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc, oneshot};
 
-use crate::asyncrt::universal_block_on;
+use crate::asyncrt::{Duration, timeout, universal_block_on};
 use crate::device::xiaomi::components::{
-    info::InfoSystem,
-    mass::{SendMassCallbackData, send_file_for_owner},
-    resource::ResourceSystem,
+    info::{InfoComponent, InfoSystem},
+    mass::{
+        SendMassCallbackData, compress_payload, confirmed_compress_mode,
+        send_compressed_file_for_owner,
+    },
+    resource::{ResourceComponent, ResourceSystem},
 };
 use crate::device::xiaomi::config::ResConfig;
-use crate::device::xiaomi::packet::{self, mass::MassDataType};
-use crate::device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet};
-use crate::device::xiaomi::{XiaomiDevice, resutils};
+use crate::device::xiaomi::packet::{
+    self,
+    mass::{MassDataType, codec::COMPRESS_MODE_NONE},
+};
+use crate::device::xiaomi::sar::LinkState;
+use crate::device::xiaomi::system::{
+    L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet,
+};
+use crate::device::xiaomi::{self, XiaomiDevice, resutils};
 use crate::ecs::{Component, access::with_device_component_mut};
 use parking_lot::Mutex;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
 #[cfg(target_arch = "wasm32")]
-type InstallFuture = Pin<Box<dyn Future<Output = Result<()>>>>;
+type InstallFuture = Pin<Box<dyn Future<Output = Result<InstallOutcome>>>>;
 
 #[cfg(not(target_arch = "wasm32"))]
-type InstallFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+type InstallFuture = Pin<Box<dyn Future<Output = Result<InstallOutcome>> + Send>>;
+
+/// 安装进度的接收端：`while let Some(p) = progress.next().await { .. }`，不是 `futures::Stream`。
+/// 安装流程结束、进度回调被释放后自然结束；不消费也不会挡住安装
+pub struct InstallProgressReceiver {
+    rx: mpsc::UnboundedReceiver<SendMassCallbackData>,
+}
+
+impl InstallProgressReceiver {
+    pub async fn next(&mut self) -> Option<SendMassCallbackData> {
+        self.rx.recv().await
+    }
+
+    /// 不等待，当前没有就返回 None
+    pub fn try_next(&mut self) -> Option<SendMassCallbackData> {
+        self.rx.try_recv().ok()
+    }
+}
+
+/// 给只接受回调的接口用：回调里塞进 channel，另一头是接收端
+pub fn progress_channel() -> (
+    Arc<dyn Fn(SendMassCallbackData) + Send + Sync>,
+    InstallProgressReceiver,
+) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let cb: Arc<dyn Fn(SendMassCallbackData) + Send + Sync> = Arc::new(move |data| {
+        // 接收端被 drop 了就当没人看
+        let _ = tx.send(data);
+    });
+    (cb, InstallProgressReceiver { rx })
+}
+
+// 等安装结果期间多久看一眼链路
+const INSTALL_LINK_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// 以前所有表盘都发这个，部分老固件只认它
+pub const LEGACY_WATCHFACE_VERSION_CODE: u32 = 65536;
+
+// 已安装列表超过这个时间没同步就重新拉一次再判断
+const INSTALLED_LIST_MAX_AGE: Duration = Duration::from_secs(30);
+const INSTALLED_LIST_TIMEOUT: Duration = Duration::from_secs(5);
+// 装表盘前补拿设备信息最多等这么久
+const DEVICE_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 安装流程的结束方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallOutcome {
+    /// 设备明确回了安装结果（或该类型本来就没有结果消息）。
+    /// 表盘和快应用会带上这次实际发给设备的 version_code
+    Completed { version_code: Option<u32> },
+    /// 固件传完后等结果期间断链了，大概率是手表重启去刷了，按成功处理
+    PresumedRebooting,
+    /// 设备上已经有同一个东西了，没走 MASS 直接返回
+    AlreadyPresent,
+    /// 通知图标传完了。`confirmed` 表示手表在传完后又回了 AppIconResponse 确认；
+    /// 只有 `ResConfig::icon_ack_firmwares` 里的固件才等这个确认，其余传完就算送达
+    IconUploaded { confirmed: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct InstallOptions {
+    /// 传输前先看设备上是不是已经装过了，装过就直接返回 `AlreadyPresent`
+    pub skip_if_present: bool,
+    /// 不管装没装过都重新传，用于同 id 但内容变了的表盘
+    pub force: bool,
+    /// 表盘/快应用的 version_code。表盘不给就按内容算一个，快应用不给就读包里的 manifest
+    pub version_code: Option<u32>,
+    /// 合成总进度（`SendMassCallbackData::install_percent`）里各阶段的比重
+    pub progress_weights: ProgressWeights,
+    /// 调用方已经用 `resutils::validate_package` 校验过这个包了（比如 `install_file`），不再解一遍
+    #[serde(skip)]
+    pub prevalidated: bool,
+}
+
+impl Default for InstallOptions {
+    fn default() -> Self {
+        Self {
+            skip_if_present: true,
+            force: false,
+            version_code: None,
+            progress_weights: ProgressWeights::default(),
+            prevalidated: false,
+        }
+    }
+}
+
+/// 按比例算，不要求加起来正好 100；全填 0 就全压在传输上
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ProgressWeights {
+    /// 等设备回 prepare
+    pub prepare: u32,
+    /// MASS 传输
+    pub transfer: u32,
+    /// 传完到设备回安装结果（固件校验能有十几二十秒）
+    pub verify: u32,
+}
+
+impl Default for ProgressWeights {
+    fn default() -> Self {
+        Self {
+            prepare: 5,
+            transfer: 80,
+            verify: 15,
+        }
+    }
+}
+
+// 校验阶段多久推一次估算进度
+const VERIFY_PROGRESS_TICK: Duration = Duration::from_millis(500);
+// 真正拿到结果之前总进度最多到这
+const VERIFY_PROGRESS_CAP: f32 = 99.0;
+
+/// 把 prepare 等待、MASS 传输、设备校验拼成一个 0~100 的总进度。
+/// MASS 重传时分片进度可能往回走，这里只增不减
+#[derive(Debug, Clone)]
+struct CompositeProgress {
+    prepare: f32,
+    transfer: f32,
+    verify: f32,
+    last: f32,
+}
+
+impl CompositeProgress {
+    fn new(weights: ProgressWeights) -> Self {
+        let total = weights.prepare + weights.transfer + weights.verify;
+        if total == 0 {
+            return Self {
+                prepare: 0.0,
+                transfer: 100.0,
+                verify: 0.0,
+                last: 0.0,
+            };
+        }
+        let scale = 100.0 / total as f32;
+        Self {
+            prepare: weights.prepare as f32 * scale,
+            transfer: weights.transfer as f32 * scale,
+            verify: weights.verify as f32 * scale,
+            last: 0.0,
+        }
+    }
+
+    fn advance(&mut self, value: f32) -> f32 {
+        self.last = self.last.max(value.min(100.0));
+        self.last
+    }
+
+    fn prepared(&mut self) -> f32 {
+        self.advance(self.prepare)
+    }
+
+    fn transferring(&mut self, fraction: f32) -> f32 {
+        self.advance(self.prepare + self.transfer * fraction.clamp(0.0, 1.0))
+    }
+
+    /// 校验没有真实进度，按时间指数逼近：`expected` 时刻走完校验段的约 63%，封顶 99
+    fn verifying(&mut self, elapsed: Duration, expected: Duration) -> f32 {
+        let tau = expected.as_secs_f32().max(0.001);
+        let fraction = 1.0 - (-elapsed.as_secs_f32() / tau).exp();
+        let value = self.prepare + self.transfer + self.verify * fraction;
+        self.advance(value.min(VERIFY_PROGRESS_CAP))
+    }
+
+    fn finished(&mut self) -> f32 {
+        self.advance(100.0)
+    }
+}
+
+/// 等 `fut` 的同时每隔 `interval` 调一次 `tick`（参数是已经等了多久）
+async fn tick_while<F: Future>(
+    fut: F,
+    interval: Duration,
+    mut tick: impl FnMut(Duration),
+) -> F::Output {
+    let started = Instant::now();
+    tokio::pin!(fut);
+    loop {
+        tokio::select! {
+            out = &mut fut => return out,
+            _ = crate::asyncrt::sleep(interval) => tick(started.elapsed()),
+        }
+    }
+}
+
+impl InstallOptions {
+    fn should_skip_present(&self) -> bool {
+        self.skip_if_present && !self.force
+    }
+}
+
+/// 用来在已安装列表里找同一个东西
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PresenceKey {
+    Watchface(String),
+    QuickApp {
+        package_name: String,
+        version_code: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallError {
+    /// MASS 传完了，但设备在超时内没回安装结果
+    InstallResultTimeout {
+        data_type: MassDataType,
+        waited_secs: u64,
+    },
+}
+
+impl std::fmt::Display for InstallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InstallResultTimeout {
+                data_type,
+                waited_secs,
+            } => write!(
+                f,
+                "timed out after {waited_secs}s waiting for {data_type} install result"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InstallError {}
+
+enum InstallResultWait {
+    Event(InstallResultEvent),
+    /// 结果通道被关了或者链路断了
+    LinkLost,
+    TimedOut,
+}
 
 #[derive(Component)]
 pub struct InstallSystem {
     owner_id: String,
 }
 
+/// 固件和通知图标的 prepare 回包跟最终结果是同一种包，只能靠阶段区分是哪个
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WaiterStage {
+    AwaitingPrepare,
+    /// prepare 已经交出去，MASS 还没传完；这时候来的同类包只能是重复的 prepare
+    Transferring,
+    AwaitingResult,
+}
+
 struct InstallWaiters {
     data_type: MassDataType,
+    stage: WaiterStage,
     prepare_tx: Option<oneshot::Sender<i32>>,
     result_tx: Option<oneshot::Sender<InstallResultEvent>>,
+    /// 最后一片的 ACK 已经到了，之后再来的同类包才可能是结果
+    last_part_acked: bool,
+    /// 最后一片 ACK 之后、阶段推进之前到的结果，等阶段推进到 AwaitingResult 再交出去
+    early_result: Option<InstallResultEvent>,
+}
+
+impl InstallWaiters {
+    fn new(
+        data_type: MassDataType,
+        prepare_tx: oneshot::Sender<i32>,
+        result_tx: Option<oneshot::Sender<InstallResultEvent>>,
+    ) -> Self {
+        Self {
+            data_type,
+            stage: WaiterStage::AwaitingPrepare,
+            prepare_tx: Some(prepare_tx),
+            result_tx,
+            last_part_acked: false,
+            early_result: None,
+        }
+    }
+
+    /// 最后一片确认之前设备还在收数据，这期间来的同类包只能是重发的 prepare
+    fn note_transfer_progress(&mut self, part_num: u16, total_parts: u16) {
+        if self.stage == WaiterStage::Transferring && part_num >= total_parts {
+            self.last_part_acked = true;
+        }
+    }
+
+    fn advance(&mut self, stage: WaiterStage) {
+        self.stage = stage;
+        if stage == WaiterStage::AwaitingResult {
+            if let Some(event) = self.early_result.take() {
+                self.deliver_result(event);
+            }
+        }
+    }
+
+    fn deliver_prepare(&mut self, status: i32) {
+        match self.prepare_tx.take() {
+            Some(tx) => {
+                let _ = tx.send(status);
+                self.stage = WaiterStage::Transferring;
+            }
+            None => log::debug!(
+                "[Install] duplicate {} prepare response ignored",
+                self.data_type
+            ),
+        }
+    }
+
+    fn deliver_result(&mut self, event: InstallResultEvent) {
+        match self.result_tx.take() {
+            Some(tx) => {
+                let _ = tx.send(event);
+            }
+            None => log::debug!(
+                "[Install] duplicate {} install result ignored",
+                self.data_type
+            ),
+        }
+    }
+
+    /// 固件/通知图标用：同一种回包按当前阶段决定算 prepare 还是结果
+    fn deliver_staged(&mut self, prepare_status: i32, event: impl FnOnce() -> InstallResultEvent) {
+        match self.stage {
+            WaiterStage::AwaitingPrepare => self.deliver_prepare(prepare_status),
+            // 最后一片 ACK 之后结果可能比阶段推进先到，先存着；之前的都是重发的 prepare
+            WaiterStage::Transferring if self.last_part_acked => self.early_result = Some(event()),
+            WaiterStage::Transferring => log::debug!(
+                "[Install] {} prepare response repeated during transfer, ignored",
+                self.data_type
+            ),
+            WaiterStage::AwaitingResult => self.deliver_result(event()),
+        }
+    }
+
+    /// 返回这个包是不是当前安装的回包
+    fn route(&mut self, packet: protocol::WearPacket) -> bool {
+        match (self.data_type, packet.payload) {
+            (MassDataType::Watchface, Some(protocol::wear_packet::Payload::WatchFace(wf))) => {
+                match wf.payload {
+                    Some(protocol::watch_face::Payload::PrepareStatus(status)) => {
+                        self.deliver_prepare(status)
+                    }
+                    Some(protocol::watch_face::Payload::InstallResult(result)) => {
+                        self.deliver_result(InstallResultEvent::Watchface(result))
+                    }
+                    _ => return false,
+                }
+            }
+            (
+                MassDataType::ThirdPartyApp,
+                Some(protocol::wear_packet::Payload::ThirdpartyApp(ta)),
+            ) => match ta.payload {
+                Some(protocol::thirdparty_app::Payload::InstallResponse(resp)) => {
+                    self.deliver_prepare(resp.prepare_status)
+                }
+                Some(protocol::thirdparty_app::Payload::InstallResult(result)) => {
+                    self.deliver_result(InstallResultEvent::ThirdpartyApp(result))
+                }
+                _ => return false,
+            },
+            (MassDataType::Firmware, Some(protocol::wear_packet::Payload::System(sys))) => {
+                let Some(protocol::system::Payload::PrepareOtaResponse(resp)) = sys.payload else {
+                    return false;
+                };
+                self.deliver_staged(resp.prepare_status, || InstallResultEvent::Firmware(resp));
+            }
+            (
+                MassDataType::NotificationIcon,
+                Some(protocol::wear_packet::Payload::Notification(nc)),
+            ) => {
+                let Some(protocol::notification::Payload::AppIconResponse(resp)) = nc.payload
+                else {
+                    return false;
+                };
+                let prepare_status = resp.prepare_status;
+                self.deliver_staged(prepare_status, || InstallResultEvent::NotificationIcon {
+                    prepare_status,
+                });
+            }
+            _ => return false,
+        }
+        true
+    }
 }
 
 enum InstallResultEvent {
     ThirdpartyApp(protocol::app_installer::Result),
     Watchface(protocol::InstallResult),
     Firmware(protocol::prepare_ota::Response),
+    /// 传完后手表再回的 AppIconResponse，只关心里面的状态
+    NotificationIcon {
+        prepare_status: i32,
+    },
 }
 
 impl Default for InstallSystem {
@@ -78,6 +464,122 @@ impl InstallSystem {
         package_name: Option<&str>,
         progress_cb: Arc<dyn Fn(SendMassCallbackData) + Send + Sync>,
         watchface_id: Option<&str>,
+    ) -> Result<InstallFuture> {
+        self.send_install_request_with_options(
+            r#type,
+            file_data,
+            package_name,
+            progress_cb,
+            watchface_id,
+            InstallOptions::default(),
+        )
+    }
+
+    /// 同 send_install_request_with_options，进度不走回调而是从返回的接收端里取
+    pub fn install_with_progress(
+        &mut self,
+        r#type: MassDataType,
+        file_data: Vec<u8>,
+        package_name: Option<&str>,
+        watchface_id: Option<&str>,
+        options: InstallOptions,
+    ) -> Result<(InstallProgressReceiver, InstallFuture)> {
+        let (progress_cb, progress) = progress_channel();
+        let fut = self.send_install_request_with_options(
+            r#type,
+            file_data,
+            package_name,
+            progress_cb,
+            watchface_id,
+            options,
+        )?;
+        Ok((progress, fut))
+    }
+
+    pub fn send_install_request_with_options(
+        &mut self,
+        r#type: MassDataType,
+        file_data: Vec<u8>,
+        package_name: Option<&str>,
+        progress_cb: Arc<dyn Fn(SendMassCallbackData) + Send + Sync>,
+        watchface_id: Option<&str>,
+        options: InstallOptions,
+    ) -> Result<InstallFuture> {
+        // 校验要把整个包解一遍，放到 future 里做，不占着 ECS 线程
+        let owner = self.owner_id.clone();
+        let package_name = package_name.map(str::to_string);
+        let watchface_id = watchface_id.map(str::to_string);
+        let fut = async move {
+            let res_config = xiaomi::with_device_ref(&owner, |dev| dev.config.res.clone())
+                .await
+                .ok_or_else(|| anyhow_site!("device {owner} not found"))?;
+            // 拿错文件、文件下坏了在这里就报，不用等 prepare 握手和传输
+            if !options.prevalidated {
+                resutils::validate_package(&file_data, r#type, &res_config)
+                    .with_context(|| format!("invalid {} package", r#type))?;
+            }
+
+            // 老固件只认老的 version_code，装表盘前版本号还没拿到就先问一次
+            if r#type == MassDataType::Watchface
+                && !res_config.legacy_watchface_version_firmwares.is_empty()
+            {
+                ensure_firmware_version(&owner).await;
+            }
+
+            // 已安装列表可能要现拉，得等设备回包，所以判断完再真正开始
+            let key = if options.should_skip_present() {
+                presence_key(
+                    r#type,
+                    &file_data,
+                    package_name.as_deref(),
+                    watchface_id.as_deref(),
+                    options.version_code,
+                    &res_config,
+                )?
+            } else {
+                None
+            };
+            if let Some(key) = key {
+                match is_already_installed(owner.clone(), &key).await {
+                    Ok(true) => {
+                        log::info!("[Install] {key:?} is already on {owner}, skipping transfer");
+                        return Ok(InstallOutcome::AlreadyPresent);
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        log::warn!(
+                            "[Install] failed to check installed items, installing anyway: {err:?}"
+                        );
+                    }
+                }
+            }
+
+            let started = xiaomi::with_component_mut::<InstallSystem, _, _>(&owner, move |sys| {
+                sys.start_install(
+                    r#type,
+                    file_data,
+                    package_name.as_deref(),
+                    progress_cb,
+                    watchface_id.as_deref(),
+                    options,
+                )
+            })
+            .await
+            .ok_or_else(|| anyhow_site!("install system not found"))?;
+            started?.await
+        };
+
+        Ok(Box::pin(fut))
+    }
+
+    fn start_install(
+        &mut self,
+        r#type: MassDataType,
+        file_data: Vec<u8>,
+        package_name: Option<&str>,
+        progress_cb: Arc<dyn Fn(SendMassCallbackData) + Send + Sync>,
+        watchface_id: Option<&str>,
+        options: InstallOptions,
     ) -> Result<InstallFuture> {
         let owner = self.owner_id.clone();
 
@@ -94,37 +596,45 @@ impl InstallSystem {
                 .map_err(|err| anyhow_site!("failed to set watchface id: {}", err))?;
         }
 
+        // 安装请求里的长度/md5 得和 MASS 实际发的一致，所以在建请求之前就压好；
+        // 被拒了没法原样重来，只用手表已经认过的模式。表盘 id、版本号这些还是从原始包里读
+        let compress_mode = confirmed_compress_mode(owner.clone());
+        let packed = compress_payload(compress_mode, r#type, &file_data);
+        let (sent_len, sent_mode) = packed
+            .as_ref()
+            .map_or((file_data.len(), COMPRESS_MODE_NONE), |(data, mode)| {
+                (data.len(), *mode)
+            });
+
         let (prepare_tx, prepare_rx) = oneshot::channel::<i32>();
         let (result_tx_opt, result_rx_opt) = match r#type {
-            MassDataType::NotificationIcon => (None, None),
             MassDataType::Music => {
                 return Err(anyhow_site!(
                     "music payloads are not supported by InstallSystem; use MediaSystem instead"
                 ));
             }
+            // 不回确认的固件等了也是白等
+            MassDataType::NotificationIcon if !icon_ack_expected(&owner) => (None, None),
             _ => {
                 let (tx, rx) = oneshot::channel();
                 (Some(tx), Some(rx))
             }
         };
 
-        with_device_component_mut::<InstallComponent, _, _>(
+        let waiters = with_device_component_mut::<InstallComponent, _, _>(
             owner.clone(),
-            move |comp| -> Result<()> {
+            move |comp| -> Result<SharedWaiters> {
                 let mut waiters = comp.waiters.lock();
                 if waiters.is_some() {
                     bail_site!("install request is already in progress");
                 }
-                *waiters = Some(InstallWaiters {
-                    data_type: r#type,
-                    prepare_tx: Some(prepare_tx),
-                    result_tx: result_tx_opt,
-                });
-                Ok(())
+                *waiters = Some(InstallWaiters::new(r#type, prepare_tx, result_tx_opt));
+                Ok(comp.waiters.clone())
             },
         )
         .map_err(|err| anyhow_site!("failed to access install component: {:?}", err))??;
 
+        let mut sent_version_code = None;
         let req_result: Result<WearPacket> = (|| {
             Ok(match r#type {
                 MassDataType::Watchface => {
@@ -135,10 +645,28 @@ impl InstallSystem {
                     .map_err(|err| anyhow_site!("failed to access resource config: {:?}", err))?;
                     let id = resutils::get_watchface_id(&file_data, &res_config)
                         .context("invalid watchface id")?;
-                    build_watchface_install_request(&id, file_data.len())
+                    let firmware = with_device_component_mut::<InfoComponent, String, _>(
+                        owner.clone(),
+                        |info| info.firmware_version().to_string(),
+                    )
+                    .unwrap_or_default();
+                    if firmware.is_empty()
+                        && !res_config.legacy_watchface_version_firmwares.is_empty()
+                    {
+                        log::warn!(
+                            "[Install] firmware version still unknown, sending the regular watchface version_code"
+                        );
+                    }
+                    let version_code = watchface_version_code(
+                        &file_data,
+                        options.version_code,
+                        res_config.needs_legacy_watchface_version(&firmware),
+                    );
+                    sent_version_code = Some(version_code);
+                    build_watchface_install_request(&id, sent_len, version_code, sent_mode)
                 }
-                MassDataType::Firmare => build_firmware_install_request(
-                    "99.99.99".to_string(),
+                MassDataType::Firmware => build_firmware_install_request(
+                    crate::constants::XIAOMI_FIRMWARE_PLACEHOLDER_VERSION.to_string(),
                     &crate::tools::calc_md5(&file_data),
                     "AstroBox Firmware Update".to_string(),
                 ),
@@ -163,11 +691,15 @@ impl InstallSystem {
                 MassDataType::ThirdPartyApp => {
                     let pkg =
                         package_name.context("package_name is required for third-party app")?;
-                    build_thirdparty_app_install_request(pkg, 114514, file_data.len())
+                    let version_code =
+                        resolve_quickapp_version_code(&file_data, pkg, options.version_code)?;
+                    sent_version_code = Some(version_code);
+                    build_thirdparty_app_install_request(pkg, version_code, file_data.len())
                 }
             })
         })();
 
+        let (file_data, compress_mode) = packed.unwrap_or((file_data, COMPRESS_MODE_NONE));
         let req = match req_result {
             Ok(req) => req,
             Err(err) => {
@@ -177,63 +709,145 @@ impl InstallSystem {
             }
         };
 
-        if let Err(err) =
-            with_device_component_mut::<XiaomiDevice, (), _>(owner.clone(), move |dev| {
-                packet::cipher::enqueue_pb_packet(
-                    dev,
-                    req,
-                    "InstallSystem::send_install_request_with_progress",
-                );
+        let result_timeout =
+            with_device_component_mut::<XiaomiDevice, Duration, _>(owner.clone(), move |dev| {
+                dev.config.res.install_result_timeout(r#type)
             })
-        {
-            let owner_for_cleanup = owner.clone();
-            universal_block_on(|| clear_install_waiters(owner_for_cleanup.clone()));
-            return Err(anyhow_site!("failed to enqueue install request: {:?}", err));
-        }
+            .unwrap_or_else(|_| ResConfig::default().install_result_timeout(r#type));
 
         let owner_for_future = owner.clone();
         let progress_cb_future = progress_cb.clone();
+        let composite = Arc::new(Mutex::new(CompositeProgress::new(options.progress_weights)));
+        // prepare / 校验阶段没有 MASS 回调，拿最后一次的分片数据补上总进度再报
+        let last_report: Arc<Mutex<SendMassCallbackData>> =
+            Arc::new(Mutex::new(SendMassCallbackData {
+                progress: 0.0,
+                total_parts: 0,
+                current_part_num: 0,
+                actual_data_payload_len: 0,
+                device_busy: false,
+                install_percent: None,
+            }));
+        let report_percent = {
+            let progress_cb = progress_cb.clone();
+            let last_report = last_report.clone();
+            move |percent: f32| {
+                let mut report = last_report.lock().clone();
+                report.install_percent = Some(percent);
+                progress_cb(report);
+            }
+        };
 
         let fut = async move {
             let result = async {
+                // 请求在 future 里发，cipher 还没建好时直接 await，不在调用方线程上 block_on
+                packet::cipher::enqueue_pb_packet_async(
+                    &owner_for_future,
+                    req,
+                    "InstallSystem::send_install_request_with_progress",
+                )
+                .await
+                .context("failed to enqueue install request")?;
+
                 let prepare_status = prepare_rx
                     .await
                     .map_err(|_| anyhow_site!("prepare response channel closed unexpectedly"))?;
+                report_percent(composite.lock().prepared());
 
                 let prepare_enum = protocol::PrepareStatus::try_from(prepare_status)
                     .map_err(|_| anyhow_site!("unknown prepare status: {prepare_status}"))?;
 
+                if options.should_skip_present() && prepare_reports_present(prepare_enum) {
+                    log::info!("[Install] device already has this {r#type}, skipping transfer");
+                    return Ok(InstallOutcome::AlreadyPresent);
+                }
                 if prepare_enum != protocol::PrepareStatus::Ready {
                     bail_site!("install prepare failed with status: {:?}", prepare_enum);
                 }
 
-                send_file_for_owner(owner_for_future.clone(), file_data, r#type, move |d| {
-                    (progress_cb_future)(d)
-                })
+                let transfer_composite = composite.clone();
+                let transfer_last = last_report.clone();
+                let transfer_waiters = waiters.clone();
+                send_compressed_file_for_owner(
+                    owner_for_future.clone(),
+                    file_data,
+                    compress_mode,
+                    r#type,
+                    move |mut d| {
+                        if !d.device_busy {
+                            if let Some(waiters) = transfer_waiters.lock().as_mut() {
+                                waiters.note_transfer_progress(d.current_part_num, d.total_parts);
+                            }
+                        }
+                        d.install_percent =
+                            Some(transfer_composite.lock().transferring(d.progress));
+                        *transfer_last.lock() = d.clone();
+                        (progress_cb_future)(d)
+                    },
+                )
                 .await
                 .context("failed to send MASS payload")?;
+                if let Some(waiters) = waiters.lock().as_mut() {
+                    waiters.advance(WaiterStage::AwaitingResult);
+                }
 
                 if let Some(result_rx) = result_rx_opt {
-                    let event = match result_rx.await {
-                        Ok(event) => event,
-                        Err(_) if matches!(r#type, MassDataType::Firmare) => {
+                    let verify_expected = result_timeout / 4;
+                    let waited = tick_while(
+                        wait_install_result(&owner_for_future, r#type, result_rx, result_timeout),
+                        VERIFY_PROGRESS_TICK,
+                        |elapsed| {
+                            report_percent(composite.lock().verifying(elapsed, verify_expected))
+                        },
+                    )
+                    .await;
+                    let event = match waited {
+                        InstallResultWait::Event(event) => event,
+                        InstallResultWait::LinkLost if matches!(r#type, MassDataType::Firmware) => {
                             log::info!(
-                                "[Install] firmware payload sent; install result message missing because the device may be rebooting"
+                                "[Install] firmware payload sent; link lost while waiting for install result, the device is probably rebooting"
                             );
-                            return Ok(());
+                            return Ok(InstallOutcome::PresumedRebooting);
                         }
-                        Err(_) => {
+                        InstallResultWait::LinkLost => {
                             return Err(anyhow_site!("install result message missing"));
                         }
+                        InstallResultWait::TimedOut
+                            if matches!(r#type, MassDataType::NotificationIcon) =>
+                        {
+                            log::debug!(
+                                "[Install] icon sent, device did not confirm within {}s",
+                                result_timeout.as_secs()
+                            );
+                            return Ok(InstallOutcome::IconUploaded { confirmed: false });
+                        }
+                        InstallResultWait::TimedOut => {
+                            return Err(InstallError::InstallResultTimeout {
+                                data_type: r#type,
+                                waited_secs: result_timeout.as_secs(),
+                            }
+                            .into());
+                        }
                     };
                     handle_install_result(r#type, event)?;
                     refresh_post_install_state(owner_for_future.clone(), r#type).await;
+                    if matches!(r#type, MassDataType::NotificationIcon) {
+                        return Ok(InstallOutcome::IconUploaded { confirmed: true });
+                    }
+                }
+                if matches!(r#type, MassDataType::NotificationIcon) {
+                    return Ok(InstallOutcome::IconUploaded { confirmed: false });
                 }
 
-                Ok(())
+                Ok(InstallOutcome::Completed {
+                    version_code: sent_version_code,
+                })
             }
             .await;
 
+            if result.is_ok() {
+                report_percent(composite.lock().finished());
+            }
             clear_install_waiters(owner_for_future).await;
             result
         };
@@ -245,128 +859,226 @@ impl InstallSystem {
 impl L2PbExt for InstallSystem {
     fn on_pb_packet(&mut self, payload: protocol::WearPacket) {
         let owner = self.owner_id.clone();
-        let _ = with_device_component_mut::<InstallComponent, _, _>(owner, move |comp| {
-            let mut waiters_guard = comp.waiters.lock();
-            if let Some(waiters) = waiters_guard.as_mut() {
-                match payload.payload {
-                    Some(protocol::wear_packet::Payload::WatchFace(wf)) => {
-                        if let MassDataType::Watchface = waiters.data_type {
-                            match wf.payload {
-                                Some(protocol::watch_face::Payload::PrepareStatus(status)) => {
-                                    if let Some(tx) = waiters.prepare_tx.take() {
-                                        let _ = tx.send(status);
-                                    }
-                                }
-                                Some(protocol::watch_face::Payload::InstallResult(result)) => {
-                                    if let Some(tx) = waiters.result_tx.take() {
-                                        let _ = tx.send(InstallResultEvent::Watchface(result));
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                    Some(protocol::wear_packet::Payload::ThirdpartyApp(ta)) => {
-                        if let MassDataType::ThirdPartyApp = waiters.data_type {
-                            match ta.payload {
-                                Some(protocol::thirdparty_app::Payload::InstallResponse(resp)) => {
-                                    if let Some(tx) = waiters.prepare_tx.take() {
-                                        let _ = tx.send(resp.prepare_status);
-                                    }
-                                }
-                                Some(protocol::thirdparty_app::Payload::InstallResult(result)) => {
-                                    if let Some(tx) = waiters.result_tx.take() {
-                                        let _ = tx.send(InstallResultEvent::ThirdpartyApp(result));
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                    }
-                    Some(protocol::wear_packet::Payload::System(sys)) => {
-                        if let MassDataType::Firmare = waiters.data_type {
-                            if let Some(protocol::system::Payload::PrepareOtaResponse(resp)) =
-                                sys.payload
-                            {
-                                if let Some(tx) = waiters.prepare_tx.take() {
-                                    let _ = tx.send(resp.prepare_status);
-                                } else if let Some(tx) = waiters.result_tx.take() {
-                                    let _ = tx.send(InstallResultEvent::Firmware(resp));
-                                }
-                            }
-                        }
-                    }
-                    Some(protocol::wear_packet::Payload::Notification(nc)) => {
-                        if let MassDataType::NotificationIcon = waiters.data_type {
-                            if let Some(protocol::notification::Payload::AppIconResponse(resp)) =
-                                nc.payload
-                            {
-                                if let Some(tx) = waiters.prepare_tx.take() {
-                                    let _ = tx.send(resp.prepare_status);
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            }
+        let routed = with_device_component_mut::<InstallComponent, _, _>(owner, move |comp| {
+            comp.waiters
+                .lock()
+                .as_mut()
+                .is_some_and(|waiters| waiters.route(payload))
         });
+        if routed.unwrap_or(false) {
+            mark_pb_consumed();
+        }
     }
 }
 
-async fn clear_install_waiters(owner: String) {
-    let _ = crate::ecs::with_rt_mut({
-        let owner = owner.clone();
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(comp) = world.get_mut::<InstallComponent>(entity) {
-                    *comp.waiters.lock() = None;
-                }
-            });
+/// 设备在 prepare 阶段就说已经有了（通知图标走的就是这个）
+pub(crate) fn prepare_reports_present(status: protocol::PrepareStatus) -> bool {
+    status == protocol::PrepareStatus::Duplicated
+}
+
+/// 传完图标后手表回的状态：Ready 是收下了，Duplicated 说明本来就有，也算成功
+fn icon_upload_accepted(status: protocol::PrepareStatus) -> bool {
+    matches!(
+        status,
+        protocol::PrepareStatus::Ready | protocol::PrepareStatus::Duplicated
+    )
+}
+
+fn find_installed(
+    key: &PresenceKey,
+    watchfaces: &[protocol::WatchFaceItem],
+    quick_apps: &[protocol::AppItem],
+) -> bool {
+    match key {
+        PresenceKey::Watchface(id) => watchfaces.iter().any(|face| &face.id == id),
+        PresenceKey::QuickApp {
+            package_name,
+            version_code,
+        } => quick_apps
+            .iter()
+            .any(|app| &app.package_name == package_name && app.version_code == *version_code),
+    }
+}
+
+fn presence_key(
+    r#type: MassDataType,
+    file_data: &[u8],
+    package_name: Option<&str>,
+    watchface_id: Option<&str>,
+    requested_version_code: Option<u32>,
+    res_config: &ResConfig,
+) -> Result<Option<PresenceKey>> {
+    Ok(match r#type {
+        MassDataType::Watchface => {
+            let id = match watchface_id {
+                Some(id) => id.to_string(),
+                None => resutils::get_watchface_id(file_data, res_config)
+                    .context("invalid watchface id")?,
+            };
+            Some(PresenceKey::Watchface(id))
+        }
+        MassDataType::ThirdPartyApp => {
+            let pkg = package_name.context("package_name is required for third-party app")?;
+            Some(PresenceKey::QuickApp {
+                package_name: pkg.to_string(),
+                version_code: resolve_quickapp_version_code(
+                    file_data,
+                    pkg,
+                    requested_version_code,
+                )?,
+            })
+        }
+        // 图标要等 AppIconResponse 才知道，在 start_install 里处理
+        _ => None,
+    })
+}
+
+async fn is_already_installed(owner: String, key: &PresenceKey) -> Result<bool> {
+    Ok(match key {
+        PresenceKey::Watchface(_) => {
+            let watchfaces = installed_list(
+                owner,
+                |comp| {
+                    comp.fresh_watchfaces(INSTALLED_LIST_MAX_AGE)
+                        .map(<[_]>::to_vec)
+                },
+                |sys| sys.request_watchface_list(),
+            )
+            .await?;
+            find_installed(key, &watchfaces, &[])
+        }
+        PresenceKey::QuickApp { .. } => {
+            let quick_apps = installed_list(
+                owner,
+                |comp| {
+                    comp.fresh_quick_apps(INSTALLED_LIST_MAX_AGE)
+                        .map(<[_]>::to_vec)
+                },
+                |sys| sys.request_quick_app_list(),
+            )
+            .await?;
+            find_installed(key, &[], &quick_apps)
+        }
+    })
+}
+
+/// 缓存够新就直接用，否则找设备重新要一份
+async fn installed_list<T, C, R>(owner: String, cached: C, refresh: R) -> Result<Vec<T>>
+where
+    T: Send + 'static,
+    C: FnOnce(&ResourceComponent) -> Option<Vec<T>> + Send + 'static,
+    R: FnOnce(&mut ResourceSystem) -> oneshot::Receiver<Result<Vec<T>>> + Send + 'static,
+{
+    let cached = xiaomi::with_component_mut::<ResourceComponent, _, _>(&owner, |comp| cached(comp))
+        .await
+        .ok_or_else(|| anyhow_site!("resource component not found"))?;
+    if let Some(list) = cached {
+        return Ok(list);
+    }
+    let rx = xiaomi::with_component_mut::<ResourceSystem, _, _>(&owner, refresh)
+        .await
+        .ok_or_else(|| anyhow_site!("resource system not found"))?;
+    timeout(INSTALLED_LIST_TIMEOUT, rx)
+        .await
+        .map_err(|_| anyhow_site!("timed out refreshing installed list"))?
+        .map_err(|_| anyhow_site!("installed list response not received"))?
+}
+
+async fn wait_install_result(
+    owner: &str,
+    data_type: MassDataType,
+    mut result_rx: oneshot::Receiver<InstallResultEvent>,
+    limit: Duration,
+) -> InstallResultWait {
+    let deadline = Instant::now() + limit;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return InstallResultWait::TimedOut;
+        }
+        match timeout(remaining.min(INSTALL_LINK_POLL_INTERVAL), &mut result_rx).await {
+            Ok(Ok(event)) => return InstallResultWait::Event(event),
+            Ok(Err(_)) => return InstallResultWait::LinkLost,
+            Err(_) => {}
+        }
+        // 只有固件需要把断链当成结果，其他类型老老实实等超时
+        if matches!(data_type, MassDataType::Firmware) && !is_link_alive(owner).await {
+            return InstallResultWait::LinkLost;
         }
+    }
+}
+
+/// 只有链路判死或者设备被移除才算断开，重连宽限期里的 Paused 还得接着等
+async fn is_link_alive(owner: &str) -> bool {
+    xiaomi::with_device_ref(owner, |dev| dev.sar.lock().link_state())
+        .await
+        .is_some_and(|state| state != LinkState::Failed)
+}
+
+async fn clear_install_waiters(owner: String) {
+    xiaomi::with_component_mut::<InstallComponent, _, _>(&owner, |comp| {
+        *comp.waiters.lock() = None;
     })
     .await;
 }
 
 async fn refresh_quick_app_list(owner: String) {
-    let _ = crate::ecs::with_rt_mut({
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(mut system) = world.get_mut::<ResourceSystem>(entity) {
-                    let _ = system.request_quick_app_list();
-                }
-            });
-        }
+    xiaomi::with_component_mut::<ResourceSystem, _, _>(&owner, |system| {
+        let _ = system.request_quick_app_list();
     })
     .await;
 }
 
 async fn refresh_watchface_list(owner: String) {
-    let _ = crate::ecs::with_rt_mut({
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(mut system) = world.get_mut::<ResourceSystem>(entity) {
-                    let _ = system.request_watchface_list();
-                }
-            });
-        }
+    xiaomi::with_component_mut::<ResourceSystem, _, _>(&owner, |system| {
+        let _ = system.request_watchface_list();
     })
     .await;
 }
 
 async fn refresh_storage_info(owner: String) {
-    let _ = crate::ecs::with_rt_mut({
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(mut system) = world.get_mut::<InfoSystem>(entity) {
-                    let _ = system.request_device_storage();
-                }
-            });
-        }
+    xiaomi::with_component_mut::<InfoSystem, _, _>(&owner, |system| {
+        let _ = system.request_device_storage();
     })
     .await;
 }
 
+/// InfoComponent 里还没有固件版本（连上后设备信息还没回来）就现要一次，拿不到只打日志
+async fn ensure_firmware_version(owner: &str) {
+    let known = xiaomi::with_component_mut::<InfoComponent, _, _>(owner, |info| {
+        !info.firmware_version().is_empty()
+    })
+    .await
+    .unwrap_or(false);
+    if known {
+        return;
+    }
+    let Some(rx) =
+        xiaomi::with_component_mut::<InfoSystem, _, _>(owner, |sys| sys.request_device_info())
+            .await
+    else {
+        return;
+    };
+    match timeout(DEVICE_INFO_TIMEOUT, rx).await {
+        Ok(Ok(Ok(_))) => {}
+        Ok(Ok(Err(err))) => log::warn!("[Install] failed to fetch device info: {err:?}"),
+        Ok(Err(_)) | Err(_) => log::warn!("[Install] device info not received in time"),
+    }
+}
+
+/// 这台设备的固件传完图标后会不会再回一次确认，版本还不知道就按不回算
+fn icon_ack_expected(owner: &str) -> bool {
+    let firmware =
+        with_device_component_mut::<InfoComponent, String, _>(owner.to_string(), |info| {
+            info.firmware_version().to_string()
+        })
+        .unwrap_or_default();
+    with_device_component_mut::<XiaomiDevice, bool, _>(owner.to_string(), move |dev| {
+        dev.config.res.sends_icon_ack(&firmware)
+    })
+    .unwrap_or(false)
+}
+
 async fn refresh_post_install_state(owner: String, data_type: MassDataType) {
     match data_type {
         MassDataType::Watchface => {
@@ -377,7 +1089,7 @@ async fn refresh_post_install_state(owner: String, data_type: MassDataType) {
             refresh_quick_app_list(owner.clone()).await;
             refresh_storage_info(owner).await;
         }
-        MassDataType::Firmare
+        MassDataType::Firmware
         | MassDataType::NotificationIcon
         | MassDataType::Music
         | MassDataType::WatchfaceImage
@@ -409,7 +1121,7 @@ fn handle_install_result(r#type: MassDataType, event: InstallResultEvent) -> Res
                 }
             }
         }
-        (MassDataType::Firmare, InstallResultEvent::Firmware(resp)) => {
+        (MassDataType::Firmware, InstallResultEvent::Firmware(resp)) => {
             let status = protocol::PrepareStatus::try_from(resp.prepare_status).map_err(|_| {
                 anyhow_site!("unknown firmware prepare status: {}", resp.prepare_status)
             })?;
@@ -418,6 +1130,17 @@ fn handle_install_result(r#type: MassDataType, event: InstallResultEvent) -> Res
             }
             Ok(())
         }
+        (
+            MassDataType::NotificationIcon,
+            InstallResultEvent::NotificationIcon { prepare_status },
+        ) => {
+            let status = protocol::PrepareStatus::try_from(prepare_status)
+                .map_err(|_| anyhow_site!("unknown icon upload status: {}", prepare_status))?;
+            if !icon_upload_accepted(status) {
+                bail_site!("notification icon upload rejected: {:?}", status);
+            }
+            Ok(())
+        }
         (MassDataType::Music, _) => {
             bail_site!("music payloads are not supported by InstallSystem")
         }
@@ -429,6 +1152,7 @@ fn handle_install_result(r#type: MassDataType, event: InstallResultEvent) -> Res
                     InstallResultEvent::ThirdpartyApp(_) => "thirdparty",
                     InstallResultEvent::Watchface(_) => "watchface",
                     InstallResultEvent::Firmware(_) => "firmware",
+                    InstallResultEvent::NotificationIcon { .. } => "notification_icon",
                 }
             )
         }
@@ -438,23 +1162,46 @@ fn handle_install_result(r#type: MassDataType, event: InstallResultEvent) -> Res
 #[derive(Component, serde::Serialize)]
 pub struct InstallComponent {
     #[serde(skip_serializing)]
-    waiters: Mutex<Option<InstallWaiters>>,
+    waiters: SharedWaiters,
 }
 
+/// 安装任务自己也拿一份，进度回调里同步推进阶段，不用再排 runtime 任务
+type SharedWaiters = Arc<Mutex<Option<InstallWaiters>>>;
+
 impl InstallComponent {
     pub fn new() -> Self {
         Self {
-            waiters: Mutex::new(None),
+            waiters: Arc::new(Mutex::new(None)),
         }
     }
+
+    /// 有安装在等手表回包
+    pub fn is_installing(&self) -> bool {
+        self.waiters.lock().is_some()
+    }
 }
 
-pub fn build_watchface_install_request(id: &str, package_size: usize) -> protocol::WearPacket {
+/// 表盘的 version_code：老固件只能用常量；否则调用方给了就用，没给就取内容 crc 的低 31 位。
+/// 有的固件按 (id, version_code) 缓存预览图，同 id 改了内容也得换个 code，不然一直显示旧图
+pub fn watchface_version_code(file_data: &[u8], requested: Option<u32>, legacy: bool) -> u32 {
+    if legacy {
+        return LEGACY_WATCHFACE_VERSION_CODE;
+    }
+    requested.unwrap_or_else(|| (packet::mass::wire::checksum(file_data) & 0x7FFF_FFFF).max(1))
+}
+
+/// `package_size` 是 MASS 实际要发的长度，压缩过就是压缩后的，同时带上压缩模式
+pub fn build_watchface_install_request(
+    id: &str,
+    package_size: usize,
+    version_code: u32,
+    compress_mode: u8,
+) -> protocol::WearPacket {
     let prepare_info = protocol::PrepareInfo {
         id: id.to_string(),
         size: package_size as u32,
-        version_code: Some(65536),
-        support_compress_mode: None,
+        version_code: Some(version_code),
+        support_compress_mode: (compress_mode != COMPRESS_MODE_NONE).then(|| compress_mode.into()),
         verification: None,
     };
 
@@ -469,6 +1216,41 @@ pub fn build_watchface_install_request(id: &str, package_size: usize) -> protoco
     }
 }
 
+/// 从快应用包 manifest 里取 versionCode，并核对包名；解析不了才退回时间戳版本号
+/// 调用方给了 version_code 就用给的（manifest 能解析时照样核对包名），
+/// 没给就用 manifest 里的，manifest 都解析不了才用兜底值
+fn resolve_quickapp_version_code(
+    file_data: &[u8],
+    package_name: &str,
+    requested: Option<u32>,
+) -> Result<u32> {
+    match resutils::parse_quickapp_manifest(file_data) {
+        Ok(manifest) => {
+            if manifest.package != package_name {
+                bail_site!(
+                    "package name mismatch: caller supplied {}, manifest declares {}",
+                    package_name,
+                    manifest.package
+                );
+            }
+            Ok(requested.unwrap_or(manifest.version_code))
+        }
+        Err(err) => {
+            if let Some(code) = requested {
+                return Ok(code);
+            }
+            let code = resutils::fallback_quickapp_version_code();
+            log::warn!(
+                "[InstallSystem] failed to parse quick app manifest for {}: {:?}, falling back to version code {}",
+                package_name,
+                err,
+                code
+            );
+            Ok(code)
+        }
+    }
+}
+
 pub fn build_thirdparty_app_install_request(
     package_name: &str,
     version_code: u32,
@@ -535,3 +1317,447 @@ pub fn build_notification_icon_request(package_name: &str) -> protocol::WearPack
         payload: Some(protocol::wear_packet::Payload::Notification(pkt_payload)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(part: u16) -> SendMassCallbackData {
+        SendMassCallbackData {
+            progress: f32::from(part) / 3.0,
+            total_parts: 3,
+            current_part_num: part,
+            actual_data_payload_len: 0,
+            device_busy: false,
+            install_percent: None,
+        }
+    }
+
+    #[test]
+    fn composite_progress_never_goes_back() {
+        let mut composite = CompositeProgress::new(ProgressWeights::default());
+        let expected = Duration::from_secs(5);
+        let mut seen = vec![composite.prepared()];
+        // MASS 中途重传一次，分片进度往回跳
+        for fraction in [0.1, 0.5, 0.4, 0.9, 1.0] {
+            seen.push(composite.transferring(fraction));
+        }
+        // 慢吞吞的固件校验，等了远超预期的时间
+        for secs in [0, 1, 5, 20, 60, 600] {
+            seen.push(composite.verifying(Duration::from_secs(secs), expected));
+        }
+        let before_result = *seen.last().unwrap();
+        seen.push(composite.finished());
+
+        assert!(seen.windows(2).all(|w| w[0] <= w[1]), "{seen:?}");
+        assert_eq!(seen[0], 5.0);
+        assert_eq!(seen[5], 85.0);
+        assert!(before_result <= VERIFY_PROGRESS_CAP && before_result > 98.0);
+        assert_eq!(seen.last(), Some(&100.0));
+    }
+
+    #[test]
+    fn zero_weights_fall_back_to_transfer() {
+        let mut composite = CompositeProgress::new(ProgressWeights {
+            prepare: 0,
+            transfer: 0,
+            verify: 0,
+        });
+        assert_eq!(composite.prepared(), 0.0);
+        assert_eq!(composite.transferring(0.5), 50.0);
+    }
+
+    #[test]
+    fn verify_ticks_while_waiting() {
+        let mut ticks = Vec::new();
+        let out = tokio::runtime::Runtime::new().unwrap().block_on(tick_while(
+            async {
+                crate::asyncrt::sleep(Duration::from_millis(120)).await;
+                7
+            },
+            Duration::from_millis(20),
+            |elapsed| ticks.push(elapsed),
+        ));
+        assert_eq!(out, 7);
+        assert!(ticks.len() >= 3, "{ticks:?}");
+        assert!(ticks.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn progress_receiver_ends_with_callback() {
+        let (cb, mut progress_rx) = progress_channel();
+        for part in 1..=3 {
+            cb(progress(part));
+        }
+        drop(cb);
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let mut parts = Vec::new();
+            while let Some(p) = progress_rx.next().await {
+                parts.push(p.current_part_num);
+            }
+            assert_eq!(parts, vec![1, 2, 3]);
+        });
+    }
+
+    fn face(id: &str) -> protocol::WatchFaceItem {
+        protocol::WatchFaceItem {
+            id: id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn app(package_name: &str, version_code: u32) -> protocol::AppItem {
+        protocol::AppItem {
+            package_name: package_name.to_string(),
+            version_code,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn watchface_matched_by_id() {
+        let faces = [face("167210065"), face("367210021")];
+        assert!(find_installed(
+            &PresenceKey::Watchface("367210021".into()),
+            &faces,
+            &[]
+        ));
+        assert!(!find_installed(
+            &PresenceKey::Watchface("100".into()),
+            &faces,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn quick_app_needs_same_version() {
+        let apps = [app("com.example.timer", 3)];
+        let key = |version_code| PresenceKey::QuickApp {
+            package_name: "com.example.timer".into(),
+            version_code,
+        };
+        assert!(find_installed(&key(3), &[], &apps));
+        // 升级包照常装
+        assert!(!find_installed(&key(4), &[], &apps));
+    }
+
+    #[test]
+    fn notification_icon_duplicated_means_present() {
+        assert!(prepare_reports_present(protocol::PrepareStatus::Duplicated));
+        assert!(!prepare_reports_present(protocol::PrepareStatus::Ready));
+    }
+
+    #[test]
+    fn icon_confirmation_is_checked() {
+        let event = |status: protocol::PrepareStatus| InstallResultEvent::NotificationIcon {
+            prepare_status: status as i32,
+        };
+        assert!(
+            handle_install_result(
+                MassDataType::NotificationIcon,
+                event(protocol::PrepareStatus::Ready)
+            )
+            .is_ok()
+        );
+        assert!(
+            handle_install_result(
+                MassDataType::NotificationIcon,
+                event(protocol::PrepareStatus::Duplicated)
+            )
+            .is_ok()
+        );
+        assert!(
+            handle_install_result(
+                MassDataType::Watchface,
+                event(protocol::PrepareStatus::Ready)
+            )
+            .is_err()
+        );
+    }
+
+    fn ota_response(status: protocol::PrepareStatus) -> protocol::WearPacket {
+        protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::System as i32,
+            id: protocol::system::SystemId::PrepareOta as u32,
+            payload: Some(protocol::wear_packet::Payload::System(protocol::System {
+                payload: Some(protocol::system::Payload::PrepareOtaResponse(
+                    protocol::prepare_ota::Response {
+                        prepare_status: status as i32,
+                        ..Default::default()
+                    },
+                )),
+            })),
+        }
+    }
+
+    #[test]
+    fn route_claims_only_packets_for_the_current_install() {
+        let (prepare_tx, _prepare_rx) = oneshot::channel();
+        let mut waiters = InstallWaiters::new(MassDataType::Watchface, prepare_tx, None);
+
+        // 装表盘时手表推过来的 OTA 回包不归这里管
+        assert!(!waiters.route(ota_response(protocol::PrepareStatus::Ready)));
+        assert!(waiters.route(protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::WatchFace as i32,
+            id: protocol::watch_face::WatchFaceId::PrepareInstallWatchFace as u32,
+            payload: Some(protocol::wear_packet::Payload::WatchFace(
+                protocol::WatchFace {
+                    payload: Some(protocol::watch_face::Payload::PrepareStatus(
+                        protocol::PrepareStatus::Ready as i32,
+                    )),
+                },
+            )),
+        }));
+    }
+
+    #[test]
+    fn duplicated_firmware_prepare_is_not_taken_as_result() {
+        let (prepare_tx, mut prepare_rx) = oneshot::channel();
+        let (result_tx, mut result_rx) = oneshot::channel();
+        let mut waiters = InstallWaiters::new(MassDataType::Firmware, prepare_tx, Some(result_tx));
+
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+        // 固件把 prepare 回包重发了一遍，MASS 还没开始
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+
+        assert_eq!(
+            prepare_rx.try_recv().unwrap(),
+            protocol::PrepareStatus::Ready as i32
+        );
+        assert!(result_rx.try_recv().is_err());
+        assert_eq!(waiters.stage, WaiterStage::Transferring);
+
+        waiters.note_transfer_progress(1, 4);
+        waiters.advance(WaiterStage::AwaitingResult);
+        assert!(result_rx.try_recv().is_err());
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+        assert!(matches!(
+            result_rx.try_recv(),
+            Ok(InstallResultEvent::Firmware(_))
+        ));
+        // 结果也重复了就只打日志
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+    }
+
+    fn icon_response(status: protocol::PrepareStatus) -> protocol::WearPacket {
+        protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::Notification as i32,
+            id: protocol::notification::NotificationId::PrepareAppIcon as u32,
+            payload: Some(protocol::wear_packet::Payload::Notification(
+                protocol::Notification {
+                    payload: Some(protocol::notification::Payload::AppIconResponse(
+                        protocol::prepare_app_icon::Response {
+                            prepare_status: status as i32,
+                            ..Default::default()
+                        },
+                    )),
+                },
+            )),
+        }
+    }
+
+    #[test]
+    fn duplicated_icon_prepare_is_ignored_until_transfer_ends() {
+        let (prepare_tx, mut prepare_rx) = oneshot::channel();
+        let (result_tx, mut result_rx) = oneshot::channel();
+        let mut waiters =
+            InstallWaiters::new(MassDataType::NotificationIcon, prepare_tx, Some(result_tx));
+
+        waiters.route(icon_response(protocol::PrepareStatus::Ready));
+        waiters.route(icon_response(protocol::PrepareStatus::Ready));
+        assert!(prepare_rx.try_recv().is_ok());
+        assert!(result_rx.try_recv().is_err());
+
+        waiters.note_transfer_progress(1, 2);
+        waiters.advance(WaiterStage::AwaitingResult);
+        assert!(result_rx.try_recv().is_err());
+        waiters.route(icon_response(protocol::PrepareStatus::Duplicated));
+        assert!(matches!(
+            result_rx.try_recv(),
+            Ok(InstallResultEvent::NotificationIcon { prepare_status })
+                if prepare_status == protocol::PrepareStatus::Duplicated as i32
+        ));
+    }
+
+    #[test]
+    fn single_part_icon_ignores_prepare_repeated_before_its_ack() {
+        let (prepare_tx, _prepare_rx) = oneshot::channel();
+        let (result_tx, mut result_rx) = oneshot::channel();
+        let mut waiters =
+            InstallWaiters::new(MassDataType::NotificationIcon, prepare_tx, Some(result_tx));
+
+        waiters.route(icon_response(protocol::PrepareStatus::Ready));
+        // 只有一片，ACK 还没回来 prepare 就重发了
+        waiters.route(icon_response(protocol::PrepareStatus::Ready));
+        waiters.note_transfer_progress(1, 1);
+        waiters.advance(WaiterStage::AwaitingResult);
+        assert!(result_rx.try_recv().is_err());
+
+        waiters.route(icon_response(protocol::PrepareStatus::Duplicated));
+        assert!(matches!(
+            result_rx.try_recv(),
+            Ok(InstallResultEvent::NotificationIcon { prepare_status })
+                if prepare_status == protocol::PrepareStatus::Duplicated as i32
+        ));
+    }
+
+    #[test]
+    fn firmware_result_before_stage_advance_is_kept() {
+        let (prepare_tx, _prepare_rx) = oneshot::channel();
+        let (result_tx, mut result_rx) = oneshot::channel();
+        let mut waiters = InstallWaiters::new(MassDataType::Firmware, prepare_tx, Some(result_tx));
+
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+        waiters.note_transfer_progress(2, 3);
+        waiters.note_transfer_progress(3, 3);
+        // 最后一片的 ACK 刚到，安装任务还没来得及推进阶段，结果就先来了
+        waiters.route(ota_response(protocol::PrepareStatus::Ready));
+        assert!(result_rx.try_recv().is_err());
+
+        waiters.advance(WaiterStage::AwaitingResult);
+        assert!(matches!(
+            result_rx.try_recv(),
+            Ok(InstallResultEvent::Firmware(_))
+        ));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn firmware_result_survives_a_paused_link() {
+        use crate::device::spawn_mock_xiaomi_with_sender;
+        use crate::device::xiaomi::{
+            SendError,
+            config::XiaomiDeviceConfig,
+            packet::v2::layer1::{L1DataType, L1Packet},
+        };
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:install-paused-link";
+        let link_state =
+            || crate::device::xiaomi::with_device_ref(addr, |dev| dev.sar.lock().link_state());
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let waited = rt.block_on(async {
+            // 一开始发什么都报 Disconnected，SAR 进入宽限期
+            let link_down = Arc::new(AtomicBool::new(true));
+            spawn_mock_xiaomi_with_sender(addr, XiaomiDeviceConfig::default(), {
+                let link_down = link_down.clone();
+                move |_frames: Vec<Vec<u8>>| {
+                    let down = link_down.load(Ordering::SeqCst);
+                    async move {
+                        if down {
+                            Err(SendError::Disconnected)
+                        } else {
+                            Ok(())
+                        }
+                    }
+                }
+            })
+            .await;
+            for _ in 0..100 {
+                if matches!(link_state().await, Some(LinkState::Paused { .. })) {
+                    break;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(matches!(link_state().await, Some(LinkState::Paused { .. })));
+
+            let (result_tx, result_rx) = oneshot::channel();
+            let waiter = tokio::spawn(async move {
+                wait_install_result(
+                    addr,
+                    MassDataType::Firmware,
+                    result_rx,
+                    Duration::from_secs(5),
+                )
+                .await
+            });
+            // 至少跨过一次链路检查，暂停期间不能当成断开
+            crate::asyncrt::sleep(INSTALL_LINK_POLL_INTERVAL * 2).await;
+            assert!(!waiter.is_finished());
+
+            // 手表回来了，随后报校验结果
+            link_down.store(false, Ordering::SeqCst);
+            crate::ecs::with_rt_mut(move |rt| {
+                if let Some(dev) = rt.component_ref::<XiaomiDevice>(addr) {
+                    let pkt = L1Packet::new(L1DataType::Ack, false, 200, Vec::new());
+                    dev.sar.lock().on_l1_packet(&pkt);
+                }
+            })
+            .await;
+            assert_eq!(link_state().await, Some(LinkState::Active));
+            let _ = result_tx.send(InstallResultEvent::Firmware(Default::default()));
+            let waited = waiter.await.unwrap();
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            waited
+        });
+        crate::device::xiaomi::cleanup_cached_state(addr);
+        assert!(matches!(
+            waited,
+            InstallResultWait::Event(InstallResultEvent::Firmware(_))
+        ));
+    }
+
+    #[test]
+    fn explicit_quickapp_version_code_wins() {
+        let not_a_zip = b"definitely not an rpk";
+        assert_eq!(
+            resolve_quickapp_version_code(not_a_zip, "com.example.app", Some(42)).unwrap(),
+            42
+        );
+        assert!(resolve_quickapp_version_code(not_a_zip, "com.example.app", None).unwrap() > 0);
+    }
+
+    #[test]
+    fn force_overrides_skip() {
+        assert!(InstallOptions::default().should_skip_present());
+        let forced = InstallOptions {
+            force: true,
+            ..Default::default()
+        };
+        assert!(!forced.should_skip_present());
+    }
+
+    fn prepare_version_code(pkt: &protocol::WearPacket) -> Option<u32> {
+        match &pkt.payload {
+            Some(protocol::wear_packet::Payload::WatchFace(face)) => match &face.payload {
+                Some(protocol::watch_face::Payload::PrepareInfo(info)) => info.version_code,
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn watchface_version_code_from_content() {
+        // "123456789" 的 crc32 是 0xCBF43926，去掉最高位
+        let code = watchface_version_code(b"123456789", None, false);
+        assert_eq!(code, 0x4BF4_3926);
+        assert_ne!(watchface_version_code(b"123456780", None, false), code);
+
+        let pkt = build_watchface_install_request("367210021", 9, code, COMPRESS_MODE_NONE);
+        assert_eq!(prepare_version_code(&pkt), Some(0x4BF4_3926));
+
+        // 调用方指定的优先
+        assert_eq!(watchface_version_code(b"123456789", Some(7), false), 7);
+    }
+
+    #[test]
+    fn legacy_firmware_keeps_constant_version_code() {
+        let config = ResConfig {
+            legacy_watchface_version_firmwares: vec!["1.2.".into()],
+            ..Default::default()
+        };
+        assert!(config.needs_legacy_watchface_version("1.2.48"));
+        assert!(!config.needs_legacy_watchface_version("2.1.5"));
+        assert!(!config.needs_legacy_watchface_version(""));
+        assert!(!config.sends_icon_ack("1.2.48"));
+
+        let code = watchface_version_code(b"123456789", Some(7), true);
+        assert_eq!(code, 65536);
+        let pkt = build_watchface_install_request("367210021", 9, code, COMPRESS_MODE_NONE);
+        assert_eq!(prepare_version_code(&pkt), Some(65536));
+    }
+}
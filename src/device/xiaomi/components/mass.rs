@@ -1,7 +1,6 @@
-use crate::asyncrt::{Duration, timeout};
+use crate::asyncrt::Duration;
 use crate::bail_site;
 use anyhow::{Context, Result};
-use byteorder::{LittleEndian, WriteBytesExt};
 use pb::xiaomi::protocol;
 use prost::Message;
 use serde::Serialize;
@@ -12,28 +11,113 @@ use std::sync::Arc;
 use tokio::sync::oneshot;
 
 #[cfg(not(target_arch = "wasm32"))]
-use std::time::Instant;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 #[cfg(target_arch = "wasm32")]
-use web_time::Instant;
+use web_time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crate::device::xiaomi::XiaomiDevice;
-use crate::device::xiaomi::config::MassConfig;
+use crate::device::xiaomi::config::{ChannelCrypto, MassConfig};
 use crate::device::xiaomi::packet::{
     self,
-    mass::{MassDataType, MassPacket, ReverseMassPacket},
-    v2::layer2::{L2Channel, L2OpCode, L2Packet},
+    mass::{MassDataType, MassPacket, ReverseMassPacket, codec, wire},
+    v2::layer2::{L2Channel, L2OpCode},
+};
+use crate::device::xiaomi::sar::{AckWait, LinkState};
+use crate::device::xiaomi::system::{
+    XiaomiSystemExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet,
 };
-use crate::device::xiaomi::system::{XiaomiSystemExt, register_xiaomi_system_ext_on_l2packet};
 use crate::device::xiaomi::transport_profiler::TransportProfilerHandle;
-use crate::ecs::{Component, access::with_device_component_mut};
+use crate::ecs::{
+    Component,
+    access::{with_device_component_mut, with_device_component_ref},
+};
+use crate::tools::{AudioContainer, sniff_audio_container};
 use parking_lot::Mutex;
 
+use super::shared::{HasOwnerId, SystemRequestExt};
+
+mod cursor;
+pub mod incoming;
+pub mod incoming_policy;
+mod resilient;
+use cursor::DeviceCursor;
+pub use incoming::{IncomingFile, IncomingReceiver, incoming_files};
+pub use incoming_policy::{
+    IncomingTransferDecision, IncomingTransferPolicy, IncomingTransferRequest,
+    clear_incoming_transfer_policy, set_incoming_transfer_policy,
+};
+pub use resilient::{ResilientSendOptions, send_file_resilient};
+
+/// 传输中途连接没了，重连后还能靠设备保留的进度续传
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MassError {
+    /// SAR 宽限期耗尽，链路判死
+    LinkLost { owner_id: String },
+    /// 设备实体已经被移除（断开后清理掉了）
+    DeviceGone { owner_id: String },
+}
+
+impl std::fmt::Display for MassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LinkLost { owner_id } => {
+                write!(f, "link to {owner_id} lost during MASS transfer")
+            }
+            Self::DeviceGone { owner_id } => {
+                write!(f, "device {owner_id} disconnected during MASS transfer")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MassError {}
+
+/// 这个错误是不是因为断连，断连的话换个连接可以续传
+pub fn is_disconnect_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<MassError>().is_some()
+}
+
+/// 一次发送怎么算完，跟着这次传输走。标准 Mass 传输每片都有 ACK；
+/// 不是每帧都 ACK 的那些按 ACK 等只会一直超时，换成后两种
+#[derive(Debug, Default)]
+pub enum MassCompletion {
+    /// 每片等设备 ACK，节流也按 ACK 来
+    #[default]
+    PerSeqAck,
+    /// 不看单片 ACK：节流按 SAR 发送池积压，发送池清空就算完
+    WindowDrain,
+    /// 节流同上，分片交完后等设备的完成包，见 `completion_packet`
+    DeviceCompletionPacket(CompletionPacket),
+}
+
+/// 等设备完成包的那一头。完成包是什么由发起方定，收到后往配对的发送端里报结果
+#[derive(Debug)]
+pub struct CompletionPacket {
+    rx: oneshot::Receiver<Result<()>>,
+    timeout: Duration,
+}
+
+/// 建一个 `DeviceCompletionPacket`，发送端交给收完成包的地方；
+/// 分片交完后最多等 `timeout`，发送端被丢掉也算失败
+pub fn completion_packet(timeout: Duration) -> (oneshot::Sender<Result<()>>, MassCompletion) {
+    let (tx, rx) = oneshot::channel();
+    (
+        tx,
+        MassCompletion::DeviceCompletionPacket(CompletionPacket { rx, timeout }),
+    )
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct SendMassCallbackData {
     pub progress: f32,
     pub total_parts: u16,
     pub current_part_num: u16,
     pub actual_data_payload_len: usize,
+    /// 设备还在回包但迟迟不给 ACK（多半在写 flash），UI 可以显示“手表写入中…”
+    pub device_busy: bool,
+    /// 走安装流程时由 InstallSystem 填：prepare/传输/校验合起来的 0~100 总进度，只增不减
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_percent: Option<f32>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -49,17 +133,47 @@ pub struct ReceiveMassCallbackData {
 pub struct ReverseMassReceiveResult {
     pub channel: L2Channel,
     pub file_name: String,
+    /// 流式接收时数据已经通过 on_file_chunk 交出去了，这里为空
+    pub data: Vec<u8>,
+    pub streamed: bool,
+    /// 语音备忘录的音频容器（看文件头猜的），其它通道为 None
+    pub audio: Option<AudioContainer>,
+}
+
+impl ReverseMassReceiveResult {
+    /// 手表上录的语音备忘录走 MassVoice 通道
+    pub fn is_voice_memo(&self) -> bool {
+        self.channel == L2Channel::MassVoice
+    }
+}
+
+/// 流式接收时按顺序交给上层的文件分片
+#[derive(Debug, Clone)]
+pub struct ReverseMassFileChunk {
+    pub channel: L2Channel,
+    pub file_name: String,
+    pub part_num: u32,
+    pub total_parts: u32,
     pub data: Vec<u8>,
 }
 
+pub type ReverseMassChunkCallback = Arc<dyn Fn(ReverseMassFileChunk) + Send + Sync>;
+
 struct ReverseMassWaiter {
     packet: ReverseMassPacket,
     progress_cb: Arc<dyn Fn(ReceiveMassCallbackData) + Send + Sync>,
+    on_file_chunk: Option<ReverseMassChunkCallback>,
     /// 针对 reverse mass 的完成信号发送器
     /// 当多个通道共享同一个逻辑接收过程 (看不懂的猪请看begin_reverse_mass_receive_multi)，所有相关的等待方会共用一个被 mutex 包裹的发送器
     /// 这样可以确保只有第一个完成的通道会发送结果，其余通道的发送操作都no-op
     tx: Arc<parking_lot::Mutex<Option<oneshot::Sender<Result<ReverseMassReceiveResult>>>>>,
     siblings: Vec<u8>,
+    /// 没人显式 begin，是因为有 incoming 订阅才自动接的，收完交给订阅者
+    passive: bool,
+    /// 手表 prepare 里说数据压过，收完按这个模式解压。流式接收的分片原样交出去
+    compress_mode: u8,
+    /// 流式接收时文件头只在第一个分片里，交出去之前先猜好容器
+    audio: Option<AudioContainer>,
 }
 
 /// 记录已经等待确认的 MASS 分片，用于推进进度与续传。
@@ -131,6 +245,15 @@ impl MassSystem {
         .await
     }
 
+    /// 这台设备正在发的文件，没在发返回 None
+    pub fn current_transfer(&self) -> Option<ActiveTransferInfo> {
+        with_device_component_ref::<MassComponent, _, _>(self.owner_id.clone(), |comp| {
+            comp.active_transfer.clone()
+        })
+        .ok()
+        .flatten()
+    }
+
     pub fn begin_reverse_mass_receive(
         &mut self,
         channel: L2Channel,
@@ -146,13 +269,42 @@ impl MassSystem {
         &mut self,
         channels: &[L2Channel],
         progress_cb: Arc<dyn Fn(ReceiveMassCallbackData) + Send + Sync>,
+    ) -> Result<oneshot::Receiver<Result<ReverseMassReceiveResult>>> {
+        self.begin_reverse_mass_receive_with_chunks(channels, progress_cb, None)
+    }
+
+    /// 接收手表上的语音备忘录 (MassVoice)
+    /// 几分钟的录音就是几百个分片，小内存宿主建议传 on_file_chunk 边收边写
+    pub fn begin_voice_memo_receive(
+        &mut self,
+        progress_cb: Arc<dyn Fn(ReceiveMassCallbackData) + Send + Sync>,
+        on_file_chunk: Option<ReverseMassChunkCallback>,
+    ) -> Result<oneshot::Receiver<Result<ReverseMassReceiveResult>>> {
+        self.begin_reverse_mass_receive_with_chunks(
+            &[L2Channel::MassVoice],
+            progress_cb,
+            on_file_chunk,
+        )
+    }
+
+    /// 传入 on_file_chunk 时走流式接收，分片按顺序回调后立刻释放，最终结果里 data 为空
+    pub fn begin_reverse_mass_receive_with_chunks(
+        &mut self,
+        channels: &[L2Channel],
+        progress_cb: Arc<dyn Fn(ReceiveMassCallbackData) + Send + Sync>,
+        on_file_chunk: Option<ReverseMassChunkCallback>,
     ) -> Result<oneshot::Receiver<Result<ReverseMassReceiveResult>>> {
         if channels.is_empty() {
             bail_site!("reverse MASS receive requires at least one channel");
         }
 
         for channel in channels {
-            if self.reverse_mass_waits.contains_key(&(*channel as u8)) {
+            // 被动接收是给 incoming 订阅兜底的，宿主显式要收就让给宿主
+            if self
+                .reverse_mass_waits
+                .get(&(*channel as u8))
+                .is_some_and(|waiter| !waiter.passive)
+            {
                 bail_site!(
                     "reverse MASS receive already in progress on channel {:?}",
                     channel
@@ -170,16 +322,46 @@ impl MassSystem {
             self.reverse_mass_waits.insert(
                 key,
                 ReverseMassWaiter {
-                    packet: ReverseMassPacket::new(),
+                    packet: if on_file_chunk.is_some() {
+                        ReverseMassPacket::new_streaming()
+                    } else {
+                        ReverseMassPacket::new()
+                    },
                     progress_cb: progress_cb.clone(),
+                    on_file_chunk: on_file_chunk.clone(),
                     tx: shared_tx.clone(),
                     siblings: other_siblings,
+                    passive: false,
+                    compress_mode: codec::COMPRESS_MODE_NONE,
+                    audio: None,
                 },
             );
         }
         Ok(rx)
     }
 
+    /// 有 incoming 订阅时，手表主动推的文件不用等宿主 begin，自己接下来交给订阅者
+    fn begin_passive_receive(&mut self, channel: L2Channel) {
+        log::debug!(
+            "[MassSystem] {} passive reverse MASS receive on {:?}",
+            self.owner_id,
+            channel
+        );
+        self.reverse_mass_waits.insert(
+            channel as u8,
+            ReverseMassWaiter {
+                packet: ReverseMassPacket::new(),
+                progress_cb: Arc::new(|_: ReceiveMassCallbackData| {}),
+                on_file_chunk: None,
+                tx: Arc::new(parking_lot::Mutex::new(None)),
+                siblings: Vec::new(),
+                passive: true,
+                compress_mode: codec::COMPRESS_MODE_NONE,
+                audio: None,
+            },
+        );
+    }
+
     pub fn clear_reverse_mass_wait(&mut self, channel: L2Channel) {
         let key = channel as u8;
         let siblings = self
@@ -202,10 +384,80 @@ impl MassSystem {
         });
     }
 
+    /// 手表发来 PrepareRequest 要推文件：问策略，回 PrepareResponse，接受的话在 Mass 通道上准备接收
+    fn handle_incoming_prepare(&mut self, req: protocol::PrepareRequest) {
+        let (max_incoming_bytes, slice_length) =
+            with_device_component_mut::<XiaomiDevice, _, _>(self.owner_id.clone(), |dev| {
+                (
+                    dev.config.mass.max_incoming_bytes,
+                    dev.config.mass.incoming_slice_length,
+                )
+            })
+            .unwrap_or_else(|_| {
+                let defaults = MassConfig::default();
+                (defaults.max_incoming_bytes, defaults.incoming_slice_length)
+            });
+        let compress_mode = req
+            .support_compress_mode
+            .and_then(|mode| u8::try_from(mode).ok())
+            .unwrap_or(codec::COMPRESS_MODE_NONE);
+        let request = IncomingTransferRequest {
+            addr: self.owner_id.clone(),
+            data_type: req.data_type,
+            data_id: req.data_id,
+            data_length: req.data_length.into(),
+        };
+        let data_id = crate::tools::to_hex_string(&request.data_id);
+        let decision = incoming_policy::decide(
+            request,
+            incoming_policy::current_policy().as_ref(),
+            max_incoming_bytes,
+        );
+        log::info!(
+            "[MassSystem] {} incoming transfer {} (type {}, {} bytes): {:?}",
+            self.owner_id,
+            data_id,
+            req.data_type,
+            req.data_length,
+            decision
+        );
+
+        if decision == IncomingTransferDecision::Accept {
+            let key = L2Channel::Mass as u8;
+            // 宿主自己 begin 过就交给它，否则走被动接收，收完交给 incoming 订阅者
+            if !self.reverse_mass_waits.contains_key(&key) {
+                if !incoming::wants(&self.owner_id, L2Channel::Mass) {
+                    log::warn!(
+                        "[MassSystem] {} accepted incoming transfer {} but nobody subscribed to incoming files, it will be dropped",
+                        self.owner_id,
+                        data_id
+                    );
+                }
+                self.begin_passive_receive(L2Channel::Mass);
+            }
+            if let Some(waiter) = self.reverse_mass_waits.get_mut(&key) {
+                waiter.compress_mode = compress_mode;
+            }
+        }
+        // 手表自己用这个模式压过，我们发给它的时候也能用
+        if compress_mode != codec::COMPRESS_MODE_NONE {
+            let _ = with_device_component_mut::<MassComponent, _, _>(
+                self.owner_id.clone(),
+                move |comp| comp.note_compress_mode(compress_mode, true),
+            );
+        }
+        self.enqueue_pb_request(
+            incoming_policy::build_prepare_response(decision, slice_length),
+            "MassSystem::incoming_prepare",
+        );
+    }
+
     fn handle_reverse_mass_payload(&mut self, channel: L2Channel, payload: &[u8]) {
         let channel_key = channel as u8;
         let mut progress_cb = None;
         let mut progress = None;
+        let mut chunk_cb = None;
+        let mut chunks: Vec<ReverseMassFileChunk> = Vec::new();
         let mut completion = None;
         let mut should_remove = false;
 
@@ -219,6 +471,26 @@ impl MassSystem {
                     let total_parts = waiter.packet.total_block();
                     let current_part_num = waiter.packet.current_block();
                     let file_name = waiter.packet.file_name();
+                    if let Some(cb) = waiter.on_file_chunk.as_ref() {
+                        chunk_cb = Some(cb.clone());
+                        chunks = waiter
+                            .packet
+                            .take_ready_chunks()
+                            .into_iter()
+                            .map(|(part_num, data)| ReverseMassFileChunk {
+                                channel,
+                                file_name: file_name.clone(),
+                                part_num,
+                                total_parts,
+                                data,
+                            })
+                            .collect();
+                        if channel == L2Channel::MassVoice && waiter.audio.is_none() {
+                            waiter.audio = chunks
+                                .first()
+                                .map(|chunk| sniff_audio_container(&chunk.data));
+                        }
+                    }
                     progress = Some(ReceiveMassCallbackData {
                         channel: channel_key,
                         progress: if total_parts == 0 {
@@ -233,17 +505,29 @@ impl MassSystem {
 
                     if waiter.packet.complete() {
                         should_remove = true;
-                        completion = Some(
+                        completion = Some(if waiter.packet.is_streaming() {
+                            Ok(ReverseMassReceiveResult {
+                                channel,
+                                file_name,
+                                data: Vec::new(),
+                                streamed: true,
+                                audio: waiter.audio,
+                            })
+                        } else {
                             waiter
                                 .packet
                                 .file(false)
+                                .and_then(|data| decompress_incoming(waiter.compress_mode, data))
                                 .map(|data| ReverseMassReceiveResult {
+                                    audio: (channel == L2Channel::MassVoice)
+                                        .then(|| sniff_audio_container(&data)),
                                     channel,
                                     file_name,
                                     data,
+                                    streamed: false,
                                 })
-                                .context("failed to assemble reverse MASS payload"),
-                        );
+                                .context("failed to assemble reverse MASS payload")
+                        });
                     }
                 }
                 Err(err) => {
@@ -253,6 +537,12 @@ impl MassSystem {
             }
         }
 
+        if let Some(cb) = chunk_cb {
+            for chunk in chunks {
+                (cb)(chunk);
+            }
+        }
+
         if let (Some(cb), Some(data)) = (progress_cb, progress) {
             (cb)(data);
         }
@@ -270,6 +560,23 @@ impl MassSystem {
             self.reverse_mass_waits.remove(sibling);
         }
 
+        if waiter.passive {
+            match completion {
+                Some(Ok(result)) => incoming::publish(IncomingFile {
+                    addr: self.owner_id.clone(),
+                    channel,
+                    file_name: result.file_name,
+                    data: result.data,
+                    audio: result.audio,
+                }),
+                Some(Err(err)) => {
+                    log::warn!("[MassSystem] passive reverse MASS receive failed: {err:?}");
+                }
+                None => {}
+            }
+            return;
+        }
+
         if let Some(result) = completion {
             if let Some(tx) = waiter.tx.lock().take() {
                 if tx.send(result).is_err() {
@@ -280,14 +587,28 @@ impl MassSystem {
     }
 }
 
+impl HasOwnerId for MassSystem {
+    fn owner_id(&self) -> &str {
+        &self.owner_id
+    }
+}
+
 impl XiaomiSystemExt for MassSystem {
     fn on_layer2_packet(&mut self, channel: L2Channel, _opcode: L2OpCode, payload: &[u8]) {
         match channel {
             L2Channel::Pb => match protocol::WearPacket::decode(Cursor::new(payload)) {
                 Ok(packet) => {
                     if let Some(protocol::wear_packet::Payload::Mass(mass)) = packet.payload {
-                        if let Some(protocol::mass::Payload::PrepareResponse(resp)) = mass.payload {
-                            self.handle_prepare_response(resp);
+                        match mass.payload {
+                            Some(protocol::mass::Payload::PrepareResponse(resp)) => {
+                                mark_pb_consumed();
+                                self.handle_prepare_response(resp);
+                            }
+                            Some(protocol::mass::Payload::PrepareRequest(req)) => {
+                                mark_pb_consumed();
+                                self.handle_incoming_prepare(req);
+                            }
+                            _ => {}
                         }
                     }
                 }
@@ -301,6 +622,13 @@ impl XiaomiSystemExt for MassSystem {
             },
             _ => {
                 let key = channel as u8;
+                if !self.reverse_mass_waits.contains_key(&key)
+                    && incoming::FILE_CHANNELS.contains(&channel)
+                    && looks_like_reverse_mass_packet(payload)
+                    && incoming::wants(&self.owner_id, channel)
+                {
+                    self.begin_passive_receive(channel);
+                }
                 if self.reverse_mass_waits.contains_key(&key) {
                     self.handle_reverse_mass_payload(channel, payload);
                 }
@@ -313,31 +641,243 @@ impl XiaomiSystemExt for MassSystem {
 pub struct MassComponent {
     #[serde(skip_serializing)]
     prepare_wait: Mutex<Option<oneshot::Sender<protocol::PrepareResponse>>>, // 等 Prepare 回包的单次通道
+    /// 正在发的文件，图快照和诊断导出里能直接看到传到哪了
+    active_transfer: Option<ActiveTransferInfo>,
+    /// 手表对各压缩模式的态度：自己推文件时带过、或者压过的 prepare 回了 Ready 是 true，
+    /// 压过的被拒、原样重发又成了是 false。没表过态的不在表里
+    compress_modes: HashMap<u8, bool>,
 }
 
 impl MassComponent {
     pub fn new() -> Self {
         Self {
             prepare_wait: Mutex::new(None),
+            active_transfer: None,
+            compress_modes: HashMap::new(),
         }
     }
+
+    pub fn active_transfer(&self) -> Option<&ActiveTransferInfo> {
+        self.active_transfer.as_ref()
+    }
+
+    /// 发了 MASS prepare，还在等手表回
+    pub fn awaiting_prepare(&self) -> bool {
+        self.prepare_wait.lock().is_some()
+    }
+
+    /// 手表认不认这个压缩模式，None 是还不知道
+    pub fn compress_mode_supported(&self, mode: u8) -> Option<bool> {
+        self.compress_modes.get(&mode).copied()
+    }
+
+    fn note_compress_mode(&mut self, mode: u8, supported: bool) {
+        self.compress_modes.insert(mode, supported);
+    }
+}
+
+/// 一次 MASS 发送的概况，开始分片时写进 MassComponent，发完（不管成败）清掉
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ActiveTransferInfo {
+    pub device_addr: String,
+    /// unix 毫秒
+    pub started_at: u64,
+    pub data_type: String,
+    pub bytes: usize,
+    /// 整文件 md5 的 hex，也就是 Prepare 里的 data_id
+    pub md5: String,
+    /// 设备说上次已经收过的字节数，续传时非 0
+    pub resumed_from: usize,
+    /// 已经交给 SAR 的最后一片，不代表已经 ACK
+    pub current_part: u16,
+    pub total_parts: u16,
+    pub percent: f32,
+}
+
+impl ActiveTransferInfo {
+    fn new(
+        device_addr: &str,
+        data_type: MassDataType,
+        bytes: usize,
+        md5: String,
+        resumed_from: usize,
+        total_parts: u16,
+    ) -> Self {
+        let mut info = Self {
+            device_addr: device_addr.to_string(),
+            started_at: unix_millis(),
+            data_type: format!("{data_type:?}"),
+            bytes,
+            md5,
+            resumed_from,
+            current_part: 0,
+            total_parts,
+            percent: 0.0,
+        };
+        info.set_current_part(0);
+        info
+    }
+
+    fn set_current_part(&mut self, part: u16) {
+        self.current_part = part.min(self.total_parts);
+        let base = if self.bytes == 0 {
+            0.0
+        } else {
+            (self.resumed_from as f32 / self.bytes as f32).clamp(0.0, 1.0)
+        };
+        let sent = if self.total_parts == 0 {
+            1.0
+        } else {
+            self.current_part as f32 / self.total_parts as f32
+        };
+        self.percent = (base + (1.0 - base) * sent) * 100.0;
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+async fn update_active_transfer<F>(cursor: &DeviceCursor, f: F)
+where
+    F: FnOnce(&mut Option<ActiveTransferInfo>) + Send + 'static,
+{
+    let _ = cursor.mass(move |comp| f(&mut comp.active_transfer)).await;
 }
 
 fn looks_like_reverse_mass_packet(payload: &[u8]) -> bool {
     payload.len() >= 12 && payload.first().copied() == Some(0)
 }
 
+/// 这个类型的数据能不能压：固件和快应用的安装请求里 md5/长度是按整包算的，又没有字段告诉手表压过，
+/// 压了两边就对不上；通知图标本来就小。剩下的表盘和纯 MASS 传输在 prepare 里会带上压缩模式，
+/// 手表认不认见 `negotiate_compress_mode`
+pub fn compress_allowed(data_type: MassDataType) -> bool {
+    !matches!(
+        data_type,
+        MassDataType::Firmware | MassDataType::ThirdPartyApp | MassDataType::NotificationIcon
+    )
+}
+
+/// 按 `MassConfig::compress_mode` 从注册表取编解码器压一遍，压了才返回 (压缩后的数据, comp_data)。
+/// 类型不允许、编解码器没注册、压缩失败都返回 None，按原样发送，不为这个让整个传输失败
+pub fn compress_payload(
+    mode: Option<u8>,
+    data_type: MassDataType,
+    file_data: &[u8],
+) -> Option<(Vec<u8>, u8)> {
+    let mode = mode.filter(|mode| *mode != codec::COMPRESS_MODE_NONE)?;
+    if !compress_allowed(data_type) {
+        log::debug!("[Mass] {data_type} payloads are never compressed, ignoring mode {mode}");
+        return None;
+    }
+    let Some(codec) = codec::get_codec(mode) else {
+        log::warn!("[Mass] compress mode {mode} has no registered codec, sending uncompressed");
+        return None;
+    };
+    match codec.compress(file_data) {
+        Ok(packed) => {
+            log::info!(
+                "[Mass] compressed with `{}` (mode {mode}): {} -> {} bytes",
+                codec.name(),
+                file_data.len(),
+                packed.len()
+            );
+            Some((packed, mode))
+        }
+        Err(err) => {
+            log::warn!(
+                "[Mass] `{}` failed to compress, sending uncompressed: {err:?}",
+                codec.name()
+            );
+            None
+        }
+    }
+}
+
+/// 配置的压缩模式能不能真用：手表明确不认的不压；`confirmed_only` 时没表过态的也不压
+/// （安装请求里得先填压缩后的长度，被拒了没法原样重来）
+pub fn negotiate_compress_mode(
+    configured: Option<u8>,
+    supported: Option<bool>,
+    confirmed_only: bool,
+) -> Option<u8> {
+    let mode = configured.filter(|mode| *mode != codec::COMPRESS_MODE_NONE)?;
+    match supported {
+        Some(true) => Some(mode),
+        Some(false) => None,
+        None => (!confirmed_only).then_some(mode),
+    }
+}
+
+/// 同步取这台设备已经确认能用的压缩模式，给安装流程在建请求前用
+pub fn confirmed_compress_mode(owner_id: String) -> Option<u8> {
+    let configured = with_device_component_mut::<XiaomiDevice, _, _>(owner_id.clone(), |dev| {
+        dev.config.mass.compress_mode
+    })
+    .ok()
+    .flatten()?;
+    let supported = with_device_component_mut::<MassComponent, _, _>(owner_id, move |comp| {
+        comp.compress_mode_supported(configured)
+    })
+    .ok()
+    .flatten();
+    negotiate_compress_mode(Some(configured), supported, true)
+}
+
+async fn compress_for_mass(
+    cursor: &DeviceCursor,
+    data_type: MassDataType,
+    file_data: &[u8],
+) -> Option<(Vec<u8>, u8)> {
+    let (configured, supported) = cursor
+        .device_and_mass(|dev, comp| {
+            let configured = dev.config.mass.compress_mode;
+            (
+                configured,
+                configured.and_then(|mode| comp.compress_mode_supported(mode)),
+            )
+        })
+        .await?;
+    let configured = configured?;
+    let mode = negotiate_compress_mode(Some(configured), supported, false);
+    if mode.is_none() {
+        log::debug!(
+            "[Mass] device rejected compress mode {configured} before, sending uncompressed"
+        );
+    }
+    compress_payload(mode, data_type, file_data)
+}
+
+/// 手表推过来的数据在 prepare 里说压过的，按模式解压；没注册对应编解码器就报错，不把压缩数据当原文交出去
+fn decompress_incoming(mode: u8, data: Vec<u8>) -> Result<Vec<u8>> {
+    if mode == codec::COMPRESS_MODE_NONE {
+        return Ok(data);
+    }
+    let codec = codec::get_codec(mode).ok_or_else(|| {
+        anyhow_site!("incoming payload uses compress mode {mode} but no codec is registered")
+    })?;
+    codec
+        .decompress(&data)
+        .with_context(|| format!("`{}` failed to decompress incoming payload", codec.name()))
+}
+
 /// 构造 Prepare 请求（问设备：你能吃多大一口？）
 fn build_mass_prepare_request(
     data_type: MassDataType,
     file_md5: &Vec<u8>,
     file_length: usize,
+    compress_mode: u8,
 ) -> protocol::WearPacket {
     let mass_payload = protocol::PrepareRequest {
         data_type: data_type as u32,
         data_id: file_md5.to_vec(),
         data_length: file_length as u32,
-        support_compress_mode: None,
+        support_compress_mode: (compress_mode != codec::COMPRESS_MODE_NONE)
+            .then(|| compress_mode.into()),
     };
 
     let mass_pkt = protocol::Mass {
@@ -360,46 +900,172 @@ pub async fn send_file_for_owner<F>(
 where
     F: Fn(SendMassCallbackData) + Send + Sync,
 {
-    let file_md5 = crate::tools::calc_md5(&file_data);
+    send_file_with_completion(
+        owner_id,
+        file_data,
+        data_type,
+        MassCompletion::PerSeqAck,
+        progress_cb,
+    )
+    .await
+}
+
+/// 同 `send_file_for_owner`，这次传输按 `completion` 判定完成
+pub async fn send_file_with_completion<F>(
+    owner_id: String,
+    file_data: Vec<u8>,
+    data_type: MassDataType,
+    completion: MassCompletion,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let cursor = DeviceCursor::new(owner_id);
+    let Some((packed, compress_mode)) = compress_for_mass(&cursor, data_type, &file_data).await
+    else {
+        return send_prepared_file(
+            cursor,
+            file_data,
+            codec::COMPRESS_MODE_NONE,
+            data_type,
+            completion,
+            progress_cb,
+        )
+        .await;
+    };
+
+    let prepare_resp = request_prepare(&cursor, &packed, compress_mode, data_type).await?;
+    if prepare_is_ready(&prepare_resp) {
+        note_compress_mode(&cursor, compress_mode, true).await;
+        return send_after_prepare(
+            cursor,
+            packed,
+            compress_mode,
+            data_type,
+            prepare_resp,
+            completion,
+            progress_cb,
+        )
+        .await;
+    }
+
+    // 手表不认这个压缩模式就不会回 Ready，原样再问一次；原样能成说明确实是压缩的问题，记下来以后不压
+    log::warn!(
+        "[Mass] {} refused compress mode {} (status {}), retrying uncompressed",
+        cursor.owner(),
+        compress_mode,
+        prepare_resp.prepare_status
+    );
+    drop(packed);
+    let prepare_resp =
+        request_prepare(&cursor, &file_data, codec::COMPRESS_MODE_NONE, data_type).await?;
+    if !prepare_is_ready(&prepare_resp) {
+        bail_site!("Mass data prepare was not READY");
+    }
+    note_compress_mode(&cursor, compress_mode, false).await;
+    send_after_prepare(
+        cursor,
+        file_data,
+        codec::COMPRESS_MODE_NONE,
+        data_type,
+        prepare_resp,
+        completion,
+        progress_cb,
+    )
+    .await
+}
+
+/// 数据已经按 `compress_mode` 压好了（安装请求里得填压缩后的长度，只能先压），这里不会再压
+pub async fn send_compressed_file_for_owner<F>(
+    owner_id: String,
+    file_data: Vec<u8>,
+    compress_mode: u8,
+    data_type: MassDataType,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    send_prepared_file(
+        DeviceCursor::new(owner_id),
+        file_data,
+        compress_mode,
+        data_type,
+        MassCompletion::PerSeqAck,
+        progress_cb,
+    )
+    .await
+}
+
+async fn send_prepared_file<F>(
+    cursor: DeviceCursor,
+    file_data: Vec<u8>,
+    compress_mode: u8,
+    data_type: MassDataType,
+    completion: MassCompletion,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let prepare_resp = request_prepare(&cursor, &file_data, compress_mode, data_type).await?;
+    if !prepare_is_ready(&prepare_resp) {
+        bail_site!("Mass data prepare was not READY");
+    }
+    if compress_mode != codec::COMPRESS_MODE_NONE {
+        note_compress_mode(&cursor, compress_mode, true).await;
+    }
+    send_after_prepare(
+        cursor,
+        file_data,
+        compress_mode,
+        data_type,
+        prepare_resp,
+        completion,
+        progress_cb,
+    )
+    .await
+}
+
+fn prepare_is_ready(resp: &protocol::PrepareResponse) -> bool {
+    resp.prepare_status == protocol::PrepareStatus::Ready as i32
+}
+
+async fn note_compress_mode(cursor: &DeviceCursor, mode: u8, supported: bool) {
+    let _ = cursor
+        .mass(move |comp| comp.note_compress_mode(mode, supported))
+        .await;
+}
+
+/// 发 Prepare 等设备回话，回的是不是 Ready 由调用方判断
+async fn request_prepare(
+    cursor: &DeviceCursor,
+    file_data: &[u8],
+    compress_mode: u8,
+    data_type: MassDataType,
+) -> Result<protocol::PrepareResponse> {
+    // 压缩后 md5、长度、续传进度都按压缩后的数据算
+    let file_md5 = crate::tools::calc_md5(file_data);
     let file_len = file_data.len();
-    let profiler = get_transport_profiler(&owner_id).await;
+    let profiler = get_transport_profiler(cursor).await;
     log::info!("Building MASS Prepare response listener...");
 
     // 1) 建立一次性通道，等设备的 PrepareResponse
     let (tx, rx) = oneshot::channel();
-    crate::ecs::with_rt_mut({
-        let owner = owner_id.clone();
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(comp) = world.get_mut::<MassComponent>(entity) {
-                    *comp.prepare_wait.lock() = Some(tx);
-                }
-            });
-        }
-    })
-    .await;
+    let _ = cursor
+        .mass(move |comp| *comp.prepare_wait.lock() = Some(tx))
+        .await;
 
     log::info!("Sending MASS Prepare...");
     let prepare_started_at = Instant::now();
     // 2) 发 Prepare 请求（data_id = 整文件 md5，当全世界最尊重手环的主机。）
-    crate::ecs::with_rt_mut({
-        let owner = owner_id.clone();
-        let file_md5_clone = file_md5.clone();
-        move |rt| {
-            let _ = rt.with_device_mut(&owner, |world, entity| {
-                if let Some(mut dev) = world.get_mut::<XiaomiDevice>(entity) {
-                    let prepare_pkt =
-                        build_mass_prepare_request(data_type, &file_md5_clone, file_len);
-                    packet::cipher::enqueue_pb_packet(
-                        dev.as_mut(),
-                        prepare_pkt,
-                        "MassSystem::send_file_for_owner.prepare",
-                    );
-                }
-            });
-        }
-    })
-    .await;
+    packet::cipher::enqueue_pb_packet_async(
+        cursor.owner(),
+        build_mass_prepare_request(data_type, &file_md5, file_len, compress_mode),
+        "MassSystem::send_file_for_owner.prepare",
+    )
+    .await?;
     if let Some(profiler) = profiler.as_ref() {
         profiler.record(
             "mass",
@@ -416,10 +1082,8 @@ where
     log::info!("Waiting for prepare response...");
 
     // 3) 等设备回能力参数
-    let prepare_resp = rx.await.context("Mass prepare response not received")?;
-    if prepare_resp.prepare_status != protocol::PrepareStatus::Ready as i32 {
-        bail_site!("Mass data prepare was not READY");
-    }
+    // 设备被移除时 MassComponent 跟着析构，发送端被丢掉
+    let prepare_resp = rx.await.map_err(|_| cursor.gone())?;
     if let Some(profiler) = profiler.as_ref() {
         profiler.record(
             "mass",
@@ -434,13 +1098,29 @@ where
             Some(1),
             None,
             None,
-            Some(true),
+            Some(prepare_is_ready(&prepare_resp)),
             Some(format!(
                 "expected_slice_length={}",
                 prepare_resp.expected_slice_length()
             )),
         );
     }
+    Ok(prepare_resp)
+}
+
+async fn send_after_prepare<F>(
+    cursor: DeviceCursor,
+    file_data: Vec<u8>,
+    compress_mode: u8,
+    data_type: MassDataType,
+    prepare_resp: protocol::PrepareResponse,
+    completion: MassCompletion,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let file_len = file_data.len();
     let expected_slice_length = prepare_resp.expected_slice_length() as usize;
 
     let sent_length = (prepare_resp.remained_data_length() as usize).min(file_len);
@@ -452,11 +1132,13 @@ where
         );
     }
     send_file_for_owner_with_slice_length(
-        owner_id,
+        cursor,
         file_data,
         data_type,
+        compress_mode,
         expected_slice_length,
         sent_length,
+        completion,
         progress_cb,
     )
     .await
@@ -473,31 +1155,68 @@ where
     F: Fn(SendMassCallbackData) + Send + Sync,
 {
     send_file_for_owner_with_slice_length(
-        owner_id,
+        DeviceCursor::new(owner_id),
         file_data,
         data_type,
+        codec::COMPRESS_MODE_NONE,
         expected_slice_length,
         0,
+        MassCompletion::PerSeqAck,
         progress_cb,
     )
     .await
 }
 
 async fn send_file_for_owner_with_slice_length<F>(
-    owner_id: String,
+    cursor: DeviceCursor,
     file_data: Vec<u8>,
     data_type: MassDataType,
+    compress_mode: u8,
     expected_slice_length: usize,
     sent_length: usize,
+    completion: MassCompletion,
     progress_cb: F,
 ) -> Result<()>
 where
     F: Fn(SendMassCallbackData) + Send + Sync,
 {
-    let profiler = get_transport_profiler(&owner_id).await;
-    let miwear_packet_body_max_len = expected_slice_length;
-    if miwear_packet_body_max_len == 0 {
-        bail_site!("Device reported expected_slice_length of 0, cannot proceed.");
+    let result = send_mass_fragments(
+        &cursor,
+        file_data,
+        data_type,
+        compress_mode,
+        expected_slice_length,
+        sent_length,
+        completion,
+        progress_cb,
+    )
+    .await;
+    update_active_transfer(&cursor, |slot| *slot = None).await;
+    log::debug!(
+        "[Mass] {} transfer finished after {} runtime lookups",
+        cursor.owner(),
+        cursor.lookups()
+    );
+    result
+}
+
+async fn send_mass_fragments<F>(
+    cursor: &DeviceCursor,
+    file_data: Vec<u8>,
+    data_type: MassDataType,
+    compress_mode: u8,
+    expected_slice_length: usize,
+    sent_length: usize,
+    completion: MassCompletion,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let profiler = get_transport_profiler(cursor).await;
+    let miwear_packet_body_max_len = expected_slice_length;
+    if miwear_packet_body_max_len == 0 {
+        bail_site!("Device reported expected_slice_length of 0, cannot proceed.");
     }
 
     let file_len = file_data.len();
@@ -509,7 +1228,10 @@ where
         (sent_length as f32 / file_len as f32).clamp(0.0, 1.0)
     };
 
-    let mass_inner_payload = MassPacket::build(file_data, data_type)?;
+    let mass_inner_payload =
+        MassPacket::build(file_data, data_type)?.with_compress_mode(compress_mode);
+    // build 里已经算过一遍 md5 了，别再对整个文件哈希一次
+    let file_md5 = crate::tools::to_hex_string(&mass_inner_payload.md5);
     let mass_inner_payload_with_crc32 = mass_inner_payload.encode_with_crc32_from(sent_length);
 
     // MiWearPacket Body 结构：Channel(1) | Op(1) | blocks_num(2) | resume_block(2) | MassFragment
@@ -522,49 +1244,64 @@ where
         );
     }
 
-    // 算总片数：向上取整
-    let total_parts =
-        (mass_inner_payload_with_crc32.len() as f32 / mass_fragment_max_len as f32).ceil() as u16;
-    if total_parts == 0 && !mass_inner_payload_with_crc32.is_empty() {
-        bail_site!("Calculated total_parts is 0 for non-empty payload.");
-    }
-
     // 从 SAR 拿窗口大小 & 发送超时，便于自适应批量/等待策略
-    let sar_hints = crate::ecs::with_rt_mut({
-        let owner = owner_id.clone();
-        move |rt| {
-            rt.with_device_mut(&owner, |world, entity| {
-                world.get_mut::<XiaomiDevice>(entity).map(|dev| {
-                    let sar = dev.sar.lock();
-                    (
-                        sar.tx_window_size(),
-                        sar.raw_tx_window_size(),
-                        sar.send_timeout_ms(),
-                    )
-                })
-            })
-            .flatten()
-        }
-    })
-    .await;
+    let sar_hints = cursor
+        .device(|dev| {
+            let sar = dev.sar.lock();
+            (
+                sar.tx_window_size(),
+                sar.raw_tx_window_size(),
+                sar.send_timeout_ms(),
+            )
+        })
+        .await;
 
     let (tx_window_hint, raw_tx_window, send_timeout_hint_ms) = sar_hints
         .map(|(soft, raw, timeout)| (Some(soft), Some(raw), Some(timeout)))
         .unwrap_or((None, None, None));
 
-    let mass_config = crate::ecs::with_rt_mut({
-        let owner = owner_id.clone();
-        move |rt| {
-            rt.with_device_mut(&owner, |world, entity| {
-                world
-                    .get::<XiaomiDevice>(entity)
-                    .map(|dev| dev.config.mass.clone())
-            })
-            .flatten()
+    let (mass_config, mass_crypto, sar_version) = cursor
+        .device(|dev| {
+            (
+                dev.config.mass.clone(),
+                dev.config.channel_crypto.get(L2Channel::Mass),
+                dev.sar_version,
+            )
+        })
+        .await
+        .with_context(|| {
+            format!(
+                "Device {} not found when retrieving MASS config",
+                cursor.owner()
+            )
+        })?;
+    // 分片循环里拿不到 dev，cipher 提前取好
+    let mass_cipher = match mass_crypto {
+        ChannelCrypto::Never => None,
+        ChannelCrypto::Always | ChannelCrypto::Auto => {
+            packet::cipher::ensure_l2_cipher(cursor.owner(), sar_version).await
         }
+    };
+
+    // 算总片数：向上取整，超出 16 位或者配置上限的直接拒掉，别发到一半才炸
+    let total_parts = checked_total_parts(
+        mass_inner_payload_with_crc32.len(),
+        mass_fragment_max_len,
+        mass_config.max_total_parts,
+    )?;
+
+    update_active_transfer(cursor, {
+        let info = ActiveTransferInfo::new(
+            cursor.owner(),
+            data_type,
+            file_len,
+            file_md5,
+            sent_length,
+            total_parts,
+        );
+        move |slot| *slot = Some(info)
     })
-    .await
-    .with_context(|| format!("Device {} not found when retrieving MASS config", owner_id))?;
+    .await;
 
     // 7) 基于 hint 计算我们的批大小/软上限/卡顿判定门限
     let batch_limit = compute_batch_limit(&mass_config, tx_window_hint);
@@ -598,11 +1335,13 @@ where
         );
     }
 
+    let per_seq_ack = matches!(completion, MassCompletion::PerSeqAck);
+
     // 发送主循环：按批次装包 -> 入队 -> 根据 ACK 控制节奏
     let mut pending_parts = VecDeque::new();
     let mut batch_payloads: Vec<Vec<u8>> = Vec::with_capacity(batch_limit);
     let mut batch_meta: Vec<(u16, usize)> = Vec::with_capacity(batch_limit);
-    let mut last_progress_at = Instant::now();
+    let mut last_progress_at = mass_config.clock.now();
     let mut flush_count = 0usize;
 
     for i in 0..total_parts {
@@ -615,26 +1354,33 @@ where
         let fragment = &mass_inner_payload_with_crc32[start_index..end_index];
 
         // MASS 片内的实际负载：总片数(2B) + 当前片号(2B) + 数据片
-        let mut actual_data_payload = Vec::with_capacity(4 + fragment.len());
-        actual_data_payload
-            .write_u16::<LittleEndian>(total_parts)
-            .unwrap();
-        actual_data_payload
-            .write_u16::<LittleEndian>(current_part_num)
-            .unwrap();
+        let mut actual_data_payload = Vec::with_capacity(wire::PART_HEADER_LEN + fragment.len());
+        wire::write_part_header(
+            &mut actual_data_payload,
+            wire::PartHeader {
+                total: total_parts,
+                current: current_part_num,
+            },
+        );
         actual_data_payload.extend_from_slice(fragment);
 
         // 打成 L2 包（Mass 写操作）
         let actual_data_payload_len = actual_data_payload.len();
-        batch_payloads
-            .push(L2Packet::new(L2Channel::Mass, L2OpCode::Write, actual_data_payload).to_bytes());
+        let l2_bytes = packet::cipher::encode_l2_with(
+            mass_crypto,
+            L2Channel::Mass,
+            actual_data_payload,
+            mass_cipher.as_deref(),
+        )
+        .map_err(|err| crate::anyhow_site!("failed to encode MASS fragment: {err}"))?;
+        batch_payloads.push(l2_bytes);
         batch_meta.push((current_part_num, actual_data_payload_len));
 
         // 批攒够了就立刻下发，并根据 ACK 调整节奏
         if batch_payloads.len() >= batch_limit {
             flush_count += 1;
             flush_mass_batch(
-                &owner_id,
+                cursor,
                 &mut batch_payloads,
                 &mut batch_meta,
                 &mut pending_parts,
@@ -643,12 +1389,13 @@ where
             )
             .await?;
             enforce_flow_control(
-                &owner_id,
+                cursor,
                 &mut pending_parts,
                 total_parts,
                 progress_base,
                 &progress_cb,
                 &mass_config,
+                per_seq_ack,
                 ack_stall_deadline,
                 backlog_soft_limit,
                 &mut last_progress_at,
@@ -662,7 +1409,7 @@ where
     if !batch_payloads.is_empty() {
         flush_count += 1;
         flush_mass_batch(
-            &owner_id,
+            cursor,
             &mut batch_payloads,
             &mut batch_meta,
             &mut pending_parts,
@@ -674,12 +1421,13 @@ where
 
     // 再来一轮节流/推进
     enforce_flow_control(
-        &owner_id,
+        cursor,
         &mut pending_parts,
         total_parts,
         progress_base,
         &progress_cb,
         &mass_config,
+        per_seq_ack,
         ack_stall_deadline,
         backlog_soft_limit,
         &mut last_progress_at,
@@ -687,11 +1435,46 @@ where
     )
     .await?;
 
+    match completion {
+        MassCompletion::PerSeqAck => {}
+        MassCompletion::WindowDrain => {
+            wait_for_window(cursor, 0, &mass_config).await?;
+            report_handed_off(
+                &mut pending_parts,
+                0,
+                total_parts,
+                progress_base,
+                &progress_cb,
+            );
+            return Ok(());
+        }
+        // 分片都交出去了，把剩下的 pending 清掉、报满进度，再等设备的完成包
+        MassCompletion::DeviceCompletionPacket(packet) => {
+            report_handed_off(
+                &mut pending_parts,
+                0,
+                total_parts,
+                progress_base,
+                &progress_cb,
+            );
+            return await_completion_packet(cursor, packet, &mass_config).await;
+        }
+    }
+
     // 最后把队头一个个等 ACK，直到清空
     while let Some(front_seq) = pending_parts.front().map(|p| p.seq) {
-        wait_for_seq_ack(&owner_id, front_seq, &mass_config).await?;
+        let busy = busy_report(&pending_parts, total_parts, progress_base);
+        wait_for_seq_ack(
+            cursor,
+            front_seq,
+            &mass_config,
+            ack_stall_deadline,
+            &progress_cb,
+            busy,
+        )
+        .await?;
         consume_acked_parts(
-            &owner_id,
+            cursor,
             &mut pending_parts,
             total_parts,
             progress_base,
@@ -703,9 +1486,10 @@ where
     Ok(())
 }
 
-/// 把一批分片丢进底层 SAR 队列，并记录 seq -> part 的映射到 pending 队列
+/// 把一批分片丢进底层 SAR 队列，并记录 seq -> part 的映射到 pending 队列；
+/// 传输进度在入队的同一个任务里记到这批的最后一片
 async fn flush_mass_batch(
-    owner_id: &str,
+    cursor: &DeviceCursor,
     batch_payloads: &mut Vec<Vec<u8>>,
     batch_meta: &mut Vec<(u16, usize)>,
     pending_parts: &mut VecDeque<PendingMassPart>,
@@ -719,8 +1503,9 @@ async fn flush_mass_batch(
     let payloads = mem::take(batch_payloads);
     let meta = mem::take(batch_meta);
     let meta_len = meta.len();
+    let last_part = meta.last().map(|(part_num, _)| *part_num);
 
-    let seqs = enqueue_mass_batch(owner_id, payloads).await?;
+    let seqs = enqueue_mass_batch(cursor, payloads, last_part).await?;
     if seqs.len() != meta_len {
         bail_site!(
             "enqueue_batch returned {} seqs but {} payloads were submitted",
@@ -764,12 +1549,13 @@ async fn flush_mass_batch(
 /// 根据 ACK 推进进度 + 节流：
 /// 思想：先尽量“吃掉”已经 ACK 的队头；如果 backlog 太大或太久没进展，就强制等一个 ACK。
 async fn enforce_flow_control<F>(
-    owner_id: &str,
+    cursor: &DeviceCursor,
     pending_parts: &mut VecDeque<PendingMassPart>,
     total_parts: u16,
     progress_base: f32,
     progress_cb: &F,
     config: &MassConfig,
+    per_seq_ack: bool,
     ack_stall_deadline: Duration,
     backlog_soft_limit: usize,
     last_progress_at: &mut Instant,
@@ -778,12 +1564,27 @@ async fn enforce_flow_control<F>(
 where
     F: Fn(SendMassCallbackData) + Send + Sync,
 {
+    // 不按单片 ACK 的通道：等发送池积压降下来，出了池子的就当完成
+    if !per_seq_ack {
+        let still_queued = wait_for_window(cursor, backlog_soft_limit, config).await?;
+        if report_handed_off(
+            pending_parts,
+            still_queued,
+            total_parts,
+            progress_base,
+            progress_cb,
+        ) {
+            *last_progress_at = config.clock.now();
+        }
+        return Ok(());
+    }
+
     // 先看看能不能把队头消费一波
     let consumed =
-        consume_acked_parts(owner_id, pending_parts, total_parts, progress_base, progress_cb)
+        consume_acked_parts(cursor, pending_parts, total_parts, progress_base, progress_cb)
             .await?;
     if consumed > 0 {
-        *last_progress_at = Instant::now();
+        *last_progress_at = config.clock.now();
     }
 
     if pending_parts.is_empty() {
@@ -792,7 +1593,7 @@ where
 
     // 两种情况需要“踩刹车”：
     // 1) backlog 超过软上限；2) 太久没进展（可能设备处理不过来）
-    let now = Instant::now();
+    let now = config.clock.now();
     let mut should_wait = pending_parts.len() >= backlog_soft_limit;
     if !should_wait && now.duration_since(*last_progress_at) >= ack_stall_deadline {
         should_wait = true;
@@ -805,16 +1606,26 @@ where
             } else {
                 "stall"
             };
-            let wait_started_at = Instant::now();
-            // 等队头 ACK 一个，再继续推进
-            wait_for_seq_ack(owner_id, front_seq, config).await?;
+            let wait_started_at = config.clock.now();
+            // 等队头 ACK 一个，再继续推进；设备忙的话 wait 里会持续回调 device_busy
+            let busy = busy_report(pending_parts, total_parts, progress_base);
+            wait_for_seq_ack(
+                cursor,
+                front_seq,
+                config,
+                ack_stall_deadline,
+                progress_cb,
+                busy,
+            )
+            .await?;
             if let Some(profiler) = profiler {
                 profiler.record(
                     "mass",
                     "flow_wait",
                     Some(
-                        wait_started_at
-                            .elapsed()
+                        config
+                            .clock
+                            .elapsed(wait_started_at)
                             .as_millis()
                             .try_into()
                             .unwrap_or(u64::MAX),
@@ -831,7 +1642,7 @@ where
                 );
             }
             let consumed_after_wait = consume_acked_parts(
-                owner_id,
+                cursor,
                 pending_parts,
                 total_parts,
                 progress_base,
@@ -839,7 +1650,7 @@ where
             )
             .await?;
             if consumed_after_wait > 0 {
-                *last_progress_at = Instant::now();
+                *last_progress_at = config.clock.now();
             }
         }
     }
@@ -847,39 +1658,49 @@ where
     Ok(())
 }
 
-async fn get_transport_profiler(owner_id: &str) -> Option<TransportProfilerHandle> {
-    let owner = owner_id.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
-        rt.with_device_mut(&owner, |world, entity| {
-            world
-                .get::<XiaomiDevice>(entity)
-                .map(|dev| dev.transport_profiler.clone())
-        })
-        .flatten()
-    })
-    .await
+async fn get_transport_profiler(cursor: &DeviceCursor) -> Option<TransportProfilerHandle> {
+    cursor.device(|dev| dev.transport_profiler.clone()).await
 }
 
-/// 真正把批量包入队（交给 SAR），拿回每个包对应的 seq
-async fn enqueue_mass_batch(owner_id: &str, payloads: Vec<Vec<u8>>) -> Result<Vec<u8>> {
+/// 真正把批量包入队（交给 SAR），拿回每个包对应的 seq；给了 `last_part` 就顺手更新当前传输进度
+async fn enqueue_mass_batch(
+    cursor: &DeviceCursor,
+    payloads: Vec<Vec<u8>>,
+    last_part: Option<u16>,
+) -> Result<Vec<u8>> {
     if payloads.is_empty() {
         return Ok(Vec::new());
     }
 
-    crate::ecs::with_rt_mut({
-        let owner = owner_id.to_string();
-        move |rt| -> Result<Vec<u8>> {
-            rt.with_device_mut(&owner, |world, entity| {
-                if let Some(dev) = world.get_mut::<XiaomiDevice>(entity) {
-                    Ok(dev.sar.lock().enqueue_batch(payloads))
-                } else {
-                    bail_site!("Device {} not found when enqueueing MASS batch", owner)
-                }
-            })
-            .unwrap_or_else(|| bail_site!("Device {} not found when enqueueing MASS batch", owner))
-        }
-    })
-    .await
+    cursor
+        .device_and_mass(move |dev, mass| {
+            let seqs = dev.sar.lock().enqueue_batch(payloads);
+            if let (Some(part), Some(info)) = (last_part, mass.active_transfer.as_mut()) {
+                info.set_current_part(part);
+            }
+            seqs
+        })
+        .await
+        .ok_or_else(|| cursor.gone())
+}
+
+/// 总片数，片数编号是 u16，所以上限最多也就 u16::MAX
+fn checked_total_parts(payload_len: usize, fragment_len: usize, max_parts: usize) -> Result<u16> {
+    let total_parts = payload_len.div_ceil(fragment_len);
+    if total_parts == 0 && payload_len > 0 {
+        bail_site!("Calculated total_parts is 0 for non-empty payload.");
+    }
+    let limit = max_parts.min(u16::MAX as usize);
+    if total_parts > limit {
+        bail_site!(
+            "MASS transfer needs {} parts ({} bytes / {} bytes per part), exceeding the limit of {}",
+            total_parts,
+            payload_len,
+            fragment_len,
+            limit
+        );
+    }
+    Ok(total_parts as u16)
 }
 
 /// 批大小：有窗口 hint 就按窗口来（上限 MAX_BATCH_PARTS），否则用保守值
@@ -921,57 +1742,278 @@ fn compute_ack_stall_deadline(
     Duration::from_millis(combined.clamp(config.ack_stall_min_ms, config.ack_stall_max_ms))
 }
 
-/// 阻塞等待某个 seq 收到 ACK（带总超时保护）
-async fn wait_for_seq_ack(owner_id: &str, seq: u8, config: &MassConfig) -> Result<()> {
-    let owner = owner_id.to_string();
-    let ack_notifier = crate::ecs::with_rt_mut({
-        let owner = owner.clone();
-        move |rt| {
-            rt.with_device_mut(&owner, |world, entity| {
-                world
-                    .get_mut::<XiaomiDevice>(entity)
-                    .map(|dev| dev.sar.lock().ack_notifier())
-            })
-            .flatten()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AckWaitVerdict {
+    /// 接着等
+    Keep,
+    /// 设备最近还在回包，只是 ACK 没来：接着等，并告诉 UI 设备在忙
+    Busy,
+    /// 设备完全不出声了，按链路断开处理
+    Silent,
+    TimedOut,
+}
+
+/// 等 ACK 过程中的判定：
+/// - `waited` 只算链路正常的时间（暂停期间不计）
+/// - `since_inbound` 是距离设备上次发来任何 L1 包的时间
+fn judge_ack_wait(
+    waited: Duration,
+    since_inbound: Duration,
+    stall_deadline: Duration,
+    config: &MassConfig,
+) -> AckWaitVerdict {
+    if since_inbound >= Duration::from_millis(config.link_silence_timeout_ms) {
+        return AckWaitVerdict::Silent;
+    }
+    if waited >= Duration::from_secs(config.busy_patience_secs) {
+        return AckWaitVerdict::TimedOut;
+    }
+    let device_alive = since_inbound < stall_deadline;
+    if waited >= Duration::from_secs(config.ack_wait_timeout_secs) && !device_alive {
+        return AckWaitVerdict::TimedOut;
+    }
+    if waited >= stall_deadline && device_alive {
+        return AckWaitVerdict::Busy;
+    }
+    AckWaitVerdict::Keep
+}
+
+/// 队头还没 ACK 时报给 UI 的进度：停在队头前一片，带上 device_busy
+fn busy_report(
+    pending_parts: &VecDeque<PendingMassPart>,
+    total_parts: u16,
+    progress_base: f32,
+) -> SendMassCallbackData {
+    let done = pending_parts
+        .front()
+        .map(|p| p.part_num.saturating_sub(1))
+        .unwrap_or(total_parts);
+    let local_progress = if total_parts == 0 {
+        1.0
+    } else {
+        done as f32 / total_parts as f32
+    };
+    SendMassCallbackData {
+        progress: (progress_base + (1.0 - progress_base) * local_progress).clamp(0.0, 1.0),
+        total_parts,
+        current_part_num: done,
+        actual_data_payload_len: 0,
+        device_busy: true,
+        install_percent: None,
+    }
+}
+
+/// 等 ACK 时多久醒来核算一次链路状态和耐心，ACK 本身由 SAR 直接叫醒
+const ACK_RECHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// 阻塞等待某个 seq 收到 ACK。
+/// 链路暂停期间不计入超时，宽限期耗尽（Failed）则直接失败；
+/// 设备还在回包就放宽耐心并持续回调 busy，完全没动静就尽快按断链处理
+async fn wait_for_seq_ack<F>(
+    cursor: &DeviceCursor,
+    seq: u8,
+    config: &MassConfig,
+    stall_deadline: Duration,
+    progress_cb: &F,
+    busy: SendMassCallbackData,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let owner_id = cursor.owner();
+    // ACK 到了 SAR 直接叫醒，runtime 只在定期核算时进一次
+    let mut ack_wait = register_ack_wait(cursor, seq).await?;
+
+    let mut waited = Duration::ZERO;
+    let mut last_check = config.clock.now();
+    let mut active_streak = Duration::ZERO;
+    let mut reported_busy = false;
+    loop {
+        // false 是链路判死或者设备没了，下面查状态会分清是哪种
+        let woke = config
+            .clock
+            .timeout(ACK_RECHECK_INTERVAL, &mut ack_wait)
+            .await;
+        if woke == Some(true) {
+            if reported_busy {
+                log::info!("[MassSystem] {} device finished busy period", owner_id);
+            }
+            return Ok(());
         }
-    })
-    .await
-    .with_context(|| format!("Device {owner_id} not found when waiting for MASS ACK"))?;
-
-    let ack_future = async {
-        loop {
-            let notified = ack_notifier.notified();
-            let owner_clone = owner.clone();
-            let acked = crate::ecs::with_rt_mut(move |rt| {
-                rt.with_device_mut(&owner_clone, |world, entity| {
-                    if let Some(dev) = world.get_mut::<XiaomiDevice>(entity) {
-                        return dev.sar.lock().is_acked(seq);
+
+        let (link_state, since_inbound) = cursor
+            .device(|dev| {
+                let sar = dev.sar.lock();
+                (Some(sar.link_state()), sar.since_last_inbound())
+            })
+            .await
+            .unwrap_or((None, None));
+
+        let now = config.clock.now();
+        let elapsed = now.duration_since(last_check);
+        last_check = now;
+        match link_state {
+            None => return Err(cursor.gone()),
+            Some(LinkState::Failed) => {
+                return Err(MassError::LinkLost {
+                    owner_id: owner_id.to_string(),
+                }
+                .into());
+            }
+            // 暂停期间不计时，也不判静默
+            Some(LinkState::Paused { .. }) => active_streak = Duration::ZERO,
+            Some(LinkState::Active) => {
+                waited += elapsed;
+                active_streak += elapsed;
+                // 静默时间最多从本次等待（或上次暂停恢复）算起，暂停期间没包是正常的
+                let since_inbound = since_inbound.map_or(active_streak, |d| d.min(active_streak));
+                match judge_ack_wait(waited, since_inbound, stall_deadline, config) {
+                    AckWaitVerdict::Keep => {}
+                    AckWaitVerdict::Busy => {
+                        if !reported_busy {
+                            log::info!(
+                                "[MassSystem] {} no ACK for seq {} after {}ms but device is still talking, treating as busy",
+                                owner_id,
+                                seq,
+                                waited.as_millis()
+                            );
+                            reported_busy = true;
+                        }
+                        (progress_cb)(busy.clone());
                     }
-                    false
-                })
-                .unwrap_or(false)
+                    AckWaitVerdict::Silent => {
+                        log::warn!(
+                            "[MassSystem] {} silent for {}ms while waiting ACK for seq {}, giving up",
+                            owner_id,
+                            since_inbound.as_millis(),
+                            seq
+                        );
+                        return Err(MassError::LinkLost {
+                            owner_id: owner_id.to_string(),
+                        }
+                        .into());
+                    }
+                    AckWaitVerdict::TimedOut => {
+                        bail_site!("Timeout waiting for mass packet ACK");
+                    }
+                }
+            }
+        }
+
+        // 等待被结束了但链路又是好的（同地址的设备重建过），重新挂一个，免得空转
+        if woke == Some(false) {
+            ack_wait = register_ack_wait(cursor, seq).await?;
+        }
+    }
+}
+
+async fn register_ack_wait(cursor: &DeviceCursor, seq: u8) -> Result<AckWait> {
+    cursor
+        .require(move |dev| dev.sar.lock().await_acks(&[seq]))
+        .await
+}
+
+/// 等 SAR 发送池里还没发出去的数据降到 `limit` 以下，返回当时还剩多少。
+/// 链路暂停期间不计时，超过 ack_wait_timeout_secs 还降不下来就报超时
+async fn wait_for_window(
+    cursor: &DeviceCursor,
+    limit: usize,
+    config: &MassConfig,
+) -> Result<usize> {
+    let patience = Duration::from_secs(config.ack_wait_timeout_secs.max(1));
+    let poll = Duration::from_millis(config.ack_poll_interval_ms.max(1));
+    let mut waited = Duration::ZERO;
+    let mut last_check = config.clock.now();
+    loop {
+        let snapshot = cursor
+            .read(|dev| {
+                let sar = dev.sar.lock();
+                (sar.pending_counts().0, sar.link_state(), sar.ack_notifier())
             })
             .await;
-            if acked {
-                break;
+        let Some((queued, link_state, notifier)) = snapshot else {
+            return Err(cursor.gone());
+        };
+        if queued <= limit {
+            return Ok(queued);
+        }
+
+        let now = config.clock.now();
+        match link_state {
+            LinkState::Failed => {
+                return Err(MassError::LinkLost {
+                    owner_id: cursor.owner().to_string(),
+                }
+                .into());
             }
-            notified.await;
+            LinkState::Paused { .. } => {}
+            LinkState::Active => waited += now.duration_since(last_check),
+        }
+        last_check = now;
+        if waited >= patience {
+            bail_site!("Timeout waiting for SAR send window to drain ({queued} still queued)");
         }
-    };
 
-    timeout(
-        Duration::from_secs(config.ack_wait_timeout_secs),
-        ack_future,
-    )
-    .await
-    .context("Timeout waiting for mass packet ACK")?;
-    Ok(())
+        let _ = config.clock.timeout(poll, notifier.notified()).await;
+    }
+}
+
+/// 等 `completion_packet` 的发送端报结果，超时按 MASS 配置的时钟算
+async fn await_completion_packet(
+    cursor: &DeviceCursor,
+    packet: CompletionPacket,
+    config: &MassConfig,
+) -> Result<()> {
+    match config.clock.timeout(packet.timeout, packet.rx).await {
+        Some(Ok(result)) => result,
+        Some(Err(_)) => bail_site!(
+            "nobody is listening for {}'s MASS completion packet anymore",
+            cursor.owner()
+        ),
+        None => bail_site!(
+            "Timeout waiting for {}'s MASS completion packet after {:?}",
+            cursor.owner(),
+            packet.timeout
+        ),
+    }
+}
+
+/// 发送池里还剩 `still_queued` 条时，之前的分片都算已经交出去了：弹出并回调进度。
+/// 发送池里可能混着别的数据，只是估算。返回是否有推进
+fn report_handed_off<F>(
+    pending_parts: &mut VecDeque<PendingMassPart>,
+    still_queued: usize,
+    total_parts: u16,
+    progress_base: f32,
+    progress_cb: &F,
+) -> bool
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let handed_off = pending_parts.len().saturating_sub(still_queued);
+    let Some(last) = pending_parts.drain(..handed_off).last() else {
+        return false;
+    };
+    let local_progress = if total_parts == 0 {
+        1.0
+    } else {
+        last.part_num as f32 / total_parts as f32
+    };
+    (progress_cb)(SendMassCallbackData {
+        progress: (progress_base + (1.0 - progress_base) * local_progress).clamp(0.0, 1.0),
+        total_parts,
+        current_part_num: last.part_num,
+        actual_data_payload_len: last.payload_len,
+        device_busy: false,
+        install_percent: None,
+    });
+    true
 }
 
 /// 把已经 ACK 的队头逐个弹出，顺便更新进度回调。
 /// `progress_base` 对应手环侧续传进度
 async fn consume_acked_parts<F>(
-    owner_id: &str,
+    cursor: &DeviceCursor,
     pending_parts: &mut VecDeque<PendingMassPart>,
     total_parts: u16,
     progress_base: f32,
@@ -980,6 +2022,26 @@ async fn consume_acked_parts<F>(
 where
     F: Fn(SendMassCallbackData) + Send + Sync,
 {
+    // 还没标 acked 的一次性交给 SAR 查，连续确认的前缀都标上，整批只进一次 runtime
+    let unchecked: Vec<u8> = pending_parts
+        .iter()
+        .skip_while(|p| p.acked)
+        .map(|p| p.seq)
+        .collect();
+    if !unchecked.is_empty() {
+        let newly_acked = cursor
+            .device(move |dev| dev.sar.lock().consume_acked_prefix(unchecked))
+            .await
+            .unwrap_or(0);
+        for part in pending_parts
+            .iter_mut()
+            .skip_while(|p| p.acked)
+            .take(newly_acked)
+        {
+            part.acked = true;
+        }
+    }
+
     let mut consumed = 0usize;
     let mut latest_progress = None;
 
@@ -991,35 +2053,7 @@ where
             };
 
             if !front.acked {
-                // 如果队头还没标记 acked，就去底层查一下；ack 了就把该 seq 标可消费
-                let seq = front.seq;
-                let owner = owner_id.to_string();
-                let acked = crate::ecs::with_rt_mut({
-                    let owner = owner.clone();
-                    move |rt| {
-                        rt.with_device_mut(&owner, |world, entity| {
-                            let acked = if let Some(dev) = world.get_mut::<XiaomiDevice>(entity) {
-                                dev.sar.lock().is_acked(seq)
-                            } else {
-                                false
-                            };
-                            if acked {
-                                if let Some(dev) = world.get_mut::<XiaomiDevice>(entity) {
-                                    dev.sar.lock().mark_ack_consumed(seq);
-                                }
-                            }
-                            acked
-                        })
-                        .unwrap_or(false)
-                    }
-                })
-                .await;
-
-                if !acked {
-                    break;
-                }
-
-                front.acked = true;
+                break;
             }
 
             (front.part_num, front.payload_len, front.seq)
@@ -1040,6 +2074,8 @@ where
             total_parts,
             current_part_num: part_num,
             actual_data_payload_len: payload_len,
+            device_busy: false,
+            install_percent: None,
         });
         let _ = seq;
         consumed += 1;
@@ -1051,3 +2087,628 @@ where
 
     Ok(consumed)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handed_off_parts_report_progress_without_acks() {
+        let mut pending: VecDeque<PendingMassPart> = (1..=4)
+            .map(|part_num| PendingMassPart {
+                part_num,
+                seq: part_num as u8,
+                payload_len: 100,
+                acked: false,
+            })
+            .collect();
+        let reports = Mutex::new(Vec::new());
+        let cb = |data: SendMassCallbackData| reports.lock().push(data.current_part_num);
+
+        // 池子里还剩 1 条：前 3 片算交出去了
+        assert!(report_handed_off(&mut pending, 1, 4, 0.0, &cb));
+        assert_eq!(pending.len(), 1);
+        assert!(!report_handed_off(&mut pending, 1, 4, 0.0, &cb));
+        assert!(report_handed_off(&mut pending, 0, 4, 0.0, &cb));
+        assert!(pending.is_empty());
+        assert_eq!(*reports.lock(), vec![3, 4]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn snapshot_shows_mid_transfer_state() {
+        use crate::device::{Device, DeviceKind};
+
+        crate::ecs::init_runtime_default();
+        let id = "test:mass-active-transfer";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            crate::ecs::with_rt_mut(move |rt| {
+                rt.spawn_device(
+                    id.to_string(),
+                    (
+                        MassComponent::new(),
+                        Device::new("mock".to_string(), id.to_string(), DeviceKind::Xiaomi),
+                    ),
+                );
+            })
+            .await;
+            // 续传：设备说前 1000 字节已经有了，剩下的分 4 片
+            let info = ActiveTransferInfo::new(
+                id,
+                MassDataType::Firmware,
+                2000,
+                "00112233445566778899aabbccddeeff".to_string(),
+                1000,
+                4,
+            );
+            let cursor = DeviceCursor::new(id.to_string());
+            update_active_transfer(&cursor, move |slot| {
+                let mut info = info;
+                info.set_current_part(2);
+                *slot = Some(info);
+            })
+            .await;
+        });
+
+        let sys = MassSystem::new(id.to_string());
+        let current = sys.current_transfer().unwrap();
+        assert_eq!(current.current_part, 2);
+        assert_eq!(current.percent, 75.0);
+
+        let graph = rt.block_on(crate::ecs::graph::export_react_flow_graph());
+        let node = graph
+            .nodes
+            .iter()
+            .find(|node| {
+                node.id.starts_with(&format!("component:{id}:"))
+                    && node.id.ends_with("MassComponent")
+            })
+            .expect("mass component node");
+        let transfer = &node.data.extra.as_ref().unwrap()["data"]["active_transfer"];
+        assert_eq!(transfer["md5"], "00112233445566778899aabbccddeeff");
+        assert_eq!(transfer["device_addr"], id);
+        assert_eq!(transfer["data_type"], "Firmware");
+        assert_eq!(transfer["current_part"], 2);
+        assert_eq!(transfer["total_parts"], 4);
+
+        rt.block_on(async {
+            update_active_transfer(&DeviceCursor::new(id.to_string()), |slot| *slot = None).await;
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)).await;
+        });
+        assert!(sys.current_transfer().is_none());
+    }
+
+    #[test]
+    fn total_parts_rounds_up_and_respects_limits() {
+        assert_eq!(checked_total_parts(0, 100, 10).unwrap(), 0);
+        assert_eq!(checked_total_parts(1001, 100, 11).unwrap(), 11);
+        assert!(checked_total_parts(1001, 100, 10).is_err());
+
+        // 小分片配大文件，超出 16 位片号
+        let err = checked_total_parts(10 * 1024 * 1024, 10, usize::MAX).unwrap_err();
+        assert!(err.to_string().contains("1048576 parts"), "{err}");
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn busy_device_keeps_transfer_alive() {
+        let config = MassConfig::default();
+        let stall = ms(400);
+
+        // 刚开始等，什么都不说
+        assert_eq!(
+            judge_ack_wait(ms(100), ms(100), stall, &config),
+            AckWaitVerdict::Keep
+        );
+        // 写 flash：ACK 不来，但设备一直有包回来，超过原来 30s 也接着等
+        for waited in [ms(500), ms(10_000), ms(45_000), ms(170_000)] {
+            assert_eq!(
+                judge_ack_wait(waited, ms(50), stall, &config),
+                AckWaitVerdict::Busy
+            );
+        }
+        // 耐心也是有上限的
+        assert_eq!(
+            judge_ack_wait(ms(180_000), ms(50), stall, &config),
+            AckWaitVerdict::TimedOut
+        );
+    }
+
+    #[test]
+    fn silent_link_escalates_quickly() {
+        let config = MassConfig::default();
+        let stall = ms(400);
+
+        // 没动静但还没到静默门限：只是等着，不报 busy
+        assert_eq!(
+            judge_ack_wait(ms(2_000), ms(2_000), stall, &config),
+            AckWaitVerdict::Keep
+        );
+        // 8s 没收到任何包就按断链处理，远早于 30s 的 ACK 超时
+        assert_eq!(
+            judge_ack_wait(ms(8_000), ms(8_000), stall, &config),
+            AckWaitVerdict::Silent
+        );
+    }
+
+    /// 在假时钟上跑 wait_for_seq_ack，一步拨 250ms；`talking` 时每步先让设备回个无关的包。
+    /// 返回等待结果和报了几次 busy
+    #[cfg(not(target_arch = "wasm32"))]
+    fn drive_ack_wait(addr: &'static str, talking: bool) -> (Result<()>, usize) {
+        use crate::device::spawn_mock_xiaomi;
+        use crate::device::xiaomi::{
+            clock::MockClock,
+            config::{SarConfig, XiaomiDeviceConfig},
+            packet::v2::layer1::{L1DataType, L1Packet},
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let clock = MockClock::new();
+        let config = MassConfig {
+            busy_patience_secs: 20,
+            clock: clock.shared(),
+            ..MassConfig::default()
+        };
+        rt.block_on(spawn_mock_xiaomi(
+            addr,
+            XiaomiDeviceConfig {
+                sar: SarConfig {
+                    clock: clock.shared(),
+                    ..SarConfig::default()
+                },
+                mass: config.clone(),
+                ..XiaomiDeviceConfig::default()
+            },
+        ));
+
+        let busy_reports = Arc::new(AtomicUsize::new(0));
+        let waiter = rt.spawn({
+            let busy_reports = busy_reports.clone();
+            async move {
+                let cb = move |data: SendMassCallbackData| {
+                    if data.device_busy {
+                        busy_reports.fetch_add(1, Ordering::Relaxed);
+                    }
+                };
+                let busy = busy_report(&VecDeque::new(), 1, 0.0);
+                let cursor = DeviceCursor::new(addr.to_string());
+                wait_for_seq_ack(&cursor, 0, &config, ms(400), &cb, busy).await
+            }
+        });
+        let mut steps = 0;
+        while !waiter.is_finished() {
+            steps += 1;
+            assert!(steps < 1_000, "ACK wait never ended");
+            if talking {
+                rt.block_on(crate::ecs::with_rt_mut(move |rt| {
+                    if let Some(dev) = rt.component_ref::<XiaomiDevice>(addr) {
+                        let pkt = L1Packet::new(L1DataType::Ack, false, 200, Vec::new());
+                        dev.sar.lock().on_l1_packet(&pkt);
+                    }
+                }));
+            }
+            clock.advance(ms(250));
+            std::thread::sleep(ms(1));
+        }
+        let result = rt.block_on(waiter).unwrap();
+
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)));
+        crate::device::xiaomi::cleanup_cached_state(addr);
+        (result, busy_reports.load(Ordering::Relaxed))
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn cursor_takes_one_lookup_per_step() {
+        use crate::device::{spawn_mock_xiaomi, xiaomi::config::XiaomiDeviceConfig};
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:mass-cursor-lookups";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            spawn_mock_xiaomi(addr, XiaomiDeviceConfig::default()).await;
+            let info = ActiveTransferInfo::new(
+                addr,
+                MassDataType::Watchface,
+                24,
+                "00112233445566778899aabbccddeeff".to_string(),
+                0,
+                3,
+            );
+            update_active_transfer(&DeviceCursor::new(addr.to_string()), move |slot| {
+                *slot = Some(info)
+            })
+            .await;
+
+            let cursor = DeviceCursor::new(addr.to_string());
+            let mut payloads = vec![vec![0u8; 8]; 3];
+            let mut meta = vec![(1u16, 8usize), (2, 8), (3, 8)];
+            let mut pending = VecDeque::new();
+            flush_mass_batch(&cursor, &mut payloads, &mut meta, &mut pending, 1, None)
+                .await
+                .unwrap();
+            assert_eq!(pending.len(), 3);
+            // 进度跟着入队一起记，不多进一次
+            assert_eq!(cursor.lookups(), 1);
+            let part = cursor
+                .mass(|comp| comp.active_transfer.as_ref().map(|info| info.current_part))
+                .await
+                .flatten();
+            assert_eq!(part, Some(3));
+
+            // 整批查 ACK 也只进一次
+            let consumed = consume_acked_parts(&cursor, &mut pending, 3, 0.0, &|_| {})
+                .await
+                .unwrap();
+            assert_eq!(consumed, 0);
+            assert_eq!(cursor.lookups(), 3);
+            wait_for_window(&cursor, usize::MAX, &MassConfig::default())
+                .await
+                .unwrap();
+            assert_eq!(cursor.lookups(), 4);
+
+            // 空批不进 runtime
+            enqueue_mass_batch(&cursor, Vec::new(), None).await.unwrap();
+            assert_eq!(cursor.lookups(), 4);
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            let err = enqueue_mass_batch(&cursor, vec![vec![0u8; 8]], None)
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.downcast_ref::<MassError>(),
+                Some(&MassError::DeviceGone {
+                    owner_id: addr.to_string()
+                })
+            );
+            assert_eq!(cursor.lookups(), 5);
+        });
+        crate::device::xiaomi::cleanup_cached_state(addr);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn silent_device_is_dropped_on_mock_clock() {
+        let (result, busy) = drive_ack_wait("test:mass-ack-silent", false);
+        let err = result.unwrap_err();
+        assert!(
+            matches!(
+                err.downcast_ref::<MassError>(),
+                Some(MassError::LinkLost { .. })
+            ),
+            "{err:?}"
+        );
+        assert_eq!(busy, 0);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn busy_device_runs_out_of_patience_on_mock_clock() {
+        let (result, busy) = drive_ack_wait("test:mass-ack-busy", true);
+        let err = result.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("Timeout waiting for mass packet ACK"),
+            "{err}"
+        );
+        assert!(busy > 0);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn device_completion_waits_for_the_packet() {
+        use crate::device::xiaomi::clock::MockClock;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let clock = MockClock::new();
+        let config = MassConfig {
+            clock: clock.shared(),
+            ..MassConfig::default()
+        };
+        let wait = |completion: MassCompletion| {
+            let MassCompletion::DeviceCompletionPacket(packet) = completion else {
+                unreachable!()
+            };
+            let config = config.clone();
+            rt.spawn(async move {
+                let cursor = DeviceCursor::new("test:mass-completion".to_string());
+                await_completion_packet(&cursor, packet, &config).await
+            })
+        };
+
+        // 分片交完也不算完，得等完成包
+        let (tx, completion) = completion_packet(ms(5_000));
+        let waiter = wait(completion);
+        std::thread::sleep(ms(20));
+        assert!(!waiter.is_finished());
+        tx.send(Ok(())).unwrap();
+        assert!(rt.block_on(waiter).unwrap().is_ok());
+
+        // 收包方没了
+        let (tx, completion) = completion_packet(ms(5_000));
+        drop(tx);
+        assert!(rt.block_on(wait(completion)).unwrap().is_err());
+
+        // 一直没等到
+        let (_tx, completion) = completion_packet(ms(5_000));
+        let waiter = wait(completion);
+        while clock.pending_sleeps() == 0 {
+            std::thread::sleep(ms(1));
+        }
+        clock.advance(ms(5_000));
+        assert!(rt.block_on(waiter).unwrap().is_err());
+    }
+
+    #[test]
+    fn busy_report_stops_before_front_part() {
+        let pending: VecDeque<_> = [(5u16, 10u8), (6, 11)]
+            .into_iter()
+            .map(|(part_num, seq)| PendingMassPart {
+                part_num,
+                seq,
+                payload_len: 100,
+                acked: false,
+            })
+            .collect();
+        let report = busy_report(&pending, 10, 0.0);
+        assert!(report.device_busy);
+        assert_eq!(report.current_part_num, 4);
+        assert!((report.progress - 0.4).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn incoming_streams_receive_scripted_session() {
+        use crate::device::xiaomi::packet::mass::reverse_mass_parts;
+
+        let addr = "test:incoming-session";
+        let mut files = incoming_files(addr);
+        let mut fitness = crate::device::fitness::records(addr);
+        let mut sys = MassSystem::new(addr.to_string());
+
+        let script = [
+            (
+                L2Channel::MassVoice,
+                "memo.opus",
+                b"first voice memo payload".to_vec(),
+            ),
+            (
+                L2Channel::FileFitness,
+                "sport_0815.bin",
+                b"fitness record bytes".to_vec(),
+            ),
+            (
+                L2Channel::MassVoice,
+                "memo2.opus",
+                b"second voice memo payload".to_vec(),
+            ),
+        ];
+        for (channel, name, body) in &script {
+            for part in reverse_mass_parts(name, body) {
+                sys.on_layer2_packet(*channel, L2OpCode::Write, &part);
+            }
+        }
+        // 收完的被动接收不会留在等待表里
+        assert!(sys.reverse_mass_waits.is_empty());
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            for (channel, name, body) in &script {
+                let file = files.next().await.unwrap();
+                assert_eq!((file.channel, file.file_name.as_str()), (*channel, *name));
+                assert_eq!(&file.data, body);
+            }
+            let record = fitness.next().await.unwrap();
+            assert_eq!(record.file_name, "sport_0815.bin");
+            assert_eq!(record.data, b"fitness record bytes");
+        });
+        assert!(files.try_next().is_none() && fitness.try_next().is_none());
+
+        // 没人订阅时不会自动接收
+        drop(files);
+        drop(fitness);
+        for part in reverse_mass_parts("late.opus", b"nobody listens") {
+            sys.on_layer2_packet(L2Channel::MassVoice, L2OpCode::Write, &part);
+        }
+        assert!(sys.reverse_mass_waits.is_empty());
+    }
+
+    #[test]
+    fn voice_memo_reports_audio_container() {
+        use crate::device::xiaomi::packet::mass::reverse_mass_parts;
+
+        let mut memo = b"OggS".to_vec();
+        memo.extend_from_slice(&[0; 22]);
+        memo.extend_from_slice(&[1, 19]);
+        memo.extend_from_slice(b"OpusHead");
+        memo.resize(96, 0);
+
+        let mut sys = MassSystem::new("test:voice-memo".to_string());
+        for streaming in [false, true] {
+            let chunks = Arc::new(Mutex::new(Vec::new()));
+            let on_file_chunk: Option<ReverseMassChunkCallback> = streaming.then(|| {
+                let chunks = chunks.clone();
+                Arc::new(move |chunk: ReverseMassFileChunk| chunks.lock().push(chunk))
+                    as ReverseMassChunkCallback
+            });
+            let mut rx = sys
+                .begin_voice_memo_receive(Arc::new(|_: ReceiveMassCallbackData| {}), on_file_chunk)
+                .unwrap();
+            for part in reverse_mass_parts("memo.opus", &memo) {
+                sys.on_layer2_packet(L2Channel::MassVoice, L2OpCode::Write, &part);
+            }
+
+            let result = rx.try_recv().unwrap().unwrap();
+            assert!(result.is_voice_memo());
+            assert_eq!(result.streamed, streaming);
+            // 流式接收时数据已经交出去了，容器是从第一个分片猜的
+            assert_eq!(result.audio, Some(AudioContainer::OggOpus));
+            assert_eq!(chunks.lock().len(), if streaming { 2 } else { 0 });
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn accepted_incoming_prepare_receives_on_mass_channel() {
+        use crate::device::xiaomi::packet::mass::reverse_mass_parts;
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:incoming-prepare";
+        let mut files = incoming_files(addr);
+        let mut sys = MassSystem::new(addr.to_string());
+        let prepare = |data_length: u32| {
+            protocol::WearPacket {
+                r#type: protocol::wear_packet::Type::Mass as i32,
+                id: protocol::mass::MassId::Prepare as u32,
+                payload: Some(protocol::wear_packet::Payload::Mass(protocol::Mass {
+                    payload: Some(protocol::mass::Payload::PrepareRequest(
+                        protocol::PrepareRequest {
+                            data_type: 32,
+                            data_id: vec![0x5c; 16],
+                            data_length,
+                            ..Default::default()
+                        },
+                    )),
+                })),
+            }
+            .encode_to_vec()
+        };
+
+        // 超过默认上限的直接拒，不准备接收
+        sys.on_layer2_packet(L2Channel::Pb, L2OpCode::Write, &prepare(u32::MAX));
+        assert!(sys.reverse_mass_waits.is_empty());
+
+        let body = b"fake screenshot bytes";
+        sys.on_layer2_packet(L2Channel::Pb, L2OpCode::Write, &prepare(body.len() as u32));
+        assert!(
+            sys.reverse_mass_waits
+                .contains_key(&(L2Channel::Mass as u8))
+        );
+        for part in reverse_mass_parts("screenshot.png", body) {
+            sys.on_layer2_packet(L2Channel::Mass, L2OpCode::Write, &part);
+        }
+        let file = files.try_next().unwrap();
+        assert_eq!(file.file_name, "screenshot.png");
+        assert_eq!(file.data, body);
+    }
+
+    /// 测试用的"压缩"：字节倒过来，解压再倒回去
+    struct Reverse;
+
+    impl codec::CompressionCodec for Reverse {
+        fn name(&self) -> &str {
+            "reverse"
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.compress(data)
+        }
+    }
+
+    #[test]
+    fn compression_skips_types_whose_prepare_cannot_say_so() {
+        const MODE: u8 = 0xd2;
+        codec::register_codec(MODE, Arc::new(Reverse)).unwrap();
+        let raw = b"face".to_vec();
+
+        assert_eq!(
+            compress_payload(Some(MODE), MassDataType::Watchface, &raw),
+            Some((b"ecaf".to_vec(), MODE))
+        );
+        for data_type in [
+            MassDataType::Firmware,
+            MassDataType::ThirdPartyApp,
+            MassDataType::NotificationIcon,
+        ] {
+            assert_eq!(compress_payload(Some(MODE), data_type, &raw), None);
+        }
+        assert_eq!(compress_payload(None, MassDataType::Watchface, &raw), None);
+        codec::unregister_codec(MODE);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn compression_waits_for_the_watch_to_use_the_mode() {
+        use crate::device::{spawn_mock_xiaomi, xiaomi::config::XiaomiDeviceConfig};
+
+        const MODE: u8 = 0xd4;
+        assert_eq!(negotiate_compress_mode(Some(MODE), None, false), Some(MODE));
+        assert_eq!(negotiate_compress_mode(Some(MODE), None, true), None);
+        assert_eq!(
+            negotiate_compress_mode(Some(MODE), Some(true), true),
+            Some(MODE)
+        );
+        assert_eq!(
+            negotiate_compress_mode(Some(MODE), Some(false), false),
+            None
+        );
+        assert_eq!(negotiate_compress_mode(None, Some(true), false), None);
+
+        crate::ecs::init_runtime_default();
+        let addr = "test:mass-compress-negotiation";
+        let mut config = XiaomiDeviceConfig::default();
+        config.mass.compress_mode = Some(MODE);
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(addr, config));
+        // 手表还没表过态，安装流程不压
+        assert_eq!(confirmed_compress_mode(addr.to_string()), None);
+
+        // 手表自己推文件时用了这个模式，之后就能用
+        let mut sys = MassSystem::new(addr.to_string());
+        sys.handle_incoming_prepare(protocol::PrepareRequest {
+            data_type: 32,
+            data_id: vec![0x3c; 16],
+            data_length: 4,
+            support_compress_mode: Some(MODE.into()),
+        });
+        assert_eq!(confirmed_compress_mode(addr.to_string()), Some(MODE));
+
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)));
+        crate::device::xiaomi::cleanup_cached_state(addr);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn compressed_incoming_file_is_decompressed() {
+        use crate::device::xiaomi::packet::mass::reverse_mass_parts;
+
+        const MODE: u8 = 0xd3;
+        codec::register_codec(MODE, Arc::new(Reverse)).unwrap();
+        crate::ecs::init_runtime_default();
+        let addr = "test:incoming-compressed";
+        let mut files = incoming_files(addr);
+        let mut sys = MassSystem::new(addr.to_string());
+        let body = b"watch log line";
+        let prepare = protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::Mass as i32,
+            id: protocol::mass::MassId::Prepare as u32,
+            payload: Some(protocol::wear_packet::Payload::Mass(protocol::Mass {
+                payload: Some(protocol::mass::Payload::PrepareRequest(
+                    protocol::PrepareRequest {
+                        data_type: 32,
+                        data_id: vec![0x7e; 16],
+                        data_length: body.len() as u32,
+                        support_compress_mode: Some(MODE.into()),
+                    },
+                )),
+            })),
+        };
+        sys.on_layer2_packet(L2Channel::Pb, L2OpCode::Write, &prepare.encode_to_vec());
+
+        let packed: Vec<u8> = body.iter().rev().copied().collect();
+        for part in reverse_mass_parts("log.txt", &packed) {
+            sys.on_layer2_packet(L2Channel::Mass, L2OpCode::Write, &part);
+        }
+        let file = files.try_next().unwrap();
+        assert_eq!(file.data, body);
+        codec::unregister_codec(MODE);
+    }
+}
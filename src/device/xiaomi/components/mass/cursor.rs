@@ -0,0 +1,98 @@
+//! 发送期间一直拿着的设备句柄。其实就是 owner id，每次访问还是一个 runtime 任务，
+//! 只是统一了设备没了时的错误，顺便记一下整个传输进了几次 runtime
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::Result;
+
+use crate::device::xiaomi::{self, XiaomiDevice};
+
+use super::{MassComponent, MassError};
+
+pub(super) struct DeviceCursor {
+    owner: String,
+    lookups: AtomicUsize,
+}
+
+impl DeviceCursor {
+    pub(super) fn new(owner: String) -> Self {
+        Self {
+            owner,
+            lookups: AtomicUsize::new(0),
+        }
+    }
+
+    pub(super) fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// 到目前为止进了几次 runtime
+    pub(super) fn lookups(&self) -> usize {
+        self.lookups.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn gone(&self) -> anyhow::Error {
+        MassError::DeviceGone {
+            owner_id: self.owner.clone(),
+        }
+        .into()
+    }
+
+    /// 设备没了返回 None
+    pub(super) async fn device<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut XiaomiDevice) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        xiaomi::with_device_mut(&self.owner, f).await
+    }
+
+    /// 设备没了按 `MassError::DeviceGone` 报
+    pub(super) async fn require<R, F>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&mut XiaomiDevice) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.device(f).await.ok_or_else(|| self.gone())
+    }
+
+    pub(super) async fn read<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&XiaomiDevice) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        xiaomi::with_device_ref(&self.owner, f).await
+    }
+
+    pub(super) async fn mass<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut MassComponent) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        xiaomi::with_component_mut::<MassComponent, _, _>(&self.owner, f).await
+    }
+
+    /// 设备和 MASS 组件一起拿，一个任务里把两边的事都做了
+    pub(super) async fn device_and_mass<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut XiaomiDevice, &mut MassComponent) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.lookups.fetch_add(1, Ordering::Relaxed);
+        let owner = self.owner.clone();
+        crate::ecs::with_rt_mut(move |rt| {
+            rt.with_device_mut(&owner, |world, entity| {
+                let mut query = world.query::<(&mut XiaomiDevice, &mut MassComponent)>();
+                query
+                    .get_mut(world, entity)
+                    .ok()
+                    .map(|(mut dev, mut mass)| f(&mut dev, &mut mass))
+            })
+            .flatten()
+        })
+        .await
+    }
+}
@@ -0,0 +1,251 @@
+//! 手表主动推过来的文件（reverse MASS）排进订阅者各自的队列，宿主从接收端一个个取
+//! （只有 `next().await`，不是 `futures::Stream`）：
+//!
+//! ```ignore
+//! let mut incoming = incoming_files(&addr);
+//! while let Some(file) = incoming.next().await { ... }
+//! ```
+//!
+//! 订阅按设备地址挂在全局表里，不跟实体走，断线重连后新实体收到的文件照样进同一个接收端。
+//! 接收端被 drop 就自动退订。队列有上限，宿主消费不过来时丢最旧的并计数
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, Weak};
+
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::Notify;
+
+use crate::device::xiaomi::packet::v2::layer2::L2Channel;
+
+/// 每个接收端默认最多攒这么多个文件，文件本身可能很大，别攒太多
+pub const DEFAULT_INCOMING_CAPACITY: usize = 16;
+
+/// 会走 reverse MASS 的通道，其它通道（Network / Lyra 之类）的包不当文件看
+pub const FILE_CHANNELS: [L2Channel; 4] = [
+    L2Channel::Mass,
+    L2Channel::MassVoice,
+    L2Channel::FileSensor,
+    L2Channel::FileFitness,
+];
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct IncomingFile {
+    pub addr: String,
+    pub channel: L2Channel,
+    pub file_name: String,
+    pub data: Vec<u8>,
+    /// 语音备忘录的音频容器，其它通道为 None
+    pub audio: Option<crate::tools::AudioContainer>,
+}
+
+trait IncomingSink: Send + Sync {
+    fn accepts(&self, channel: L2Channel) -> bool;
+    fn offer(&self, file: &IncomingFile);
+    fn close(&self);
+}
+
+struct Queue<T> {
+    items: Mutex<VecDeque<T>>,
+    capacity: usize,
+    notify: Notify,
+    dropped: AtomicU64,
+    closed: AtomicBool,
+    channels: Vec<L2Channel>,
+    convert: fn(&IncomingFile) -> T,
+}
+
+impl<T: Send> IncomingSink for Queue<T> {
+    fn accepts(&self, channel: L2Channel) -> bool {
+        self.channels.contains(&channel)
+    }
+
+    fn offer(&self, file: &IncomingFile) {
+        if !self.accepts(file.channel) {
+            return;
+        }
+        {
+            let mut items = self.items.lock();
+            if items.len() >= self.capacity {
+                items.pop_front();
+                let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+                log::warn!(
+                    "[Incoming] {} consumer lagging, dropped oldest file ({} so far)",
+                    file.addr,
+                    dropped
+                );
+            }
+            items.push_back((self.convert)(file));
+        }
+        self.notify.notify_one();
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.notify.notify_one();
+    }
+}
+
+/// 单个订阅者拿到的接收端，`next()` 在 `close_incoming` 之后排空队列再返回 None
+pub struct IncomingReceiver<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T: Send + 'static> IncomingReceiver<T> {
+    pub async fn next(&mut self) -> Option<T> {
+        loop {
+            let notified = self.queue.notify.notified();
+            if let Some(item) = self.queue.items.lock().pop_front() {
+                return Some(item);
+            }
+            if self.queue.closed.load(Ordering::Acquire) {
+                return None;
+            }
+            notified.await;
+        }
+    }
+
+    /// 不等待，当前没有就返回 None
+    pub fn try_next(&mut self) -> Option<T> {
+        self.queue.items.lock().pop_front()
+    }
+
+    /// 因为消费太慢被丢掉的文件数
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+type Registry = RwLock<HashMap<String, Vec<Weak<dyn IncomingSink>>>>;
+
+static INCOMING_SUBSCRIBERS: OnceLock<Registry> = OnceLock::new();
+
+fn registry() -> &'static Registry {
+    INCOMING_SUBSCRIBERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// 订阅 `channels` 上收到的文件，`convert` 把文件转成接收端里的元素类型
+pub(crate) fn subscribe_with<T: Send + 'static>(
+    addr: &str,
+    channels: &[L2Channel],
+    capacity: usize,
+    convert: fn(&IncomingFile) -> T,
+) -> IncomingReceiver<T> {
+    let queue = Arc::new(Queue {
+        items: Mutex::new(VecDeque::new()),
+        capacity: capacity.max(1),
+        notify: Notify::new(),
+        dropped: AtomicU64::new(0),
+        closed: AtomicBool::new(false),
+        channels: channels.to_vec(),
+        convert,
+    });
+    let sink: Arc<dyn IncomingSink> = queue.clone();
+    registry()
+        .write()
+        .entry(addr.to_string())
+        .or_default()
+        .push(Arc::downgrade(&sink));
+    IncomingReceiver { queue }
+}
+
+/// 设备推过来的所有文件（语音备忘录、传感器、运动记录……）
+pub fn incoming_files(addr: &str) -> IncomingReceiver<IncomingFile> {
+    subscribe_with(
+        addr,
+        &FILE_CHANNELS,
+        DEFAULT_INCOMING_CAPACITY,
+        IncomingFile::clone,
+    )
+}
+
+/// 有没有活着的订阅者想要这个通道上的文件，MassSystem 靠这个决定要不要被动接收
+pub(crate) fn wants(addr: &str, channel: L2Channel) -> bool {
+    registry().read().get(addr).is_some_and(|sinks| {
+        sinks
+            .iter()
+            .filter_map(Weak::upgrade)
+            .any(|sink| sink.accepts(channel))
+    })
+}
+
+pub(crate) fn publish(file: IncomingFile) {
+    let mut registry = registry().write();
+    let Some(sinks) = registry.get_mut(&file.addr) else {
+        return;
+    };
+    // 接收端被 drop 了的顺手清掉
+    sinks.retain(|sink| sink.strong_count() > 0);
+    if sinks.is_empty() {
+        registry.remove(&file.addr);
+        return;
+    }
+    for sink in sinks.iter().filter_map(Weak::upgrade) {
+        sink.offer(&file);
+    }
+}
+
+/// 设备彻底不要了（不是断线重连）时调用，所有接收端排空后结束
+pub fn close_incoming(addr: &str) {
+    if let Some(sinks) = registry().write().remove(addr) {
+        for sink in sinks.iter().filter_map(Weak::upgrade) {
+            sink.close();
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    fn file(addr: &str, channel: L2Channel, name: &str) -> IncomingFile {
+        IncomingFile {
+            addr: addr.to_string(),
+            channel,
+            file_name: name.to_string(),
+            data: name.as_bytes().to_vec(),
+            audio: None,
+        }
+    }
+
+    #[test]
+    fn drops_oldest_when_lagging() {
+        let addr = "test:incoming-lag";
+        let mut rx = subscribe_with(addr, &FILE_CHANNELS, 2, IncomingFile::clone);
+        for name in ["a", "b", "c"] {
+            publish(file(addr, L2Channel::Mass, name));
+        }
+
+        assert_eq!(rx.dropped(), 1);
+        assert_eq!(rx.try_next().unwrap().file_name, "b");
+        assert_eq!(rx.try_next().unwrap().file_name, "c");
+        assert!(rx.try_next().is_none());
+        close_incoming(addr);
+    }
+
+    #[test]
+    fn dropping_receiver_unsubscribes() {
+        let addr = "test:incoming-drop";
+        let rx = incoming_files(addr);
+        assert!(wants(addr, L2Channel::FileFitness));
+        assert!(!wants(addr, L2Channel::Network));
+
+        drop(rx);
+        assert!(!wants(addr, L2Channel::FileFitness));
+        publish(file(addr, L2Channel::Mass, "orphan"));
+        assert!(registry().read().get(addr).is_none());
+    }
+
+    #[test]
+    fn next_ends_after_close() {
+        let addr = "test:incoming-close";
+        let mut rx = incoming_files(addr);
+        publish(file(addr, L2Channel::MassVoice, "memo"));
+        close_incoming(addr);
+
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            assert_eq!(rx.next().await.unwrap().file_name, "memo");
+            assert!(rx.next().await.is_none());
+        });
+    }
+}
@@ -0,0 +1,171 @@
+//! 手表主动要往手机传文件（截图、日志）时会先发一个 PrepareRequest 问能不能传，
+//! 这里决定收不收。没设策略就按 `MassConfig::max_incoming_bytes` 限大小，
+//! 拒绝时回非 Ready 的状态，手表看到就不会一直重试
+
+use std::sync::{Arc, OnceLock, RwLock};
+
+use pb::xiaomi::protocol;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingTransferRequest {
+    pub addr: String,
+    /// 原样给出，手表推过来的类型不一定在 `MassDataType` 里
+    pub data_type: u32,
+    pub data_id: Vec<u8>,
+    pub data_length: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncomingTransferDecision {
+    Accept,
+    /// 回给手表的 prepare_status，传 Ready 会按 LowStorage 处理
+    Reject(protocol::PrepareStatus),
+}
+
+pub type IncomingTransferPolicy =
+    Arc<dyn Fn(IncomingTransferRequest) -> IncomingTransferDecision + Send + Sync>;
+
+static POLICY: OnceLock<RwLock<Option<IncomingTransferPolicy>>> = OnceLock::new();
+
+fn policy_slot() -> &'static RwLock<Option<IncomingTransferPolicy>> {
+    POLICY.get_or_init(|| RwLock::new(None))
+}
+
+/// 所有设备共用一个策略，会替换掉默认的大小限制
+pub fn set_incoming_transfer_policy(policy: IncomingTransferPolicy) {
+    *policy_slot()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(policy);
+}
+
+/// 恢复默认的按大小判断
+pub fn clear_incoming_transfer_policy() {
+    *policy_slot()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = None;
+}
+
+pub(super) fn current_policy() -> Option<IncomingTransferPolicy> {
+    policy_slot()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .clone()
+}
+
+pub(super) fn decide(
+    request: IncomingTransferRequest,
+    policy: Option<&IncomingTransferPolicy>,
+    max_incoming_bytes: u64,
+) -> IncomingTransferDecision {
+    if let Some(policy) = policy {
+        return match policy(request) {
+            IncomingTransferDecision::Reject(protocol::PrepareStatus::Ready) => {
+                IncomingTransferDecision::Reject(protocol::PrepareStatus::LowStorage)
+            }
+            decision => decision,
+        };
+    }
+    if request.data_length > max_incoming_bytes {
+        log::info!(
+            "[MassSystem] {} wants to push {} bytes (type {}), over the {} byte limit",
+            request.addr,
+            request.data_length,
+            request.data_type,
+            max_incoming_bytes
+        );
+        return IncomingTransferDecision::Reject(protocol::PrepareStatus::LowStorage);
+    }
+    IncomingTransferDecision::Accept
+}
+
+/// 接受时带上我们想要的分片长度，拒绝时只有状态
+pub(super) fn build_prepare_response(
+    decision: IncomingTransferDecision,
+    slice_length: u32,
+) -> protocol::WearPacket {
+    let response = match decision {
+        IncomingTransferDecision::Accept => protocol::PrepareResponse {
+            prepare_status: protocol::PrepareStatus::Ready as i32,
+            expected_slice_length: Some(slice_length),
+            ..Default::default()
+        },
+        IncomingTransferDecision::Reject(status) => protocol::PrepareResponse {
+            prepare_status: status as i32,
+            ..Default::default()
+        },
+    };
+    protocol::WearPacket {
+        r#type: protocol::wear_packet::Type::Mass as i32,
+        id: protocol::mass::MassId::Prepare as u32,
+        payload: Some(protocol::wear_packet::Payload::Mass(protocol::Mass {
+            payload: Some(protocol::mass::Payload::PrepareResponse(response)),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(data_length: u64) -> IncomingTransferRequest {
+        IncomingTransferRequest {
+            addr: "test:incoming-policy".to_string(),
+            data_type: 32,
+            data_id: vec![0xab; 16],
+            data_length,
+        }
+    }
+
+    fn status_of(packet: &protocol::WearPacket) -> (i32, Option<u32>) {
+        match &packet.payload {
+            Some(protocol::wear_packet::Payload::Mass(protocol::Mass {
+                payload: Some(protocol::mass::Payload::PrepareResponse(resp)),
+            })) => (resp.prepare_status, resp.expected_slice_length),
+            other => panic!("not a prepare response: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn default_policy_limits_size() {
+        let accepted = decide(request(1024), None, 4096);
+        assert_eq!(accepted, IncomingTransferDecision::Accept);
+        assert_eq!(
+            status_of(&build_prepare_response(accepted, 2048)),
+            (protocol::PrepareStatus::Ready as i32, Some(2048))
+        );
+
+        let rejected = decide(request(4097), None, 4096);
+        assert_eq!(
+            rejected,
+            IncomingTransferDecision::Reject(protocol::PrepareStatus::LowStorage)
+        );
+        assert_eq!(
+            status_of(&build_prepare_response(rejected, 2048)),
+            (protocol::PrepareStatus::LowStorage as i32, None)
+        );
+    }
+
+    #[test]
+    fn registered_policy_overrides_size_limit() {
+        let policy: IncomingTransferPolicy = Arc::new(|req: IncomingTransferRequest| {
+            if req.data_type == 32 {
+                IncomingTransferDecision::Accept
+            } else {
+                IncomingTransferDecision::Reject(protocol::PrepareStatus::Ready)
+            }
+        });
+        assert_eq!(
+            decide(request(1 << 30), Some(&policy), 4096),
+            IncomingTransferDecision::Accept
+        );
+        // 拒绝却给了 Ready 的不能原样回，手表会当成能传
+        let other = IncomingTransferRequest {
+            data_type: 7,
+            ..request(10)
+        };
+        assert_eq!(
+            decide(other, Some(&policy), 4096),
+            IncomingTransferDecision::Reject(protocol::PrepareStatus::LowStorage)
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! 断线自动续传：链路掉了就等同一个地址重连，再走一遍 Prepare，
+//! 设备会在 PrepareResponse 里告诉我们已经收了多少，从那接着发。
+
+use anyhow::{Context, Result};
+
+use super::{MassDataType, SendMassCallbackData, is_disconnect_error, send_file_for_owner};
+use crate::asyncrt::{Duration, sleep};
+use crate::bail_site;
+use crate::device::xiaomi::XiaomiDevice;
+use crate::device::xiaomi::components::auth::AuthComponent;
+use crate::device::xiaomi::sar::LinkState;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+const RECONNECT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone)]
+pub struct ResilientSendOptions {
+    /// 每次断开后最多等多久重连
+    pub reconnect_timeout: Duration,
+    /// 最多续传几次，防止链路一直抖的时候没完没了
+    pub max_reconnects: u32,
+}
+
+impl Default for ResilientSendOptions {
+    fn default() -> Self {
+        Self {
+            reconnect_timeout: Duration::from_secs(60),
+            max_reconnects: 5,
+        }
+    }
+}
+
+/// 和 send_file_for_owner 一样，但遇到断连会等设备重连后续传。
+/// 重连本身由宿主负责（重新 create_device 同一个 addr），这里只等结果
+pub async fn send_file_resilient<F>(
+    owner_id: String,
+    file_data: Vec<u8>,
+    data_type: MassDataType,
+    options: ResilientSendOptions,
+    progress_cb: F,
+) -> Result<()>
+where
+    F: Fn(SendMassCallbackData) + Send + Sync,
+{
+    let mut reconnects = 0u32;
+    loop {
+        let result = send_file_for_owner(owner_id.clone(), file_data.clone(), data_type, |data| {
+            progress_cb(data)
+        })
+        .await;
+        let err = match result {
+            Ok(()) => return Ok(()),
+            Err(err) if is_disconnect_error(&err) => err,
+            Err(err) => return Err(err),
+        };
+
+        if reconnects >= options.max_reconnects {
+            return Err(err).context(format!(
+                "MASS transfer to {owner_id} gave up after {reconnects} reconnects"
+            ));
+        }
+        reconnects += 1;
+        log::warn!(
+            "[Mass] {err}, waiting up to {}s for {owner_id} to reconnect ({reconnects}/{})",
+            options.reconnect_timeout.as_secs(),
+            options.max_reconnects
+        );
+
+        wait_for_reconnect(&owner_id, options.reconnect_timeout)
+            .await
+            .with_context(|| format!("MASS transfer interrupted: {err}"))?;
+        log::info!("[Mass] {owner_id} is back, resuming transfer");
+    }
+}
+
+/// 等设备重新连上并认证完成，SAR 链路正常
+async fn wait_for_reconnect(owner_id: &str, wait: Duration) -> Result<()> {
+    let deadline = Instant::now() + wait;
+    loop {
+        if is_ready(owner_id).await {
+            return Ok(());
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            bail_site!(
+                "device {} did not reconnect within {}s",
+                owner_id,
+                wait.as_secs()
+            );
+        }
+        sleep(remaining.min(RECONNECT_POLL_INTERVAL)).await;
+    }
+}
+
+async fn is_ready(owner_id: &str) -> bool {
+    let owner = owner_id.to_string();
+    crate::ecs::with_rt_read(move |rt| {
+        rt.with_device_ref(&owner, |world, entity| {
+            let authed = world
+                .get::<AuthComponent>(entity)
+                .is_some_and(|auth| auth.is_authed);
+            let link_ok = world
+                .get::<XiaomiDevice>(entity)
+                .is_some_and(|dev| dev.sar.lock().link_state() == LinkState::Active);
+            authed && link_ok
+        })
+        .unwrap_or(false)
+    })
+    .await
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::super::MassError;
+    use super::*;
+
+    #[test]
+    fn only_disconnects_are_retried() {
+        let lost: anyhow::Error = MassError::LinkLost {
+            owner_id: "dev".to_string(),
+        }
+        .into();
+        let wrapped = Err::<(), _>(lost).context("while sending").unwrap_err();
+        assert!(is_disconnect_error(&wrapped));
+        assert!(!is_disconnect_error(&crate::anyhow_site!(
+            "Mass data prepare was not READY"
+        )));
+    }
+
+    #[test]
+    fn wait_for_reconnect_times_out_without_device() {
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let started = Instant::now();
+        let result = rt.block_on(wait_for_reconnect(
+            "test:resilient-missing",
+            Duration::from_millis(300),
+        ));
+
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(300));
+    }
+}
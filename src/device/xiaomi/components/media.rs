@@ -25,7 +25,7 @@ use crate::{
             mass::MassDataType,
             v2::layer2::{L2Channel, L2OpCode},
         },
-        system::{XiaomiSystemExt, register_xiaomi_system_ext_on_l2packet},
+        system::{XiaomiSystemExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
     },
     ecs::{Component, access::with_device_component_mut},
 };
@@ -336,6 +336,7 @@ impl MediaSystem {
         if let Some(protocol::wear_packet::Payload::Media(media)) = payload.payload {
             match media.payload {
                 Some(protocol::media::Payload::SongSummary(summary)) => {
+                    mark_pb_consumed();
                     let comp_summary = summary.clone();
                     let update_res = with_device_component_mut::<MediaComponent, _, _>(
                         self.owner_id.clone(),
@@ -359,6 +360,7 @@ impl MediaSystem {
                     }
                 }
                 Some(protocol::media::Payload::MediaFileSummary(summary)) => {
+                    mark_pb_consumed();
                     let comp_summary = summary.clone();
                     let update_res = with_device_component_mut::<MediaComponent, _, _>(
                         self.owner_id.clone(),
@@ -381,18 +383,23 @@ impl MediaSystem {
                     }
                 }
                 Some(protocol::media::Payload::SongGetResponse(resp)) => {
+                    mark_pb_consumed();
                     fulfill_single_waiter(&mut self.song_page_wait, resp);
                 }
                 Some(protocol::media::Payload::SonglistResponse(resp)) => {
+                    mark_pb_consumed();
                     fulfill_single_waiter(&mut self.songlist_wait, resp);
                 }
                 Some(protocol::media::Payload::SongAddResponse(resp)) => {
+                    mark_pb_consumed();
                     fulfill_single_waiter(&mut self.song_add_wait, resp);
                 }
                 Some(protocol::media::Payload::SongRemoveResponse(resp)) => {
+                    mark_pb_consumed();
                     fulfill_single_waiter(&mut self.song_remove_wait, resp);
                 }
                 Some(protocol::media::Payload::SongReportResult(resp)) => {
+                    mark_pb_consumed();
                     fulfill_single_waiter(&mut self.song_report_wait, resp);
                 }
                 Some(protocol::media::Payload::RecordResponse(_))
@@ -491,7 +498,10 @@ impl XiaomiSystemExt for MediaSystem {
                     None => Ok(None),
                 }
             }) {
-                Ok(Some(list_payload)) => self.handle_media_file_list_payload(&list_payload),
+                Ok(Some(list_payload)) => {
+                    mark_pb_consumed();
+                    self.handle_media_file_list_payload(&list_payload)
+                }
                 Ok(None) => {
                     log::warn!(
                         "[MediaSystem] media file list report on {} did not contain payload",
@@ -1,13 +1,15 @@
 pub mod auth;
+pub mod capability;
 pub mod info;
 pub mod install;
 pub mod mass;
 pub mod media;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod network;
+pub mod notification;
 pub mod report;
 pub mod resource;
-mod shared;
+pub(crate) mod shared;
 pub mod sync;
 pub mod thirdparty_app;
 pub mod watchface;
@@ -3,6 +3,7 @@ use dhcproto::{Decodable, Encodable, v4};
 use packet_crafter::{Packet, headers::Header};
 use std::net::Ipv4Addr;
 
+use crate::constants::{DHCP_CLIENT_PORT, DHCP_SERVER_PORT};
 use crate::tools::to_hex_string;
 
 const ROUTER_ADDR: Ipv4Addr = Ipv4Addr::new(10, 1, 10, 1);
@@ -38,7 +39,32 @@ fn compute_checksum(buffer: &[u8]) -> u16 {
     !(sum as u16)
 }
 
+/// 先自己把 IPv4/UDP 头里的长度字段过一遍，长度对不上的包不交给 packet_crafter 解析，
+/// 返回 IP 头长度
+fn plausible_ipv4_udp(pkt: &[u8]) -> Option<usize> {
+    if pkt.len() < 20 || pkt[0] >> 4 != 4 {
+        return None;
+    }
+    let ihl = usize::from(pkt[0] & 0x0f) * 4;
+    if ihl < 20 || pkt.len() < ihl + 8 || pkt[9] != 17 {
+        return None;
+    }
+    let total_len = usize::from(u16::from_be_bytes([pkt[2], pkt[3]]));
+    if total_len < ihl + 8 || total_len > pkt.len() {
+        return None;
+    }
+    let udp_len = usize::from(u16::from_be_bytes([pkt[ihl + 4], pkt[ihl + 5]]));
+    if udp_len < 8 || ihl + udp_len > total_len {
+        return None;
+    }
+    Some(ihl)
+}
+
 pub fn maybe_build_reply(network_packet: &[u8]) -> Result<Option<Vec<u8>>> {
+    let Some(ip_header_len) = plausible_ipv4_udp(network_packet) else {
+        return Ok(None);
+    };
+
     let parsed = match Packet::parse(network_packet) {
         Ok(pkt) => pkt,
         Err(err) => {
@@ -47,20 +73,19 @@ pub fn maybe_build_reply(network_packet: &[u8]) -> Result<Option<Vec<u8>>> {
         }
     };
 
-    let ip = match parsed.get_ip_header() {
-        Some(header) => header,
-        None => return Ok(None),
-    };
+    if parsed.get_ip_header().is_none() {
+        return Ok(None);
+    }
     let udp = match parsed.get_udp_header() {
         Some(header) => header,
         None => return Ok(None),
     };
 
-    if *udp.get_src_port() != 68 || *udp.get_dst_port() != 67 {
+    if *udp.get_src_port() != DHCP_CLIENT_PORT || *udp.get_dst_port() != DHCP_SERVER_PORT {
         return Ok(None);
     }
 
-    let offset = (ip.get_length() + 8) as usize;
+    let offset = ip_header_len + 8;
     if network_packet.len() <= offset {
         return Ok(None);
     }
@@ -114,8 +139,8 @@ pub fn maybe_build_reply(network_packet: &[u8]) -> Result<Option<Vec<u8>>> {
     let dst_addr = ROUTER_ADDR;
 
     let mut udp_header = Vec::with_capacity(8);
-    push_u16(&mut udp_header, 0x43);
-    push_u16(&mut udp_header, 0x44);
+    push_u16(&mut udp_header, DHCP_SERVER_PORT);
+    push_u16(&mut udp_header, DHCP_CLIENT_PORT);
     push_u16(&mut udp_header, udp_len);
     push_u16(&mut udp_header, 0); // checksum placeholder
 
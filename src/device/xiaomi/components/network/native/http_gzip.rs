@@ -0,0 +1,873 @@
+//! BLE 上网只有十几二十 kB/s，明文 HTTP 的响应源站没压缩的话在手机这边顺手 gzip 一下。
+//!
+//! 只认 HTTP/1.x 的报文边界（头、Content-Length、chunked、读到关闭为止），不是完整代理：
+//! - 上行：请求没带 Accept-Encoding 就补一个 gzip，其它原样转发
+//! - 下行：源站没压缩、类型值得压的 2xx 响应才压；Content-Length 的整段攒完压好再改长度，
+//!   chunked 和读到关闭为止的边收边压
+//!
+//! 开头不像 HTTP 请求（TLS 之类）、解析出错、协议升级之后，整条连接都原样透传
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use flate2::{Compression, write::GzEncoder};
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// 头部超过这么长还没结束就不当 HTTP 看了
+const MAX_HEAD_LEN: usize = 64 * 1024;
+/// Content-Length 的响应要整段攒着压，太大的不压
+const MAX_BUFFERED_BODY: u64 = 1024 * 1024;
+/// 太小的压了也省不了几个字节
+const MIN_COMPRESS_LEN: u64 = 256;
+const CHUNK_LINE_LIMIT: usize = 4096;
+const RELAY_BUF_LEN: usize = 16 * 1024;
+
+const METHODS: [&str; 9] = [
+    "GET", "POST", "PUT", "HEAD", "DELETE", "OPTIONS", "PATCH", "TRACE", "CONNECT",
+];
+
+/// 手表 <-> 源站之间按 HTTP 改写转发，任何一边读到 EOF 就关另一边的写端，两边都结束才返回
+pub(super) async fn relay<W, O>(watch_side: &mut W, origin: &mut O) -> io::Result<()>
+where
+    W: AsyncRead + AsyncWrite + Unpin,
+    O: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut requests, mut responses) = pair();
+    let (mut watch_rx, mut watch_tx) = tokio::io::split(watch_side);
+    let (mut origin_rx, mut origin_tx) = tokio::io::split(origin);
+
+    let upstream = async {
+        let mut buf = vec![0u8; RELAY_BUF_LEN];
+        loop {
+            let n = watch_rx.read(&mut buf).await?;
+            if n == 0 {
+                origin_tx.write_all(&requests.finish()).await?;
+                return origin_tx.shutdown().await;
+            }
+            let out = requests.feed(&buf[..n]);
+            origin_tx.write_all(&out).await?;
+        }
+    };
+    let downstream = async {
+        let mut buf = vec![0u8; RELAY_BUF_LEN];
+        loop {
+            let n = origin_rx.read(&mut buf).await?;
+            if n == 0 {
+                watch_tx.write_all(&responses.finish()).await?;
+                return watch_tx.shutdown().await;
+            }
+            let out = responses.feed(&buf[..n]);
+            watch_tx.write_all(&out).await?;
+        }
+    };
+    tokio::try_join!(upstream, downstream)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Mode {
+    /// 还没看到第一个请求
+    #[default]
+    Unknown,
+    Http,
+    Passthrough,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RequestInfo {
+    is_head: bool,
+    accepts_gzip: bool,
+    /// CONNECT 或者带 Upgrade，响应成功后整条连接改透传
+    upgrade: bool,
+}
+
+/// 上下行共享：连接是不是 HTTP、还没等到响应的请求
+#[derive(Default)]
+struct Shared {
+    mode: Mode,
+    requests: VecDeque<RequestInfo>,
+}
+
+fn pair() -> (RequestRewriter, ResponseCompressor) {
+    let shared = Arc::new(Mutex::new(Shared::default()));
+    (
+        RequestRewriter {
+            shared: shared.clone(),
+            buf: Vec::new(),
+            state: ReqState::Head,
+        },
+        ResponseCompressor {
+            shared,
+            buf: Vec::new(),
+            state: RespState::Head,
+        },
+    )
+}
+
+/// 一个请求/响应头。只在头部都是 ASCII/UTF-8 时解析，否则当不是 HTTP
+struct Head {
+    start_line: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// `raw` 不含结尾的空行
+    fn parse(raw: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(raw).ok()?;
+        let mut lines = text.split("\r\n");
+        let start_line = lines.next()?.to_string();
+        let mut headers = Vec::new();
+        for line in lines {
+            // 折行头早就废弃了，碰到就不碰这条连接
+            if line.starts_with([' ', '\t']) {
+                return None;
+            }
+            let (name, value) = line.split_once(':')?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Some(Self {
+            start_line,
+            headers,
+        })
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case(name))
+            .flat_map(|(_, v)| v.split(','))
+            .any(|t| {
+                t.split(';')
+                    .next()
+                    .is_some_and(|t| t.trim().eq_ignore_ascii_case(token))
+            })
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    fn push(&mut self, name: &str, value: impl Into<String>) {
+        self.headers.push((name.to_string(), value.into()));
+    }
+
+    /// 多个不一致的 Content-Length 或者不是数字都算坏报文
+    fn content_length(&self) -> Result<Option<u64>, ()> {
+        let mut found = None;
+        for (_, value) in self
+            .headers
+            .iter()
+            .filter(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+        {
+            let len = value.parse::<u64>().map_err(|_| ())?;
+            if found.is_some_and(|prev| prev != len) {
+                return Err(());
+            }
+            found = Some(len);
+        }
+        Ok(found)
+    }
+
+    fn is_chunked(&self) -> bool {
+        self.has_token("transfer-encoding", "chunked")
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.start_line.as_bytes());
+        out.extend_from_slice(b"\r\n");
+        for (name, value) in &self.headers {
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b": ");
+            out.extend_from_slice(value.as_bytes());
+            out.extend_from_slice(b"\r\n");
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+/// 在 buf 里找头部结束位置，返回 (头部长度, 含空行的总长度)
+fn find_head_end(buf: &[u8]) -> Option<(usize, usize)> {
+    buf.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| (pos, pos + 4))
+}
+
+/// 流式拆 chunked 编码，只管边界，数据交给调用方
+#[derive(Debug, Default)]
+struct ChunkedDecoder {
+    state: ChunkState,
+    line: Vec<u8>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+enum ChunkState {
+    #[default]
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+impl ChunkedDecoder {
+    /// 返回 (吃掉的字节数, 是否到结尾)，格式不对返回 None。到结尾后剩下的字节属于下一个报文
+    fn feed(&mut self, input: &[u8], data: &mut Vec<u8>) -> Option<(usize, bool)> {
+        let mut i = 0;
+        while i < input.len() {
+            match self.state {
+                ChunkState::Done => break,
+                ChunkState::Data(remaining) => {
+                    let take = remaining.min((input.len() - i) as u64) as usize;
+                    data.extend_from_slice(&input[i..i + take]);
+                    i += take;
+                    let remaining = remaining - take as u64;
+                    self.state = if remaining == 0 {
+                        ChunkState::DataEnd
+                    } else {
+                        ChunkState::Data(remaining)
+                    };
+                }
+                ChunkState::Size | ChunkState::DataEnd | ChunkState::Trailer => {
+                    let Some(pos) = input[i..].iter().position(|&b| b == b'\n') else {
+                        self.line.extend_from_slice(&input[i..]);
+                        if self.line.len() > CHUNK_LINE_LIMIT {
+                            return None;
+                        }
+                        return Some((input.len(), false));
+                    };
+                    self.line.extend_from_slice(&input[i..i + pos]);
+                    i += pos + 1;
+                    let line = self.line.strip_suffix(b"\r").unwrap_or(&self.line);
+                    self.state = match self.state {
+                        ChunkState::Size => {
+                            let text = std::str::from_utf8(line).ok()?;
+                            let size = text.split(';').next()?.trim();
+                            match u64::from_str_radix(size, 16).ok()? {
+                                0 => ChunkState::Trailer,
+                                n => ChunkState::Data(n),
+                            }
+                        }
+                        ChunkState::DataEnd if line.is_empty() => ChunkState::Size,
+                        ChunkState::DataEnd => return None,
+                        ChunkState::Trailer if line.is_empty() => ChunkState::Done,
+                        state => state,
+                    };
+                    self.line.clear();
+                }
+            }
+        }
+        Some((i, matches!(self.state, ChunkState::Done)))
+    }
+}
+
+/// 不改内容的报文体
+#[derive(Debug)]
+enum Framing {
+    Length(u64),
+    Chunked(ChunkedDecoder),
+    /// 读到连接关闭为止
+    Close,
+}
+
+impl Framing {
+    /// 原样转发，返回 (吃掉的字节数, 报文体是否结束)
+    fn forward(&mut self, input: &[u8], out: &mut Vec<u8>) -> Option<(usize, bool)> {
+        let (used, done) = match self {
+            Self::Length(remaining) => {
+                let take = (*remaining).min(input.len() as u64);
+                *remaining -= take;
+                (take as usize, *remaining == 0)
+            }
+            Self::Chunked(decoder) => decoder.feed(input, &mut Vec::new())?,
+            Self::Close => (input.len(), false),
+        };
+        out.extend_from_slice(&input[..used]);
+        Some((used, done))
+    }
+}
+
+/// 看开头像不像 HTTP 请求，字节还不够判断时返回 None
+fn sniff_request(buf: &[u8]) -> Option<bool> {
+    let mut undecided = false;
+    for method in METHODS {
+        let token = [method.as_bytes(), b" "].concat();
+        if buf.starts_with(&token) {
+            return Some(true);
+        }
+        if token.starts_with(buf) {
+            undecided = true;
+        }
+    }
+    if undecided { None } else { Some(false) }
+}
+
+enum ReqState {
+    Head,
+    Body(Framing),
+    Passthrough,
+}
+
+/// 上行：补 Accept-Encoding，记下每个请求等响应时要用的信息
+struct RequestRewriter {
+    shared: Arc<Mutex<Shared>>,
+    buf: Vec<u8>,
+    state: ReqState,
+}
+
+impl RequestRewriter {
+    fn feed(&mut self, input: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(input);
+        let mut out = Vec::with_capacity(self.buf.len() + 32);
+        loop {
+            match &mut self.state {
+                ReqState::Passthrough => {
+                    out.append(&mut self.buf);
+                    break;
+                }
+                ReqState::Body(framing) => {
+                    let Some((used, done)) = framing.forward(&self.buf, &mut out) else {
+                        self.passthrough();
+                        continue;
+                    };
+                    self.buf.drain(..used);
+                    if !done {
+                        break;
+                    }
+                    self.state = ReqState::Head;
+                }
+                ReqState::Head => {
+                    if self.buf.is_empty() || !self.take_head(&mut out) {
+                        break;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buf)
+    }
+
+    fn passthrough(&mut self) {
+        self.state = ReqState::Passthrough;
+        let mut shared = self.shared.lock();
+        if shared.mode == Mode::Unknown {
+            shared.mode = Mode::Passthrough;
+        }
+    }
+
+    /// 处理一个请求头，字节不够返回 false
+    fn take_head(&mut self, out: &mut Vec<u8>) -> bool {
+        let mode = self.shared.lock().mode;
+        let looks_like_http = match mode {
+            Mode::Http => Some(true),
+            // 服务器先开口的协议，下行那边已经认定了
+            Mode::Passthrough => Some(false),
+            Mode::Unknown => sniff_request(&self.buf),
+        };
+        match looks_like_http {
+            None => return false,
+            Some(false) => {
+                self.passthrough();
+                return true;
+            }
+            Some(true) => {}
+        }
+        let Some((head_len, total_len)) = find_head_end(&self.buf) else {
+            if self.buf.len() > MAX_HEAD_LEN {
+                self.passthrough();
+                return true;
+            }
+            return false;
+        };
+        let Some(mut head) = Head::parse(&self.buf[..head_len]) else {
+            self.passthrough();
+            return true;
+        };
+        let Ok(content_length) = head.content_length() else {
+            self.passthrough();
+            return true;
+        };
+
+        let method = head.start_line.split(' ').next().unwrap_or_default();
+        let upgrade = method == "CONNECT" || head.get("upgrade").is_some();
+        let info = RequestInfo {
+            is_head: method == "HEAD",
+            accepts_gzip: match head.get("accept-encoding") {
+                None => true,
+                Some(_) => head.has_token("accept-encoding", "gzip"),
+            },
+            upgrade,
+        };
+        if head.get("accept-encoding").is_none() {
+            head.push("Accept-Encoding", "gzip");
+        }
+        let framing = if head.is_chunked() {
+            Some(Framing::Chunked(ChunkedDecoder::default()))
+        } else {
+            content_length.filter(|&len| len > 0).map(Framing::Length)
+        };
+
+        {
+            let mut shared = self.shared.lock();
+            shared.mode = Mode::Http;
+            shared.requests.push_back(info);
+        }
+        head.encode(out);
+        self.buf.drain(..total_len);
+        self.state = match framing {
+            // 升级之后的字节不再是 HTTP
+            _ if upgrade => ReqState::Passthrough,
+            Some(framing) => ReqState::Body(framing),
+            None => ReqState::Head,
+        };
+        true
+    }
+}
+
+enum RespState {
+    Head,
+    Body(Framing),
+    /// Content-Length 的整段攒着，压完比原来小才替换
+    GzipLength {
+        head: Head,
+        raw: Vec<u8>,
+        remaining: u64,
+    },
+    GzipChunked {
+        decoder: ChunkedDecoder,
+        gz: GzEncoder<Vec<u8>>,
+    },
+    GzipClose {
+        gz: GzEncoder<Vec<u8>>,
+    },
+    Passthrough,
+}
+
+/// 下行：挑值得压的响应压掉
+struct ResponseCompressor {
+    shared: Arc<Mutex<Shared>>,
+    buf: Vec<u8>,
+    state: RespState,
+}
+
+impl ResponseCompressor {
+    fn feed(&mut self, input: &[u8]) -> Vec<u8> {
+        self.buf.extend_from_slice(input);
+        let mut out = Vec::with_capacity(self.buf.len());
+        loop {
+            match &mut self.state {
+                RespState::Passthrough => {
+                    out.append(&mut self.buf);
+                    break;
+                }
+                RespState::Body(framing) => {
+                    let Some((used, done)) = framing.forward(&self.buf, &mut out) else {
+                        self.passthrough();
+                        continue;
+                    };
+                    self.buf.drain(..used);
+                    if !done {
+                        break;
+                    }
+                    self.state = RespState::Head;
+                }
+                RespState::GzipLength {
+                    head,
+                    raw,
+                    remaining,
+                } => {
+                    let take = (*remaining).min(self.buf.len() as u64) as usize;
+                    raw.extend(self.buf.drain(..take));
+                    *remaining -= take as u64;
+                    if *remaining > 0 {
+                        break;
+                    }
+                    encode_buffered(head, raw, &mut out);
+                    self.state = RespState::Head;
+                }
+                RespState::GzipChunked { decoder, gz } => {
+                    let mut data = Vec::new();
+                    let Some((used, done)) = decoder.feed(&self.buf, &mut data) else {
+                        // 已经发出去一部分压缩数据了，后面只能原样给
+                        self.passthrough();
+                        continue;
+                    };
+                    self.buf.drain(..used);
+                    let _ = gz.write_all(&data);
+                    if done {
+                        let gz = std::mem::replace(
+                            gz,
+                            GzEncoder::new(Vec::new(), Compression::default()),
+                        );
+                        push_chunk(&mut out, &gz.finish().unwrap_or_default());
+                        out.extend_from_slice(b"0\r\n\r\n");
+                        self.state = RespState::Head;
+                        continue;
+                    }
+                    // 每次收到就刷出去，别让手表干等
+                    let _ = gz.flush();
+                    push_chunk(&mut out, &std::mem::take(gz.get_mut()));
+                    break;
+                }
+                RespState::GzipClose { gz } => {
+                    let _ = gz.write_all(&self.buf);
+                    self.buf.clear();
+                    let _ = gz.flush();
+                    out.append(gz.get_mut());
+                    break;
+                }
+                RespState::Head => {
+                    if self.buf.is_empty() || !self.take_head(&mut out) {
+                        break;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// 源站关了连接
+    fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match std::mem::replace(&mut self.state, RespState::Passthrough) {
+            RespState::GzipClose { gz } => out.extend(gz.finish().unwrap_or_default()),
+            // 截断了，能给多少给多少
+            RespState::GzipLength { head, raw, .. } => {
+                head.encode(&mut out);
+                out.extend(raw);
+            }
+            RespState::GzipChunked { gz, .. } => {
+                push_chunk(&mut out, &gz.finish().unwrap_or_default());
+            }
+            _ => {}
+        }
+        out.append(&mut self.buf);
+        out
+    }
+
+    fn passthrough(&mut self) {
+        self.state = RespState::Passthrough;
+        self.shared.lock().mode = Mode::Passthrough;
+    }
+
+    /// 处理一个响应头，字节不够返回 false
+    fn take_head(&mut self, out: &mut Vec<u8>) -> bool {
+        if self.shared.lock().mode != Mode::Http {
+            // 服务器先说话，或者上行已经认定不是 HTTP
+            self.passthrough();
+            return true;
+        }
+        let Some((head_len, total_len)) = find_head_end(&self.buf) else {
+            if self.buf.len() > MAX_HEAD_LEN {
+                self.passthrough();
+                return true;
+            }
+            return false;
+        };
+        let parsed = Head::parse(&self.buf[..head_len]).and_then(|head| {
+            let status = parse_status(&head.start_line)?;
+            let content_length = head.content_length().ok()?;
+            Some((head, status, content_length))
+        });
+        let Some((mut head, status, content_length)) = parsed else {
+            self.passthrough();
+            return true;
+        };
+        self.buf.drain(..total_len);
+
+        // 1xx 不消耗请求（101 除外）
+        let request = if (100..200).contains(&status) && status != 101 {
+            None
+        } else {
+            self.shared.lock().requests.pop_front()
+        };
+        let is_head = request.is_some_and(|r| r.is_head);
+        let accepts_gzip = request.is_some_and(|r| r.accepts_gzip);
+
+        if status == 101 || request.is_some_and(|r| r.upgrade && (200..300).contains(&status)) {
+            head.encode(out);
+            self.passthrough();
+            return true;
+        }
+
+        let framing = if is_head || (100..200).contains(&status) || status == 204 || status == 304 {
+            None
+        } else if head.is_chunked() {
+            Some(Framing::Chunked(ChunkedDecoder::default()))
+        } else if let Some(len) = content_length {
+            Some(Framing::Length(len))
+        } else {
+            Some(Framing::Close)
+        };
+
+        let compress = accepts_gzip
+            && (200..300).contains(&status)
+            && status != 206
+            && head
+                .get("content-encoding")
+                .is_none_or(|enc| enc.eq_ignore_ascii_case("identity"))
+            && head.get("content-range").is_none()
+            && head.get("content-type").is_some_and(is_compressible);
+
+        self.state = match framing {
+            None => {
+                head.encode(out);
+                RespState::Head
+            }
+            Some(Framing::Length(len))
+                if compress && (MIN_COMPRESS_LEN..=MAX_BUFFERED_BODY).contains(&len) =>
+            {
+                RespState::GzipLength {
+                    head,
+                    raw: Vec::with_capacity(len as usize),
+                    remaining: len,
+                }
+            }
+            Some(Framing::Chunked(decoder)) if compress => {
+                head.remove("content-encoding");
+                head.push("Content-Encoding", "gzip");
+                head.encode(out);
+                RespState::GzipChunked {
+                    decoder,
+                    gz: GzEncoder::new(Vec::new(), Compression::default()),
+                }
+            }
+            Some(Framing::Close) if compress => {
+                head.remove("content-encoding");
+                head.push("Content-Encoding", "gzip");
+                head.encode(out);
+                RespState::GzipClose {
+                    gz: GzEncoder::new(Vec::new(), Compression::default()),
+                }
+            }
+            Some(Framing::Length(0)) => {
+                head.encode(out);
+                RespState::Head
+            }
+            Some(framing) => {
+                head.encode(out);
+                RespState::Body(framing)
+            }
+        };
+        true
+    }
+}
+
+fn parse_status(start_line: &str) -> Option<u16> {
+    let mut parts = start_line.split(' ');
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+fn is_compressible(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime.starts_with("text/")
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+        || matches!(
+            mime.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/x-www-form-urlencoded"
+                | "image/svg+xml"
+        )
+}
+
+/// 攒齐的 Content-Length 响应：压完更小就改头发压缩版，否则原样发
+fn encode_buffered(head: &mut Head, raw: &mut Vec<u8>, out: &mut Vec<u8>) {
+    let mut gz = GzEncoder::new(Vec::with_capacity(raw.len() / 2), Compression::default());
+    let compressed = gz.write_all(raw).and_then(|_| gz.finish());
+    match compressed {
+        Ok(compressed) if compressed.len() < raw.len() => {
+            head.remove("content-length");
+            head.remove("content-encoding");
+            head.push("Content-Encoding", "gzip");
+            head.push("Content-Length", compressed.len().to_string());
+            head.encode(out);
+            out.extend(compressed);
+        }
+        _ => {
+            head.encode(out);
+            out.append(raw);
+        }
+    }
+}
+
+fn push_chunk(out: &mut Vec<u8>, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    out.extend_from_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    const TEXT_BODY: &str = include_str!("../../../../../../LICENSE");
+
+    /// 按很小的片喂进去，模拟 TCP 随便切
+    fn run(feed: impl FnMut(&[u8]) -> Vec<u8>, input: &[u8], step: usize) -> Vec<u8> {
+        input.chunks(step).flat_map(feed).collect()
+    }
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut text = String::new();
+        GzDecoder::new(data).read_to_string(&mut text).unwrap();
+        text
+    }
+
+    fn split_response(raw: &[u8]) -> (Head, &[u8]) {
+        let (head_len, total_len) = find_head_end(raw).unwrap();
+        (Head::parse(&raw[..head_len]).unwrap(), &raw[total_len..])
+    }
+
+    fn get_request(extra: &str) -> String {
+        format!("GET /readme HTTP/1.1\r\nHost: example.com\r\n{extra}\r\n")
+    }
+
+    #[test]
+    fn request_gets_accept_encoding_only_when_missing() {
+        let (mut req, _) = pair();
+        let out = run(|b| req.feed(b), get_request("").as_bytes(), 3);
+        let (head, _) = split_response(&out);
+        assert_eq!(head.get("accept-encoding"), Some("gzip"));
+
+        let (mut req, _) = pair();
+        let original = get_request("Accept-Encoding: identity\r\n");
+        let out = run(|b| req.feed(b), original.as_bytes(), 5);
+        assert_eq!(out, original.as_bytes());
+    }
+
+    #[test]
+    fn content_length_text_is_gzipped() {
+        let (mut req, mut resp) = pair();
+        req.feed(get_request("").as_bytes());
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}",
+            TEXT_BODY.len(),
+            TEXT_BODY
+        );
+        let out = run(|b| resp.feed(b), response.as_bytes(), 700);
+        let (head, body) = split_response(&out);
+        assert_eq!(head.get("content-encoding"), Some("gzip"));
+        assert_eq!(head.content_length(), Ok(Some(body.len() as u64)));
+        assert!(body.len() < TEXT_BODY.len());
+        assert_eq!(gunzip(body), TEXT_BODY);
+    }
+
+    #[test]
+    fn chunked_text_is_gzipped_on_the_fly() {
+        let (mut req, mut resp) = pair();
+        req.feed(get_request("").as_bytes());
+        let mut response =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nTransfer-Encoding: chunked\r\n\r\n"
+                .to_vec();
+        for part in TEXT_BODY.as_bytes().chunks(1000) {
+            push_chunk(&mut response, part);
+        }
+        response.extend_from_slice(b"0\r\nX-Trailer: 1\r\n\r\n");
+
+        let out = run(|b| resp.feed(b), &response, 333);
+        let (head, body) = split_response(&out);
+        assert_eq!(head.get("content-encoding"), Some("gzip"));
+        assert!(head.is_chunked());
+        let mut decoder = ChunkedDecoder::default();
+        let mut compressed = Vec::new();
+        assert_eq!(
+            decoder.feed(body, &mut compressed),
+            Some((body.len(), true))
+        );
+        assert_eq!(gunzip(&compressed), TEXT_BODY);
+    }
+
+    #[test]
+    fn keep_alive_exchange_stays_in_sync() {
+        let (mut req, mut resp) = pair();
+        let requests = format!(
+            "HEAD /a HTTP/1.1\r\nHost: x\r\n\r\nPOST /b HTTP/1.1\r\nHost: x\r\nContent-Length: 4\r\n\r\nping{}",
+            get_request("")
+        );
+        let out = run(|b| req.feed(b), requests.as_bytes(), 7);
+        assert_eq!(out.windows(4).filter(|w| *w == b"ping").count(), 1);
+        assert_eq!(shared_len(&resp), 3);
+
+        // HEAD 的响应带 Content-Length 但没有报文体；第二个是图片不压
+        let responses = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 5000\r\n\r\n\
+             HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 300\r\n\r\n{}\
+             HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n\r\n{}",
+            "p".repeat(300),
+            TEXT_BODY.len(),
+            TEXT_BODY
+        );
+        let out = run(|b| resp.feed(b), responses.as_bytes(), 50);
+        let (first, rest) = split_response(&out);
+        assert_eq!(first.get("content-encoding"), None);
+        let (second, rest) = split_response(rest);
+        assert_eq!(second.get("content-encoding"), None);
+        assert_eq!(&rest[..300], "p".repeat(300).as_bytes());
+        let (third, body) = split_response(&rest[300..]);
+        assert_eq!(third.get("content-encoding"), Some("gzip"));
+        assert_eq!(gunzip(body), TEXT_BODY);
+        assert_eq!(shared_len(&resp), 0);
+    }
+
+    fn shared_len(resp: &ResponseCompressor) -> usize {
+        resp.shared.lock().requests.len()
+    }
+
+    #[test]
+    fn already_encoded_and_non_http_pass_through() {
+        let (mut req, mut resp) = pair();
+        req.feed(get_request("").as_bytes());
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Encoding: br\r\nContent-Length: {}\r\n\r\n{}",
+            TEXT_BODY.len(),
+            TEXT_BODY
+        );
+        let out = run(|b| resp.feed(b), response.as_bytes(), 100);
+        assert_eq!(out, response.as_bytes());
+
+        // TLS ClientHello 开头
+        let (mut req, mut resp) = pair();
+        let hello = [0x16, 0x03, 0x01, 0x02, 0x00, 0x01, 0x00];
+        assert_eq!(req.feed(&hello), hello);
+        assert_eq!(resp.feed(b"\x16\x03\x03"), b"\x16\x03\x03");
+    }
+
+    #[test]
+    fn close_delimited_body_is_finished_on_eof() {
+        let (mut req, mut resp) = pair();
+        req.feed(get_request("").as_bytes());
+        let response = format!("HTTP/1.0 200 OK\r\nContent-Type: text/css\r\n\r\n{TEXT_BODY}");
+        let mut out = run(|b| resp.feed(b), response.as_bytes(), 512);
+        out.extend(resp.finish());
+        let (head, body) = split_response(&out);
+        assert_eq!(head.get("content-encoding"), Some("gzip"));
+        assert_eq!(gunzip(body), TEXT_BODY);
+    }
+}
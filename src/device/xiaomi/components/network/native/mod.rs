@@ -9,15 +9,15 @@ use std::{
 };
 
 use anyhow::Result;
+use async_trait::async_trait;
 use chrono::Local;
 use etherparse::{Icmpv4Header, Icmpv4Type};
 use ipstack::{IpNumber, IpStack, IpStackConfig, IpStackStream};
 use pb::xiaomi::protocol;
-use pcap_file::pcap::PcapWriter;
+use prost::Message;
 use tokio::sync::mpsc::error::TrySendError;
 use tokio::{
     io::{self, AsyncWriteExt},
-    net::TcpStream,
     runtime::Handle,
     sync::{mpsc, watch},
 };
@@ -27,25 +27,39 @@ use udp_stream::UdpStream;
 use crate::{
     anyhow_site,
     device::xiaomi::{
-        XiaomiDevice,
+        self,
+        components::shared::{HasOwnerId, SystemRequestExt},
         config::NetworkConfig,
         packet::{
             self,
-            v2::layer2::{L2Channel, L2OpCode, L2Packet},
+            v2::layer2::{L2Channel, L2OpCode},
         },
         system::{XiaomiSystemExt, register_xiaomi_system_ext_on_l2packet},
     },
-    ecs::{Component, access::with_device_component_mut},
+    ecs::Component,
 };
 use parking_lot::Mutex;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
 mod dhcp;
+mod http_gzip;
 mod meter;
+mod pumps;
+mod session;
 mod tun;
 
-use dhcp::maybe_build_reply;
+// fuzz/ 下的 dhcp 目标要直接调
+#[cfg(feature = "fuzzing")]
+pub use dhcp::maybe_build_reply as fuzz_dhcp_reply;
 use meter::BandwidthMeter;
-use tun::MiWearTunDevice;
+use pumps::{EgressPump, IngressPump, PacketStack, PayloadSink, StackDriver};
+pub use session::{ConnectFailure, FailedSession, SessionProto};
+use session::{ConnectRetry, SessionTable, connect_or_close, connect_within};
+use tun::{MiWearTunDevice, PacketCapture};
 
 #[derive(Clone, Copy, Debug, Default, serde::Serialize)]
 pub struct NetWorkSpeed {
@@ -69,11 +83,24 @@ impl NetworkComponent {
     }
 }
 
+/// 上报给设备的联网能力值
+pub const NETWORK_STATUS_CAPABILITY: u32 = 2;
+/// 相同状态在这个窗口内重复同步会被直接跳过，防止重连风暴刷爆控制通道
+const NETWORK_STATUS_DEDUP_WINDOW: Duration = Duration::from_secs(3);
+
 #[derive(Component)]
 pub struct NetworkSystem {
     owner_id: String,
     runtime: Mutex<Option<NetworkRuntime>>,
     meter: Mutex<Option<BandwidthMeter>>,
+    last_status_sync: Option<StatusSync>,
+}
+
+/// 上一次真正发出去的联网状态同步，去重按整个请求的编码比
+struct StatusSync {
+    request: Vec<u8>,
+    capability: u32,
+    sent_at: Instant,
 }
 
 impl Default for NetworkSystem {
@@ -82,6 +109,12 @@ impl Default for NetworkSystem {
     }
 }
 
+impl HasOwnerId for NetworkSystem {
+    fn owner_id(&self) -> &str {
+        &self.owner_id
+    }
+}
+
 impl NetworkSystem {
     pub fn new(owner_id: String) -> Self {
         register_xiaomi_system_ext_on_l2packet::<Self>();
@@ -89,12 +122,27 @@ impl NetworkSystem {
             owner_id,
             runtime: Mutex::new(None),
             meter: Mutex::new(None),
+            last_status_sync: None,
         }
     }
 
+    /// 协议栈已经在跑就什么都不做。宿主重建过 tokio runtime 的话（比如 Android 的
+    /// Activity 重启），老 runtime 上的任务已经没了，这时拆掉残骸在 `handle` 上重建
     pub fn ensure_runtime(&mut self, handle: Handle, config: NetworkConfig) -> Result<()> {
-        if self.runtime.lock().is_some() {
-            return Ok(());
+        {
+            let mut runtime = self.runtime.lock();
+            match runtime.as_ref() {
+                Some(existing) if existing.is_alive() => return Ok(()),
+                Some(_) => {
+                    log::warn!(
+                        "[NetworkSystem] network stack for {} lost its tokio runtime, rebuilding",
+                        self.owner_id
+                    );
+                    // Drop 里会 abort 掉剩下的任务
+                    runtime.take();
+                }
+                None => {}
+            }
         }
         if self.owner_id.is_empty() {
             return Err(anyhow_site!("NetworkSystem missing owner"));
@@ -107,19 +155,83 @@ impl NetworkSystem {
         Ok(())
     }
 
-    pub fn sync_network_status(&mut self) -> Result<()> {
-        with_device_component_mut::<XiaomiDevice, _, _>(self.owner_id.clone(), |dev| {
-            packet::cipher::enqueue_pb_packet(
-                dev,
-                build_sync_network_status(),
-                "NetworkComponent::sync_network_status",
+    /// 拆掉现有的协议栈（如果有）再重新拉起来，给启动时失败或者跑挂了的情况用
+    pub fn restart_stack(&mut self, handle: Handle, config: NetworkConfig) -> Result<()> {
+        if let Some(old) = self.runtime.lock().take() {
+            log::info!(
+                "[NetworkSystem] tearing down network stack for {}",
+                self.owner_id
             );
-        })
-        .map_err(|err| anyhow_site!("failed to access resource config: {:?}", err))?;
+            // Drop 里会通知 shutdown 并 abort 掉所有任务
+            drop(old);
+        }
+        self.ensure_runtime(handle, config)?;
+        log::info!(
+            "[NetworkSystem] network stack restarted for {}",
+            self.owner_id
+        );
+        Ok(())
+    }
+
+    /// 真的在跑：建过协议栈，而且它的任务都还活着（宿主的 tokio runtime 没被换掉）
+    pub fn is_running(&self) -> bool {
+        self.runtime
+            .lock()
+            .as_ref()
+            .is_some_and(NetworkRuntime::is_alive)
+    }
+
+    pub fn is_stack_running(&self) -> bool {
+        self.is_running()
+    }
+
+    /// 同步网络状态，短时间内重复同步相同状态会被跳过，返回是否真的发了包
+    pub fn sync_network_status(&mut self) -> Result<bool> {
+        Ok(self.sync_status(NETWORK_STATUS_CAPABILITY, false))
+    }
 
+    /// 无视去重强制同步一次
+    pub fn force_sync_network_status(&mut self) -> Result<()> {
+        self.sync_status(NETWORK_STATUS_CAPABILITY, true);
         Ok(())
     }
 
+    pub fn last_synced_capability(&self) -> Option<u32> {
+        self.last_status_sync.as_ref().map(|last| last.capability)
+    }
+
+    /// 要发的包和上次一模一样、离上次又不到去重窗口才跳过，包里内容变了照发
+    fn sync_status(&mut self, capability: u32, force: bool) -> bool {
+        let packet = build_sync_network_status(capability);
+        let request = packet.encode_to_vec();
+        if !force {
+            if let Some(last) = &self.last_status_sync {
+                if last.request == request && last.sent_at.elapsed() < NETWORK_STATUS_DEDUP_WINDOW {
+                    log::debug!(
+                        "[NetworkSystem] network status unchanged ({capability}), skipping redundant sync"
+                    );
+                    return false;
+                }
+            }
+        }
+        self.enqueue_pb_request(packet, "NetworkComponent::sync_network_status");
+        self.last_status_sync = Some(StatusSync {
+            request,
+            capability,
+            sent_at: Instant::now(),
+        });
+        true
+    }
+
+    /// 最近代手表连不上的目标，按时间先后排
+    pub fn connect_failures(&self) -> Vec<FailedSession> {
+        self.runtime
+            .lock()
+            .as_ref()
+            .map(|runtime| runtime.sessions.failures())
+            .unwrap_or_default()
+    }
+
     pub fn get_speed(&self) -> NetWorkSpeed {
         let meter = self.meter.lock().as_ref().unwrap().clone();
         NetWorkSpeed {
@@ -164,8 +276,10 @@ impl XiaomiSystemExt for NetworkSystem {
     }
 }
 
-fn build_sync_network_status() -> protocol::WearPacket {
-    let network_status = protocol::NetworkStatus { capability: 2 };
+fn build_sync_network_status(capability: u32) -> protocol::WearPacket {
+    let network_status = protocol::NetworkStatus {
+        capability: capability as _,
+    };
 
     let pkt_payload = protocol::System {
         payload: Some(protocol::system::Payload::NetworkStatus(network_status)),
@@ -182,6 +296,7 @@ fn build_sync_network_status() -> protocol::WearPacket {
 
 struct NetworkRuntime {
     ingress_tx: mpsc::Sender<Vec<u8>>,
+    sessions: Arc<SessionTable>,
     shutdown: watch::Sender<bool>,
     tasks: Vec<crate::asyncrt::TaskHandle>,
 }
@@ -197,235 +312,73 @@ impl NetworkRuntime {
         let tun_capacity = config.tun_buffer.max(1);
         let outbound_capacity = config.outbound_buffer.max(1);
 
-        let (ingress_tx, mut ingress_rx) = mpsc::channel::<Vec<u8>>(ingress_capacity);
+        let (ingress_tx, ingress_rx) = mpsc::channel::<Vec<u8>>(ingress_capacity);
         let (tun_tx, tun_rx) = mpsc::channel::<Vec<u8>>(tun_capacity);
-        let (send_tx, mut send_rx) = mpsc::channel::<Vec<u8>>(outbound_capacity);
+        let (send_tx, send_rx) = mpsc::channel::<Vec<u8>>(outbound_capacity);
         let capture = prepare_capture_writer(&owner, &config);
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let sessions = Arc::new(SessionTable::default());
+        let connect_timeout = Duration::from_secs(config.connect_timeout_secs.max(1));
+        let connect_retry = ConnectRetry::from_config(&config);
+        let http_compression = config.http_compression;
 
         let mut tasks = Vec::new();
 
         // 入口循环：设备 -> 协议栈（带 DHCP 处理）
-        {
-            let owner_clone = owner.clone();
-            let mut shutdown = shutdown_rx.clone();
-            tasks.push(crate::asyncrt::spawn_with_handle(
-                async move {
-                    loop {
-                        tokio::select! {
-                            packet = ingress_rx.recv() => {
-                                match packet {
-                                    Some(data) => {
-                                        match maybe_build_reply(&data) {
-                                            Ok(Some(reply)) => {
-                                                if let Err(err) = enqueue_network_payload(&owner_clone, reply).await {
-                                                    log::error!("[NetworkRuntime] failed to send DHCP reply: {err:?}");
-                                                }
-                                                continue;
-                                            }
-                                            Ok(None) => {}
-                                            Err(err) => log::warn!("[NetworkRuntime] DHCP parse error: {err:?}"),
-                                        }
-
-                                        if let Err(err) = tun_tx.send(data).await {
-                                            log::warn!("[NetworkRuntime] tun channel closed: {err}");
-                                            break;
-                                        }
-                                    }
-                                    None => break,
-                                }
-                            }
-                            changed = shutdown.changed() => {
-                                if changed.is_ok() {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                },
-                handle.clone(),
-            ));
-        }
+        let ingress = IngressPump::new(
+            ingress_rx,
+            tun_tx,
+            DeviceEnqueue::new(&owner),
+            shutdown_rx.clone(),
+        );
+        tasks.push(crate::asyncrt::spawn_with_handle(
+            ingress.run(),
+            handle.clone(),
+        ));
 
         // 发送循环：协议栈 -> 设备
-        {
-            let owner_clone = owner.clone();
-            let mut shutdown = shutdown_rx.clone();
-            tasks.push(crate::asyncrt::spawn_with_handle(
-                async move {
-                    loop {
-                        tokio::select! {
-                            packet = send_rx.recv() => {
-                                match packet {
-                                    Some(payload) => {
-                                        if let Err(err) = enqueue_network_payload(&owner_clone, payload).await {
-                                            log::error!("[NetworkRuntime] failed to send network payload: {err:?}");
-                                            break;
-                                        }
-                                    }
-                                    None => break,
-                                }
-                            }
-                            changed = shutdown.changed() => {
-                                if changed.is_ok() {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                },
-                handle.clone(),
-            ));
-        }
+        let egress = EgressPump::new(send_rx, DeviceEnqueue::new(&owner), shutdown_rx.clone());
+        tasks.push(crate::asyncrt::spawn_with_handle(
+            egress.run(),
+            handle.clone(),
+        ));
 
-        // IpStack 处理循环
+        // IpStack 处理循环，IpStack 要在 tokio 上下文里建，放进任务里
         {
-            let owner_clone = owner.clone();
-            let mut shutdown = shutdown_rx.clone();
             let meter_for_stack = meter.clone();
-            let capture = capture;
-            let send_tx_clone = send_tx.clone();
-            let config_for_stack = config.clone();
+            let sessions = sessions.clone();
             tasks.push(crate::asyncrt::spawn_with_handle(
                 async move {
-                    let session_count = Arc::new(AtomicUsize::new(0));
-                    let serial = Arc::new(AtomicUsize::new(0));
-                    let poll_sender = PollSender::new(send_tx_clone);
-                    let tun_device = MiWearTunDevice {
-                        rx: tun_rx,
-                        tx_send: poll_sender,
+                    let tun_device = MiWearTunDevice::new(
+                        tun_rx,
+                        PollSender::new(send_tx),
                         capture,
-                        meter: meter_for_stack,
-                    };
+                        meter_for_stack,
+                        config.oversized_inbound,
+                    );
                     let mut stack_cfg = IpStackConfig::default();
-                    stack_cfg.mtu(config_for_stack.mtu);
-                    let mut ip_stack = IpStack::new(stack_cfg, tun_device);
+                    stack_cfg.mtu(config.mtu);
+                    let ip_stack = IpStack::new(stack_cfg, tun_device);
                     log::info!(
                         "[NetworkRuntime] network stack started for {} (mtu={})",
-                        owner_clone,
-                        config_for_stack.mtu
+                        owner,
+                        config.mtu
                     );
-                    loop {
-                        tokio::select! {
-                            accept_res = ip_stack.accept() => {
-                                match accept_res {
-                                    Ok(stream) => {
-                                        let id = serial.fetch_add(1, Ordering::Relaxed);
-                                        match stream {
-                                            IpStackStream::Tcp(mut tcp) => {
-                                                let mut peer = match TcpStream::connect(tcp.peer_addr()).await {
-                                                    Ok(stream) => stream,
-                                                    Err(err) => {
-                                                        log::warn!("[NetworkRuntime] TCP connect failed: {err}");
-                                                        continue;
-                                                    }
-                                                };
-                                                let count = session_count.fetch_add(1, Ordering::Relaxed) + 1;
-                                                log::info!("[NetworkRuntime] TCP#{id} established, sessions={count}");
-                                                let counter = session_count.clone();
-                                                crate::asyncrt::spawn(async move {
-                                                    if let Err(err) = io::copy_bidirectional(&mut tcp, &mut peer).await {
-                                                        log::info!("[NetworkRuntime] TCP#{id} ended with error: {err}");
-                                                    }
-                                                    let _ = peer.shutdown().await;
-                                                    let _ = tcp.shutdown().await;
-                                                    let remaining = counter.fetch_sub(1, Ordering::Relaxed) - 1;
-                                                    log::info!("[NetworkRuntime] TCP#{id} closed, sessions={remaining}");
-                                                });
-                                            }
-                                            IpStackStream::Udp(mut udp) => {
-                                                let local_addr = udp.local_addr();
-                                                let remote_addr = udp.peer_addr();
-                                                let mut peer = match UdpStream::connect(remote_addr).await {
-                                                    Ok(stream) => stream,
-                                                    Err(err) => {
-                                                        log::warn!("[NetworkRuntime] UDP connect failed {local_addr} -> {remote_addr}: {err}");
-                                                        continue;
-                                                    }
-                                                };
-                                                let count = session_count.fetch_add(1, Ordering::Relaxed) + 1;
-                                                log::info!(
-                                                    "[NetworkRuntime] UDP#{id} established {} -> {}, sessions={count}",
-                                                    local_addr,
-                                                    remote_addr
-                                                );
-                                                let counter = session_count.clone();
-                                                crate::asyncrt::spawn({
-                                                    let local_addr = local_addr;
-                                                    let remote_addr = remote_addr;
-                                                    async move {
-                                                        if let Err(err) = io::copy_bidirectional(&mut udp, &mut peer).await {
-                                                            log::info!(
-                                                                "[NetworkRuntime] UDP#{id} ended with error: {err} ({} -> {})",
-                                                                local_addr,
-                                                                remote_addr
-                                                            );
-                                                        }
-                                                        peer.shutdown();
-                                                        let _ = udp.shutdown().await;
-                                                        let remaining = counter.fetch_sub(1, Ordering::Relaxed) - 1;
-                                                        log::info!(
-                                                            "[NetworkRuntime] UDP#{id} closed, sessions={remaining} ({} -> {})",
-                                                            local_addr,
-                                                            remote_addr
-                                                        );
-                                                    }
-                                                });
-                                            }
-                                            IpStackStream::UnknownTransport(pkt) => {
-                                                if pkt.src_addr().is_ipv4()
-                                                    && pkt.ip_protocol() == IpNumber::ICMP
-                                                {
-                                                    if let Ok((header, payload)) =
-                                                        Icmpv4Header::from_slice(pkt.payload())
-                                                    {
-                                                        if let Icmpv4Type::EchoRequest(echo) =
-                                                            header.icmp_type
-                                                        {
-                                                            let mut response = Icmpv4Header::new(
-                                                                Icmpv4Type::EchoReply(echo),
-                                                            );
-                                                            response.update_checksum(payload);
-                                                            let mut bytes =
-                                                                response.to_bytes().to_vec();
-                                                            bytes.extend_from_slice(payload);
-                                                            if let Err(err) = pkt.send(bytes) {
-                                                                log::warn!(
-                                                                    "[NetworkRuntime] ICMP send failed: {err}"
-                                                                );
-                                                            } else {
-                                                                log::info!(
-                                                                    "[NetworkRuntime] ICMP echo replied"
-                                                                );
-                                                            }
-                                                            continue;
-                                                        }
-                                                    }
-                                                }
-                                                log::debug!(
-                                                    "[NetworkRuntime] unknown transport {:?}",
-                                                    pkt.ip_protocol()
-                                                );
-                                            }
-                                            IpStackStream::UnknownNetwork(pkt) => {
-                                                log::debug!("[NetworkRuntime] unknown network payload ({} bytes)", pkt.len());
-                                            }
-                                        }
-                                    }
-                                    Err(err) => {
-                                        log::error!("[NetworkRuntime] IpStack halted with error: {err}");
-                                        break;
-                                    }
-                                }
-                            }
-                            changed = shutdown.changed() => {
-                                if changed.is_ok() {
-                                    log::info!("[NetworkRuntime] shutting down network stack for {}", owner_clone);
-                                    break;
-                                }
-                            }
-                        }
-                    }
+                    let serial = AtomicUsize::new(0);
+                    let handler = move |stream| {
+                        let id = serial.fetch_add(1, Ordering::Relaxed);
+                        handle_ip_stream(
+                            id,
+                            stream,
+                            sessions.clone(),
+                            connect_timeout,
+                            connect_retry,
+                            http_compression,
+                        )
+                    };
+                    StackDriver::new(owner, ip_stack, handler, shutdown_rx)
+                        .run()
+                        .await;
                 },
                 handle,
             ));
@@ -433,11 +386,17 @@ impl NetworkRuntime {
 
         Ok(Self {
             ingress_tx,
+            sessions,
             shutdown: shutdown_tx,
             tasks,
         })
     }
 
+    /// 几个循环都是跑到 shutdown 才退的，有一个结束了就说明所在的 tokio runtime 没了
+    fn is_alive(&self) -> bool {
+        !self.ingress_tx.is_closed() && !self.tasks.iter().any(|task| task.is_finished())
+    }
+
     fn push_inbound(&self, packet: Vec<u8>) -> Result<(), IngressError> {
         self.ingress_tx.try_send(packet).map_err(|err| match err {
             TrySendError::Full(_) => IngressError::Backpressure,
@@ -462,24 +421,184 @@ enum IngressError {
     Closed,
 }
 
+/// 正式的出口：编码后塞进设备的 SAR 队列
+struct DeviceEnqueue {
+    owner: String,
+}
+
+impl DeviceEnqueue {
+    fn new(owner: &str) -> Self {
+        Self {
+            owner: owner.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl PayloadSink for DeviceEnqueue {
+    async fn send(&self, payload: Vec<u8>) -> Result<()> {
+        enqueue_network_payload(&self.owner, payload).await
+    }
+}
+
+#[async_trait]
+impl PacketStack for IpStack {
+    type Stream = IpStackStream;
+
+    async fn accept(&mut self) -> Result<IpStackStream> {
+        IpStack::accept(self)
+            .await
+            .map_err(|err| anyhow_site!("{err}"))
+    }
+}
+
+/// 协议栈吐出来的一条会话。TCP、UDP 连目标（连同重试）都放到会话任务里，并受 `connect_timeout` 限制，
+/// 慢的目标不会卡住 accept。`http_compression` 开着时 TCP 走 `http_gzip::relay`
+async fn handle_ip_stream(
+    id: usize,
+    stream: IpStackStream,
+    sessions: Arc<SessionTable>,
+    connect_timeout: Duration,
+    connect_retry: ConnectRetry,
+    http_compression: bool,
+) {
+    match stream {
+        IpStackStream::Tcp(mut tcp) => {
+            crate::asyncrt::spawn(async move {
+                let remote_addr = tcp.peer_addr();
+                let mut peer = match connect_or_close(
+                    &mut tcp,
+                    remote_addr,
+                    connect_timeout,
+                    connect_retry,
+                )
+                .await
+                {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        let reason = ConnectFailure::classify(&err);
+                        log::warn!(
+                            "[NetworkRuntime] TCP#{id} connect to {remote_addr} failed ({reason:?}): {err}"
+                        );
+                        sessions.record_failure(FailedSession {
+                            id,
+                            proto: SessionProto::Tcp,
+                            remote: remote_addr,
+                            reason,
+                            message: err.to_string(),
+                        });
+                        drop(tcp);
+                        return;
+                    }
+                };
+                let count = sessions.opened();
+                log::info!("[NetworkRuntime] TCP#{id} established, sessions={count}");
+                // 443 上基本都是 TLS，连看都不用看
+                let relayed = if http_compression && remote_addr.port() != 443 {
+                    http_gzip::relay(&mut tcp, &mut peer).await
+                } else {
+                    io::copy_bidirectional(&mut tcp, &mut peer)
+                        .await
+                        .map(|_| ())
+                };
+                if let Err(err) = relayed {
+                    log::info!("[NetworkRuntime] TCP#{id} ended with error: {err}");
+                }
+                let _ = peer.shutdown().await;
+                let _ = tcp.shutdown().await;
+                let remaining = sessions.closed();
+                log::info!("[NetworkRuntime] TCP#{id} closed, sessions={remaining}");
+            });
+        }
+        IpStackStream::Udp(mut udp) => {
+            crate::asyncrt::spawn(async move {
+                let local_addr = udp.local_addr();
+                let remote_addr = udp.peer_addr();
+                let connect = connect_within(connect_timeout, connect_retry, || {
+                    UdpStream::connect(remote_addr)
+                });
+                let mut peer = match connect.await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        let reason = ConnectFailure::classify(&err);
+                        log::warn!(
+                            "[NetworkRuntime] UDP connect failed {local_addr} -> {remote_addr} ({reason:?}): {err}"
+                        );
+                        sessions.record_failure(FailedSession {
+                            id,
+                            proto: SessionProto::Udp,
+                            remote: remote_addr,
+                            reason,
+                            message: err.to_string(),
+                        });
+                        // UDP 没有连接可关，直接丢掉会话
+                        drop(udp);
+                        return;
+                    }
+                };
+                let count = sessions.opened();
+                log::info!(
+                    "[NetworkRuntime] UDP#{id} established {} -> {}, sessions={count}",
+                    local_addr,
+                    remote_addr
+                );
+                if let Err(err) = io::copy_bidirectional(&mut udp, &mut peer).await {
+                    log::info!(
+                        "[NetworkRuntime] UDP#{id} ended with error: {err} ({} -> {})",
+                        local_addr,
+                        remote_addr
+                    );
+                }
+                peer.shutdown();
+                let _ = udp.shutdown().await;
+                let remaining = sessions.closed();
+                log::info!(
+                    "[NetworkRuntime] UDP#{id} closed, sessions={remaining} ({} -> {})",
+                    local_addr,
+                    remote_addr
+                );
+            });
+        }
+        IpStackStream::UnknownTransport(pkt) => {
+            if pkt.src_addr().is_ipv4() && pkt.ip_protocol() == IpNumber::ICMP {
+                if let Ok((header, payload)) = Icmpv4Header::from_slice(pkt.payload()) {
+                    if let Icmpv4Type::EchoRequest(echo) = header.icmp_type {
+                        let mut response = Icmpv4Header::new(Icmpv4Type::EchoReply(echo));
+                        response.update_checksum(payload);
+                        let mut bytes = response.to_bytes().to_vec();
+                        bytes.extend_from_slice(payload);
+                        if let Err(err) = pkt.send(bytes) {
+                            log::warn!("[NetworkRuntime] ICMP send failed: {err}");
+                        } else {
+                            log::info!("[NetworkRuntime] ICMP echo replied");
+                        }
+                        return;
+                    }
+                }
+            }
+            log::debug!("[NetworkRuntime] unknown transport {:?}", pkt.ip_protocol());
+        }
+        IpStackStream::UnknownNetwork(pkt) => {
+            log::debug!(
+                "[NetworkRuntime] unknown network payload ({} bytes)",
+                pkt.len()
+            );
+        }
+    }
+}
+
 async fn enqueue_network_payload(owner: &str, payload: Vec<u8>) -> Result<()> {
-    let owner_id = owner.to_string();
-    crate::ecs::with_rt_mut(move |rt| {
-        rt.with_device_mut(&owner_id, |world, entity| {
-            let dev = world
-                .get_mut::<XiaomiDevice>(entity)
-                .ok_or_else(|| anyhow_site!("device {} not found for network send", owner_id))?;
-            dev.sar
-                .lock()
-                .enqueue(L2Packet::new(L2Channel::Network, L2OpCode::Write, payload).to_bytes());
-            Ok(())
-        })
-        .ok_or_else(|| anyhow_site!("device {} not found for network send", owner_id))?
+    xiaomi::with_device_mut(owner, move |dev| -> Result<()> {
+        let bytes = packet::cipher::encode_l2_for_channel(dev, L2Channel::Network, payload)
+            .map_err(|err| anyhow_site!("failed to encode network packet: {err}"))?;
+        dev.sar.lock().enqueue(bytes);
+        Ok(())
     })
     .await
+    .ok_or_else(|| anyhow_site!("device {} not found for network send", owner))?
 }
 
-fn prepare_capture_writer(owner: &str, config: &NetworkConfig) -> Option<PcapWriter<File>> {
+fn prepare_capture_writer(owner: &str, config: &NetworkConfig) -> Option<PacketCapture> {
     if !config.enable_capture {
         return None;
     }
@@ -500,7 +619,7 @@ fn prepare_capture_writer(owner: &str, config: &NetworkConfig) -> Option<PcapWri
     let sanitized_owner = owner.replace(':', "_");
     let file_path = base_dir.join(format!("{sanitized_owner}_{timestamp}.pcap"));
     match File::create(&file_path) {
-        Ok(file) => match PcapWriter::new(file) {
+        Ok(file) => match PacketCapture::new(file, config.capture_link_type) {
             Ok(writer) => Some(writer),
             Err(err) => {
                 log::warn!(
@@ -521,3 +640,41 @@ fn prepare_capture_writer(owner: &str, config: &NetworkConfig) -> Option<PcapWri
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_dedup_follows_the_request() {
+        crate::ecs::init_runtime_default();
+        let mut sys = NetworkSystem::new("test:network-status-dedup".to_string());
+        assert!(sys.sync_status(NETWORK_STATUS_CAPABILITY, false));
+        assert!(!sys.sync_status(NETWORK_STATUS_CAPABILITY, false));
+        // 要报的能力变了就是另一个请求，不算重复
+        assert!(sys.sync_status(1, false));
+        assert_eq!(sys.last_synced_capability(), Some(1));
+        assert!(!sys.sync_status(1, false));
+        assert!(sys.sync_status(1, true));
+    }
+
+    #[test]
+    fn dead_tokio_runtime_is_detected_and_rebuilt() {
+        let mut sys = NetworkSystem::new("test:network-stale-runtime".to_string());
+        let old_rt = tokio::runtime::Runtime::new().unwrap();
+        sys.ensure_runtime(old_rt.handle().clone(), NetworkConfig::default())
+            .unwrap();
+        assert!(sys.is_running());
+
+        // 宿主把 runtime 换掉了，老任务跟着一起没了
+        drop(old_rt);
+        assert!(sys.runtime.lock().is_some());
+        assert!(!sys.is_running());
+
+        let new_rt = tokio::runtime::Runtime::new().unwrap();
+        sys.ensure_runtime(new_rt.handle().clone(), NetworkConfig::default())
+            .unwrap();
+        assert!(sys.is_running());
+        drop(sys);
+    }
+}
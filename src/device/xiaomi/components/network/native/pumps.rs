@@ -0,0 +1,335 @@
+//! NetworkRuntime 的几个后台循环，每个一个结构体，`new` 把依赖传进去，`run` 跑到结束。
+//! 往设备发包和协议栈都藏在小 trait 后面，测试里换成 channel 和脚本化的假协议栈
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::sync::{mpsc, watch};
+
+use super::dhcp::maybe_build_reply;
+
+/// 把一个网络负载交给设备发出去
+#[async_trait]
+pub(super) trait PayloadSink: Send + Sync + 'static {
+    async fn send(&self, payload: Vec<u8>) -> Result<()>;
+}
+
+/// 用户态协议栈，一次吐出一条新会话/包
+#[async_trait]
+pub(super) trait PacketStack: Send + 'static {
+    type Stream: Send + 'static;
+
+    async fn accept(&mut self) -> Result<Self::Stream>;
+}
+
+/// 入口：设备 -> 协议栈。DHCP 请求直接在这里回掉，不进协议栈
+pub(super) struct IngressPump<S> {
+    rx: mpsc::Receiver<Vec<u8>>,
+    tun_tx: mpsc::Sender<Vec<u8>>,
+    sink: S,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl<S: PayloadSink> IngressPump<S> {
+    pub fn new(
+        rx: mpsc::Receiver<Vec<u8>>,
+        tun_tx: mpsc::Sender<Vec<u8>>,
+        sink: S,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            rx,
+            tun_tx,
+            sink,
+            shutdown,
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                packet = self.rx.recv() => {
+                    let Some(data) = packet else {
+                        break;
+                    };
+                    match maybe_build_reply(&data) {
+                        Ok(Some(reply)) => {
+                            if let Err(err) = self.sink.send(reply).await {
+                                log::error!("[NetworkRuntime] failed to send DHCP reply: {err:?}");
+                            }
+                            continue;
+                        }
+                        Ok(None) => {}
+                        Err(err) => log::warn!("[NetworkRuntime] DHCP parse error: {err:?}"),
+                    }
+
+                    if let Err(err) = self.tun_tx.send(data).await {
+                        log::warn!("[NetworkRuntime] tun channel closed: {err}");
+                        break;
+                    }
+                }
+                changed = self.shutdown.changed() => {
+                    if changed.is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 出口：协议栈 -> 设备，发失败一次就停
+pub(super) struct EgressPump<S> {
+    rx: mpsc::Receiver<Vec<u8>>,
+    sink: S,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl<S: PayloadSink> EgressPump<S> {
+    pub fn new(rx: mpsc::Receiver<Vec<u8>>, sink: S, shutdown: watch::Receiver<bool>) -> Self {
+        Self { rx, sink, shutdown }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                packet = self.rx.recv() => {
+                    let Some(payload) = packet else {
+                        break;
+                    };
+                    if let Err(err) = self.sink.send(payload).await {
+                        log::error!("[NetworkRuntime] failed to send network payload: {err:?}");
+                        break;
+                    }
+                }
+                changed = self.shutdown.changed() => {
+                    if changed.is_ok() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 协议栈 accept 循环，每条新会话交给 `handler`。
+/// handler 是串行 await 的，耗时的活（比如 TCP 连目标）要自己 spawn 出去
+pub(super) struct StackDriver<P, H> {
+    owner: String,
+    stack: P,
+    handler: H,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl<P, H, Fut> StackDriver<P, H>
+where
+    P: PacketStack,
+    H: FnMut(P::Stream) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    pub fn new(owner: String, stack: P, handler: H, shutdown: watch::Receiver<bool>) -> Self {
+        Self {
+            owner,
+            stack,
+            handler,
+            shutdown,
+        }
+    }
+
+    pub async fn run(mut self) {
+        loop {
+            tokio::select! {
+                accept_res = self.stack.accept() => {
+                    match accept_res {
+                        Ok(stream) => (self.handler)(stream).await,
+                        Err(err) => {
+                            log::error!("[NetworkRuntime] IpStack halted with error: {err}");
+                            break;
+                        }
+                    }
+                }
+                changed = self.shutdown.changed() => {
+                    if changed.is_ok() {
+                        log::info!("[NetworkRuntime] shutting down network stack for {}", self.owner);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use parking_lot::Mutex;
+
+    const DHCP_DISCOVER: &[u8] = include_bytes!("../../../../../../fuzz/corpus/dhcp/discover");
+
+    /// 收到的负载都记下来，`fail` 为 true 时记完再报错
+    #[derive(Clone, Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<Vec<u8>>>>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl PayloadSink for RecordingSink {
+        async fn send(&self, payload: Vec<u8>) -> Result<()> {
+            self.sent.lock().push(payload);
+            if self.fail {
+                anyhow::bail!("link gone");
+            }
+            Ok(())
+        }
+    }
+
+    /// 按脚本吐结果，脚本用完就一直挂着
+    struct ScriptedStack {
+        script: VecDeque<Result<u32>>,
+    }
+
+    #[async_trait]
+    impl PacketStack for ScriptedStack {
+        type Stream = u32;
+
+        async fn accept(&mut self) -> Result<u32> {
+            match self.script.pop_front() {
+                Some(item) => item,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        tokio::runtime::Runtime::new().unwrap().block_on(fut)
+    }
+
+    async fn finishes<F: Future>(fut: F) -> bool {
+        tokio::time::timeout(Duration::from_secs(1), fut)
+            .await
+            .is_ok()
+    }
+
+    #[test]
+    fn dhcp_is_answered_without_reaching_the_stack() {
+        let (ingress_tx, ingress_rx) = mpsc::channel(4);
+        let (tun_tx, mut tun_rx) = mpsc::channel(4);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let sink = RecordingSink::default();
+        let pump = IngressPump::new(ingress_rx, tun_tx, sink.clone(), shutdown_rx);
+
+        block_on(async move {
+            ingress_tx.send(DHCP_DISCOVER.to_vec()).await.unwrap();
+            ingress_tx.send(vec![0x45, 0, 0, 20]).await.unwrap();
+            drop(ingress_tx);
+            assert!(finishes(pump.run()).await);
+
+            assert_eq!(tun_rx.recv().await, Some(vec![0x45, 0, 0, 20]));
+            assert_eq!(tun_rx.recv().await, None);
+        });
+        let sent = sink.sent.lock();
+        assert_eq!(sent.len(), 1);
+        // 回的是 BOOTREPLY，源端口 67 -> 68
+        assert_eq!(&sent[0][20..24], &[0x00, 0x43, 0x00, 0x44]);
+    }
+
+    #[test]
+    fn ingress_stops_when_tun_closes() {
+        let (ingress_tx, ingress_rx) = mpsc::channel(4);
+        let (tun_tx, tun_rx) = mpsc::channel(4);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        drop(tun_rx);
+        let pump = IngressPump::new(ingress_rx, tun_tx, RecordingSink::default(), shutdown_rx);
+
+        block_on(async move {
+            ingress_tx.send(vec![1, 2, 3]).await.unwrap();
+            // ingress_tx 还活着，能结束只能是因为 tun 关了
+            assert!(finishes(pump.run()).await);
+            drop(ingress_tx);
+        });
+    }
+
+    #[test]
+    fn egress_stops_on_first_send_error() {
+        let (send_tx, send_rx) = mpsc::channel(4);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let sink = RecordingSink {
+            fail: true,
+            ..Default::default()
+        };
+        let pump = EgressPump::new(send_rx, sink.clone(), shutdown_rx);
+
+        block_on(async move {
+            send_tx.send(vec![1]).await.unwrap();
+            send_tx.send(vec![2]).await.unwrap();
+            assert!(finishes(pump.run()).await);
+        });
+        // 第二个包没再尝试
+        assert_eq!(*sink.sent.lock(), vec![vec![1]]);
+    }
+
+    #[test]
+    fn stack_driver_handles_streams_until_error() {
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let handled = Arc::new(Mutex::new(Vec::new()));
+        let stack = ScriptedStack {
+            script: VecDeque::from([Ok(1), Ok(2), Err(anyhow::anyhow!("halted")), Ok(3)]),
+        };
+        let driver = StackDriver::new(
+            "test:stack".to_string(),
+            stack,
+            {
+                let handled = handled.clone();
+                move |id| {
+                    handled.lock().push(id);
+                    async {}
+                }
+            },
+            shutdown_rx,
+        );
+
+        block_on(async move { assert!(finishes(driver.run()).await) });
+        assert_eq!(*handled.lock(), vec![1, 2]);
+    }
+
+    #[test]
+    fn shutdown_reaches_every_pump() {
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        // 各个 channel 的发送端都留着，只有 shutdown 能让它们退出
+        let (_ingress_tx, ingress_rx) = mpsc::channel(4);
+        let (tun_tx, _tun_rx) = mpsc::channel(4);
+        let (_send_tx, send_rx) = mpsc::channel(4);
+        let ingress = IngressPump::new(
+            ingress_rx,
+            tun_tx,
+            RecordingSink::default(),
+            shutdown_rx.clone(),
+        );
+        let egress = EgressPump::new(send_rx, RecordingSink::default(), shutdown_rx.clone());
+        let driver = StackDriver::new(
+            "test:shutdown".to_string(),
+            ScriptedStack {
+                script: VecDeque::new(),
+            },
+            |_: u32| async {},
+            shutdown_rx,
+        );
+
+        block_on(async move {
+            let ingress = tokio::spawn(ingress.run());
+            let egress = tokio::spawn(egress.run());
+            let driver = tokio::spawn(driver.run());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            assert!(!ingress.is_finished() && !egress.is_finished() && !driver.is_finished());
+
+            shutdown_tx.send(true).unwrap();
+            assert!(finishes(ingress).await);
+            assert!(finishes(egress).await);
+            assert!(finishes(driver).await);
+        });
+    }
+}
@@ -0,0 +1,301 @@
+use std::{collections::VecDeque, io, net::SocketAddr, time::Duration};
+
+use parking_lot::Mutex;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+// 只留最近这么多条连接失败记录，够诊断用就行
+const MAX_FAILED_SESSIONS: usize = 32;
+// 关手表侧的流最多等这么久，协议栈卡住也不能拖住会话任务
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionProto {
+    Tcp,
+    Udp,
+}
+
+/// 代手表连目标地址失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectFailure {
+    Refused,
+    Unreachable,
+    TimedOut,
+    Other,
+}
+
+impl ConnectFailure {
+    pub fn classify(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => Self::Refused,
+            io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::AddrNotAvailable => Self::Unreachable,
+            io::ErrorKind::TimedOut => Self::TimedOut,
+            _ => Self::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FailedSession {
+    pub id: usize,
+    pub proto: SessionProto,
+    pub remote: SocketAddr,
+    pub reason: ConnectFailure,
+    pub message: String,
+}
+
+#[derive(Default)]
+struct SessionTableInner {
+    active: usize,
+    failed: VecDeque<FailedSession>,
+}
+
+/// 代理会话表：当前活跃数 + 最近连不上的目标
+#[derive(Default)]
+pub(super) struct SessionTable {
+    inner: Mutex<SessionTableInner>,
+}
+
+impl SessionTable {
+    /// 返回打开后的活跃会话数
+    pub fn opened(&self) -> usize {
+        let mut inner = self.inner.lock();
+        inner.active += 1;
+        inner.active
+    }
+
+    /// 返回关闭后的活跃会话数
+    pub fn closed(&self) -> usize {
+        let mut inner = self.inner.lock();
+        inner.active = inner.active.saturating_sub(1);
+        inner.active
+    }
+
+    pub fn record_failure(&self, failure: FailedSession) {
+        let mut inner = self.inner.lock();
+        if inner.failed.len() >= MAX_FAILED_SESSIONS {
+            inner.failed.pop_front();
+        }
+        inner.failed.push_back(failure);
+    }
+
+    pub fn failures(&self) -> Vec<FailedSession> {
+        self.inner.lock().failed.iter().cloned().collect()
+    }
+}
+
+impl ConnectFailure {
+    /// 网络抖一下就可能好的错误才值得重试；被拒说明对面真没在听，超时已经等够久了
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::Unreachable | Self::Other)
+    }
+}
+
+/// 连目标的重试策略，来自 `NetworkConfig`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ConnectRetry {
+    pub retries: u32,
+    pub backoff: Duration,
+}
+
+impl ConnectRetry {
+    pub fn from_config(config: &crate::device::xiaomi::config::NetworkConfig) -> Self {
+        Self {
+            retries: config.connect_retries,
+            backoff: Duration::from_millis(config.connect_retry_backoff_ms),
+        }
+    }
+}
+
+/// 最多 `retries + 1` 次，瞬时错误才重试，间隔从 `backoff` 开始翻倍
+pub(super) async fn connect_with_retry<T, F, Fut>(
+    retry: ConnectRetry,
+    mut connect: F,
+) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    let mut backoff = retry.backoff;
+    let mut attempt = 0;
+    loop {
+        let err = match connect().await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => err,
+        };
+        if attempt >= retry.retries || !ConnectFailure::classify(&err).is_transient() {
+            return Err(err);
+        }
+        attempt += 1;
+        log::debug!(
+            "[NetworkRuntime] connect failed ({err}), retry {attempt}/{} in {backoff:?}",
+            retry.retries
+        );
+        crate::asyncrt::sleep(backoff).await;
+        backoff = backoff.saturating_mul(2);
+    }
+}
+
+/// `connect_with_retry` 外面再套一层总时限，重试也算在里面，超时按 TimedOut 报
+pub(super) async fn connect_within<T, F, Fut>(
+    limit: Duration,
+    retry: ConnectRetry,
+    connect: F,
+) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    match crate::asyncrt::timeout(limit, connect_with_retry(retry, connect)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("connect timed out after {}s", limit.as_secs()),
+        )),
+    }
+}
+
+/// 连目标地址；连不上就立刻把手表侧的流关掉，让手表上的应用马上失败，
+/// 而不是半开着等它自己的长超时。重试也算在 `limit` 里
+pub(super) async fn connect_or_close<S>(
+    watch_side: &mut S,
+    remote: SocketAddr,
+    limit: Duration,
+    retry: ConnectRetry,
+) -> io::Result<TcpStream>
+where
+    S: AsyncWrite + Unpin,
+{
+    let err = match connect_within(limit, retry, || TcpStream::connect(remote)).await {
+        Ok(stream) => return Ok(stream),
+        Err(err) => err,
+    };
+    // ipstack 没有单独发 RST 的接口，shutdown 会发 FIN，之后调用方 drop 掉流就会清掉协议栈里的会话
+    let _ = crate::asyncrt::timeout(CLOSE_GRACE, watch_side.shutdown()).await;
+    Err(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn closed_port_closes_watch_side_promptly() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            // 先占一个端口再放掉，保证没人监听
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let remote = listener.local_addr().unwrap();
+            drop(listener);
+
+            let (mut watch_side, mut watch_app) = tokio::io::duplex(64);
+            let started = Instant::now();
+            let retry = ConnectRetry {
+                retries: 2,
+                backoff: Duration::from_secs(1),
+            };
+            let err = connect_or_close(&mut watch_side, remote, Duration::from_secs(5), retry)
+                .await
+                .unwrap_err();
+            assert_eq!(ConnectFailure::classify(&err), ConnectFailure::Refused);
+
+            // 手表那头应该马上读到 EOF
+            let mut buf = [0u8; 8];
+            let read =
+                crate::asyncrt::timeout(Duration::from_millis(500), watch_app.read(&mut buf))
+                    .await
+                    .expect("watch side was left half-open")
+                    .unwrap();
+            assert_eq!(read, 0);
+            assert!(started.elapsed() < Duration::from_secs(2));
+        });
+    }
+
+    #[test]
+    fn connect_that_never_answers_is_cut_off() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let retry = ConnectRetry {
+                retries: 2,
+                backoff: Duration::from_millis(10),
+            };
+            // 比如 UDP 目标的 DNS/路由一直卡着
+            let err = connect_within(Duration::from_millis(50), retry, || {
+                std::future::pending::<io::Result<()>>()
+            })
+            .await
+            .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        });
+    }
+
+    fn io_err(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "mock")
+    }
+
+    #[test]
+    fn retries_only_transient_failures() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let retry = ConnectRetry {
+                retries: 2,
+                backoff: Duration::from_millis(10),
+            };
+            let attempts = |kinds: Vec<io::ErrorKind>| {
+                let mut kinds = VecDeque::from(kinds);
+                let mut calls = 0;
+                async move {
+                    let result = connect_with_retry(retry, || {
+                        calls += 1;
+                        let next = kinds.pop_front();
+                        async move { next.map_or(Ok(()), |kind| Err(io_err(kind))) }
+                    })
+                    .await;
+                    (result.is_ok(), calls)
+                }
+            };
+
+            // 抖一下就好了
+            assert_eq!(
+                attempts(vec![io::ErrorKind::NetworkUnreachable]).await,
+                (true, 2)
+            );
+            // 一直不可达，重试用完就放弃
+            assert_eq!(
+                attempts(vec![io::ErrorKind::HostUnreachable; 5]).await,
+                (false, 3)
+            );
+            // 被拒不重试
+            assert_eq!(
+                attempts(vec![io::ErrorKind::ConnectionRefused]).await,
+                (false, 1)
+            );
+        });
+    }
+
+    #[test]
+    fn keeps_only_recent_failures() {
+        let table = SessionTable::default();
+        for id in 0..MAX_FAILED_SESSIONS + 3 {
+            table.record_failure(FailedSession {
+                id,
+                proto: SessionProto::Tcp,
+                remote: "10.0.0.1:443".parse().unwrap(),
+                reason: ConnectFailure::TimedOut,
+                message: String::new(),
+            });
+        }
+        let failures = table.failures();
+        assert_eq!(failures.len(), MAX_FAILED_SESSIONS);
+        assert_eq!(failures[0].id, 3);
+
+        assert_eq!(table.opened(), 1);
+        assert_eq!(table.closed(), 0);
+        assert_eq!(table.closed(), 0);
+    }
+}
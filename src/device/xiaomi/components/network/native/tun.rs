@@ -2,24 +2,126 @@ use std::{
     fs::File,
     io::{Error, ErrorKind, Result},
     pin::Pin,
-    task::{Context, Poll},
+    task::{Context, Poll, ready},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use pcap_file::pcap::{PcapPacket, PcapWriter};
+use pcap_file::{
+    DataLink, PcapResult,
+    pcap::{PcapHeader, PcapPacket, PcapWriter},
+};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::sync::mpsc;
 use tokio_util::sync::PollSender;
 
-use crate::tools::{hex_stream_to_bytes, to_hex_string};
+use crate::tools::to_hex_string;
 
 use super::meter::BandwidthMeter;
+use crate::device::xiaomi::config::{CaptureLinkType, OversizedPacketPolicy};
+
+// 假以太网头里的 MAC，只在 CaptureLinkType::Ethernet 时用
+const WATCH_MAC: [u8; 6] = [0xa5; 6];
+const PHONE_MAC: [u8; 6] = [0x00; 6];
+
+/// 往 pcap 里写 TUN 两个方向的 IP 包
+pub struct PacketCapture {
+    writer: PcapWriter<File>,
+    link: CaptureLinkType,
+}
+
+impl PacketCapture {
+    pub fn new(file: File, link: CaptureLinkType) -> PcapResult<Self> {
+        let header = PcapHeader {
+            datalink: match link {
+                CaptureLinkType::Raw => DataLink::RAW,
+                CaptureLinkType::Ethernet => DataLink::ETHERNET,
+            },
+            ..Default::default()
+        };
+        Ok(Self {
+            writer: PcapWriter::with_header(file, header)?,
+            link,
+        })
+    }
+
+    /// `outbound` 是协议栈发给手表的方向
+    fn write(&mut self, packet: &[u8], outbound: bool) -> PcapResult<()> {
+        let data = match self.link {
+            CaptureLinkType::Raw => packet.to_vec(),
+            CaptureLinkType::Ethernet => ethernet_frame(packet, outbound),
+        };
+        self.writer.write_packet(&PcapPacket {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+            orig_len: data.len() as u32,
+            data: data.into(),
+        })?;
+        Ok(())
+    }
+}
+
+fn ethernet_frame(packet: &[u8], outbound: bool) -> Vec<u8> {
+    let (dst, src) = if outbound {
+        (WATCH_MAC, PHONE_MAC)
+    } else {
+        (PHONE_MAC, WATCH_MAC)
+    };
+    // 按 IP 版本号填 EtherType，v6 包以前也被标成 IPv4
+    let ether_type: [u8; 2] = match packet.first().map(|b| b >> 4) {
+        Some(6) => [0x86, 0xdd],
+        _ => [0x08, 0x00],
+    };
+    let mut frame = Vec::with_capacity(14 + packet.len());
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ether_type);
+    frame.extend_from_slice(packet);
+    frame
+}
 
 pub struct MiWearTunDevice {
     pub rx: mpsc::Receiver<Vec<u8>>,
     pub tx_send: PollSender<Vec<u8>>,
-    pub capture: Option<PcapWriter<File>>,
+    pub capture: Option<PacketCapture>,
     pub meter: BandwidthMeter,
+    oversized: OversizedPacketPolicy,
+    // Carry 策略下上一个包没读完的部分
+    leftover: Vec<u8>,
+}
+
+impl MiWearTunDevice {
+    pub fn new(
+        rx: mpsc::Receiver<Vec<u8>>,
+        tx_send: PollSender<Vec<u8>>,
+        capture: Option<PacketCapture>,
+        meter: BandwidthMeter,
+        oversized: OversizedPacketPolicy,
+    ) -> Self {
+        Self {
+            rx,
+            tx_send,
+            capture,
+            meter,
+            oversized,
+            leftover: Vec::new(),
+        }
+    }
+
+    fn record_inbound(&mut self, packet: &[u8]) {
+        self.meter.add_read(packet.len());
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(err) = capture.write(packet, false) {
+                log::warn!("[MiWearTunDevice] failed to capture inbound packet: {err}");
+            }
+        }
+        #[cfg(debug_assertions)]
+        log::debug!(
+            "[MiWearTunDevice] read {} bytes {}",
+            packet.len(),
+            to_hex_string(packet)
+        );
+    }
 }
 
 impl AsyncRead for MiWearTunDevice {
@@ -28,56 +130,61 @@ impl AsyncRead for MiWearTunDevice {
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        match self.rx.poll_recv(cx) {
-            Poll::Ready(Some(packet)) => {
-                if packet.len() > buf.remaining() {
+        if !self.leftover.is_empty() {
+            let take = self.leftover.len().min(buf.remaining());
+            buf.put_slice(&self.leftover[..take]);
+            self.leftover.drain(..take);
+            return Poll::Ready(Ok(()));
+        }
+        // 丢掉超长包以后接着收下一个，空手返回 Ready 会被当成 EOF
+        loop {
+            let packet = match ready!(self.rx.poll_recv(cx)) {
+                Some(packet) => packet,
+                None => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::BrokenPipe,
+                        "network ingress channel closed",
+                    )));
+                }
+            };
+            self.record_inbound(&packet);
+            if packet.len() <= buf.remaining() {
+                buf.put_slice(&packet);
+                return Poll::Ready(Ok(()));
+            }
+            match self.oversized {
+                OversizedPacketPolicy::Drop => {
                     log::warn!(
-                        "[MiWearTunDevice] incoming packet truncated ({} > {})",
+                        "[MiWearTunDevice] dropping oversized inbound packet ({} > {})",
                         packet.len(),
                         buf.remaining()
                     );
-                    buf.put_slice(&packet[..buf.remaining()]);
-                } else {
-                    buf.put_slice(&packet);
                 }
-                self.meter.add_read(packet.len());
-                if let Some(capture) = self.capture.as_mut() {
-                    let mut ethernet = hex_stream_to_bytes("000000000000a5a5a5a5a5a50800").unwrap();
-                    ethernet.extend_from_slice(&packet);
-                    let packet = PcapPacket {
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default(),
-                        orig_len: ethernet.len() as u32,
-                        data: ethernet.into(),
-                    };
-                    if let Err(err) = capture.write_packet(&packet) {
-                        log::warn!("[MiWearTunDevice] failed to capture inbound packet: {err}");
-                    }
+                OversizedPacketPolicy::Carry => {
+                    let take = buf.remaining();
+                    buf.put_slice(&packet[..take]);
+                    self.leftover = packet[take..].to_vec();
+                    return Poll::Ready(Ok(()));
                 }
-                #[cfg(debug_assertions)]
-                log::debug!(
-                    "[MiWearTunDevice] read {} bytes {}",
-                    packet.len(),
-                    to_hex_string(&packet)
-                );
-                Poll::Ready(Ok(()))
             }
-            Poll::Ready(None) => Poll::Ready(Err(Error::new(
-                ErrorKind::BrokenPipe,
-                "network ingress channel closed",
-            ))),
-            Poll::Pending => Poll::Pending,
         }
     }
 }
 
+fn egress_closed() -> Error {
+    Error::new(ErrorKind::BrokenPipe, "network egress channel closed")
+}
+
 impl AsyncWrite for MiWearTunDevice {
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize>> {
+        // 先占到通道里的位置再记账/抓包，Pending 之后重试不会重复记
+        if ready!(self.tx_send.poll_reserve(cx)).is_err() {
+            return Poll::Ready(Err(egress_closed()));
+        }
         let outbound = buf.to_vec();
         #[cfg(debug_assertions)]
         log::debug!(
@@ -87,43 +194,141 @@ impl AsyncWrite for MiWearTunDevice {
         );
         self.meter.add_written(outbound.len());
         if let Some(capture) = self.capture.as_mut() {
-            let mut ethernet = hex_stream_to_bytes("a5a5a5a5a5a50000000000000800").unwrap();
-            ethernet.extend_from_slice(buf);
-            let packet = PcapPacket {
-                timestamp: SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap_or_default(),
-                orig_len: ethernet.len() as u32,
-                data: ethernet.into(),
-            };
-            if let Err(err) = capture.write_packet(&packet) {
+            if let Err(err) = capture.write(buf, true) {
                 log::warn!("[MiWearTunDevice] failed to capture outbound packet: {err}");
             }
         }
-        match self.tx_send.poll_reserve(cx) {
-            Poll::Ready(Ok(())) => {
-                if let Err(_) = self.tx_send.send_item(outbound) {
-                    Poll::Ready(Err(Error::new(
-                        ErrorKind::BrokenPipe,
-                        "network egress channel closed",
-                    )))
-                } else {
-                    Poll::Ready(Ok(buf.len()))
-                }
-            }
-            Poll::Ready(Err(_)) => Poll::Ready(Err(Error::new(
-                ErrorKind::BrokenPipe,
-                "network egress channel closed",
-            ))),
-            Poll::Pending => Poll::Pending,
+        if self.tx_send.send_item(outbound).is_err() {
+            return Poll::Ready(Err(egress_closed()));
         }
+        Poll::Ready(Ok(buf.len()))
     }
 
+    /// poll_write 返回 Ready 时包已经进了通道，没有攒着的；
+    /// 通道没了要报出来，不然调用方会以为之前写的都发出去了
     fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.tx_send.is_closed() {
+            return Poll::Ready(Err(egress_closed()));
+        }
         Poll::Ready(Ok(()))
     }
 
-    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+    /// 关掉发送端：没用上的通道占位会被放掉，EgressPump 把已经排队的包发完后
+    /// 收到 None 正常退出，尾包不会丢。之后再写会报 BrokenPipe
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.tx_send.close();
         Poll::Ready(Ok(()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn device(
+        rx: mpsc::Receiver<Vec<u8>>,
+        tx: mpsc::Sender<Vec<u8>>,
+        oversized: OversizedPacketPolicy,
+    ) -> MiWearTunDevice {
+        MiWearTunDevice::new(
+            rx,
+            PollSender::new(tx),
+            None,
+            BandwidthMeter::new(Duration::from_secs(1)),
+            oversized,
+        )
+    }
+
+    #[test]
+    fn oversized_inbound_packets_follow_policy() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let read = |policy, times| async move {
+                let (ingress, rx) = mpsc::channel(4);
+                let (tx, _egress) = mpsc::channel(1);
+                let mut tun = device(rx, tx, policy);
+                ingress.send(vec![0xaa; 6]).await.unwrap();
+                ingress.send(vec![0x45, 0x01]).await.unwrap();
+                let mut reads = Vec::new();
+                for _ in 0..times {
+                    let mut buf = [0u8; 4];
+                    let n = tun.read(&mut buf).await.unwrap();
+                    reads.push(buf[..n].to_vec());
+                }
+                reads
+            };
+
+            // 超长的整包丢掉，下一次读直接拿到后面的完整包
+            assert_eq!(
+                read(OversizedPacketPolicy::Drop, 1).await,
+                vec![vec![0x45, 0x01]]
+            );
+            assert_eq!(
+                read(OversizedPacketPolicy::Carry, 3).await,
+                vec![vec![0xaa; 4], vec![0xaa; 2], vec![0x45, 0x01]]
+            );
+        });
+    }
+
+    #[test]
+    fn capture_uses_configured_link_type() {
+        use pcap_file::pcap::PcapReader;
+
+        let ipv4 = [0x45, 0x00, 0x00, 0x14];
+        let ipv6 = [0x60, 0x00, 0x00, 0x00];
+        let read_back = |link| {
+            let path = std::env::temp_dir().join(format!(
+                "astrobox_tun_capture_{link:?}_{}.pcap",
+                std::process::id()
+            ));
+            let mut capture = PacketCapture::new(File::create(&path).unwrap(), link).unwrap();
+            capture.write(&ipv4, false).unwrap();
+            capture.write(&ipv6, true).unwrap();
+            drop(capture);
+
+            let mut reader = PcapReader::new(File::open(&path).unwrap()).unwrap();
+            let datalink = reader.header().datalink;
+            let mut packets = Vec::new();
+            while let Some(packet) = reader.next_packet() {
+                packets.push(packet.unwrap().data.into_owned());
+            }
+            let _ = std::fs::remove_file(&path);
+            (datalink, packets)
+        };
+
+        let (datalink, packets) = read_back(CaptureLinkType::Raw);
+        assert_eq!(datalink, DataLink::RAW);
+        assert_eq!(packets, vec![ipv4.to_vec(), ipv6.to_vec()]);
+
+        let (datalink, packets) = read_back(CaptureLinkType::Ethernet);
+        assert_eq!(datalink, DataLink::ETHERNET);
+        assert_eq!(&packets[0][..12], [PHONE_MAC, WATCH_MAC].concat());
+        assert_eq!(&packets[0][12..14], [0x08, 0x00]);
+        assert_eq!(&packets[1][..12], [WATCH_MAC, PHONE_MAC].concat());
+        assert_eq!(&packets[1][12..14], [0x86, 0xdd]);
+        assert_eq!(&packets[1][14..], ipv6);
+    }
+
+    #[test]
+    fn shutdown_delivers_queued_packets_then_eof() {
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let (tx, mut egress) = mpsc::channel(4);
+            let (_ingress_tx, rx) = mpsc::channel(1);
+            let mut tun = device(rx, tx, OversizedPacketPolicy::Drop);
+
+            tun.write_all(&[0x45, 0x01]).await.unwrap();
+            tun.write_all(&[0x45, 0x02]).await.unwrap();
+            tun.flush().await.unwrap();
+            tun.shutdown().await.unwrap();
+
+            assert_eq!(egress.recv().await, Some(vec![0x45, 0x01]));
+            assert_eq!(egress.recv().await, Some(vec![0x45, 0x02]));
+            assert_eq!(egress.recv().await, None);
+
+            let err = tun.write(&[0x45]).await.unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+            assert!(tun.flush().await.is_err());
+        });
+    }
+}
@@ -0,0 +1,606 @@
+//! 通知图标。手表按包名存图标，宿主每次连上都全量推一遍太浪费，
+//! 这里先单独发一次 prepare 问手表有没有，有就不走 MASS。
+//! 另外存着通知转发规则（`NotificationFilter`），被规则挡掉的包不会往手表上推
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use anyhow::Result;
+use pb::xiaomi::protocol::{self, WearPacket};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+
+use crate::asyncrt::{Duration, timeout};
+use crate::device::xiaomi::components::install::{
+    InstallComponent, InstallOptions, InstallOutcome, InstallSystem,
+    build_notification_icon_request, prepare_reports_present,
+};
+use crate::device::xiaomi::packet::mass::MassDataType;
+use crate::device::xiaomi::system::{
+    L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet,
+};
+use crate::ecs::{Component, access::with_device_component_mut};
+use crate::{anyhow_site, bail_site};
+
+use super::shared::{HasOwnerId, SystemRequestExt};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub type IconFuture = Pin<Box<dyn Future<Output = Result<IconStatus>>>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type IconFuture = Pin<Box<dyn Future<Output = Result<IconStatus>> + Send>>;
+
+/// 问过的图标在这段时间内不再问。用户在手表上清了通知图标的话最多晚这么久才会重传
+pub const ICON_PRESENCE_TTL: Duration = Duration::from_secs(10 * 60);
+// 单独问一次用不了多久，超时多半是固件不认
+const ICON_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IconStatus {
+    /// 手表上已经有了（或者缓存里记着有），没走 MASS
+    Present,
+    /// 这次传上去了，`confirmed` 同 `InstallOutcome::IconUploaded`
+    Uploaded { confirmed: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotificationError {
+    /// 手表要这个图标，但调用方没给图标数据
+    IconMissingLocally { package_name: String },
+    /// 这个包被 NotificationFilter 挡掉了，不往手表上推
+    Filtered { package_name: String },
+}
+
+impl std::fmt::Display for NotificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IconMissingLocally { package_name } => {
+                write!(
+                    f,
+                    "device asked for the icon of {package_name}, but none was provided"
+                )
+            }
+            Self::Filtered { package_name } => {
+                write!(f, "notifications of {package_name} are filtered out")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NotificationError {}
+
+/// 对应 Android 的 NotificationManager.IMPORTANCE_*，按大小可比
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationImportance {
+    #[default]
+    Min,
+    Low,
+    Default,
+    High,
+    Max,
+}
+
+/// 通知转发规则，运行时随时可以换。deny 优先于 allow，allow 为空表示不限包名
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NotificationFilter {
+    pub allow_packages: HashSet<String>,
+    pub deny_packages: HashSet<String>,
+    /// 低于这个重要性的通知不转发，默认 Min 即全转
+    pub min_importance: NotificationImportance,
+}
+
+impl NotificationFilter {
+    pub fn allows_package(&self, package_name: &str) -> bool {
+        !self.deny_packages.contains(package_name)
+            && (self.allow_packages.is_empty() || self.allow_packages.contains(package_name))
+    }
+
+    pub fn allows(&self, package_name: &str, importance: NotificationImportance) -> bool {
+        importance >= self.min_importance && self.allows_package(package_name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct IconPresence {
+    present: bool,
+    #[serde(skip)]
+    checked_at: Instant,
+}
+
+/// 按包名缓存手表有没有图标，断开重连后实体重建，缓存也跟着清掉。
+/// 转发规则也在这，同样只活到断开为止，宿主重连后要重新设
+#[derive(Component, Default, Serialize)]
+pub struct NotificationComponent {
+    icon_presence: HashMap<String, IconPresence>,
+    filter: NotificationFilter,
+}
+
+impl NotificationComponent {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn filter(&self) -> &NotificationFilter {
+        &self.filter
+    }
+
+    pub fn set_filter(&mut self, filter: NotificationFilter) {
+        self.filter = filter;
+    }
+
+    /// 过了 `ICON_PRESENCE_TTL` 的当没问过
+    pub fn cached_icon_presence(&self, package_name: &str) -> Option<bool> {
+        self.icon_presence
+            .get(package_name)
+            .filter(|entry| entry.checked_at.elapsed() < ICON_PRESENCE_TTL)
+            .map(|entry| entry.present)
+    }
+
+    pub fn record_icon_presence(&mut self, package_name: String, present: bool) {
+        self.icon_presence.insert(
+            package_name,
+            IconPresence {
+                present,
+                checked_at: Instant::now(),
+            },
+        );
+    }
+
+    /// 宿主换了图标想强制重传时用
+    pub fn forget_icon(&mut self, package_name: &str) {
+        self.icon_presence.remove(package_name);
+    }
+}
+
+#[derive(Component)]
+pub struct NotificationSystem {
+    owner_id: String,
+    // AppIconResponse 里不带包名，同时只能问一个
+    icon_probe_wait: Option<oneshot::Sender<i32>>,
+}
+
+enum IconCheck {
+    Cached(bool),
+    Probing(oneshot::Receiver<i32>),
+    /// 有数据就不单独问了，InstallSystem 的 prepare 会处理手表已经有的情况
+    Upload(Vec<u8>),
+}
+
+impl Default for NotificationSystem {
+    fn default() -> Self {
+        Self::new(String::new())
+    }
+}
+
+impl NotificationSystem {
+    pub fn new(owner_id: String) -> Self {
+        register_xiaomi_system_ext_on_l2packet::<Self>();
+        Self {
+            owner_id,
+            icon_probe_wait: None,
+        }
+    }
+
+    /// 确保手表上有这个包的通知图标，先看缓存。给了 `icon_png` 就直接走 InstallSystem：
+    /// 它发的 prepare 就是在问手表有没有，Duplicated 时不走 MASS，返回 `Present`；
+    /// 没给数据才单独发 prepare 问一次，手表要的话返回 `NotificationError::IconMissingLocally`。
+    /// 被转发规则挡掉的包直接返回 `NotificationError::Filtered`，不去问手表
+    pub fn ensure_icon(
+        &mut self,
+        package_name: &str,
+        icon_png: Option<Vec<u8>>,
+    ) -> Result<IconFuture> {
+        let owner = self.owner_id.clone();
+        let package_name = package_name.to_string();
+
+        let (allowed, cached) =
+            with_device_component_mut::<NotificationComponent, _, _>(owner.clone(), {
+                let package_name = package_name.clone();
+                move |comp| {
+                    (
+                        comp.filter.allows_package(&package_name),
+                        comp.cached_icon_presence(&package_name),
+                    )
+                }
+            })
+            .map_err(|err| anyhow_site!("failed to access notification component: {:?}", err))?;
+        if !allowed {
+            return Err(NotificationError::Filtered { package_name }.into());
+        }
+        let check = match (cached, icon_png) {
+            (Some(true), _) => IconCheck::Cached(true),
+            (_, Some(icon_png)) => IconCheck::Upload(icon_png),
+            (Some(false), None) => IconCheck::Cached(false),
+            (None, None) => IconCheck::Probing(self.send_icon_probe(&package_name)?),
+        };
+
+        Ok(Box::pin(async move {
+            let icon_png = match check {
+                IconCheck::Cached(true) => return Ok(IconStatus::Present),
+                IconCheck::Cached(false) => {
+                    return Err(NotificationError::IconMissingLocally { package_name }.into());
+                }
+                IconCheck::Probing(rx) => {
+                    let status = timeout(ICON_PROBE_TIMEOUT, rx)
+                        .await
+                        .map_err(|_| anyhow_site!("icon prepare response not received"))?
+                        .map_err(|_| anyhow_site!("icon prepare channel closed unexpectedly"))?;
+                    let present = probe_reports_present(status)?;
+                    remember_icon(owner.clone(), package_name.clone(), present).await;
+                    if present {
+                        return Ok(IconStatus::Present);
+                    }
+                    return Err(NotificationError::IconMissingLocally { package_name }.into());
+                }
+                IconCheck::Upload(icon_png) => icon_png,
+            };
+
+            let install = crate::ecs::with_rt_mut_labeled("notification::ensure_icon", {
+                let owner = owner.clone();
+                let package_name = package_name.clone();
+                move |rt| {
+                    rt.with_device_mut(&owner, |world, entity| {
+                        let mut sys = world
+                            .get_mut::<InstallSystem>(entity)
+                            .ok_or_else(|| anyhow_site!("install system not found"))?;
+                        sys.send_install_request_with_options(
+                            MassDataType::NotificationIcon,
+                            icon_png,
+                            Some(&package_name),
+                            Arc::new(|_| {}),
+                            None,
+                            InstallOptions::default(),
+                        )
+                    })
+                    .ok_or_else(|| anyhow_site!("device {owner} not found"))?
+                }
+            })
+            .await?;
+            let status = match install.await? {
+                InstallOutcome::AlreadyPresent => IconStatus::Present,
+                InstallOutcome::IconUploaded { confirmed } => IconStatus::Uploaded { confirmed },
+                other => bail_site!("unexpected install outcome for icon: {other:?}"),
+            };
+            remember_icon(owner, package_name, true).await;
+            Ok(status)
+        }))
+    }
+
+    fn send_icon_probe(&mut self, package_name: &str) -> Result<oneshot::Receiver<i32>> {
+        // 上一个调用方超时放弃了的不算占着
+        if self
+            .icon_probe_wait
+            .as_ref()
+            .is_some_and(|tx| !tx.is_closed())
+        {
+            bail_site!("icon presence query is already in progress");
+        }
+        let (tx, rx) = oneshot::channel();
+        self.icon_probe_wait = Some(tx);
+        self.enqueue_pb_request(
+            build_notification_icon_request(package_name),
+            "NotificationSystem::send_icon_probe",
+        );
+        Ok(rx)
+    }
+}
+
+impl L2PbExt for NotificationSystem {
+    fn on_pb_packet(&mut self, payload: WearPacket) {
+        if let Some(protocol::wear_packet::Payload::Notification(nc)) = payload.payload {
+            if let Some(protocol::notification::Payload::AppIconResponse(resp)) = nc.payload {
+                // 调用方超时放弃了的不算在问
+                if self
+                    .icon_probe_wait
+                    .as_ref()
+                    .is_some_and(|tx| tx.is_closed())
+                {
+                    self.icon_probe_wait = None;
+                }
+                // 回包不带包名：没在问、或者 InstallSystem 正在传图标（它的 prepare 回包长得一样）就不管
+                if self.icon_probe_wait.is_none() || install_waiting(&self.owner_id) {
+                    return;
+                }
+                if let Some(tx) = self.icon_probe_wait.take() {
+                    mark_pb_consumed();
+                    let _ = tx.send(resp.prepare_status);
+                }
+            }
+        }
+    }
+}
+
+impl HasOwnerId for NotificationSystem {
+    fn owner_id(&self) -> &str {
+        &self.owner_id
+    }
+}
+
+fn install_waiting(owner: &str) -> bool {
+    with_device_component_mut::<InstallComponent, _, _>(owner.to_string(), |comp| {
+        comp.is_installing()
+    })
+    .unwrap_or(false)
+}
+
+/// Duplicated 是有，Ready 是要传，别的状态说明手表这会儿收不了
+fn probe_reports_present(status: i32) -> Result<bool> {
+    let status = protocol::PrepareStatus::try_from(status)
+        .map_err(|_| anyhow_site!("unknown icon prepare status: {status}"))?;
+    if prepare_reports_present(status) {
+        return Ok(true);
+    }
+    if status != protocol::PrepareStatus::Ready {
+        bail_site!("icon prepare failed with status: {:?}", status);
+    }
+    Ok(false)
+}
+
+async fn remember_icon(owner: String, package_name: String, present: bool) {
+    crate::ecs::with_rt_mut(move |rt| {
+        rt.with_device_mut(&owner, |world, entity| {
+            if let Some(mut comp) = world.get_mut::<NotificationComponent>(entity) {
+                comp.record_icon_presence(package_name, present);
+            }
+        });
+    })
+    .await;
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::{spawn_mock_xiaomi, xiaomi::config::XiaomiDeviceConfig};
+
+    fn icon_response(status: protocol::PrepareStatus) -> WearPacket {
+        WearPacket {
+            r#type: protocol::wear_packet::Type::Notification as i32,
+            id: protocol::notification::NotificationId::PrepareAppIcon as u32,
+            payload: Some(protocol::wear_packet::Payload::Notification(
+                protocol::Notification {
+                    payload: Some(protocol::notification::Payload::AppIconResponse(
+                        protocol::prepare_app_icon::Response {
+                            prepare_status: status as i32,
+                            ..Default::default()
+                        },
+                    )),
+                },
+            )),
+        }
+    }
+
+    fn cached(id: &str, package_name: &'static str) -> Option<bool> {
+        with_device_component_mut::<NotificationComponent, _, _>(id.to_string(), move |comp| {
+            comp.cached_icon_presence(package_name)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn filter_rules() {
+        use NotificationImportance::{High, Low, Max, Min};
+        let filter = NotificationFilter {
+            allow_packages: ["com.example.chat", "com.example.mail"]
+                .map(String::from)
+                .into(),
+            deny_packages: ["com.example.mail".to_string()].into(),
+            min_importance: NotificationImportance::Default,
+        };
+        assert!(filter.allows("com.example.chat", High));
+        assert!(!filter.allows("com.example.chat", Low));
+        // deny 优先
+        assert!(!filter.allows("com.example.mail", Max));
+        assert!(!filter.allows("com.example.news", Max));
+
+        let everything = NotificationFilter::default();
+        assert!(everything.allows("com.example.news", Min));
+        let parsed: NotificationFilter =
+            serde_json::from_value(serde_json::json!({ "min_importance": "high" })).unwrap();
+        assert!(parsed.allows("com.example.news", Max));
+        assert!(!parsed.allows("com.example.news", NotificationImportance::Default));
+    }
+
+    #[test]
+    fn filtered_package_is_not_probed() {
+        let id = "test:notification-icon-filtered";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+        with_device_component_mut::<NotificationComponent, _, _>(id.to_string(), |comp| {
+            comp.set_filter(NotificationFilter {
+                deny_packages: ["com.example.ads".to_string()].into(),
+                ..NotificationFilter::default()
+            })
+        })
+        .unwrap();
+
+        let mut sys = NotificationSystem::new(id.to_string());
+        let err = sys
+            .ensure_icon("com.example.ads", Some(vec![1]))
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.downcast_ref::<NotificationError>(),
+            Some(&NotificationError::Filtered {
+                package_name: "com.example.ads".to_string()
+            })
+        );
+        assert!(sys.icon_probe_wait.is_none());
+    }
+
+    #[test]
+    fn present_icon_skips_transfer_and_is_cached() {
+        let id = "test:notification-icon-present";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+
+        let mut sys = NotificationSystem::new(id.to_string());
+        let fut = sys.ensure_icon("com.example.chat", None).unwrap();
+        sys.on_pb_packet(icon_response(protocol::PrepareStatus::Duplicated));
+        assert_eq!(rt.block_on(fut).unwrap(), IconStatus::Present);
+        assert_eq!(cached(id, "com.example.chat"), Some(true));
+
+        // 第二次走缓存，给了数据也不发 prepare，也就不用回包
+        let fut = sys
+            .ensure_icon("com.example.chat", Some(vec![1, 2, 3]))
+            .unwrap();
+        assert!(sys.icon_probe_wait.is_none());
+        assert_eq!(rt.block_on(fut).unwrap(), IconStatus::Present);
+    }
+
+    #[test]
+    fn requested_icon_without_bytes_is_missing_locally() {
+        let id = "test:notification-icon-missing";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+
+        let mut sys = NotificationSystem::new(id.to_string());
+        let fut = sys.ensure_icon("com.example.mail", None).unwrap();
+        sys.on_pb_packet(icon_response(protocol::PrepareStatus::Ready));
+        let err = rt.block_on(fut).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<NotificationError>(),
+            Some(&NotificationError::IconMissingLocally {
+                package_name: "com.example.mail".to_string()
+            })
+        );
+        assert_eq!(cached(id, "com.example.mail"), Some(false));
+    }
+
+    #[test]
+    fn requested_icon_with_bytes_goes_to_transfer() {
+        use crate::device::xiaomi::components::{install::InstallComponent, mass::MassComponent};
+
+        let id = "test:notification-icon-upload";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+        let installing = || {
+            crate::device::xiaomi::with_component_mut::<InstallComponent, _, _>(id, |comp| {
+                comp.is_installing()
+            })
+        };
+        let mass_preparing = || {
+            crate::device::xiaomi::with_component_mut::<MassComponent, _, _>(id, |comp| {
+                comp.awaiting_prepare()
+            })
+        };
+
+        let mut sys = NotificationSystem::new(id.to_string());
+        let fut = sys
+            .ensure_icon("com.example.news", Some(vec![0x89, b'P', b'N', b'G']))
+            .unwrap();
+        // 有数据就不单独问，prepare 由 InstallSystem 发
+        assert!(sys.icon_probe_wait.is_none());
+
+        let started = rt.block_on(async {
+            let task = tokio::spawn(fut);
+            for _ in 0..100 {
+                if installing().await == Some(true) {
+                    break;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(20)).await;
+            }
+            crate::ecs::with_rt_mut(move |rt| {
+                rt.with_device_mut(id, |world, entity| {
+                    if let Some(mut install) = world.get_mut::<InstallSystem>(entity) {
+                        install.on_pb_packet(icon_response(protocol::PrepareStatus::Ready));
+                    }
+                });
+            })
+            .await;
+            let mut started = false;
+            for _ in 0..100 {
+                if mass_preparing().await == Some(true) {
+                    started = true;
+                    break;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(20)).await;
+            }
+            task.abort();
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)).await;
+            started
+        });
+        crate::device::xiaomi::cleanup_cached_state(id);
+        assert!(started, "icon bytes should be handed to a MASS transfer");
+    }
+
+    #[test]
+    fn probe_leaves_install_responses_alone() {
+        use crate::device::xiaomi::components::install::InstallComponent;
+
+        let id = "test:notification-icon-install-busy";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+        let mut sys = NotificationSystem::new(id.to_string());
+
+        // 没在问的时候不认领
+        sys.on_pb_packet(icon_response(protocol::PrepareStatus::Ready));
+        assert!(sys.icon_probe_wait.is_none());
+
+        // InstallSystem 在传另一个包的图标，这时候来的回包是它的
+        let upload = rt.spawn(
+            sys.ensure_icon("com.example.news", Some(vec![1, 2, 3]))
+                .unwrap(),
+        );
+        let installing = rt.block_on(async {
+            for _ in 0..100 {
+                let busy = crate::device::xiaomi::with_component_mut::<InstallComponent, _, _>(
+                    id,
+                    |comp| comp.is_installing(),
+                )
+                .await;
+                if busy == Some(true) {
+                    return true;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(20)).await;
+            }
+            false
+        });
+        assert!(installing);
+        let probe = sys.ensure_icon("com.example.chat", None).unwrap();
+        sys.on_pb_packet(icon_response(protocol::PrepareStatus::Duplicated));
+        assert!(sys.icon_probe_wait.is_some());
+
+        upload.abort();
+        drop(probe);
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
+
+    #[test]
+    fn host_entry_point_answers_from_cache() {
+        let id = "test:notification-icon-host";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+        with_device_component_mut::<NotificationComponent, _, _>(id.to_string(), |comp| {
+            comp.record_icon_presence("com.example.chat".to_string(), true)
+        })
+        .unwrap();
+
+        let status = rt.block_on(crate::device::notification::ensure_icon(
+            id.to_string(),
+            "com.example.chat".to_string(),
+            None,
+        ));
+        assert_eq!(status.unwrap(), IconStatus::Present);
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
+}
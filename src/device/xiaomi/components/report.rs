@@ -2,7 +2,7 @@ use pb::xiaomi::protocol;
 use tokio::sync::oneshot;
 
 use crate::{
-    device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet},
+    device::xiaomi::system::{L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
     ecs::Component,
 };
 
@@ -65,6 +65,7 @@ impl L2PbExt for ReportSystem {
             return;
         };
         if result.r#type == protocol::report_data::Type::DeviceLog as i32 {
+            mark_pb_consumed();
             self.device_log_wait.fulfill(result);
         }
     }
@@ -2,12 +2,18 @@ use pb::xiaomi::protocol::{self, WearPacket};
 use tokio::sync::oneshot;
 
 use crate::{
-    device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet},
+    device::xiaomi::system::{L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
     ecs::{Component, access::with_device_component_mut},
 };
 
 use super::shared::{HasOwnerId, RequestSlot, SystemRequestExt, await_response};
 use crate::anyhow_site;
+use crate::asyncrt::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use web_time::Instant;
 
 #[derive(Component)]
 pub struct ResourceSystem {
@@ -91,12 +97,13 @@ impl L2PbExt for ResourceSystem {
 
                 match watch_face.payload {
                     Some(protocol::watch_face::Payload::WatchFaceList(list)) => {
+                        mark_pb_consumed();
                         let items = list.list.clone();
                         let comp_items = items.clone();
                         let update_res = with_device_component_mut::<ResourceComponent, _, _>(
                             self.owner_id.clone(),
                             move |comp| {
-                                comp.watchfaces = comp_items;
+                                comp.set_watchfaces(comp_items);
                             },
                         );
 
@@ -136,12 +143,13 @@ impl L2PbExt for ResourceSystem {
 
                 match thirdparty_app.payload {
                     Some(protocol::thirdparty_app::Payload::AppItemList(list)) => {
+                        mark_pb_consumed();
                         let items = list.list.clone();
                         let comp_items = items.clone();
                         let update_res = with_device_component_mut::<ResourceComponent, _, _>(
                             self.owner_id.clone(),
                             move |comp| {
-                                comp.quick_apps = comp_items;
+                                comp.set_quick_apps(comp_items);
                             },
                         );
 
@@ -182,6 +190,11 @@ impl L2PbExt for ResourceSystem {
 pub struct ResourceComponent {
     pub watchfaces: Vec<protocol::WatchFaceItem>,
     pub quick_apps: Vec<protocol::AppItem>,
+    // 上次从设备拉到列表的时间，判断缓存还能不能信
+    #[serde(skip)]
+    watchfaces_synced_at: Option<Instant>,
+    #[serde(skip)]
+    quick_apps_synced_at: Option<Instant>,
 }
 
 impl ResourceComponent {
@@ -189,11 +202,36 @@ impl ResourceComponent {
         Self {
             watchfaces: vec![],
             quick_apps: vec![],
+            watchfaces_synced_at: None,
+            quick_apps_synced_at: None,
         }
     }
+
+    pub fn set_watchfaces(&mut self, items: Vec<protocol::WatchFaceItem>) {
+        self.watchfaces = items;
+        self.watchfaces_synced_at = Some(Instant::now());
+    }
+
+    pub fn set_quick_apps(&mut self, items: Vec<protocol::AppItem>) {
+        self.quick_apps = items;
+        self.quick_apps_synced_at = Some(Instant::now());
+    }
+
+    /// 列表在 `max_age` 内同步过才返回，否则说明得重新问设备
+    pub fn fresh_watchfaces(&self, max_age: Duration) -> Option<&[protocol::WatchFaceItem]> {
+        is_fresh(self.watchfaces_synced_at, max_age).then_some(self.watchfaces.as_slice())
+    }
+
+    pub fn fresh_quick_apps(&self, max_age: Duration) -> Option<&[protocol::AppItem]> {
+        is_fresh(self.quick_apps_synced_at, max_age).then_some(self.quick_apps.as_slice())
+    }
+}
+
+fn is_fresh(synced_at: Option<Instant>, max_age: Duration) -> bool {
+    synced_at.is_some_and(|at| at.elapsed() <= max_age)
 }
 
-fn build_watchface_get_installed() -> protocol::WearPacket {
+pub(super) fn build_watchface_get_installed() -> protocol::WearPacket {
     protocol::WearPacket {
         r#type: protocol::wear_packet::Type::WatchFace as i32,
         id: protocol::watch_face::WatchFaceId::GetInstalledList as u32,
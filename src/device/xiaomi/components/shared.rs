@@ -2,12 +2,13 @@ use anyhow::{Context, Result};
 use pb::xiaomi::protocol;
 use tokio::sync::oneshot;
 
-use crate::{
-    device::xiaomi::{XiaomiDevice, packet},
-    ecs::access::with_device_component_mut,
-};
+use crate::device::xiaomi::{XiaomiDevice, packet};
+#[cfg(target_arch = "wasm32")]
+use crate::ecs::access::with_device_component_mut;
 use parking_lot::Mutex;
 
+/// 同一种请求的等待者列表。并发的调用方共用一次请求，回包时所有人都拿到一份 clone；
+/// 回包之后再 prepare 的会重新发请求
 pub struct RequestSlot<T> {
     waiters: Mutex<Vec<oneshot::Sender<Result<T>>>>,
 }
@@ -22,6 +23,8 @@ impl<T> RequestSlot<T> {
     pub fn prepare(&mut self) -> (oneshot::Receiver<Result<T>>, bool) {
         let (tx, rx) = oneshot::channel();
         let mut waiters = self.waiters.lock();
+        // 调用方超时放弃的不算数，不然请求丢了之后后来的人永远不会重发
+        waiters.retain(|tx| !tx.is_closed());
         let should_enqueue = waiters.is_empty();
         waiters.push(tx);
         (rx, should_enqueue)
@@ -85,10 +88,81 @@ impl<T> SystemRequestExt for T
 where
     T: HasOwnerId,
 {
+    /// system 一般是在 ECS 任务里被调的，这时直接拿线程本地的 runtime 入队，不用 block_on；
+    /// 不在 ECS 线程就把入队投到 ECS 任务队列里，队列先进先出，连着发的请求不会被打乱顺序
     fn enqueue_pb_request(&mut self, packet: protocol::WearPacket, log_ctx: &'static str) {
         let owner_id = self.owner_id().to_string();
+        if crate::ecs::in_rt_thread() {
+            crate::ecs::try_with_rt_local_mut(|rt| {
+                enqueue_in_runtime(rt, &owner_id, packet, log_ctx)
+            });
+            return;
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        crate::ecs::post_rt_job(log_ctx, move |rt| {
+            enqueue_in_runtime(rt, &owner_id, packet, log_ctx)
+        });
+
+        #[cfg(target_arch = "wasm32")]
         let _ = with_device_component_mut::<XiaomiDevice, _, _>(owner_id, move |dev| {
             packet::cipher::enqueue_pb_packet(dev, packet, log_ctx);
         });
     }
 }
+
+fn enqueue_in_runtime(
+    rt: &mut crate::ecs::runtime::Runtime,
+    owner_id: &str,
+    packet: protocol::WearPacket,
+    log_ctx: &'static str,
+) {
+    let found = rt
+        .with_device_mut(owner_id, |world, entity| {
+            world
+                .get_mut::<XiaomiDevice>(entity)
+                .map(|mut dev| packet::cipher::enqueue_pb_packet(&mut dev, packet, log_ctx))
+        })
+        .flatten();
+    if found.is_none() {
+        log::warn!(
+            "[{}] device {} not found, request dropped",
+            log_ctx,
+            owner_id
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fulfill_reaches_every_waiter() {
+        let mut slot = RequestSlot::<u32>::new();
+        let (mut first, should_enqueue) = slot.prepare();
+        assert!(should_enqueue);
+        let (mut second, should_enqueue) = slot.prepare();
+        assert!(!should_enqueue);
+
+        slot.fulfill(7);
+        assert_eq!(first.try_recv().unwrap().unwrap(), 7);
+        assert_eq!(second.try_recv().unwrap().unwrap(), 7);
+
+        // 回包之后来的要重新发请求
+        let (_third, should_enqueue) = slot.prepare();
+        assert!(should_enqueue);
+    }
+
+    #[test]
+    fn abandoned_waiters_do_not_block_new_requests() {
+        let mut slot = RequestSlot::<u32>::new();
+        let (first, _) = slot.prepare();
+        drop(first);
+
+        let (mut second, should_enqueue) = slot.prepare();
+        assert!(should_enqueue);
+        slot.fail(anyhow::anyhow!("boom"));
+        assert!(second.try_recv().unwrap().is_err());
+    }
+}
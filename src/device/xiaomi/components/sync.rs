@@ -26,16 +26,36 @@ impl SyncSystem {
         Self { owner_id }
     }
 
-    pub fn sync_time(&mut self, props: TimeSyncProps) {
+    /// 字段越界（月份、小时、时区偏移之类）时不发，直接报错，见 `TimeSyncProps::validate`
+    pub fn sync_time(&mut self, props: TimeSyncProps) -> anyhow::Result<()> {
+        props.validate()?;
         log::info!(
             "Syncing time with props: {}",
             serde_json::to_string(&props).unwrap_or_default()
         );
         self.enqueue_pb_request(build_time_sync_packet(props), "SyncSystem::SyncTime");
+        Ok(())
     }
 
-    pub fn set_language(&mut self, locale: String) {
+    /// 先把分隔符换成固件用的 `_`（`en-US` -> `en_US`）再发，别的不动。
+    /// `supported` 是 SyncComponent 里缓存的手表支持列表（宿主给的），没有就不判断。
+    /// 不在列表里的 locale 默认打 warn 照发（有的手表会悄悄退回中文），`strict` 时直接报错
+    pub fn set_language(
+        &mut self,
+        locale: String,
+        supported: Option<&[String]>,
+        strict: bool,
+    ) -> anyhow::Result<()> {
+        let locale = match resolve_locale(&locale, supported) {
+            Ok(resolved) => resolved,
+            Err(err) if strict => return Err(err.into()),
+            Err(err) => {
+                log::warn!("[SyncSystem] {err}, sending it anyway");
+                err.into_locale()
+            }
+        };
         self.enqueue_pb_request(build_set_language_packet(locale), "SyncSystem::SetLanguage");
+        Ok(())
     }
 }
 
@@ -50,11 +70,82 @@ impl HasOwnerId for SyncSystem {
 }
 
 #[derive(Component, serde::Serialize)]
-pub struct SyncComponent {}
+pub struct SyncComponent {
+    /// 手表支持的 locale（固件写法），None 表示还不知道
+    supported_locales: Option<Vec<String>>,
+}
 
 impl SyncComponent {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            supported_locales: None,
+        }
+    }
+
+    pub fn supported_locales(&self) -> Option<&[String]> {
+        self.supported_locales.as_deref()
+    }
+
+    pub fn set_supported_locales(&mut self, locales: Vec<String>) {
+        self.supported_locales = Some(locales);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocaleError {
+    /// 连 BCP-47 的样子都没有，原样带着
+    Malformed(String),
+    /// 格式没问题但不在手表支持的列表里，带的是换过分隔符的写法
+    Unsupported(String),
+}
+
+impl LocaleError {
+    /// 非 strict 模式下照发的那个值
+    pub fn into_locale(self) -> String {
+        match self {
+            Self::Malformed(locale) | Self::Unsupported(locale) => locale,
+        }
+    }
+}
+
+impl std::fmt::Display for LocaleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed(locale) => write!(f, "locale {locale:?} is not a BCP-47 tag"),
+            Self::Unsupported(locale) => {
+                write!(f, "locale {locale} is not in the device's supported list")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LocaleError {}
+
+/// BCP-47 形状的 locale（`-`/`_` 都行）只把 `-` 换成固件的 `_`，大小写和地区原样，
+/// 不是 locale 的样子返回 None
+pub fn normalize_locale(input: &str) -> Option<String> {
+    let input = input.trim();
+    let mut parts = input.split(['-', '_']);
+    let lang = parts.next()?;
+    if !(2..=3).contains(&lang.len()) || !lang.bytes().all(|b| b.is_ascii_alphabetic()) {
+        return None;
+    }
+    let well_formed = parts.all(|part| {
+        (1..=8).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_alphanumeric())
+    });
+    well_formed.then(|| input.replace('-', "_"))
+}
+
+/// 只按手表（宿主）给的列表判断，不区分大小写；没有列表就不判断
+pub fn resolve_locale(input: &str, supported: Option<&[String]>) -> Result<String, LocaleError> {
+    let Some(locale) = normalize_locale(input) else {
+        return Err(LocaleError::Malformed(input.to_string()));
+    };
+    match supported {
+        Some(list) if !list.iter().any(|s| s.eq_ignore_ascii_case(&locale)) => {
+            Err(LocaleError::Unsupported(locale))
+        }
+        _ => Ok(locale),
     }
 }
 
@@ -108,3 +199,62 @@ fn build_time_sync_packet(props: TimeSyncProps) -> WearPacket {
 
     pkt
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_only_get_their_separator_changed() {
+        for (input, expected) in [
+            ("en-US", "en_US"),
+            ("en_us", "en_us"),
+            ("pt", "pt"),
+            ("zh-Hant", "zh_Hant"),
+            ("es-419", "es_419"),
+            ("fil", "fil"),
+        ] {
+            assert_eq!(
+                normalize_locale(input).as_deref(),
+                Some(expected),
+                "{input}"
+            );
+        }
+        for input in ["", "english", "en-", "e1-US", "en-USA-x!"] {
+            assert_eq!(normalize_locale(input), None, "{input}");
+        }
+
+        // 没有列表就不判断
+        assert_eq!(resolve_locale("en-AU", None), Ok("en_AU".to_string()));
+        let supported = vec!["en_AU".to_string()];
+        assert_eq!(
+            resolve_locale("en-au", Some(&supported)),
+            Ok("en_au".to_string())
+        );
+        assert_eq!(
+            resolve_locale("zh-CN", Some(&supported)),
+            Err(LocaleError::Unsupported("zh_CN".to_string()))
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_before_sending() {
+        let mut sys = SyncSystem::new("test:sync-locale".to_string());
+        let err = sys
+            .set_language("not a locale".to_string(), None, true)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LocaleError>(),
+            Some(&LocaleError::Malformed("not a locale".to_string()))
+        );
+
+        let supported = vec!["en_US".to_string()];
+        let err = sys
+            .set_language("fr-FR".to_string(), Some(&supported), true)
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<LocaleError>(),
+            Some(&LocaleError::Unsupported("fr_FR".to_string()))
+        );
+    }
+}
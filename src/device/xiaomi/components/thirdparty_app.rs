@@ -1,7 +1,7 @@
 use pb::xiaomi::protocol::{self, WearPacket};
 
 use crate::{
-    device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet},
+    device::xiaomi::system::{L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
     ecs::Component,
 };
 
@@ -91,9 +91,11 @@ impl L2PbExt for ThirdpartyAppSystem {
         if let Some(protocol::wear_packet::Payload::ThirdpartyApp(app)) = payload.payload {
             match app.payload {
                 Some(protocol::thirdparty_app::Payload::BasicInfo(basic_info)) => {
+                    mark_pb_consumed();
                     self.handle_basic_info(basic_info);
                 }
                 Some(protocol::thirdparty_app::Payload::MessageContent(message)) => {
+                    mark_pb_consumed();
                     self.handle_message_content(message);
                 }
                 Some(protocol::thirdparty_app::Payload::AppStatus(status)) => {
@@ -2,11 +2,13 @@ use pb::xiaomi::protocol::{self, WearPacket};
 use tokio::sync::oneshot;
 
 use crate::{
-    device::xiaomi::system::{L2PbExt, register_xiaomi_system_ext_on_l2packet},
-    ecs::Component,
+    anyhow_site,
+    device::xiaomi::system::{L2PbExt, mark_pb_consumed, register_xiaomi_system_ext_on_l2packet},
+    ecs::{Component, access::with_device_component_mut},
 };
 
-use super::shared::{HasOwnerId, RequestSlot, SystemRequestExt};
+use super::resource::build_watchface_get_installed;
+use super::shared::{HasOwnerId, RequestSlot, SystemRequestExt, await_response};
 
 #[derive(Component)]
 pub struct WatchfaceSystem {
@@ -15,6 +17,7 @@ pub struct WatchfaceSystem {
     bg_image_wait: RequestSlot<protocol::BgImageResult>,
     font_wait: RequestSlot<protocol::FontResult>,
     support_data_wait: RequestSlot<Vec<i32>>,
+    current_wait: RequestSlot<WatchfaceInfo>,
 }
 
 impl Default for WatchfaceSystem {
@@ -32,12 +35,39 @@ impl WatchfaceSystem {
             bg_image_wait: RequestSlot::new(),
             font_wait: RequestSlot::new(),
             support_data_wait: RequestSlot::new(),
+            current_wait: RequestSlot::new(),
         }
     }
 
     pub fn set_watchface(&mut self, watchface_id: &str) {
         let packet = build_watchface_set(watchface_id);
         self.enqueue_request(packet);
+
+        // 只记成待确认，手表回 SetWatchFace / 列表时才算换了
+        let id = watchface_id.to_string();
+        if let Err(err) = with_device_component_mut::<WatchfaceComponent, _, _>(
+            self.owner_id.clone(),
+            move |comp| comp.mark_pending(id),
+        ) {
+            log::warn!("[Watchface] failed to record pending watchface: {err:?}");
+        }
+    }
+
+    /// 当前表盘，从已安装列表里的 is_current 推出来（每次都会刷新列表）
+    pub async fn get_current(&mut self) -> anyhow::Result<WatchfaceInfo> {
+        await_response(
+            self.request_current(),
+            "Current watchface response not received",
+        )
+        .await
+    }
+
+    pub fn request_current(&mut self) -> oneshot::Receiver<anyhow::Result<WatchfaceInfo>> {
+        let (rx, should_enqueue) = self.current_wait.prepare();
+        if should_enqueue {
+            self.enqueue_request(build_watchface_get_installed());
+        }
+        rx
     }
 
     pub fn uninstall_watchface(&mut self, watchface_id: &str) {
@@ -79,11 +109,64 @@ impl WatchfaceSystem {
     }
 }
 
+impl WatchfaceSystem {
+    fn on_installed_list(&mut self, list: &[protocol::WatchFaceItem]) {
+        let Some(current) = list.iter().find(|face| face.is_current) else {
+            self.current_wait
+                .fail(anyhow_site!("no watchface is marked as current"));
+            return;
+        };
+
+        let info = WatchfaceInfo::from(current);
+        self.confirm_current(&info.id);
+        self.current_wait.fulfill(info);
+    }
+
+    /// 手表报上来的当前表盘，变了就发 WatchfaceChanged
+    fn confirm_current(&mut self, watchface_id: &str) {
+        let id = watchface_id.to_string();
+        let changed = match with_device_component_mut::<WatchfaceComponent, _, _>(
+            self.owner_id.clone(),
+            move |comp| comp.confirm(id),
+        ) {
+            Ok(changed) => changed,
+            Err(err) => {
+                log::warn!("[Watchface] failed to update current watchface: {err:?}");
+                return;
+            }
+        };
+
+        if changed {
+            log::info!("[Watchface] current watchface changed to {watchface_id}");
+            crate::events::emit(crate::events::CoreEvent::WatchfaceChanged(
+                crate::events::WatchfaceChanged {
+                    device_addr: self.owner_id.clone(),
+                    watchface_id: watchface_id.to_string(),
+                },
+            ));
+        }
+    }
+}
+
 impl L2PbExt for WatchfaceSystem {
     fn on_pb_packet(&mut self, payload: WearPacket) {
         if let Some(protocol::wear_packet::Payload::WatchFace(msg)) = payload.payload {
+            let is_set_watchface =
+                payload.id == protocol::watch_face::WatchFaceId::SetWatchFace as u32;
+            let is_installed_list =
+                payload.id == protocol::watch_face::WatchFaceId::GetInstalledList as u32;
             match msg.payload {
+                // 用户在手表上切表盘时手表会主动推这个，我们自己 set 的回包也长这样
+                Some(protocol::watch_face::Payload::Id(id)) if is_set_watchface => {
+                    mark_pb_consumed();
+                    self.confirm_current(&id);
+                }
+                Some(protocol::watch_face::Payload::WatchFaceList(list)) if is_installed_list => {
+                    mark_pb_consumed();
+                    self.on_installed_list(&list.list);
+                }
                 Some(protocol::watch_face::Payload::EditResponse(resp)) => {
+                    mark_pb_consumed();
                     log::debug!(
                         "[Watchface] edit response: {:?}",
                         serde_json::to_string(&resp).unwrap_or_default()
@@ -91,6 +174,7 @@ impl L2PbExt for WatchfaceSystem {
                     self.edit_wait.fulfill(resp);
                 }
                 Some(protocol::watch_face::Payload::BgImageResult(result)) => {
+                    mark_pb_consumed();
                     log::debug!(
                         "[Watchface] bg image result: {:?}",
                         serde_json::to_string(&result).unwrap_or_default()
@@ -98,9 +182,11 @@ impl L2PbExt for WatchfaceSystem {
                     self.bg_image_wait.fulfill(result);
                 }
                 Some(protocol::watch_face::Payload::SupportDataList(list)) => {
+                    mark_pb_consumed();
                     self.support_data_wait.fulfill(list.list);
                 }
                 Some(protocol::watch_face::Payload::FontResult(result)) => {
+                    mark_pb_consumed();
                     log::debug!(
                         "[Watchface] font result: code={} id={}",
                         result.code,
@@ -129,12 +215,47 @@ impl HasOwnerId for WatchfaceSystem {
     }
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct WatchfaceInfo {
+    pub id: String,
+    pub name: String,
+}
+
+impl From<&protocol::WatchFaceItem> for WatchfaceInfo {
+    fn from(item: &protocol::WatchFaceItem) -> Self {
+        Self {
+            id: item.id.clone(),
+            name: item.name.clone(),
+        }
+    }
+}
+
 #[derive(Component, serde::Serialize)]
-pub struct WatchfaceComponent {}
+pub struct WatchfaceComponent {
+    pub current_id: Option<String>,
+    /// set_watchface 发出去了但手表还没确认的表盘
+    pub pending_id: Option<String>,
+}
 
 impl WatchfaceComponent {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            current_id: None,
+            pending_id: None,
+        }
+    }
+
+    fn mark_pending(&mut self, watchface_id: String) {
+        self.pending_id = Some(watchface_id);
+    }
+
+    /// 记下手表确认的当前表盘，返回是否算一次切换。
+    /// 没换成功时手表回的还是旧表盘，current_id 不动也不算切换
+    fn confirm(&mut self, watchface_id: String) -> bool {
+        self.pending_id = None;
+        let changed = self.current_id.as_deref() != Some(watchface_id.as_str());
+        self.current_id = Some(watchface_id);
+        changed
     }
 }
 
@@ -181,3 +302,77 @@ fn build_watchface_get_support_data() -> protocol::WearPacket {
         payload: None,
     }
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::{spawn_mock_xiaomi, xiaomi::config::XiaomiDeviceConfig};
+    use crate::events::{self, CoreEvent};
+
+    fn face_changed_packet(watchface_id: &str) -> WearPacket {
+        // 和 build_watchface_set 一个形状，只是方向反过来
+        build_watchface_set(watchface_id)
+    }
+
+    fn current_id(id: &str) -> Option<String> {
+        crate::ecs::access::with_device_component_ref::<WatchfaceComponent, _, _>(
+            id.to_string(),
+            |comp| comp.current_id.clone(),
+        )
+        .unwrap()
+    }
+
+    fn changed_events(
+        rx: &mut tokio::sync::broadcast::Receiver<CoreEvent>,
+        id: &str,
+    ) -> Vec<String> {
+        let mut out = vec![];
+        while let Ok(event) = rx.try_recv() {
+            if let CoreEvent::WatchfaceChanged(changed) = event {
+                if changed.device_addr == id {
+                    out.push(changed.watchface_id);
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn unsolicited_change_updates_current_and_emits() {
+        let id = "test:watchface-unsolicited";
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(spawn_mock_xiaomi(id, XiaomiDeviceConfig::default()));
+        let mut rx = events::subscribe();
+        let mut sys = WatchfaceSystem::new(id.to_string());
+
+        sys.on_pb_packet(face_changed_packet("abc"));
+        sys.on_pb_packet(face_changed_packet("abc"));
+        sys.on_pb_packet(face_changed_packet("def"));
+
+        assert_eq!(current_id(id).as_deref(), Some("def"));
+        // 重复推同一个表盘不算切换
+        assert_eq!(changed_events(&mut rx, id), vec!["abc", "def"]);
+
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| rt.remove_device(id)));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
+
+    #[test]
+    fn set_changes_current_only_when_confirmed() {
+        let mut comp = WatchfaceComponent::new();
+        assert!(comp.confirm("old".to_string()));
+
+        comp.mark_pending("new".to_string());
+        assert_eq!(comp.current_id.as_deref(), Some("old"));
+        assert_eq!(comp.pending_id.as_deref(), Some("new"));
+        assert!(comp.confirm("new".to_string()));
+        assert!(comp.pending_id.is_none());
+
+        // 手表没换成功，回来的还是旧的：不算切换
+        comp.mark_pending("other".to_string());
+        assert!(!comp.confirm("new".to_string()));
+        assert_eq!(comp.current_id.as_deref(), Some("new"));
+        assert!(comp.pending_id.is_none());
+    }
+}
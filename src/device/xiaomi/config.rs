@@ -1,7 +1,24 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::device::xiaomi::clock::SharedClock;
+use crate::device::xiaomi::packet::{mass::MassDataType, v2::layer2::L2Channel};
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct TransportConfig {
     pub chunk_size_spp: usize,
     pub chunk_size_ble: usize,
+    /// BLE 分片之间的间隔，0 表示整批一次交给传输层。
+    /// 部分安卓 BLE 栈连续 write 不等确认会静默丢包，这时候调大点
+    pub ble_chunk_delay_ms: u64,
+    /// 每个 `ble_interval_ms` 里最多写这么多片，写满了等到下个间隔再接着写。
+    /// 比固定间隔更贴合连接间隔的突发发送，GATT 写队列小的安卓栈用
+    pub ble_max_writes_per_interval: u32,
+    /// 一般设成连接间隔。和上面那个都非 0 才生效，SPP/TCP 不受影响
+    pub ble_interval_ms: u64,
+    /// 每个 L1 帧都从新的一片开始、单独交给传输层一次，片长不够放帧头时按帧头长度切。
+    /// 有的手表在帧头被拆到两次 write 里时会把解析器搞崩，开了以后每帧多一次 write
+    pub align_frames: bool,
 }
 
 impl Default for TransportConfig {
@@ -9,26 +26,75 @@ impl Default for TransportConfig {
         Self {
             chunk_size_spp: 666,
             chunk_size_ble: 244,
+            ble_chunk_delay_ms: 0,
+            ble_max_writes_per_interval: 0,
+            ble_interval_ms: 0,
+            align_frames: false,
         }
     }
 }
 
+impl TransportConfig {
+    /// 没配齐就是 None
+    pub fn ble_pacing(&self) -> Option<BlePacing> {
+        (self.ble_max_writes_per_interval > 0 && self.ble_interval_ms > 0).then_some(BlePacing {
+            max_writes_per_interval: self.ble_max_writes_per_interval,
+            interval_ms: self.ble_interval_ms,
+        })
+    }
+}
+
+/// BLE 按连接间隔限速的参数，见 `TransportConfig::ble_interval_ms`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BlePacing {
+    pub max_writes_per_interval: u32,
+    pub interval_ms: u64,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct SarConfig {
     pub tx_win_overrun_allowance: u8,
+    /// 发送端报 Disconnected 后保留队列等待恢复的宽限期
+    pub reconnect_grace_ms: u64,
+    /// 拼 L1 帧的接收缓冲超过这么大就当持续错位，巡检时直接清掉。
+    /// 正常情况下最多也就一个最大帧（64K 左右）加半个包。收包当场的硬上限见 `DispatcherConfig`
+    pub recv_buffer_limit: usize,
+    /// 收到已经收过的 Data（对端没收到我们的 ACK 在重传）时补一个 ACK。
+    /// 关掉就和官方一样静默丢弃，对端只能等超时
+    pub ack_duplicate_data: bool,
+    /// 顺序收到 Data 后攒多久再回累积 ACK，0 表示每个包都立刻 ACK
+    pub cum_ack_delay_ms: u64,
+    /// 收包时逐帧校验 L1 CRC。SPP/TCP 本身保证完整性，高速收大包时可以关掉省点 CPU；
+    /// BLE 不管这里怎么设都会校验
+    pub verify_crc: bool,
+    /// 超时巡检、累积 ACK、重传 deadline 用的时钟，测试里换成 `MockClock`。
+    /// 只在建 SarController 时读，`update_config` 不会换
+    #[serde(skip)]
+    pub clock: SharedClock,
 }
 
 impl Default for SarConfig {
     fn default() -> Self {
         Self {
             tx_win_overrun_allowance: 0,
+            reconnect_grace_ms: 5_000,
+            recv_buffer_limit: 256 * 1024,
+            ack_duplicate_data: true,
+            cum_ack_delay_ms: 500,
+            verify_crc: true,
+            clock: SharedClock::default(),
         }
     }
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct MassConfig {
+    /// 等单个 ACK 的超时；期间设备一直没出声就按这个算超时
     pub ack_wait_timeout_secs: u64,
+    /// 设备还在回包（写 flash 之类的忙）时最多耐心等这么久
+    pub busy_patience_secs: u64,
+    /// 等 ACK 时这么久没收到设备任何包，直接当链路断了，不用干等满超时
+    pub link_silence_timeout_ms: u64,
     pub ack_poll_interval_ms: u64,
     pub ack_stall_default_ms: u64,
     pub ack_stall_min_ms: u64,
@@ -37,12 +103,31 @@ pub struct MassConfig {
     pub max_batch_parts: usize,
     pub fallback_batch_parts: usize,
     pub fallback_backlog_limit: usize,
+    /// 单次传输最多分多少片，超过直接报错。协议里片号是 u16，设得再大也会被截到 65535
+    pub max_total_parts: usize,
+    /// MASS 分片要不要走 WriteEnc，宿主开关（手表没有能力位可查）：
+    /// None 不动 `channel_crypto` 里 Mass 的配置，Some 在建设备时覆盖成 Auto / Never，见 `encrypts_mass`
+    pub encrypt_frames: Option<bool>,
+    /// 发送时想用的压缩模式号（MASS 头的 comp_data），None 不压缩。手表没有能力位可查，
+    /// 纯 MASS 传输先压着问，prepare 不回 Ready 就原样重发并记下不再压；表盘安装得等手表推文件时用过
+    /// 或者之前接受过这个模式才压，见 `mass::negotiate_compress_mode`。固件、快应用、通知图标永远不压。
+    /// 对应的编解码器要先用 `packet::mass::codec::register_codec` 注册，没注册就原样发
+    pub compress_mode: Option<u8>,
+    /// 手表主动推文件时，没设 `set_incoming_transfer_policy` 就按这个大小决定收不收
+    pub max_incoming_bytes: u64,
+    /// 接受手表推文件时在 PrepareResponse 里回给它的分片长度
+    pub incoming_slice_length: u32,
+    /// 节流、等 ACK 计时用的时钟，测试里换成 `MockClock`
+    #[serde(skip)]
+    pub clock: SharedClock,
 }
 
 impl Default for MassConfig {
     fn default() -> Self {
         Self {
             ack_wait_timeout_secs: 30,
+            busy_patience_secs: 180,
+            link_silence_timeout_ms: 8_000,
             ack_poll_interval_ms: 50,
             ack_stall_default_ms: 400,
             ack_stall_min_ms: 120,
@@ -51,6 +136,12 @@ impl Default for MassConfig {
             max_batch_parts: 32,
             fallback_batch_parts: 8,
             fallback_backlog_limit: 96,
+            max_total_parts: u16::MAX as usize,
+            encrypt_frames: None,
+            compress_mode: None,
+            max_incoming_bytes: 64 * 1024 * 1024,
+            incoming_slice_length: 4096,
+            clock: SharedClock::default(),
         }
     }
 }
@@ -59,6 +150,43 @@ impl Default for MassConfig {
 pub struct ResConfig {
     pub watchface_id_offset: usize,
     pub watchface_id_field_len: usize,
+    /// MASS 传完后等安装结果的超时（表盘、快应用等）
+    pub install_result_timeout_secs: u64,
+    /// 固件校验慢得多，单独给
+    pub firmware_install_result_timeout_secs: u64,
+    /// 通知图标传完后会再回一次 AppIconResponse 确认的固件（按前缀匹配）。
+    /// 只有这些才等确认，其余的 MASS 传完就算送达
+    pub icon_ack_firmwares: Vec<String>,
+    /// 上面那些固件传完图标后等确认的时间
+    pub icon_ack_timeout_secs: u64,
+    /// 这些固件版本（按前缀匹配）不认大的表盘 version_code，只能发老的常量 65536
+    pub legacy_watchface_version_firmwares: Vec<String>,
+}
+
+impl ResConfig {
+    pub fn install_result_timeout(&self, data_type: MassDataType) -> Duration {
+        let secs = match data_type {
+            MassDataType::Firmware => self.firmware_install_result_timeout_secs,
+            MassDataType::NotificationIcon => self.icon_ack_timeout_secs,
+            _ => self.install_result_timeout_secs,
+        };
+        Duration::from_secs(secs.max(1))
+    }
+
+    pub fn needs_legacy_watchface_version(&self, firmware_version: &str) -> bool {
+        firmware_matches(&self.legacy_watchface_version_firmwares, firmware_version)
+    }
+
+    pub fn sends_icon_ack(&self, firmware_version: &str) -> bool {
+        firmware_matches(&self.icon_ack_firmwares, firmware_version)
+    }
+}
+
+fn firmware_matches(prefixes: &[String], firmware_version: &str) -> bool {
+    !firmware_version.is_empty()
+        && prefixes
+            .iter()
+            .any(|prefix| firmware_version.starts_with(prefix.as_str()))
 }
 
 impl Default for ResConfig {
@@ -66,17 +194,202 @@ impl Default for ResConfig {
         Self {
             watchface_id_offset: 34,
             watchface_id_field_len: 24,
+            install_result_timeout_secs: 45,
+            firmware_install_result_timeout_secs: 120,
+            icon_ack_firmwares: Vec::new(),
+            icon_ack_timeout_secs: 3,
+            legacy_watchface_version_firmwares: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct InfoConfig {
+    /// 电量历史环形缓冲长度，0 表示不记录
+    pub battery_history_len: usize,
+}
+
+impl Default for InfoConfig {
+    fn default() -> Self {
+        Self {
+            battery_history_len: 0,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum ChannelCrypto {
+    /// 必须加密，没有 cipher 就不发。只管发送方向：认证后手表推来的明文照收，只计数（见 `dispatcher_stats`）
+    Always,
+    /// 永远明文
+    Never,
+    /// 有 cipher（认证完成后）就加密，否则明文
+    Auto,
+}
+
+/// 每个 L2 通道发送时要不要加密。
+/// 有的固件 Mass 分片必须明文（自带 CRC），Network 在不同代固件上要求也不一样，所以做成可配
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChannelCryptoPolicy {
+    channels: HashMap<L2Channel, ChannelCrypto>,
+}
+
+impl ChannelCryptoPolicy {
+    /// 没配置过的通道按明文处理
+    pub fn get(&self, channel: L2Channel) -> ChannelCrypto {
+        self.channels
+            .get(&channel)
+            .copied()
+            .unwrap_or(ChannelCrypto::Never)
+    }
+
+    pub fn set(&mut self, channel: L2Channel, crypto: ChannelCrypto) -> &mut Self {
+        self.channels.insert(channel, crypto);
+        self
+    }
+}
+
+impl Default for ChannelCryptoPolicy {
+    fn default() -> Self {
+        // 和之前写死的行为一致：只有 Pb 在认证后加密
+        let mut policy = Self {
+            channels: HashMap::new(),
+        };
+        policy
+            .set(L2Channel::Pb, ChannelCrypto::Auto)
+            .set(L2Channel::Mass, ChannelCrypto::Never)
+            .set(L2Channel::Network, ChannelCrypto::Never);
+        policy
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct XiaomiDeviceConfig {
     pub transport: TransportConfig,
     pub sar: SarConfig,
     pub mass: MassConfig,
     pub res: ResConfig,
+    pub info: InfoConfig,
     pub network: NetworkConfig,
+    pub channel_crypto: ChannelCryptoPolicy,
+    /// 建设备时套用的 QoS 预设，None 等同 Balanced。预设管的字段里单独改过
+    /// （和默认值不一样）的以单独改的为准；会话中途换用 `device::set_qos`
+    pub qos_profile: Option<QosProfile>,
+}
+
+/// SAR / MASS / 网络那一堆互相牵连的参数的成套预设，不用自己一个个调
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum QosProfile {
+    /// 交互优先：不超发窗口、每包立刻 ACK、小批量，排队的东西少
+    Latency,
+    /// 就是默认值
+    Balanced,
+    /// 传大文件（刷固件、表盘）用：窗口超发、累积 ACK、大批量
+    Throughput,
+}
+
+impl QosProfile {
+    /// 只改预设管的那几个字段，其它配置原样保留；`config` 里已经单独改过的字段不动。
+    /// 网络缓冲在网络栈启动时才读，会话中途切换要等下次重建网络栈
+    pub fn apply(self, config: &mut XiaomiDeviceConfig) {
+        let requested = config.clone();
+        self.apply_over(config, &requested);
+    }
+
+    /// 预设管的字段按 `requested` 定：单独改过（和默认值不一样）的用它的，没改过的用预设值。
+    /// 和默认值一样的改动分辨不出来，当作没改
+    pub(crate) fn apply_over(
+        self,
+        config: &mut XiaomiDeviceConfig,
+        requested: &XiaomiDeviceConfig,
+    ) {
+        let preset = self.preset();
+        let default = XiaomiDeviceConfig::default();
+        macro_rules! pick {
+            ($($section:ident . $field:ident),* $(,)?) => {
+                $(
+                    config.$section.$field =
+                        if requested.$section.$field != default.$section.$field {
+                            requested.$section.$field
+                        } else {
+                            preset.$section.$field
+                        };
+                )*
+            };
+        }
+        pick!(
+            sar.tx_win_overrun_allowance,
+            sar.cum_ack_delay_ms,
+            mass.ack_poll_interval_ms,
+            mass.backlog_multiplier,
+            mass.max_batch_parts,
+            mass.fallback_batch_parts,
+            mass.fallback_backlog_limit,
+            network.ingress_buffer,
+            network.tun_buffer,
+            network.outbound_buffer,
+        );
+        config.qos_profile = Some(self);
+    }
+
+    /// 默认配置套上这个预设的样子
+    fn preset(self) -> XiaomiDeviceConfig {
+        let (sar, mass, network) = match self {
+            Self::Balanced => (
+                SarConfig::default(),
+                MassConfig::default(),
+                NetworkConfig::default(),
+            ),
+            Self::Latency => (
+                SarConfig {
+                    tx_win_overrun_allowance: 0,
+                    cum_ack_delay_ms: 0,
+                    ..SarConfig::default()
+                },
+                MassConfig {
+                    ack_poll_interval_ms: 20,
+                    backlog_multiplier: 2,
+                    max_batch_parts: 8,
+                    fallback_batch_parts: 4,
+                    fallback_backlog_limit: 16,
+                    ..MassConfig::default()
+                },
+                NetworkConfig {
+                    ingress_buffer: 64,
+                    tun_buffer: 64,
+                    outbound_buffer: 32,
+                    ..NetworkConfig::default()
+                },
+            ),
+            Self::Throughput => (
+                SarConfig {
+                    tx_win_overrun_allowance: 4,
+                    cum_ack_delay_ms: 500,
+                    ..SarConfig::default()
+                },
+                MassConfig {
+                    ack_poll_interval_ms: 100,
+                    backlog_multiplier: 8,
+                    max_batch_parts: 64,
+                    fallback_batch_parts: 16,
+                    fallback_backlog_limit: 192,
+                    ..MassConfig::default()
+                },
+                NetworkConfig {
+                    ingress_buffer: 1024,
+                    tun_buffer: 1024,
+                    outbound_buffer: 512,
+                    ..NetworkConfig::default()
+                },
+            ),
+        };
+        XiaomiDeviceConfig {
+            sar,
+            mass,
+            network,
+            ..XiaomiDeviceConfig::default()
+        }
+    }
 }
 
 impl Default for XiaomiDeviceConfig {
@@ -86,9 +399,103 @@ impl Default for XiaomiDeviceConfig {
             sar: SarConfig::default(),
             mass: MassConfig::default(),
             res: ResConfig::default(),
+            info: InfoConfig::default(),
             network: NetworkConfig::default(),
+            channel_crypto: ChannelCryptoPolicy::default(),
+            qos_profile: None,
+        }
+    }
+}
+
+impl XiaomiDeviceConfig {
+    /// 默认配置套上预设，之后再单独改的字段以单独改的为准
+    pub fn with_qos(profile: QosProfile) -> Self {
+        let mut config = Self::default();
+        profile.apply(&mut config);
+        config
+    }
+
+    /// 建设备时用：按 `qos_profile` 套预设，单独改过的字段盖在预设上面；
+    /// `mass.encrypt_frames` 也在这里落到 Mass 通道策略上
+    pub fn resolved(&self) -> Self {
+        let mut config = self.clone();
+        if let Some(profile) = self.qos_profile {
+            profile.apply_over(&mut config, self);
+        }
+        let mass_crypto = apply_mass_encrypt_switch(
+            config.channel_crypto.get(L2Channel::Mass),
+            config.mass.encrypt_frames,
+        );
+        config.channel_crypto.set(L2Channel::Mass, mass_crypto);
+        config
+    }
+
+    /// MASS 分片会不会用认证后的 cipher 加密发送。这是宿主的策略，不是手表报的能力；
+    /// SAR v1 没有 L2，不管怎么配都是明文
+    pub fn encrypts_mass(&self, sar_version: u32) -> bool {
+        sar_version >= 2
+            && matches!(
+                apply_mass_encrypt_switch(
+                    self.channel_crypto.get(L2Channel::Mass),
+                    self.mass.encrypt_frames,
+                ),
+                ChannelCrypto::Auto | ChannelCrypto::Always
+            )
+    }
+
+    /// 只列出和默认值不一样的字段，按原来的分区嵌套，叶子是 `{ "default": .., "current": .. }`。
+    /// 两边都先转成 JSON 再比，以后加字段不用改这里
+    pub fn diff_from_default(&self) -> serde_json::Value {
+        let current = serde_json::to_value(self).unwrap_or_default();
+        let default = serde_json::to_value(Self::default()).unwrap_or_default();
+        json_diff(&default, &current)
+            .unwrap_or_else(|| serde_json::Value::Object(Default::default()))
+    }
+}
+
+/// 把宿主的 `MassConfig::encrypt_frames` 开关落到 Mass 通道策略上。手表没有能力位可查，
+/// 这里不做协商：None 保持宿主在 channel_crypto 里配的，Some 覆盖。
+/// 开的时候用 Auto 而不是 Always：cipher 没准备好时退回明文，总比整个传输失败强
+fn apply_mass_encrypt_switch(
+    current: ChannelCrypto,
+    encrypt_frames: Option<bool>,
+) -> ChannelCrypto {
+    match encrypt_frames {
+        Some(true) => ChannelCrypto::Auto,
+        Some(false) => ChannelCrypto::Never,
+        None => current,
+    }
+}
+
+fn json_diff(
+    default: &serde_json::Value,
+    current: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    use serde_json::{Map, Value};
+
+    if default == current {
+        return None;
+    }
+    let (Value::Object(default_map), Value::Object(current_map)) = (default, current) else {
+        let mut leaf = Map::new();
+        leaf.insert("default".to_string(), default.clone());
+        leaf.insert("current".to_string(), current.clone());
+        return Some(Value::Object(leaf));
+    };
+
+    // 只在一边有的字段当另一边是 null
+    let mut diff = Map::new();
+    for key in default_map.keys().chain(current_map.keys()) {
+        if diff.contains_key(key) {
+            continue;
+        }
+        let default_val = default_map.get(key).unwrap_or(&Value::Null);
+        let current_val = current_map.get(key).unwrap_or(&Value::Null);
+        if let Some(changed) = json_diff(default_val, current_val) {
+            diff.insert(key.clone(), changed);
         }
     }
+    Some(Value::Object(diff))
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -100,6 +507,20 @@ pub struct NetworkConfig {
     pub meter_window_secs: u64,
     pub enable_capture: bool,
     pub capture_dir: Option<String>,
+    /// 抓包文件的链路层类型，见 `CaptureLinkType`
+    pub capture_link_type: CaptureLinkType,
+    /// 代手表连目标地址的超时，系统默认的太长，手表那边会一直挂着
+    pub connect_timeout_secs: u64,
+    /// 连目标遇到网络不可达之类的瞬时错误时再试几次，0 就是不重试。
+    /// 被拒/超时不重试，所有尝试加起来也不会超过 `connect_timeout_secs`
+    pub connect_retries: u32,
+    /// 第一次重试前等多久，之后每次翻倍
+    pub connect_retry_backoff_ms: u64,
+    /// 手表发来的 IP 包比协议栈的读缓冲还大时怎么办，见 `OversizedPacketPolicy`
+    pub oversized_inbound: OversizedPacketPolicy,
+    /// 明文 HTTP 的响应源站没压缩时代为 gzip，请求也会补上 Accept-Encoding。
+    /// 省的是蓝牙带宽，代价是手机这边的 CPU 和大响应要整段攒着，默认关
+    pub http_compression: bool,
 }
 
 impl Default for NetworkConfig {
@@ -112,6 +533,145 @@ impl Default for NetworkConfig {
             meter_window_secs: 5,
             enable_capture: false,
             capture_dir: None,
+            capture_link_type: CaptureLinkType::default(),
+            connect_timeout_secs: 10,
+            connect_retries: 2,
+            connect_retry_backoff_ms: 100,
+            http_compression: false,
+            oversized_inbound: OversizedPacketPolicy::default(),
         }
     }
 }
+
+/// 抓包写成哪种链路层。手表走的本来就是裸 IP，没有 MAC 可言
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub enum CaptureLinkType {
+    /// LINKTYPE_RAW，包就是 IP 头开头，Wireshark 按版本号自己分 v4/v6
+    #[default]
+    Raw,
+    /// 垫一个假的以太网头（a5:a5:.. 是手表，全 0 是手机），给只认以太网的老工具用
+    Ethernet,
+}
+
+/// 入方向 IP 包超过协议栈读缓冲时的处理。以前是截断，头完整但负载少了一截，
+/// 协议栈会被搞糊涂，比整包丢掉还糟
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub enum OversizedPacketPolicy {
+    /// 整包丢掉打个 warn，让对端重传去
+    #[default]
+    Drop,
+    /// 这次读填满缓冲，剩下的留给下次读。只在 MTU 和缓冲大小对不上、
+    /// 而读的一方按字节流拼包时才有用
+    Carry,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn mass_encrypt_switch_only_overrides_when_set() {
+        use ChannelCrypto::*;
+        // 没开关就保持宿主自己配的策略
+        assert_eq!(apply_mass_encrypt_switch(Never, None), Never);
+        assert_eq!(apply_mass_encrypt_switch(Always, None), Always);
+        assert_eq!(apply_mass_encrypt_switch(Never, Some(true)), Auto);
+        assert_eq!(apply_mass_encrypt_switch(Always, Some(false)), Never);
+
+        let mut config = XiaomiDeviceConfig::default();
+        assert!(!config.encrypts_mass(2));
+        config.mass.encrypt_frames = Some(true);
+        assert!(config.encrypts_mass(2));
+        // v1 没有 L2 cipher
+        assert!(!config.encrypts_mass(1));
+        assert_eq!(config.resolved().channel_crypto.get(L2Channel::Mass), Auto);
+    }
+
+    #[test]
+    fn diff_lists_only_overridden_fields() {
+        assert_eq!(XiaomiDeviceConfig::default().diff_from_default(), json!({}));
+
+        let mut config = XiaomiDeviceConfig::default();
+        config.transport.chunk_size_ble = 180;
+        config.mass.max_total_parts = 1_000;
+
+        assert_eq!(
+            config.diff_from_default(),
+            json!({
+                "transport": {
+                    "chunk_size_ble": {
+                        "default": TransportConfig::default().chunk_size_ble,
+                        "current": 180,
+                    },
+                },
+                "mass": {
+                    "max_total_parts": {
+                        "default": MassConfig::default().max_total_parts,
+                        "current": 1_000,
+                    },
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn qos_profiles_set_documented_values() {
+        let balanced = XiaomiDeviceConfig::with_qos(QosProfile::Balanced);
+        assert_eq!(balanced.qos_profile, Some(QosProfile::Balanced));
+        assert_eq!(
+            balanced.diff_from_default(),
+            json!({ "qos_profile": { "default": null, "current": "Balanced" } })
+        );
+
+        let latency = XiaomiDeviceConfig::with_qos(QosProfile::Latency);
+        assert_eq!(latency.sar.tx_win_overrun_allowance, 0);
+        assert_eq!(latency.sar.cum_ack_delay_ms, 0);
+        assert_eq!(latency.mass.max_batch_parts, 8);
+        assert_eq!(latency.mass.fallback_batch_parts, 4);
+        assert_eq!(latency.mass.ack_poll_interval_ms, 20);
+        assert_eq!(latency.network.outbound_buffer, 32);
+
+        let throughput = XiaomiDeviceConfig::with_qos(QosProfile::Throughput);
+        assert_eq!(throughput.sar.tx_win_overrun_allowance, 4);
+        assert_eq!(throughput.sar.cum_ack_delay_ms, 500);
+        assert_eq!(throughput.mass.max_batch_parts, 64);
+        assert_eq!(throughput.mass.backlog_multiplier, 8);
+        assert_eq!(throughput.network.ingress_buffer, 1024);
+        // 预设不管的字段不动
+        assert_eq!(throughput.mass.ack_wait_timeout_secs, 30);
+        assert_eq!(throughput.res.install_result_timeout_secs, 45);
+    }
+
+    #[test]
+    fn explicit_overrides_win_over_qos() {
+        // 调用方只选了预设，又单独改了一个预设管的字段
+        let requested = XiaomiDeviceConfig {
+            qos_profile: Some(QosProfile::Throughput),
+            sar: SarConfig {
+                tx_win_overrun_allowance: 2,
+                ..SarConfig::default()
+            },
+            ..XiaomiDeviceConfig::default()
+        };
+        let resolved = requested.resolved();
+        assert_eq!(resolved.sar.tx_win_overrun_allowance, 2);
+        assert_eq!(resolved.sar.cum_ack_delay_ms, 500);
+        assert_eq!(resolved.mass.max_batch_parts, 64);
+
+        // 中途换预设，单独改过的还是不动，其余的换成新预设的
+        let mut live = resolved.clone();
+        QosProfile::Latency.apply_over(&mut live, &requested);
+        assert_eq!(live.sar.tx_win_overrun_allowance, 2);
+        assert_eq!(live.sar.cum_ack_delay_ms, 0);
+        assert_eq!(live.mass.max_batch_parts, 8);
+        assert_eq!(live.qos_profile, Some(QosProfile::Latency));
+
+        // 没选预设就原样
+        let plain = XiaomiDeviceConfig::default();
+        assert_eq!(
+            plain.resolved().mass.max_batch_parts,
+            plain.mass.max_batch_parts
+        );
+    }
+}
@@ -1,4 +1,11 @@
 pub mod cipher;
 pub mod dispatcher;
 pub mod mass;
+pub mod raw_pb;
+pub mod read;
+// 线上帧格式的内部实现，除了 fuzz/ 下游别直接依赖这里的路径
+#[doc(hidden)]
 pub mod v2;
+
+// 自定义系统和通道加密配置要用这两个，单独提到外面来
+pub use v2::layer2::{L2Channel, L2OpCode};
@@ -4,12 +4,14 @@ use std::{
 };
 
 use pb::xiaomi::protocol;
+use prost::Message;
 
 use crate::{
     device::xiaomi::{
         XiaomiDevice,
         components::auth::AuthComponent,
-        packet::v2::layer2::{L2Cipher, L2Packet},
+        config::ChannelCrypto,
+        packet::v2::layer2::{L2Channel, L2Cipher, L2Error, L2OpCode, L2Packet},
     },
     ecs::runtime::Runtime,
 };
@@ -68,6 +70,9 @@ pub async fn ensure_l2_cipher(device_id: &str, sar_version: u32) -> Option<Share
     }
 }
 
+/// 只给拿着 `&mut XiaomiDevice` 的同步上下文用（system 回调、ECS 闭包里）。
+/// 不在 ECS 线程上时会 block_on，已经在 async 里的调用方请用 `ensure_l2_cipher` 或
+/// `enqueue_pb_packet_async`
 pub fn ensure_l2_cipher_blocking(device_id: &str, sar_version: u32) -> Option<SharedL2Cipher> {
     if let Some(existing) = get_l2_cipher(device_id) {
         return Some(existing);
@@ -86,30 +91,135 @@ pub fn ensure_l2_cipher_blocking(device_id: &str, sar_version: u32) -> Option<Sh
     })
 }
 
+/// 按设备配置里的通道加密策略把 payload 打成 L2 写包，所有发送方都走这里。
+/// 认证握手本身例外，它在 cipher 出现之前就得发，直接用 L2Packet::pb_write
+pub fn encode_l2_for_channel(
+    dev: &XiaomiDevice,
+    channel: L2Channel,
+    payload: Vec<u8>,
+) -> Result<Vec<u8>, L2Error> {
+    let crypto = dev.config.channel_crypto.get(channel);
+    let cipher = match crypto {
+        ChannelCrypto::Never => None,
+        ChannelCrypto::Always | ChannelCrypto::Auto => {
+            ensure_l2_cipher_blocking(dev.addr(), dev.sar_version)
+        }
+    };
+    encode_l2_with(crypto, channel, payload, cipher.as_deref())
+}
+
+pub(crate) fn encode_l2_with(
+    crypto: ChannelCrypto,
+    channel: L2Channel,
+    payload: Vec<u8>,
+    cipher: Option<&(dyn L2Cipher + Send + Sync)>,
+) -> Result<Vec<u8>, L2Error> {
+    let cipher = match (crypto, cipher) {
+        (ChannelCrypto::Never, _) | (ChannelCrypto::Auto, None) => None,
+        (ChannelCrypto::Always, None) => return Err(L2Error::CipherUnavailable(channel)),
+        (_, Some(cipher)) => Some(cipher),
+    };
+
+    match cipher {
+        Some(cipher) => match cipher.encrypt(&payload) {
+            Ok(ct) => Ok(L2Packet::new(channel, L2OpCode::WriteEnc, ct).to_bytes()),
+            Err(_) if crypto == ChannelCrypto::Auto => {
+                log::error!("[{:?}] l2 encrypt failed, fallback to plain write", channel);
+                Ok(L2Packet::new(channel, L2OpCode::Write, payload).to_bytes())
+            }
+            Err(_) => Err(L2Error::EncryptFailed),
+        },
+        None => Ok(L2Packet::new(channel, L2OpCode::Write, payload).to_bytes()),
+    }
+}
+
+/// 返回 None 表示按策略这个包不能发（已经打了日志）
 pub fn encode_pb_packet(
     dev: &XiaomiDevice,
     packet: protocol::WearPacket,
     log_ctx: &str,
-) -> Vec<u8> {
-    match ensure_l2_cipher_blocking(dev.addr(), dev.sar_version) {
-        Some(cipher) => match L2Packet::pb_write_enc(packet.clone(), cipher.as_ref()) {
-            Ok(pkt) => pkt.to_bytes(),
-            Err(err) => {
-                log::error!(
-                    "[{}] pb_write_enc failed, fallback to plain write: {}",
-                    log_ctx,
-                    err
-                );
-                L2Packet::pb_write(packet).to_bytes()
-            }
-        },
-        None => L2Packet::pb_write(packet).to_bytes(),
+) -> Option<Vec<u8>> {
+    #[cfg(not(target_os = "espidf"))]
+    log::trace!(
+        "[{}] pb write: {}",
+        log_ctx,
+        serde_json::to_string(&packet).unwrap_or_default()
+    );
+    encode_raw_pb_bytes(dev, packet.encode_to_vec(), log_ctx)
+}
+
+/// 和 encode_pb_packet 一样，但输入是已经编码好的 WearPacket 字节
+pub fn encode_raw_pb_bytes(dev: &XiaomiDevice, raw: Vec<u8>, log_ctx: &str) -> Option<Vec<u8>> {
+    match encode_l2_for_channel(dev, L2Channel::Pb, raw) {
+        Ok(bytes) => Some(bytes),
+        Err(err) => {
+            // Pb 被配成 Always 又还没认证完，这种包发出去手表也不认，直接丢
+            log::error!("[{}] pb encode failed, dropping packet: {}", log_ctx, err);
+            None
+        }
     }
 }
 
 pub fn enqueue_pb_packet(dev: &mut XiaomiDevice, packet: protocol::WearPacket, log_ctx: &str) {
-    let bytes = encode_pb_packet(dev, packet, log_ctx);
-    dev.sar.lock().enqueue(bytes);
+    if let Some(bytes) = encode_pb_packet(dev, packet, log_ctx) {
+        dev.sar.lock().enqueue(bytes);
+    }
+}
+
+/// 给本来就在 async 里的调用方用（mass、info 之类）：先在 ECS 外面把 cipher await 好，
+/// 再进 ECS 编码入队，全程不会 block_on
+pub async fn enqueue_pb_packet_async(
+    device_id: &str,
+    packet: protocol::WearPacket,
+    log_ctx: &'static str,
+) -> anyhow::Result<()> {
+    let owner = device_id.to_string();
+    let (crypto, sar_version) = crate::ecs::with_rt_mut_labeled("cipher::pb_policy", move |rt| {
+        rt.with_device_mut(&owner, |world, entity| {
+            world.get::<XiaomiDevice>(entity).map(|dev| {
+                (
+                    dev.config.channel_crypto.get(L2Channel::Pb),
+                    dev.sar_version,
+                )
+            })
+        })
+        .flatten()
+    })
+    .await
+    .ok_or_else(|| crate::anyhow_site!("device {} not found", device_id))?;
+
+    let cipher = match crypto {
+        ChannelCrypto::Never => None,
+        ChannelCrypto::Always | ChannelCrypto::Auto => {
+            ensure_l2_cipher(device_id, sar_version).await
+        }
+    };
+
+    #[cfg(not(target_os = "espidf"))]
+    log::trace!(
+        "[{}] pb write: {}",
+        log_ctx,
+        serde_json::to_string(&packet).unwrap_or_default()
+    );
+    let bytes = encode_l2_with(
+        crypto,
+        L2Channel::Pb,
+        packet.encode_to_vec(),
+        cipher.as_deref(),
+    )
+    .map_err(|err| crate::anyhow_site!("[{}] pb encode failed: {}", log_ctx, err))?;
+
+    let owner = device_id.to_string();
+    crate::ecs::with_rt_mut_labeled("cipher::enqueue_pb", move |rt| {
+        rt.with_device_mut(&owner, |world, entity| {
+            world.get_mut::<XiaomiDevice>(entity).map(|dev| {
+                dev.sar.lock().enqueue(bytes);
+            })
+        })
+        .flatten()
+    })
+    .await
+    .ok_or_else(|| crate::anyhow_site!("device {} gone before enqueue", device_id))
 }
 
 pub struct V2L2Cipher {
@@ -157,8 +267,10 @@ fn ensure_l2_cipher_in_runtime(
 impl V2L2Cipher {
     pub async fn new(device_id: String) -> Option<Self> {
         let device_id_clone = device_id.clone();
-        let keys =
-            crate::ecs::with_rt_mut(move |rt| auth_keys_from_runtime(rt, &device_id_clone)).await;
+        let keys = crate::ecs::with_rt_mut_labeled("cipher::auth_keys", move |rt| {
+            auth_keys_from_runtime(rt, &device_id_clone)
+        })
+        .await;
         let enc_key = keys.0;
         let dec_key = keys.1;
         if enc_key.len() == 16 && dec_key.len() == 16 {
@@ -192,3 +304,129 @@ impl L2Cipher for V2L2Cipher {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::config::ChannelCryptoPolicy;
+
+    struct XorCipher;
+
+    impl L2Cipher for XorCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, ()> {
+            Ok(plaintext.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+            self.encrypt(ciphertext)
+        }
+    }
+
+    struct BrokenCipher;
+
+    impl L2Cipher for BrokenCipher {
+        fn encrypt(&self, _plaintext: &[u8]) -> Result<Vec<u8>, ()> {
+            Err(())
+        }
+
+        fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+            Err(())
+        }
+    }
+
+    fn sample_pb() -> protocol::WearPacket {
+        protocol::WearPacket {
+            r#type: protocol::wear_packet::Type::System as i32,
+            id: protocol::system::SystemId::GetDeviceInfo as u32,
+            payload: None,
+        }
+    }
+
+    fn encode_default(
+        channel: L2Channel,
+        payload: Vec<u8>,
+        cipher: Option<&(dyn L2Cipher + Send + Sync)>,
+    ) -> Vec<u8> {
+        let crypto = ChannelCryptoPolicy::default().get(channel);
+        encode_l2_with(crypto, channel, payload, cipher).unwrap()
+    }
+
+    #[test]
+    fn default_policy_matches_legacy_bytes() {
+        let cipher = XorCipher;
+        let pb = sample_pb();
+
+        // 认证后 Pb 加密，认证前明文
+        assert_eq!(
+            encode_default(L2Channel::Pb, pb.encode_to_vec(), Some(&cipher)),
+            L2Packet::pb_write_enc(pb.clone(), &cipher)
+                .unwrap()
+                .to_bytes()
+        );
+        assert_eq!(
+            encode_default(L2Channel::Pb, pb.encode_to_vec(), None),
+            L2Packet::pb_write(pb).to_bytes()
+        );
+
+        // Mass / Network 就算有 cipher 也是明文
+        for channel in [L2Channel::Mass, L2Channel::Network] {
+            let payload = vec![0x01, 0x00, 0x01, 0x00, 0xaa, 0xbb];
+            assert_eq!(
+                encode_default(channel, payload.clone(), Some(&cipher)),
+                L2Packet::new(channel, L2OpCode::Write, payload).to_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn always_requires_cipher() {
+        let err = encode_l2_with(ChannelCrypto::Always, L2Channel::Network, vec![1, 2], None)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            L2Error::CipherUnavailable(L2Channel::Network)
+        ));
+
+        let bytes = encode_l2_with(
+            ChannelCrypto::Always,
+            L2Channel::Network,
+            vec![1, 2],
+            Some(&XorCipher),
+        )
+        .unwrap();
+        assert_eq!(
+            bytes,
+            L2Packet::new(
+                L2Channel::Network,
+                L2OpCode::WriteEnc,
+                vec![1 ^ 0x5a, 2 ^ 0x5a]
+            )
+            .to_bytes()
+        );
+    }
+
+    #[test]
+    fn encrypt_failure_is_reported_as_such() {
+        let err = encode_l2_with(
+            ChannelCrypto::Always,
+            L2Channel::Pb,
+            vec![1, 2],
+            Some(&BrokenCipher),
+        )
+        .unwrap_err();
+        assert_eq!(err, L2Error::EncryptFailed);
+
+        // Auto 退回明文
+        let bytes = encode_l2_with(
+            ChannelCrypto::Auto,
+            L2Channel::Pb,
+            vec![1, 2],
+            Some(&BrokenCipher),
+        )
+        .unwrap();
+        assert_eq!(
+            bytes,
+            L2Packet::new(L2Channel::Pb, L2OpCode::Write, vec![1, 2]).to_bytes()
+        );
+    }
+}
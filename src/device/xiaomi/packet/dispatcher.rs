@@ -1,24 +1,41 @@
 use std::{
-    collections::HashMap,
-    io::Cursor,
-    sync::{Arc, OnceLock, RwLock},
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, OnceLock, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
+use bytes::Bytes;
 use pb::xiaomi::protocol::WearPacket;
 use prost::Message;
 use tokio::runtime::Handle;
 
-use crate::device::xiaomi::XiaomiDevice;
+use crate::device::xiaomi::{XiaomiDevice, config::ChannelCrypto};
 
 use super::{
     cipher::{SharedL2Cipher, ensure_l2_cipher},
     v2::{
         layer1::L1Packet,
-        layer2::{L2Cipher, L2Packet},
+        layer2::{L2Channel, L2Cipher, L2OpCode, L2Packet},
     },
 };
 
-static RECV_BUFFERS: OnceLock<RwLock<HashMap<String, Vec<u8>>>> = OnceLock::new();
+// L1 头：magic(2) + type|frx(1) + seq(1) + len(2) + crc(2)
+const L1_HEADER_LEN: usize = L1Packet::HEADER_LEN;
+// 握手时报给设备的 mps，声明长度超过这个肯定是错位了
+const MAX_L1_PAYLOAD: usize = 64512;
+// 跨帧拼 PB 最多攒这么多，表盘/应用列表再多也到不了
+const MAX_PB_ASSEMBLY: usize = 1024 * 1024;
+
+static RECV_BUFFERS: OnceLock<RwLock<HashMap<String, RecvBuffer>>> = OnceLock::new();
+// 接收缓冲的 LRU 时间戳，单调递增就够了
+static RECV_TICK: AtomicU64 = AtomicU64::new(0);
+static DISPATCHER_CONFIG: OnceLock<RwLock<DispatcherConfig>> = OnceLock::new();
+static PB_ASSEMBLY: OnceLock<RwLock<HashMap<String, Vec<u8>>>> = OnceLock::new();
+static DISPATCHER_STATS: OnceLock<RwLock<HashMap<String, DeviceStats>>> = OnceLock::new();
+// 不校验 L1 CRC 的设备，见 SarConfig.verify_crc
+static CRC_SKIPPED: OnceLock<RwLock<HashSet<String>>> = OnceLock::new();
 static PACKET_OBSERVERS: OnceLock<RwLock<Vec<Arc<dyn Fn(XiaomiPacketEvent) + Send + Sync>>>> =
     OnceLock::new();
 
@@ -32,10 +49,349 @@ pub struct XiaomiPacketEvent {
     pub protobuf_packet_id: Option<u32>,
 }
 
-fn recv_buffer_registry() -> &'static RwLock<HashMap<String, Vec<u8>>> {
+/// 全局的接收缓冲上限，所有设备共用一份
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub struct DispatcherConfig {
+    /// 单台设备拼帧缓冲的硬上限，超过就丢掉卡住的假头，从下一个 magic 重新找帧。
+    /// 巡检时的 `SarConfig.recv_buffer_limit` 要等下一轮，这个是收包当场就卡住
+    pub max_buffer_bytes: usize,
+    /// 同时挂着半截数据的设备数，超了就把最久没收到数据的那台的缓冲丢掉
+    pub max_devices: usize,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        Self {
+            max_buffer_bytes: 1024 * 1024,
+            max_devices: 32,
+        }
+    }
+}
+
+fn dispatcher_config_slot() -> &'static RwLock<DispatcherConfig> {
+    DISPATCHER_CONFIG.get_or_init(|| RwLock::new(DispatcherConfig::default()))
+}
+
+pub fn dispatcher_config() -> DispatcherConfig {
+    *dispatcher_config_slot()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// 对之后收到的数据生效，已经超限的缓冲等下次收包时再裁
+pub fn set_dispatcher_config(config: DispatcherConfig) {
+    *dispatcher_config_slot()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner()) = config;
+}
+
+/// 认证之后收到的 Pb 帧里明文/密文各多少。有的固件认证后还会明文推一些包（电量广播之类），
+/// 这里能看出来是不是混着发的。`buffer_overflow` 是接收缓冲撞上
+/// `DispatcherConfig.max_buffer_bytes` 被裁掉的次数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct DispatcherStats {
+    pub pb_encrypted: u64,
+    pub pb_plaintext_after_auth: u64,
+    pub buffer_overflow: u64,
+}
+
+#[derive(Default)]
+struct DeviceStats {
+    stats: DispatcherStats,
+    /// Always 模式下收到明文只警告一次，后面的只计数
+    warned_plaintext: bool,
+    /// 缓冲溢出同理，只警告第一次
+    warned_overflow: bool,
+}
+
+struct RecvBuffer {
+    data: Vec<u8>,
+    last_used: u64,
+}
+
+fn stats_registry() -> &'static RwLock<HashMap<String, DeviceStats>> {
+    DISPATCHER_STATS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+pub fn dispatcher_stats(device_id: &str) -> Option<DispatcherStats> {
+    let registry = stats_registry()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    registry.get(device_id).map(|entry| entry.stats)
+}
+
+pub fn clear_dispatcher_stats(device_id: &str) {
+    stats_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(device_id);
+}
+
+/// 只统计认证后（已经有 cipher）的 Pb 帧。
+/// Always 只管发送：收到的明文照收不误，只在第一次出现时警告一下是哪种包
+fn record_pb_frame(
+    device_id: &str,
+    opcode: L2OpCode,
+    policy: ChannelCrypto,
+    pb_type: Option<(u32, u32)>,
+) {
+    let mut registry = stats_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let entry = registry.entry(device_id.to_string()).or_default();
+    match opcode {
+        L2OpCode::WriteEnc => entry.stats.pb_encrypted += 1,
+        L2OpCode::Write | L2OpCode::Read => {
+            entry.stats.pb_plaintext_after_auth += 1;
+            if policy == ChannelCrypto::Always && !entry.warned_plaintext {
+                entry.warned_plaintext = true;
+                log::warn!(
+                    "[Dispatcher] {} sent plaintext Pb packet (type/id {:?}) although Pb is encrypt-only; accepting it, further ones are only counted",
+                    device_id,
+                    pb_type
+                );
+            }
+        }
+    }
+}
+
+fn record_buffer_overflow(device_id: &str, dropped: usize, limit: usize) {
+    let mut registry = stats_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let entry = registry.entry(device_id.to_string()).or_default();
+    entry.stats.buffer_overflow += 1;
+    if !entry.warned_overflow {
+        entry.warned_overflow = true;
+        log::warn!(
+            "[Dispatcher] {} recv buffer exceeded {} bytes without a complete frame, dropped {} bytes and resyncing; further overflows are only counted",
+            device_id,
+            limit,
+            dropped
+        );
+    }
+}
+
+fn recv_buffer_registry() -> &'static RwLock<HashMap<String, RecvBuffer>> {
     RECV_BUFFERS.get_or_init(|| RwLock::new(HashMap::new()))
 }
 
+/// 收到的数据接到这台设备的缓冲后面，切出完整帧。
+/// 缓冲超过 `max_buffer_bytes` 时丢掉开头卡住的那段，从下一个 magic 继续找；
+/// 新设备挤进来时设备数超过 `max_devices` 就按 LRU 踢掉一台
+fn feed_recv_buffer(
+    device_id: &str,
+    data: &[u8],
+    verify_crc: bool,
+    config: DispatcherConfig,
+) -> Vec<Vec<u8>> {
+    let (frames, overflowed) = {
+        let mut registry = recv_buffer_registry()
+            .write()
+            .expect("poisoned MiWear recv buffer registry");
+        feed_buffer_in(&mut registry, device_id, data, verify_crc, config)
+    };
+    if overflowed > 0 {
+        record_buffer_overflow(device_id, overflowed, config.max_buffer_bytes);
+    }
+    frames
+}
+
+/// 返回切出来的帧和因为超限丢掉的字节数
+fn feed_buffer_in(
+    registry: &mut HashMap<String, RecvBuffer>,
+    device_id: &str,
+    data: &[u8],
+    verify_crc: bool,
+    config: DispatcherConfig,
+) -> (Vec<Vec<u8>>, usize) {
+    if !registry.contains_key(device_id) && registry.len() >= config.max_devices.max(1) {
+        let oldest = registry
+            .iter()
+            .min_by_key(|(_, buffer)| buffer.last_used)
+            .map(|(id, _)| id.clone());
+        if let Some(oldest) = oldest {
+            let evicted = registry
+                .remove(&oldest)
+                .map_or(0, |buffer| buffer.data.len());
+            log::warn!(
+                "[Dispatcher] recv buffer registry full ({} devices), evicted {} ({} bytes)",
+                config.max_devices,
+                oldest,
+                evicted
+            );
+        }
+    }
+    let buffer = registry
+        .entry(device_id.to_string())
+        .or_insert_with(|| RecvBuffer {
+            data: Vec::new(),
+            last_used: 0,
+        });
+    buffer.last_used = RECV_TICK.fetch_add(1, Ordering::Relaxed);
+    buffer.data.extend_from_slice(data);
+    let mut frames = split_frames_with(&mut buffer.data, verify_crc);
+    let mut overflowed = 0usize;
+
+    // 开头那个 magic 的帧一直凑不齐，跳过它到下一个 magic 再切，直到回到上限以内
+    while buffer.data.len() > config.max_buffer_bytes {
+        let next_magic = buffer
+            .data
+            .windows(2)
+            .skip(1)
+            .position(|w| w == [0xa5, 0xa5])
+            .map_or(buffer.data.len(), |pos| pos + 1);
+        buffer.data.drain(..next_magic);
+        overflowed += next_magic;
+        frames.extend(split_frames_with(&mut buffer.data, verify_crc));
+    }
+
+    if buffer.data.is_empty() {
+        registry.remove(device_id);
+    }
+    (frames, overflowed)
+}
+
+fn pb_assembly_registry() -> &'static RwLock<HashMap<String, Vec<u8>>> {
+    PB_ASSEMBLY.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+enum PbAssembly {
+    Complete(Bytes, WearPacket),
+    /// 这一帧还拼不出完整的包（攒着或者丢了），原始字节照样给观察者
+    Pending(Bytes),
+}
+
+/// 大的 PB（装了很多东西时的完整表盘/应用列表）会拆到连续几个 L1 Data 帧里，
+/// 单帧 decode 不了、又像是一个包的前半截时才攒着，拼上后面的帧直到能 decode 为止。
+/// PB 本身没有分帧，续帧碰巧也能 decode，所以攒着半截时只认拼出来的或者类型认得、payload 不空的包
+fn assemble_pb(device_id: &str, payload: Bytes) -> PbAssembly {
+    let mut registry = pb_assembly_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let Some(mut joined) = registry.remove(device_id) else {
+        if let Ok(packet) = WearPacket::decode(payload.as_ref()) {
+            return PbAssembly::Complete(payload, packet);
+        }
+        let prefix = payload.to_vec();
+        return hold_pb_prefix(&mut registry, device_id, prefix, payload);
+    };
+
+    let pending_len = joined.len();
+    joined.extend_from_slice(&payload);
+    if let Ok(packet) = WearPacket::decode(joined.as_slice()) {
+        if is_known_pb(&packet) {
+            log::debug!(
+                "[Dispatcher] {} reassembled PB packet from {} bytes",
+                device_id,
+                joined.len()
+            );
+            return PbAssembly::Complete(Bytes::from(joined), packet);
+        }
+    }
+    // 拼不上，新帧自己又是个像样的包：前面那半截的后续丢了，别让它把这个包拼坏
+    if let Ok(packet) = WearPacket::decode(payload.as_ref()) {
+        if is_known_pb(&packet) {
+            log::warn!(
+                "[Dispatcher] {} dropping {} bytes of unfinished PB packet",
+                device_id,
+                pending_len
+            );
+            return PbAssembly::Complete(payload, packet);
+        }
+    }
+    hold_pb_prefix(&mut registry, device_id, joined, payload)
+}
+
+/// 像是半截包才留着，太大或者根本不像 PB 的直接丢
+fn hold_pb_prefix(
+    registry: &mut HashMap<String, Vec<u8>>,
+    device_id: &str,
+    prefix: Vec<u8>,
+    frame: Bytes,
+) -> PbAssembly {
+    if prefix.len() > MAX_PB_ASSEMBLY {
+        log::warn!(
+            "[Dispatcher] {} PB reassembly exceeded {} bytes, dropping",
+            device_id,
+            MAX_PB_ASSEMBLY
+        );
+    } else if !looks_like_truncated_pb(&prefix) {
+        log::warn!(
+            "[Dispatcher] {} dropping {} bytes that are not a PB packet prefix",
+            device_id,
+            prefix.len()
+        );
+    } else {
+        registry.insert(device_id.to_string(), prefix);
+    }
+    PbAssembly::Pending(frame)
+}
+
+/// prost 会跳过不认识的字段，随便一段字节都可能 decode 成功，得看类型和 payload 才算数
+fn is_known_pb(packet: &WearPacket) -> bool {
+    packet.payload.is_some()
+        && pb::xiaomi::protocol::wear_packet::Type::try_from(packet.r#type).is_ok()
+}
+
+/// 按 wire 格式走一遍：前面的字段都完整、只有最后一个被截断，才像是一个 PB 包的前半截。
+/// wire type 不对、字段号为 0、正好走完都说明不是，攒着也拼不出东西
+fn looks_like_truncated_pb(buf: &[u8]) -> bool {
+    let mut rest = buf;
+    loop {
+        if rest.is_empty() {
+            return false;
+        }
+        let key = match read_varint(&mut rest) {
+            Some(Ok(key)) => key,
+            Some(Err(())) => return false,
+            None => return true,
+        };
+        if key >> 3 == 0 {
+            return false;
+        }
+        let need = match key & 0x7 {
+            0 => match read_varint(&mut rest) {
+                Some(Ok(_)) => 0,
+                Some(Err(())) => return false,
+                None => return true,
+            },
+            1 => 8,
+            2 => match read_varint(&mut rest) {
+                Some(Ok(len)) if len <= MAX_PB_ASSEMBLY as u64 => len as usize,
+                Some(_) => return false,
+                None => return true,
+            },
+            5 => 4,
+            _ => return false,
+        };
+        if rest.len() < need {
+            return true;
+        }
+        rest = &rest[need..];
+    }
+}
+
+/// 读一个 varint 并前移 `buf`；None 是数据先用完了，Err 是超过 10 字节的坏 varint
+fn read_varint(buf: &mut &[u8]) -> Option<Result<u64, ()>> {
+    let mut value = 0u64;
+    for (i, byte) in buf.iter().enumerate() {
+        if i >= 10 {
+            return Some(Err(()));
+        }
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Some(Ok(value));
+        }
+    }
+    if buf.len() >= 10 {
+        return Some(Err(()));
+    }
+    None
+}
+
 fn packet_observer_registry() -> &'static RwLock<Vec<Arc<dyn Fn(XiaomiPacketEvent) + Send + Sync>>>
 {
     PACKET_OBSERVERS.get_or_init(|| RwLock::new(Vec::new()))
@@ -64,65 +420,71 @@ fn emit_packet_event(event: XiaomiPacketEvent) {
     }
 }
 
-pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
-    crate::asyncrt::spawn_with_handle(
-        async move {
-            let mut frames: Vec<Vec<u8>> = Vec::new();
-            {
-                let mut registry = recv_buffer_registry()
-                    .write()
-                    .expect("poisoned MiWear recv buffer registry");
-                let buffer = registry.entry(device_id.clone()).or_insert_with(Vec::new);
-                buffer.extend_from_slice(&data);
-
-                let mut idx = 0usize;
-                while idx + 8 <= buffer.len() {
-                    if !(buffer[idx] == 0xa5 && buffer[idx + 1] == 0xa5) {
-                        idx = idx.saturating_add(1);
-                        continue;
-                    }
-
-                    let declared_len =
-                        u16::from_le_bytes([buffer[idx + 4], buffer[idx + 5]]) as usize;
-                    let total = 8 + declared_len;
-                    if idx + total > buffer.len() {
-                        break;
-                    }
+fn crc_skipped_registry() -> &'static RwLock<HashSet<String>> {
+    CRC_SKIPPED.get_or_init(|| RwLock::new(HashSet::new()))
+}
 
-                    frames.push(buffer[idx..idx + total].to_vec());
-                    idx += total;
-                }
+/// 设置这台设备收包时要不要校验 L1 CRC，默认校验
+pub fn set_crc_verification(device_id: &str, verify: bool) {
+    let mut registry = crc_skipped_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if verify {
+        registry.remove(device_id);
+    } else {
+        registry.insert(device_id.to_string());
+    }
+}
 
-                if idx > 0 {
-                    buffer.drain(0..idx);
-                }
+pub fn crc_verification(device_id: &str) -> bool {
+    !crc_skipped_registry()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .contains(device_id)
+}
 
-                let should_remove = buffer.is_empty();
-                if should_remove {
-                    let _ = buffer;
-                    registry.remove(&device_id);
-                }
-            }
+pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
+    crate::asyncrt::spawn_with_handle(
+        async move {
+            let verify_crc = crc_verification(&device_id);
+            let frames = feed_recv_buffer(&device_id, &data, verify_crc, dispatcher_config());
 
             if frames.is_empty() {
                 return;
             }
+            #[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+            crate::device::xiaomi::sar::record::capture(
+                &device_id,
+                crate::device::xiaomi::sar::record::Direction::In,
+                &frames,
+            );
 
-            let sar_version = crate::ecs::with_rt_mut({
+            let device_params = crate::ecs::with_rt_mut_labeled("dispatcher::sar_version", {
                 let device_id_clone = device_id.clone();
                 move |rt| {
                     rt.component_ref::<XiaomiDevice>(&device_id_clone)
-                        .map(|dev| dev.sar_version)
+                        .map(|dev| {
+                            (
+                                dev.sar_version,
+                                dev.config.channel_crypto.get(L2Channel::Pb),
+                            )
+                        })
                 }
             })
             .await;
-            let shared_cipher: Option<SharedL2Cipher> = match sar_version {
-                Some(version) => ensure_l2_cipher(&device_id, version).await,
+            let shared_cipher: Option<SharedL2Cipher> = match device_params {
+                Some((version, _)) => ensure_l2_cipher(&device_id, version).await,
                 None => None,
             };
+            let pb_policy = device_params.map_or(ChannelCrypto::Never, |(_, policy)| policy);
 
             for frame in frames {
-                let l1 = match L1Packet::from_bytes(&frame) {
+                let parsed = if verify_crc {
+                    L1Packet::from_shared(frame.into())
+                } else {
+                    L1Packet::from_shared_unverified(frame.into())
+                };
+                let l1 = match parsed {
                     Ok(p) => p,
                     Err(err) => {
                         log::warn!("Decode L1 Packet Err: {}", err.to_string());
@@ -130,7 +492,7 @@ pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
                     }
                 };
 
-                let deliver_up = crate::ecs::with_rt_mut({
+                let deliver_up = crate::ecs::with_rt_mut_labeled("dispatcher::sar_on_l1", {
                     let device_id_lookup = device_id.clone();
                     let l1_clone = l1.clone();
                     move |rt| {
@@ -152,35 +514,54 @@ pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
                     if let Ok(l2p) = L2Packet::from_l1(&l1, cipher_ref) {
                         let ch = l2p.channel;
                         let op = l2p.opcode;
-                        let payload = l2p.payload;
-                        let (protobuf_type_id, protobuf_packet_id) = if ch
-                            == super::v2::layer2::L2Channel::Pb
-                        {
-                            match WearPacket::decode(Cursor::new(&payload)) {
-                                Ok(packet) => (u32::try_from(packet.r#type).ok(), Some(packet.id)),
-                                Err(err) => {
-                                    log::debug!(
-                                        "failed to decode observed Xiaomi PB packet ({} bytes): {}",
-                                        payload.len(),
-                                        err
-                                    );
-                                    (None, None)
+                        let (payload, decoded) = if ch == L2Channel::Pb {
+                            match assemble_pb(&device_id, l2p.payload) {
+                                PbAssembly::Complete(payload, packet) => (payload, Some(packet)),
+                                PbAssembly::Pending(frame) => {
+                                    if shared_cipher.is_some() {
+                                        record_pb_frame(&device_id, op, pb_policy, None);
+                                    }
+                                    // 没拼出完整包不分发给 System，原始帧还是给观察者
+                                    emit_packet_event(XiaomiPacketEvent {
+                                        device_id: device_id.clone(),
+                                        channel_id: ch as u32,
+                                        opcode_id: op as u32,
+                                        payload: frame.to_vec(),
+                                        protobuf_type_id: None,
+                                        protobuf_packet_id: None,
+                                    });
+                                    continue;
                                 }
                             }
                         } else {
-                            (None, None)
+                            (l2p.payload, None)
                         };
+                        let (protobuf_type_id, protobuf_packet_id) = decoded
+                            .map_or((None, None), |packet| {
+                                (u32::try_from(packet.r#type).ok(), Some(packet.id))
+                            });
+                        if ch == L2Channel::Pb && shared_cipher.is_some() {
+                            record_pb_frame(
+                                &device_id,
+                                op,
+                                pb_policy,
+                                protobuf_type_id.zip(protobuf_packet_id),
+                            );
+                        }
 
                         emit_packet_event(XiaomiPacketEvent {
                             device_id: device_id.clone(),
                             channel_id: ch as u32,
                             opcode_id: op as u32,
-                            payload: payload.clone(),
+                            payload: payload.to_vec(),
                             protobuf_type_id,
                             protobuf_packet_id,
                         });
 
-                        crate::ecs::with_rt_mut({
+                        // 先喂给挂起的 L2 Read，再照常分发给各个 System
+                        super::read::try_complete_read(&device_id, ch, &payload);
+
+                        crate::ecs::with_rt_mut_labeled("dispatcher::l2_dispatch", {
                             let device_id_dispatch = device_id.clone();
                             move |rt| {
                                 let _ = rt.with_device_mut(&device_id_dispatch, |world, entity| {
@@ -189,10 +570,18 @@ pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
                                             let _ = crate::device::xiaomi::system::dispatch_xiaomi_system_ext_on_l2packet(
                                                 world,
                                                 entity,
+                                                &device_id_dispatch,
                                                 ch,
                                                 op,
                                                 &payload,
                                             );
+                                            // 类型化 System 都没认领的 PB 包兜底转给原始订阅者
+                                            if ch == L2Channel::Pb {
+                                                super::raw_pb::forward_unconsumed(
+                                                    &device_id_dispatch,
+                                                    &payload,
+                                                );
+                                            }
                                         }
                                     }
                                 });
@@ -207,6 +596,55 @@ pub fn on_packet(tk_handle: Handle, device_id: String, data: Vec<u8>) {
     );
 }
 
+/// 从接收缓冲里切出完整的 L1 帧，切走的部分（包括跳过的垃圾字节）会从 buffer 里移除。
+///
+/// 不无脑信头里的 len：类型不对、长度超过 mps、凑齐后 crc 对不上的都只跳过 1 字节重新找 magic，
+/// 免得一个错位的假头把后面的真包一起吞掉。
+pub fn split_frames(buffer: &mut Vec<u8>) -> Vec<Vec<u8>> {
+    split_frames_with(buffer, true)
+}
+
+/// `verify_crc` 为 false 时只靠 magic/类型/长度找帧，不算 CRC。
+/// 只适合本身可靠的传输，错位时的假头就没法靠 CRC 识别了
+pub fn split_frames_with(buffer: &mut Vec<u8>, verify_crc: bool) -> Vec<Vec<u8>> {
+    let mut frames = Vec::new();
+    let mut idx = 0usize;
+    while idx + L1_HEADER_LEN <= buffer.len() {
+        if !(buffer[idx] == 0xa5 && buffer[idx + 1] == 0xa5) {
+            idx += 1;
+            continue;
+        }
+
+        let declared_len = u16::from_le_bytes([buffer[idx + 4], buffer[idx + 5]]) as usize;
+        if L1Packet::unpack_type_frx(buffer[idx + 2]).is_err() || declared_len > MAX_L1_PAYLOAD {
+            idx += 1;
+            continue;
+        }
+
+        let total = L1_HEADER_LEN + declared_len;
+        if idx + total > buffer.len() {
+            break;
+        }
+
+        if verify_crc {
+            let declared_crc = u16::from_le_bytes([buffer[idx + 6], buffer[idx + 7]]);
+            let payload = &buffer[idx + L1_HEADER_LEN..idx + total];
+            if L1Packet::crc16_arc(payload) != declared_crc {
+                idx += 1;
+                continue;
+            }
+        }
+
+        frames.push(buffer[idx..idx + total].to_vec());
+        idx += total;
+    }
+
+    if idx > 0 {
+        buffer.drain(0..idx);
+    }
+    frames
+}
+
 pub fn clear_recv_buffer(device_id: &str) {
     match recv_buffer_registry().write() {
         Ok(mut registry) => {
@@ -217,4 +655,394 @@ pub fn clear_recv_buffer(device_id: &str) {
             registry.remove(device_id);
         }
     }
+    pb_assembly_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(device_id);
+}
+
+/// 当前还没拼成帧的字节数，没有缓冲（空的会被直接移除）时为 None
+pub fn recv_buffer_len(device_id: &str) -> Option<usize> {
+    match recv_buffer_registry().read() {
+        Ok(registry) => registry.get(device_id).map(|buffer| buffer.data.len()),
+        Err(poisoned) => poisoned
+            .into_inner()
+            .get(device_id)
+            .map(|buffer| buffer.data.len()),
+    }
+}
+
+/// 缓冲超过 `limit` 说明垃圾数据让帧边界一直对不上，清掉重新开始找帧头。
+/// 清了的话返回清之前的长度
+pub fn guard_recv_buffer(device_id: &str, limit: usize) -> Option<usize> {
+    let mut registry = match recv_buffer_registry().write() {
+        Ok(registry) => registry,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let len = registry.get(device_id).map(|buffer| buffer.data.len())?;
+    if len <= limit {
+        return None;
+    }
+    registry.remove(device_id);
+    log::warn!(
+        "[Dispatcher] recv buffer for {} grew to {} bytes (limit {}) without a valid frame, resetting",
+        device_id,
+        len,
+        limit
+    );
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::packet::v2::layer1::L1DataType;
+    use bytes::Bytes;
+
+    #[test]
+    fn split_frames_resyncs_after_bogus_header() {
+        let good = L1Packet::new(L1DataType::Data, false, 3, b"hello".to_vec()).to_bytes();
+        // 假头声明了一个很长的 payload，不能把后面的真包一起吞掉
+        let mut buffer = vec![0xa5, 0xa5, 0x03, 0x00, 0xff, 0x00, 0x00, 0x00];
+        buffer.extend_from_slice(&good);
+        buffer.extend(std::iter::repeat_n(0u8, 300));
+
+        let frames = split_frames(&mut buffer);
+
+        assert_eq!(frames, vec![good]);
+    }
+
+    #[test]
+    fn split_frames_keeps_partial_tail() {
+        let good = L1Packet::new(L1DataType::Ack, false, 1, Bytes::new()).to_bytes();
+        let next = L1Packet::new(L1DataType::Data, false, 2, b"tail".to_vec()).to_bytes();
+        let mut buffer = good.clone();
+        buffer.extend_from_slice(&next[..next.len() - 2]);
+
+        assert_eq!(split_frames(&mut buffer), vec![good]);
+        buffer.extend_from_slice(&next[next.len() - 2..]);
+        assert_eq!(split_frames(&mut buffer), vec![next]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn unverified_split_keeps_frames_with_bad_crc() {
+        let mut corrupted = L1Packet::new(L1DataType::Data, false, 4, b"bulk".to_vec()).to_bytes();
+        corrupted[6] ^= 0xff;
+
+        assert!(split_frames(&mut corrupted.clone()).is_empty());
+        assert_eq!(
+            split_frames_with(&mut corrupted.clone(), false),
+            vec![corrupted]
+        );
+
+        let device_id = "test:dispatcher-crc";
+        assert!(crc_verification(device_id));
+        set_crc_verification(device_id, false);
+        assert!(!crc_verification(device_id));
+        set_crc_verification(device_id, true);
+        assert!(crc_verification(device_id));
+    }
+
+    #[test]
+    fn guard_resets_only_oversized_buffers() {
+        let device_id = "dispatcher-guard-test";
+        recv_buffer_registry().write().unwrap().insert(
+            device_id.to_string(),
+            RecvBuffer {
+                data: vec![0xa5; 100],
+                last_used: 0,
+            },
+        );
+
+        assert_eq!(recv_buffer_len(device_id), Some(100));
+        assert_eq!(guard_recv_buffer(device_id, 100), None);
+        assert_eq!(guard_recv_buffer(device_id, 64), Some(100));
+        assert_eq!(recv_buffer_len(device_id), None);
+        assert_eq!(guard_recv_buffer(device_id, 64), None);
+    }
+
+    #[test]
+    fn oversized_declared_length_is_capped_and_resyncs() {
+        let device_id = "dispatcher-overflow-test";
+        let config = DispatcherConfig {
+            max_buffer_bytes: 4096,
+            max_devices: 32,
+        };
+        let good = L1Packet::new(L1DataType::Data, false, 5, b"resync".to_vec()).to_bytes();
+
+        // 0xFFFF 超过 mps 当场就被跳过；60000 在 mps 以内，只能靠上限把它顶掉
+        let mut bogus = vec![0xa5, 0xa5, 0x03, 0x00, 0xff, 0xff, 0x00, 0x00];
+        bogus.extend_from_slice(&[0xa5, 0xa5, 0x03, 0x00, 0x60, 0xea, 0x00, 0x00]);
+        assert!(feed_recv_buffer(device_id, &bogus, true, config).is_empty());
+        for _ in 0..64 {
+            assert!(feed_recv_buffer(device_id, &[0u8; 1000], true, config).is_empty());
+            assert!(recv_buffer_len(device_id).unwrap_or(0) <= config.max_buffer_bytes);
+        }
+        assert!(dispatcher_stats(device_id).unwrap().buffer_overflow >= 1);
+
+        assert_eq!(feed_recv_buffer(device_id, &good, true, config), vec![good]);
+        assert_eq!(recv_buffer_len(device_id), None);
+        clear_dispatcher_stats(device_id);
+    }
+
+    #[test]
+    fn full_registry_evicts_least_recently_used() {
+        // 全局表别的测试也在用，这里拿一张单独的表
+        let mut registry = HashMap::new();
+        let partial = [0xa5, 0xa5, 0x03];
+        let config = DispatcherConfig {
+            max_buffer_bytes: 4096,
+            max_devices: 2,
+        };
+        feed_buffer_in(&mut registry, "a", &partial, true, config);
+        feed_buffer_in(&mut registry, "b", &partial, true, config);
+        feed_buffer_in(&mut registry, "a", &[0x00], true, config);
+        feed_buffer_in(&mut registry, "c", &partial, true, config);
+
+        let mut left: Vec<_> = registry
+            .iter()
+            .map(|(id, buffer)| (id.as_str(), buffer.data.len()))
+            .collect();
+        left.sort_unstable();
+        assert_eq!(left, [("a", 4), ("c", 3)]);
+    }
+
+    struct XorCipher;
+
+    impl L2Cipher for XorCipher {
+        fn encrypt(&self, plaintext: &[u8]) -> Result<Vec<u8>, ()> {
+            Ok(plaintext.iter().map(|b| b ^ 0x5a).collect())
+        }
+
+        fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>, ()> {
+            self.encrypt(ciphertext)
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn mixed_pb_traffic_is_counted_and_delivered() {
+        use crate::device::{
+            spawn_mock_xiaomi,
+            xiaomi::{config::XiaomiDeviceConfig, packet::cipher::register_l2_cipher},
+        };
+        use std::time::Duration;
+
+        crate::ecs::init_runtime_default();
+        let addr = "dispatcher-mixed-pb";
+        let cipher = XorCipher;
+        let frames = [
+            L2Packet::new(
+                L2Channel::Pb,
+                L2OpCode::WriteEnc,
+                cipher.encrypt(&small_pb(1).encode_to_vec()).unwrap(),
+            ),
+            // 电量广播之类的明文推送
+            L2Packet::new(L2Channel::Pb, L2OpCode::Write, small_pb(2).encode_to_vec()),
+            L2Packet::new(
+                L2Channel::Pb,
+                L2OpCode::WriteEnc,
+                cipher.encrypt(&small_pb(3).encode_to_vec()).unwrap(),
+            ),
+            L2Packet::new(L2Channel::Pb, L2OpCode::Write, small_pb(4).encode_to_vec()),
+        ];
+        // 一次喂进去，四帧在同一个收包任务里按顺序处理
+        let data: Vec<u8> = frames
+            .into_iter()
+            .enumerate()
+            .flat_map(|(seq, frame)| frame.into_l1(seq as u8, true).to_bytes())
+            .collect();
+
+        let delivered = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        register_observer({
+            let delivered = delivered.clone();
+            Arc::new(move |event: XiaomiPacketEvent| {
+                if event.device_id == addr {
+                    delivered.lock().push(event.payload);
+                }
+            })
+        });
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut config = XiaomiDeviceConfig::default();
+            config
+                .channel_crypto
+                .set(L2Channel::Pb, ChannelCrypto::Always);
+            spawn_mock_xiaomi(addr, config).await;
+            register_l2_cipher(addr.to_string(), Arc::new(XorCipher));
+
+            on_packet(tokio::runtime::Handle::current(), addr.to_string(), data);
+            for _ in 0..100 {
+                if delivered.lock().len() >= 4 {
+                    break;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(10)).await;
+            }
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+        });
+
+        let expected: Vec<_> = (1..=4).map(|n| small_pb(n).encode_to_vec()).collect();
+        assert_eq!(*delivered.lock(), expected);
+        assert_eq!(
+            dispatcher_stats(addr),
+            Some(DispatcherStats {
+                pb_encrypted: 2,
+                pb_plaintext_after_auth: 2,
+                buffer_overflow: 0,
+            })
+        );
+        assert!(stats_registry().read().unwrap()[addr].warned_plaintext);
+
+        crate::device::xiaomi::cleanup_cached_state(addr);
+        assert_eq!(dispatcher_stats(addr), None);
+    }
+
+    fn small_pb(fill: u8) -> WearPacket {
+        use pb::xiaomi::protocol::{Account, account, auth::AppVerify, wear_packet};
+
+        WearPacket {
+            r#type: wear_packet::Type::Account as i32,
+            id: account::AccountId::AuthVerify as u32,
+            payload: Some(wear_packet::Payload::Account(Account {
+                payload: Some(account::Payload::AuthAppVerify(AppVerify {
+                    app_random: vec![fill; 16],
+                    app_device_id: None,
+                    check_dynamic_code: None,
+                })),
+            })),
+        }
+    }
+
+    fn large_pb() -> WearPacket {
+        large_pb_filled(7)
+    }
+
+    fn large_pb_filled(fill: u8) -> WearPacket {
+        use pb::xiaomi::protocol::{Account, account, auth::AppVerify, wear_packet};
+
+        WearPacket {
+            r#type: wear_packet::Type::Account as i32,
+            id: account::AccountId::AuthVerify as u32,
+            payload: Some(wear_packet::Payload::Account(Account {
+                payload: Some(account::Payload::AuthAppVerify(AppVerify {
+                    app_random: vec![fill; 4000],
+                    app_device_id: None,
+                    check_dynamic_code: None,
+                })),
+            })),
+        }
+    }
+
+    #[test]
+    fn split_pb_is_reassembled() {
+        let device_id = "dispatcher-split-pb";
+        let encoded = large_pb().encode_to_vec();
+        let mut chunks = encoded.chunks(1500).map(Bytes::copy_from_slice);
+
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        match assemble_pb(device_id, chunks.next().unwrap()) {
+            PbAssembly::Complete(payload, packet) => {
+                assert_eq!(payload.as_ref(), encoded.as_slice());
+                assert_eq!(packet, large_pb());
+            }
+            PbAssembly::Pending(_) => panic!("third chunk should complete the packet"),
+        }
+        assert!(chunks.next().is_none());
+        assert!(
+            pb_assembly_registry()
+                .read()
+                .unwrap()
+                .get(device_id)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn whole_pb_after_stale_fragment_is_delivered() {
+        let device_id = "dispatcher-stale-pb";
+        let encoded = large_pb().encode_to_vec();
+        assert!(matches!(
+            assemble_pb(device_id, Bytes::copy_from_slice(&encoded[..100])),
+            PbAssembly::Pending(_)
+        ));
+
+        // 半截包的后续丢了，下一个完整包不能被它拖住
+        match assemble_pb(device_id, Bytes::from(encoded.clone())) {
+            PbAssembly::Complete(payload, _) => assert_eq!(payload.as_ref(), encoded.as_slice()),
+            PbAssembly::Pending(_) => {
+                panic!("a complete packet must not wait behind a stale fragment")
+            }
+        }
+        clear_recv_buffer(device_id);
+    }
+
+    #[test]
+    fn garbage_is_not_kept_as_pb_prefix() {
+        let device_id = "dispatcher-garbage-pb";
+        let encoded = large_pb().encode_to_vec();
+        assert!(looks_like_truncated_pb(&encoded[..100]));
+        // wire type 7 不存在
+        let garbage = Bytes::from_static(&[0x0f, 0x01, 0x02]);
+        assert!(!looks_like_truncated_pb(&garbage));
+
+        assert!(matches!(
+            assemble_pb(device_id, garbage),
+            PbAssembly::Pending(_)
+        ));
+        assert!(
+            pb_assembly_registry()
+                .read()
+                .unwrap()
+                .get(device_id)
+                .is_none()
+        );
+
+        // 坏前缀没攒着，后面正常分片的包照样拼得起来
+        let mut chunks = encoded.chunks(1500).map(Bytes::copy_from_slice);
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Complete(..)
+        ));
+    }
+
+    #[test]
+    fn decodable_continuation_does_not_break_reassembly() {
+        let device_id = "dispatcher-decodable-continuation";
+        // 全是 0x08 的中间那段单独也能 decode（type=8、payload 为空），不能当成新包
+        let packet = large_pb_filled(0x08);
+        let encoded = packet.encode_to_vec();
+        let middle = WearPacket::decode(&encoded[1500..3000]).unwrap();
+        assert!(!is_known_pb(&middle));
+
+        let mut chunks = encoded.chunks(1500).map(Bytes::copy_from_slice);
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        assert!(matches!(
+            assemble_pb(device_id, chunks.next().unwrap()),
+            PbAssembly::Pending(_)
+        ));
+        match assemble_pb(device_id, chunks.next().unwrap()) {
+            PbAssembly::Complete(_, decoded) => assert_eq!(decoded, packet),
+            PbAssembly::Pending(_) => panic!("third chunk should complete the packet"),
+        }
+    }
 }
@@ -1,17 +1,22 @@
 use std::collections::HashMap;
 
 use anyhow::Result;
-use byteorder::{LittleEndian, WriteBytesExt};
 use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error as DeError};
 
 use crate::anyhow_site;
-use crate::tools::{calc_crc32_bytes, calc_md5, to_hex_string};
+use crate::tools::{calc_md5, to_hex_string};
+
+pub mod codec;
+pub mod wire;
+
+// 流式接收时用来增量计算 crc32，算法必须和 wire::checksum 保持一致
+static REVERSE_MASS_CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 #[repr(u8)]
 pub enum MassDataType {
     Watchface = 16,
-    Firmare = 32,
+    Firmware = 32,
     WatchfaceImage = 48,
     NotificationIcon = 50,
     Music = 52,
@@ -31,7 +36,7 @@ impl TryFrom<u8> for MassDataType {
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
             16 => Ok(MassDataType::Watchface),
-            32 => Ok(MassDataType::Firmare),
+            32 => Ok(MassDataType::Firmware),
             48 => Ok(MassDataType::WatchfaceImage),
             50 => Ok(MassDataType::NotificationIcon),
             52 => Ok(MassDataType::Music),
@@ -42,6 +47,68 @@ impl TryFrom<u8> for MassDataType {
     }
 }
 
+impl MassDataType {
+    /// 旧拼写，留着给下游过渡用
+    #[deprecated(note = "use MassDataType::Firmware")]
+    #[allow(non_upper_case_globals)]
+    pub const Firmare: MassDataType = MassDataType::Firmware;
+
+    pub const ALL: [MassDataType; 7] = [
+        MassDataType::Watchface,
+        MassDataType::Firmware,
+        MassDataType::WatchfaceImage,
+        MassDataType::NotificationIcon,
+        MassDataType::Music,
+        MassDataType::WatchfaceFont,
+        MassDataType::ThirdPartyApp,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MassDataType::Watchface => "Watchface",
+            MassDataType::Firmware => "Firmware",
+            MassDataType::WatchfaceImage => "WatchfaceImage",
+            MassDataType::NotificationIcon => "NotificationIcon",
+            MassDataType::Music => "Music",
+            MassDataType::WatchfaceFont => "WatchfaceFont",
+            MassDataType::ThirdPartyApp => "ThirdPartyApp",
+        }
+    }
+}
+
+impl std::fmt::Display for MassDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for MassDataType {
+    type Err = String;
+
+    /// 不区分大小写和下划线，数字和各种历史写法（WATCHFACE / Firmare / ThirdpartyApp）都认
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(value) = s.trim().parse::<u8>() {
+            return MassDataType::try_from(value).map_err(|e| e.to_string());
+        }
+        let normalized: String = s
+            .trim()
+            .chars()
+            .filter(|c| *c != '_' && *c != '-')
+            .flat_map(|c| c.to_lowercase())
+            .collect();
+        match normalized.as_str() {
+            "watchface" => Ok(MassDataType::Watchface),
+            "firmware" | "firmare" => Ok(MassDataType::Firmware),
+            "watchfaceimage" => Ok(MassDataType::WatchfaceImage),
+            "notificationicon" => Ok(MassDataType::NotificationIcon),
+            "music" => Ok(MassDataType::Music),
+            "watchfacefont" => Ok(MassDataType::WatchfaceFont),
+            "thirdpartyapp" => Ok(MassDataType::ThirdPartyApp),
+            _ => Err(format!("unknown MassDataType: {s}")),
+        }
+    }
+}
+
 impl Serialize for MassDataType {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -67,6 +134,8 @@ pub struct MassPacket {
     pub md5: Vec<u8>,
     pub length: u32,
     pub original_file_data: Vec<u8>,
+    /// 头里的 comp_data，`original_file_data` 已经按这个模式压过，见 `codec`
+    pub compress_mode: u8,
 }
 
 impl MassPacket {
@@ -76,9 +145,16 @@ impl MassPacket {
             md5: calc_md5(&original_file_data),
             length: original_file_data.len() as u32,
             original_file_data,
+            compress_mode: codec::COMPRESS_MODE_NONE,
         })
     }
 
+    /// 数据已经压缩过时标上模式号，不会再压一遍
+    pub fn with_compress_mode(mut self, mode: u8) -> Self {
+        self.compress_mode = mode;
+        self
+    }
+
     /// Encode the internal payload and append CRC32 at the end.
     /// Format: comp_data (1B) | data_type (1B) | md5 (16B) | length (4B LE) |
     /// original_file_data (...) | crc32_of_previous_fields (4B LE)
@@ -93,18 +169,14 @@ impl MassPacket {
         let mut crc_payload_buf =
             Vec::with_capacity(1 + 1 + self.md5.len() + 4 + remaining.len() + 4);
 
-        crc_payload_buf.push(0x00);
+        crc_payload_buf.push(self.compress_mode);
         crc_payload_buf.push(self.data_type as u8);
         crc_payload_buf.extend_from_slice(&self.md5);
-        crc_payload_buf
-            .write_u32::<LittleEndian>(remaining.len() as u32)
-            .unwrap();
+        wire::write_length(&mut crc_payload_buf, remaining.len() as u32);
         crc_payload_buf.extend_from_slice(remaining);
 
-        let crc32_val = u32::from_be_bytes(calc_crc32_bytes(&crc_payload_buf));
-        crc_payload_buf
-            .write_u32::<LittleEndian>(crc32_val)
-            .unwrap();
+        let crc32_val = wire::checksum(&crc_payload_buf);
+        wire::write_crc32(&mut crc_payload_buf, crc32_val);
 
         crc_payload_buf
     }
@@ -119,6 +191,11 @@ pub struct ReverseMassPacket {
     pub file: HashMap<u32, Vec<u8>>,
     pub error: bool,
     pub empty: bool,
+    /// 流式模式下分片按顺序吐给上层后立刻丢弃，不在内存里攒整个文件
+    streaming: bool,
+    next_emit: u32,
+    ready: Vec<(u32, Vec<u8>)>,
+    digest: Option<crc::Digest<'static, u32>>,
 }
 
 impl ReverseMassPacket {
@@ -131,6 +208,37 @@ impl ReverseMassPacket {
             error: false,
             empty: true,
             header: Vec::new(),
+            streaming: false,
+            next_emit: 1,
+            ready: Vec::new(),
+            digest: None,
+        }
+    }
+
+    /// 流式接收：分片通过 take_ready_chunks 取走，crc 增量校验，file() 不再可用
+    pub fn new_streaming() -> Self {
+        ReverseMassPacket {
+            streaming: true,
+            ..Self::new()
+        }
+    }
+
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// 取走已经按顺序就绪的分片 (part_num, data)
+    pub fn take_ready_chunks(&mut self) -> Vec<(u32, Vec<u8>)> {
+        std::mem::take(&mut self.ready)
+    }
+
+    fn drain_contiguous(&mut self) {
+        while let Some(chunk) = self.file.remove(&self.next_emit) {
+            if let Some(digest) = self.digest.as_mut() {
+                digest.update(&chunk);
+            }
+            self.ready.push((self.next_emit, chunk));
+            self.next_emit += 1;
         }
     }
 
@@ -147,13 +255,26 @@ impl ReverseMassPacket {
             return Err(anyhow_site!("Invalid packet version"));
         }
 
-        let total = u16::from_le_bytes(packet[2..4].try_into().unwrap());
-        let cur = u16::from_le_bytes(packet[4..6].try_into().unwrap());
+        // packet[0] 是版本，分片头从第 2 字节开始
+        let wire::PartHeader {
+            total,
+            current: cur,
+        } = wire::read_part_header(&packet[2..])
+            .ok_or_else(|| anyhow_site!("Invalid reverse mass packet"))?;
 
         let skip_offset: usize;
 
         if self.total_part == 0 {
             let len = packet[6] as usize;
+            // 文件名长度是设备说了算的，不校验就切片会直接越界
+            if packet.len() < 7 + len + 5 {
+                self.error = true;
+                return Err(anyhow_site!(
+                    "Reverse mass header truncated: name len {} but packet is {} bytes",
+                    len,
+                    packet.len()
+                ));
+            }
             let file_name = String::from_utf8(packet[7..7 + len].to_vec())?;
             self.file_name = file_name;
             self.header = packet[6..7 + len + 5].to_vec();
@@ -167,30 +288,59 @@ impl ReverseMassPacket {
                     self.total_part
                 ));
             }
-            skip_offset = 6;
+            skip_offset = 2 + wire::PART_HEADER_LEN;
         }
 
-        if cur == total {
-            self.file
-                .insert(cur as u32, packet[skip_offset..packet.len() - 4].to_vec());
-
-            let mut check_data: Vec<u8> = Vec::new();
-            check_data.extend(self.header.clone());
-            check_data.extend(self.file(true)?);
+        if self.streaming && self.digest.is_none() {
+            let mut digest = REVERSE_MASS_CRC.digest();
+            digest.update(&self.header);
+            self.digest = Some(digest);
+        }
 
-            let crc32 = u32::from_le_bytes(packet[packet.len() - 4..].try_into().unwrap());
-            let data_crc32 = u32::from_be_bytes(calc_crc32_bytes(&check_data));
+        if cur == total {
+            // 最后一片末尾还带 4 字节 crc
+            if packet.len() < skip_offset + wire::CRC32_LEN {
+                self.error = true;
+                return Err(anyhow_site!(
+                    "Reverse mass last block has no room for crc32"
+                ));
+            }
+            self.file.insert(
+                cur as u32,
+                packet[skip_offset..packet.len() - wire::CRC32_LEN].to_vec(),
+            );
+
+            let crc32 = wire::read_trailing_crc32(&packet).unwrap_or_default();
+            let data_crc32 = if self.streaming {
+                self.drain_contiguous();
+                if self.next_emit != total as u32 + 1 {
+                    self.error = true;
+                    return Err(anyhow_site!("block {} was not found!", self.next_emit));
+                }
+                self.digest
+                    .take()
+                    .map(|digest| digest.finalize())
+                    .unwrap_or_default()
+            } else {
+                let mut check_data: Vec<u8> = Vec::new();
+                check_data.extend(self.header.clone());
+                check_data.extend(self.file(true)?);
+                wire::checksum(&check_data)
+            };
 
             if crc32 != data_crc32 {
                 log::error!(
-                    "[ReverseMassPacket] Invalid crc32! check_data: {}",
-                    to_hex_string(&check_data)
+                    "[ReverseMassPacket] Invalid crc32! header: {}",
+                    to_hex_string(&self.header)
                 );
                 self.error = true;
                 return Err(anyhow_site!("Invalid crc32 {} != {}", crc32, data_crc32));
             }
         } else {
             self.file.insert(cur as u32, packet[skip_offset..].to_vec());
+            if self.streaming {
+                self.drain_contiguous();
+            }
         }
 
         self.total_part = total as u32;
@@ -203,6 +353,11 @@ impl ReverseMassPacket {
     }
 
     pub fn file(&self, force: bool) -> Result<Vec<u8>> {
+        if self.streaming {
+            return Err(anyhow_site!(
+                "streaming reverse mass packet does not keep file data"
+            ));
+        }
         if !self.complete() && !force {
             return Err(anyhow_site!("complete != true"));
         }
@@ -238,3 +393,82 @@ impl ReverseMassPacket {
         self.file_name.clone()
     }
 }
+
+/// 测试用：按设备的格式拼两片回传包，头部和 crc 都走 wire
+#[cfg(test)]
+pub(crate) fn reverse_mass_parts(file_name: &str, body: &[u8]) -> Vec<Vec<u8>> {
+    let mut header = vec![file_name.len() as u8];
+    header.extend_from_slice(file_name.as_bytes());
+    header.extend_from_slice(&[0x10, 0, 0, 0, 0]);
+
+    let mut crc_input = header.clone();
+    crc_input.extend_from_slice(body);
+
+    let (first, second) = body.split_at(body.len() / 2);
+    let mut parts = Vec::new();
+    for (current, chunk) in [(1u16, first), (2, second)] {
+        let mut part = vec![0, 0];
+        wire::write_part_header(&mut part, wire::PartHeader { total: 2, current });
+        if current == 1 {
+            part.extend_from_slice(&header);
+        }
+        part.extend_from_slice(chunk);
+        if current == 2 {
+            wire::write_crc32(&mut part, wire::checksum(&crc_input));
+        }
+        parts.push(part);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reverse_mass_rejects_truncated_header() {
+        // 文件名长度声明 0xff，但整个包只有 12 字节
+        let packet = vec![0, 0, 1, 0, 1, 0, 0xff, b'A', b'A', b'A', b'A', b'A'];
+        let mut rmp = ReverseMassPacket::new();
+
+        assert!(rmp.handle_packet(packet).is_err());
+        assert!(rmp.error());
+    }
+
+    #[test]
+    fn reverse_mass_rejects_last_block_without_crc() {
+        // 单片文件，头部刚好占满整个包，没地方放 crc32
+        let packet = vec![0, 0, 1, 0, 1, 0, 1, b'a', 0, 0x10, 0, 0, 0];
+        let mut rmp = ReverseMassPacket::new();
+
+        assert!(rmp.handle_packet(packet).is_err());
+        assert!(rmp.error());
+    }
+
+    #[test]
+    fn reverse_mass_round_trip() {
+        let body = b"voice memo bytes, a bit longer than one part".to_vec();
+        let mut rmp = ReverseMassPacket::new();
+        for part in reverse_mass_parts("memo.opus", &body) {
+            rmp.handle_packet(part).unwrap();
+        }
+
+        assert!(rmp.complete());
+        assert_eq!(rmp.file_name(), "memo.opus");
+        assert_eq!(rmp.file(false).unwrap(), body);
+    }
+
+    #[test]
+    fn mass_packet_trailer_matches_wire_crc() {
+        let encoded = MassPacket::build(b"face".to_vec(), MassDataType::Watchface)
+            .unwrap()
+            .encode_with_crc32();
+        let (payload, _) = encoded.split_at(encoded.len() - wire::CRC32_LEN);
+
+        assert_eq!(&payload[18..22], &4u32.to_le_bytes());
+        assert_eq!(
+            wire::read_trailing_crc32(&encoded),
+            Some(wire::checksum(payload))
+        );
+    }
+}
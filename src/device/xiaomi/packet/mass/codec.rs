@@ -0,0 +1,136 @@
+//! MASS 压缩编解码器注册表。
+//!
+//! 压缩模式号就是 MASS 头里的 comp_data 字节，0 表示不压缩，不能注册。
+//! crate 本身不带任何编解码器，zstd / lz4 / deflate 之类由下游按固件支持情况注册，
+//! 发送端按 `MassConfig::compress_mode` 从这里取，接收端按手表 prepare 里带的模式取来解压
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use anyhow::Result;
+
+use crate::bail_site;
+
+/// comp_data 为 0 时表示原样发送
+pub const COMPRESS_MODE_NONE: u8 = 0;
+
+pub trait CompressionCodec: Send + Sync {
+    /// 日志里用的名字
+    fn name(&self) -> &str;
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+pub type SharedCompressionCodec = Arc<dyn CompressionCodec>;
+
+static CODECS: OnceLock<RwLock<HashMap<u8, SharedCompressionCodec>>> = OnceLock::new();
+
+fn codec_registry() -> &'static RwLock<HashMap<u8, SharedCompressionCodec>> {
+    CODECS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// 注册 `mode` 对应的编解码器，同一个模式号再注册会顶掉旧的
+pub fn register_codec(mode: u8, codec: SharedCompressionCodec) -> Result<()> {
+    if mode == COMPRESS_MODE_NONE {
+        bail_site!("compress mode 0 means uncompressed and cannot be registered");
+    }
+    let mut guard = codec_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(old) = guard.insert(mode, codec) {
+        log::info!(
+            "[Mass] compress mode {mode} codec `{}` replaced",
+            old.name()
+        );
+    }
+    Ok(())
+}
+
+pub fn unregister_codec(mode: u8) -> Option<SharedCompressionCodec> {
+    codec_registry()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .remove(&mode)
+}
+
+pub fn get_codec(mode: u8) -> Option<SharedCompressionCodec> {
+    codec_registry()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(&mode)
+        .cloned()
+}
+
+/// 已注册的模式号，从小到大
+pub fn registered_modes() -> Vec<u8> {
+    let mut modes: Vec<u8> = codec_registry()
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .keys()
+        .copied()
+        .collect();
+    modes.sort_unstable();
+    modes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::packet::mass::{MassDataType, MassPacket};
+
+    /// 极简游程编码：(次数, 字节) 成对出现
+    struct Rle;
+
+    impl CompressionCodec for Rle {
+        fn name(&self) -> &str {
+            "rle"
+        }
+
+        fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            let mut out = Vec::new();
+            for run in data.chunk_by(|a, b| a == b) {
+                for part in run.chunks(u8::MAX as usize) {
+                    out.push(part.len() as u8);
+                    out.push(part[0]);
+                }
+            }
+            Ok(out)
+        }
+
+        fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+            if data.len() % 2 != 0 {
+                bail_site!("truncated rle stream");
+            }
+            Ok(data
+                .chunks(2)
+                .flat_map(|pair| std::iter::repeat_n(pair[1], pair[0] as usize))
+                .collect())
+        }
+    }
+
+    #[test]
+    fn registered_codec_round_trips_and_tags_mass_header() {
+        // 用个不太可能和别的测试撞上的模式号
+        const MODE: u8 = 0xe7;
+        assert!(register_codec(COMPRESS_MODE_NONE, Arc::new(Rle)).is_err());
+        register_codec(MODE, Arc::new(Rle)).unwrap();
+        assert!(registered_modes().contains(&MODE));
+
+        let codec = get_codec(MODE).unwrap();
+        let raw = [vec![0u8; 600], b"watchface".to_vec()].concat();
+        let packed = codec.compress(&raw).unwrap();
+        assert!(packed.len() < raw.len());
+        assert_eq!(codec.decompress(&packed).unwrap(), raw);
+
+        let encoded = MassPacket::build(packed, MassDataType::Watchface)
+            .unwrap()
+            .with_compress_mode(MODE)
+            .encode_with_crc32();
+        assert_eq!(encoded[0], MODE);
+
+        assert!(unregister_codec(MODE).is_some());
+        assert!(get_codec(MODE).is_none());
+    }
+}
@@ -0,0 +1,108 @@
+//! MASS 线上格式里跟字节序有关的部分都收在这里。
+//!
+//! 容易踩的坑：
+//! - 分片头（总片数、当前片号）、长度字段、crc 落到线上时都是**小端**
+//! - `calc_crc32_bytes` 返回的是**大端**字节，要先转回 u32 再按小端写
+//!
+//! 发送端和 `ReverseMassPacket` 都只通过这里的函数读写，别再手搓 `from_le_bytes`
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::tools::calc_crc32_bytes;
+
+/// 分片头：总片数(2B LE) + 当前片号(2B LE)，片号从 1 开始
+pub const PART_HEADER_LEN: usize = 4;
+pub const CRC32_LEN: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartHeader {
+    pub total: u16,
+    pub current: u16,
+}
+
+impl PartHeader {
+    pub fn is_last(&self) -> bool {
+        self.current == self.total
+    }
+}
+
+pub fn write_part_header(buf: &mut Vec<u8>, header: PartHeader) {
+    let mut raw = [0u8; PART_HEADER_LEN];
+    LittleEndian::write_u16(&mut raw[0..2], header.total);
+    LittleEndian::write_u16(&mut raw[2..4], header.current);
+    buf.extend_from_slice(&raw);
+}
+
+/// 从 `bytes` 开头读分片头，不够长返回 None
+pub fn read_part_header(bytes: &[u8]) -> Option<PartHeader> {
+    let raw = bytes.get(..PART_HEADER_LEN)?;
+    Some(PartHeader {
+        total: LittleEndian::read_u16(&raw[0..2]),
+        current: LittleEndian::read_u16(&raw[2..4]),
+    })
+}
+
+/// 文件长度字段，小端
+pub fn write_length(buf: &mut Vec<u8>, len: u32) {
+    let mut raw = [0u8; 4];
+    LittleEndian::write_u32(&mut raw, len);
+    buf.extend_from_slice(&raw);
+}
+
+/// 和设备一致的 crc32 数值（ISO-HDLC），已经把 `calc_crc32_bytes` 的大端字节转回来了
+pub fn checksum(data: &[u8]) -> u32 {
+    u32::from_be_bytes(calc_crc32_bytes(data))
+}
+
+/// crc 写到线上是小端
+pub fn write_crc32(buf: &mut Vec<u8>, crc: u32) {
+    let mut raw = [0u8; CRC32_LEN];
+    LittleEndian::write_u32(&mut raw, crc);
+    buf.extend_from_slice(&raw);
+}
+
+/// 读 `bytes` 末尾 4 字节的 crc
+pub fn read_trailing_crc32(bytes: &[u8]) -> Option<u32> {
+    let start = bytes.len().checked_sub(CRC32_LEN)?;
+    Some(LittleEndian::read_u32(&bytes[start..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn part_header_round_trip() {
+        let header = PartHeader {
+            total: 0x0102,
+            current: 0x0304,
+        };
+        let mut buf = Vec::new();
+        write_part_header(&mut buf, header);
+
+        assert_eq!(buf, [0x02, 0x01, 0x04, 0x03]);
+        assert_eq!(read_part_header(&buf), Some(header));
+        assert_eq!(read_part_header(&buf[..3]), None);
+    }
+
+    #[test]
+    fn crc32_is_little_endian_on_wire() {
+        // "123456789" 的 CRC-32/ISO-HDLC 是 0xCBF43926
+        let crc = checksum(b"123456789");
+        assert_eq!(crc, 0xCBF4_3926);
+        assert_eq!(calc_crc32_bytes(b"123456789"), [0xCB, 0xF4, 0x39, 0x26]);
+
+        let mut buf = b"payload".to_vec();
+        write_crc32(&mut buf, crc);
+        assert_eq!(&buf[buf.len() - 4..], [0x26, 0x39, 0xF4, 0xCB]);
+        assert_eq!(read_trailing_crc32(&buf), Some(crc));
+        assert_eq!(read_trailing_crc32(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn length_is_little_endian() {
+        let mut buf = Vec::new();
+        write_length(&mut buf, 0x0A0B0C0D);
+        assert_eq!(buf, [0x0D, 0x0C, 0x0B, 0x0A]);
+    }
+}
@@ -0,0 +1,145 @@
+//! 原始 WearPacket 收发，给想折腾未公开 PB 类型的宿主用。
+//!
+//! 不稳定 API：信封格式和“认领”规则都可能随内置 System 的增加而变化。
+
+use std::{collections::HashMap, io::Cursor, sync::OnceLock};
+
+use parking_lot::RwLock;
+use pb::xiaomi::protocol::WearPacket;
+use prost::Message;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::device::xiaomi::system::take_pb_consumed;
+
+const RAW_PB_CHANNEL_CAPACITY: usize = 64;
+
+/// 没有被内置 System 认领的 WearPacket
+#[derive(Debug, Clone, Serialize)]
+pub struct RawWearPacket {
+    pub device_id: String,
+    pub r#type: i32,
+    pub id: u32,
+    /// 解密后完整的 WearPacket 编码，宿主自己按私有 proto 解
+    pub raw: Vec<u8>,
+}
+
+struct Subscriber {
+    type_filter: Option<i32>,
+    tx: broadcast::Sender<RawWearPacket>,
+}
+
+static RAW_PB_SUBSCRIBERS: OnceLock<RwLock<HashMap<String, Vec<Subscriber>>>> = OnceLock::new();
+
+fn subscriber_registry() -> &'static RwLock<HashMap<String, Vec<Subscriber>>> {
+    RAW_PB_SUBSCRIBERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// 订阅某设备上未被认领的 WearPacket，`type_filter` 为 None 表示全要
+pub fn subscribe(device_id: &str, type_filter: Option<i32>) -> broadcast::Receiver<RawWearPacket> {
+    let mut registry = subscriber_registry().write();
+    let subscribers = registry.entry(device_id.to_string()).or_default();
+    if let Some(existing) = subscribers
+        .iter()
+        .find(|sub| sub.type_filter == type_filter)
+    {
+        return existing.tx.subscribe();
+    }
+    let (tx, rx) = broadcast::channel(RAW_PB_CHANNEL_CAPACITY);
+    subscribers.push(Subscriber { type_filter, tx });
+    rx
+}
+
+/// 把 `type` / `id` 和调用方给的字段字节拼成 WearPacket。
+/// `payload_bytes` 是已经带 tag 的 protobuf 字段（一般就是 oneof 里的那一个），原样接在后面
+pub fn build_envelope(r#type: i32, id: u32, payload_bytes: &[u8]) -> Vec<u8> {
+    let mut raw = WearPacket {
+        r#type,
+        id,
+        payload: None,
+    }
+    .encode_to_vec();
+    raw.extend_from_slice(payload_bytes);
+    raw
+}
+
+/// 分发完一个 PB 包后调用，本轮没人认领就转给订阅者
+pub(crate) fn forward_unconsumed(device_id: &str, payload: &[u8]) {
+    if take_pb_consumed() {
+        return;
+    }
+
+    let mut registry = subscriber_registry().write();
+    let Some(subscribers) = registry.get_mut(device_id) else {
+        return;
+    };
+    // 接收端都没了的就别留着了
+    subscribers.retain(|sub| sub.tx.receiver_count() > 0);
+    if subscribers.is_empty() {
+        registry.remove(device_id);
+        return;
+    }
+
+    let header = match WearPacket::decode(Cursor::new(payload)) {
+        Ok(packet) => packet,
+        Err(err) => {
+            log::debug!("[RawPb] drop undecodable PB payload for {device_id}: {err}");
+            return;
+        }
+    };
+    let packet = RawWearPacket {
+        device_id: device_id.to_string(),
+        r#type: header.r#type,
+        id: header.id,
+        raw: payload.to_vec(),
+    };
+    for sub in subscribers.iter() {
+        if sub.type_filter.is_none_or(|ty| ty == packet.r#type) {
+            let _ = sub.tx.send(packet.clone());
+        }
+    }
+}
+
+pub fn clear_subscribers(device_id: &str) {
+    subscriber_registry().write().remove(device_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::system::mark_pb_consumed;
+
+    #[test]
+    fn forwards_by_type_filter() {
+        let device = "test:raw-pb-filter";
+        let mut all = subscribe(device, None);
+        let mut only_5 = subscribe(device, Some(5));
+
+        forward_unconsumed(device, &build_envelope(5, 1, &[]));
+        forward_unconsumed(device, &build_envelope(7, 2, &[]));
+
+        assert_eq!(all.try_recv().unwrap().r#type, 5);
+        assert_eq!(all.try_recv().unwrap().r#type, 7);
+        let got = only_5.try_recv().unwrap();
+        assert_eq!((got.r#type, got.id), (5, 1));
+        assert!(only_5.try_recv().is_err());
+
+        clear_subscribers(device);
+    }
+
+    #[test]
+    fn consumed_packets_are_not_forwarded() {
+        let device = "test:raw-pb-consumed";
+        let mut rx = subscribe(device, None);
+
+        mark_pb_consumed();
+        forward_unconsumed(device, &build_envelope(9, 1, &[]));
+        assert!(rx.try_recv().is_err());
+
+        // 标记只管一轮
+        forward_unconsumed(device, &build_envelope(9, 2, &[]));
+        assert_eq!(rx.try_recv().unwrap().id, 2);
+
+        clear_subscribers(device);
+    }
+}
@@ -0,0 +1,212 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{OnceLock, RwLock},
+};
+
+use anyhow::Result;
+use tokio::sync::oneshot;
+
+use crate::{
+    anyhow_site,
+    asyncrt::{Duration, timeout},
+    bail_site,
+    device::xiaomi::{
+        XiaomiDevice,
+        packet::v2::layer2::{L2Channel, L2Packet},
+    },
+};
+
+// (设备, 通道) -> 按发送顺序排队的读请求
+type PendingReads = HashMap<(String, u8), VecDeque<oneshot::Sender<Vec<u8>>>>;
+
+static PENDING_READS: OnceLock<RwLock<PendingReads>> = OnceLock::new();
+
+fn pending_reads() -> &'static RwLock<PendingReads> {
+    PENDING_READS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// 在指定通道上发一个 Read 请求，等设备在同一通道上回包
+/// 同一通道上的多个读请求按 FIFO 匹配回包，可用通道见 `L2Channel::supports_read`
+pub async fn send_l2_read(
+    device_id: String,
+    channel: L2Channel,
+    payload: Vec<u8>,
+    wait: Duration,
+) -> Result<Vec<u8>> {
+    if !channel.supports_read() {
+        bail_site!("channel {:?} does not support L2 Read", channel);
+    }
+
+    let key = (device_id.clone(), channel as u8);
+    let (tx, rx) = oneshot::channel();
+    pending_reads()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .entry(key.clone())
+        .or_default()
+        .push_back(tx);
+
+    let bytes = L2Packet::read(channel, payload).to_bytes();
+    let enqueued = crate::ecs::with_rt_mut({
+        let device_id = device_id.clone();
+        move |rt| {
+            rt.component_ref::<XiaomiDevice>(&device_id)
+                .map(|dev| {
+                    dev.sar.lock().enqueue(bytes);
+                })
+                .is_some()
+        }
+    })
+    .await;
+    if !enqueued {
+        drop_closed_reads(&key);
+        bail_site!("Device {} not found when sending L2 read", device_id);
+    }
+
+    let result = timeout(wait, rx).await;
+    // 超时或被丢弃的请求要从队列里清掉，不然会错配后面的回包
+    drop_closed_reads(&key);
+    match result {
+        Ok(Ok(data)) => Ok(data),
+        Ok(Err(_)) => Err(anyhow_site!("L2 read on {:?} was cancelled", channel)),
+        Err(_) => Err(anyhow_site!("Timeout waiting for L2 read on {:?}", channel)),
+    }
+}
+
+/// dispatcher 收到 L2 包后调用，有挂起的读请求就交给它，返回是否被消费
+pub fn try_complete_read(device_id: &str, channel: L2Channel, payload: &[u8]) -> bool {
+    if !channel.supports_read() {
+        return false;
+    }
+    let key = (device_id.to_string(), channel as u8);
+    let mut registry = pending_reads()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let Some(queue) = registry.get_mut(&key) else {
+        return false;
+    };
+
+    let mut delivered = false;
+    while let Some(tx) = queue.pop_front() {
+        if tx.send(payload.to_vec()).is_ok() {
+            delivered = true;
+            break;
+        }
+    }
+    if queue.is_empty() {
+        registry.remove(&key);
+    }
+    delivered
+}
+
+fn drop_closed_reads(key: &(String, u8)) {
+    let mut registry = pending_reads()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(queue) = registry.get_mut(key) {
+        queue.retain(|tx| !tx.is_closed());
+        if queue.is_empty() {
+            registry.remove(key);
+        }
+    }
+}
+
+/// 清掉某个设备上接收方已经放弃的读请求，SAR 巡检时调用
+pub(crate) fn drop_closed_reads_for(device_id: &str) {
+    let mut registry = pending_reads()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    registry.retain(|(owner, _), queue| {
+        if owner == device_id {
+            queue.retain(|tx| !tx.is_closed());
+        }
+        !queue.is_empty()
+    });
+}
+
+pub fn clear_pending_reads(device_id: &str) {
+    pending_reads()
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .retain(|(owner, _), _| owner != device_id);
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::{
+        spawn_mock_xiaomi_with_sender,
+        xiaomi::{
+            SendError, cleanup_cached_state,
+            config::XiaomiDeviceConfig,
+            packet::{dispatcher, v2::layer2::L2OpCode},
+        },
+    };
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[test]
+    fn read_goes_out_and_the_reply_comes_back() {
+        crate::ecs::init_runtime_default();
+        let addr = "test:l2-read";
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let sent: Arc<Mutex<Vec<u8>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let reply = rt.block_on(async {
+            spawn_mock_xiaomi_with_sender(addr, XiaomiDeviceConfig::default(), {
+                let sent = sent.clone();
+                move |frames: Vec<Vec<u8>>| {
+                    sent.lock().extend(frames.concat());
+                    async { Ok::<(), SendError>(()) }
+                }
+            })
+            .await;
+
+            // Pb 有自己的请求/响应，不能走 Read
+            assert!(
+                send_l2_read(
+                    addr.to_string(),
+                    L2Channel::Pb,
+                    vec![1],
+                    Duration::from_secs(1)
+                )
+                .await
+                .is_err()
+            );
+
+            let read = tokio::spawn(send_l2_read(
+                addr.to_string(),
+                L2Channel::Lyra,
+                b"query".to_vec(),
+                Duration::from_secs(2),
+            ));
+            let request = L2Packet::read(L2Channel::Lyra, b"query".to_vec()).to_bytes();
+            let mut on_wire = false;
+            for _ in 0..100 {
+                if sent
+                    .lock()
+                    .windows(request.len())
+                    .any(|window| window == request.as_slice())
+                {
+                    on_wire = true;
+                    break;
+                }
+                crate::asyncrt::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(on_wire, "L2 Read request never reached the transport");
+
+            // 手表在同一通道上回包，走真正的收包路径
+            let answer = L2Packet::new(L2Channel::Lyra, L2OpCode::Write, b"answer".to_vec())
+                .into_l1(0, true)
+                .to_bytes();
+            dispatcher::on_packet(tokio::runtime::Handle::current(), addr.to_string(), answer);
+            let reply = read.await.unwrap();
+
+            crate::ecs::with_rt_mut(move |rt| rt.remove_device(addr)).await;
+            reply
+        });
+        cleanup_cached_state(addr);
+
+        assert_eq!(reply.unwrap(), b"answer");
+    }
+}
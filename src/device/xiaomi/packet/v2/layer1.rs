@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -29,7 +30,8 @@ pub struct L1Packet {
     pub seq: u8,
     pub length: u16,
     pub crc: u16,
-    pub payload: Vec<u8>,
+    /// 用 Bytes 存，重传时 clone 只是加个引用计数
+    pub payload: Bytes,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -67,10 +69,13 @@ impl fmt::Display for L1Error {
 
 impl L1Packet {
     pub const MAGIC: u16 = 0xA5A5;
+    /// magic(2) + type|frx(1) + seq(1) + length(2) + crc(2)
+    pub const HEADER_LEN: usize = 8;
     const TYPE_MASK: u8 = 0x0F;
     const FRX_MASK: u8 = 0x10;
 
-    pub fn new(pkt_type: L1DataType, frx: bool, seq: u8, payload: Vec<u8>) -> Self {
+    pub fn new(pkt_type: L1DataType, frx: bool, seq: u8, payload: impl Into<Bytes>) -> Self {
+        let payload = payload.into();
         let mut pkt = Self {
             pkt_type,
             frx,
@@ -123,6 +128,20 @@ impl L1Packet {
     }
 
     pub fn from_bytes(buf: &[u8]) -> Result<Self, L1Error> {
+        Self::from_shared(Bytes::copy_from_slice(buf))
+    }
+
+    /// 同 from_bytes，payload 直接切 `buf` 的片，不再拷贝
+    pub fn from_shared(buf: Bytes) -> Result<Self, L1Error> {
+        Self::parse(buf, true)
+    }
+
+    /// 同 from_shared 但不算 CRC，只给 SPP 这种本身保证完整性的传输用
+    pub fn from_shared_unverified(buf: Bytes) -> Result<Self, L1Error> {
+        Self::parse(buf, false)
+    }
+
+    fn parse(buf: Bytes, verify_crc: bool) -> Result<Self, L1Error> {
         // min len = 2(magic)+1(type|frx)+1(seq)+2(len)+2(crc) = 8
         if buf.len() < 8 {
             return Err(L1Error::TooShort);
@@ -155,17 +174,18 @@ impl L1Packet {
                 actual: buf.len().saturating_sub(8),
             });
         }
-        let payload = buf[8..8 + length as usize].to_vec();
+        let payload = buf.slice(8..8 + length as usize);
 
         // 校验一下CRC，看看包是否完整
         // 小米笑转之传错包
-        let computed_crc = Self::crc16_arc(&payload);
-
-        if declared_crc != computed_crc {
-            return Err(L1Error::CrcMismatch {
-                declared: declared_crc,
-                computed: computed_crc,
-            });
+        if verify_crc {
+            let computed_crc = Self::crc16_arc(&payload);
+            if declared_crc != computed_crc {
+                return Err(L1Error::CrcMismatch {
+                    declared: declared_crc,
+                    computed: computed_crc,
+                });
+            }
         }
 
         Ok(Self {
@@ -209,3 +229,44 @@ impl L1Packet {
         Ok((ty, frx))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 重传时 clone 的包和原包共用同一块 payload，不会再拷一遍
+    #[test]
+    fn clone_4k_payload_shares_buffer() {
+        let pkt = L1Packet::new(L1DataType::Data, false, 1, vec![0x5a; 4096]);
+
+        assert_eq!(pkt.clone().payload.as_ptr(), pkt.payload.as_ptr());
+    }
+
+    #[test]
+    fn from_shared_slices_without_copy() {
+        let frame =
+            Bytes::from(L1Packet::new(L1DataType::Data, false, 7, b"payload".to_vec()).to_bytes());
+
+        let pkt = L1Packet::from_shared(frame.clone()).unwrap();
+
+        assert_eq!(pkt.seq, 7);
+        assert_eq!(&pkt.payload[..], b"payload");
+        assert_eq!(pkt.payload.as_ptr(), frame[8..].as_ptr());
+    }
+
+    #[test]
+    fn unverified_parse_skips_only_crc() {
+        let mut frame = L1Packet::new(L1DataType::Data, false, 3, b"payload".to_vec()).to_bytes();
+        frame[6] ^= 0xff;
+        let frame = Bytes::from(frame);
+
+        assert!(matches!(
+            L1Packet::from_shared(frame.clone()),
+            Err(L1Error::CrcMismatch { .. })
+        ));
+        let pkt = L1Packet::from_shared_unverified(frame).unwrap();
+        assert_eq!(&pkt.payload[..], b"payload");
+        assert!(!pkt.verify_crc());
+        assert!(L1Packet::from_shared_unverified(Bytes::from_static(&[0xa5, 0xa5, 0x03])).is_err());
+    }
+}
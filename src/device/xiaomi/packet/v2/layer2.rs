@@ -1,3 +1,4 @@
+use bytes::Bytes;
 use core::convert::TryFrom;
 use pb::xiaomi::protocol::WearPacket;
 use prost::Message;
@@ -5,7 +6,7 @@ use std::fmt;
 
 use crate::device::xiaomi::packet::v2::layer1::{L1DataType, L1Packet};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize)]
 #[repr(u8)]
 pub enum L2Channel {
     Pb = 1,          // TRANSPORT_CHANNEL_PB
@@ -20,6 +21,17 @@ pub enum L2Channel {
     MultiModal = 10, // TRANSPORT_CHANNEL_MULTI_MODAL
 }
 
+impl L2Channel {
+    /// 是否可以走 Read 操作码做读式请求
+    /// Pb 有自己的请求/响应语义，Mass/MassVoice 是分片流，Network 是裸 IP 包，这几个都不走 Read
+    pub fn supports_read(&self) -> bool {
+        !matches!(
+            self,
+            L2Channel::Pb | L2Channel::Mass | L2Channel::MassVoice | L2Channel::Network
+        )
+    }
+}
+
 impl TryFrom<u8> for L2Channel {
     type Error = L2Error;
     fn try_from(v: u8) -> Result<Self, Self::Error> {
@@ -66,8 +78,14 @@ pub enum L2Error {
     TooShort,
     InvalidChannel(u8),
     InvalidOpCode(u8),
-    LengthMismatch { expected: usize, actual: usize }, // 目前不会出现（L2 自身没有显式长度），但是留着，因为可扩展性这一块。
+    LengthMismatch {
+        expected: usize,
+        actual: usize,
+    }, // 目前不会出现（L2 自身没有显式长度），但是留着，因为可扩展性这一块。
     DecryptFailed,
+    EncryptFailed,
+    /// 策略要求加密，但还没有可用的 cipher（一般是还没认证完）
+    CipherUnavailable(L2Channel),
 }
 
 impl fmt::Display for L2Error {
@@ -84,6 +102,14 @@ impl fmt::Display for L2Error {
                 )
             }
             L2Error::DecryptFailed => write!(f, "Decryption failed"),
+            L2Error::EncryptFailed => write!(f, "Encryption failed"),
+            L2Error::CipherUnavailable(ch) => {
+                write!(
+                    f,
+                    "Channel {:?} requires encryption but no cipher is ready",
+                    ch
+                )
+            }
         }
     }
 }
@@ -92,15 +118,15 @@ impl fmt::Display for L2Error {
 pub struct L2Packet {
     pub channel: L2Channel,
     pub opcode: L2OpCode,
-    pub payload: Vec<u8>,
+    pub payload: Bytes,
 }
 
 impl L2Packet {
-    pub fn new(channel: L2Channel, opcode: L2OpCode, payload: Vec<u8>) -> Self {
+    pub fn new(channel: L2Channel, opcode: L2OpCode, payload: impl Into<Bytes>) -> Self {
         Self {
             channel,
             opcode,
-            payload,
+            payload: payload.into(),
         }
     }
 
@@ -117,16 +143,23 @@ impl L2Packet {
     /// `opcode == WriteEnc` 且提供了 `cipher`，则对 payload 解密后返回明文。
     /// `opcode == WriteEnc` 但没有提供 `cipher`，则保留密文原样放在 payload 中，由上层决定何时解密。
     pub fn from_bytes(buf: &[u8], cipher: Option<&dyn L2Cipher>) -> Result<Self, L2Error> {
+        Self::from_shared(Bytes::copy_from_slice(buf), cipher)
+    }
+
+    /// 同 from_bytes，不用解密时 payload 直接切 `buf` 的片
+    pub fn from_shared(buf: Bytes, cipher: Option<&dyn L2Cipher>) -> Result<Self, L2Error> {
         if buf.len() < 2 {
             return Err(L2Error::TooShort);
         }
         let ch = L2Channel::try_from(buf[0])?;
         let op = L2OpCode::try_from(buf[1])?;
-        let body = &buf[2..];
 
         let payload = match (op, cipher) {
-            (L2OpCode::WriteEnc, Some(c)) => c.decrypt(body).map_err(|_| L2Error::DecryptFailed)?,
-            _ => body.to_vec(),
+            (L2OpCode::WriteEnc, Some(c)) => c
+                .decrypt(&buf[2..])
+                .map_err(|_| L2Error::DecryptFailed)?
+                .into(),
+            _ => buf.slice(2..),
         };
 
         Ok(Self {
@@ -136,6 +169,10 @@ impl L2Packet {
         })
     }
 
+    pub fn read(channel: L2Channel, payload: impl Into<Bytes>) -> Self {
+        Self::new(channel, L2OpCode::Read, payload)
+    }
+
     pub fn pb_write(packet: WearPacket) -> Self {
         #[cfg(not(target_os = "espidf"))]
         log::trace!("l2_pb_write: {}", serde_json::to_string(&packet).unwrap());
@@ -160,7 +197,7 @@ impl L2Packet {
 
     pub fn from_l1(l1: &L1Packet, cipher: Option<&dyn L2Cipher>) -> Result<Self, L2Error> {
         match l1.pkt_type {
-            L1DataType::Data => L2Packet::from_bytes(&l1.payload, cipher),
+            L1DataType::Data => L2Packet::from_shared(l1.payload.clone(), cipher),
             _ => Err(L2Error::InvalidOpCode(0)),
         }
     }
@@ -1,7 +1,10 @@
+use anyhow::Result;
 use serde_repr::Serialize_repr;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use crate::device::xiaomi::{config::ResConfig, packet::mass::MassDataType};
+use crate::{anyhow_site, bail_site};
 
 const VALID_WATCHFACE_ID_LENGTHS: [usize; 2] = [9, 12];
 
@@ -81,6 +84,7 @@ pub const MAX_FIRMWARE_SCAN_BYTES: usize = 200_000_000;
 
 const FACTORY_MAGIC: &[u8] = b"\x60ZZ~";
 const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const WATCHFACE_MAGIC: &[u8] = b"\x5a\xa5\x34\x12";
 
 /// 判断一段数据是否为小米可穿戴固件。
 ///
@@ -177,6 +181,164 @@ fn count_subsequence(haystack: &[u8], needle: &[u8]) -> usize {
         .count()
 }
 
+/// 快应用包 manifest 中与安装请求相关的字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuickAppManifest {
+    pub package: String,
+    pub version_code: u32,
+    pub version_name: Option<String>,
+}
+
+// manifest 候选文件名，按优先级排
+const QUICKAPP_MANIFEST_NAMES: [&str; 2] = ["manifest-watch.json", "manifest.json"];
+// manifest 正常也就几 KB，防一下塞了个超大文件进来的包
+const MAX_QUICKAPP_MANIFEST_SIZE: u64 = 1024 * 1024;
+
+/// 从快应用包（rpk，本质是 zip）中解析 manifest。
+///
+/// 支持两种布局：
+/// - 普通 rpk：manifest 位于根目录（或某个子目录）；
+/// - 分包 rpks：外层 zip 里套着若干 `.rpk`，manifest 在主包里。
+pub fn parse_quickapp_manifest(data: &[u8]) -> Result<QuickAppManifest> {
+    parse_quickapp_manifest_inner(data, true)
+}
+
+fn parse_quickapp_manifest_inner(data: &[u8], allow_nested: bool) -> Result<QuickAppManifest> {
+    if data.len() < ZIP_MAGIC.len() || &data[..ZIP_MAGIC.len()] != ZIP_MAGIC {
+        bail_site!("quick app package is not a zip archive");
+    }
+
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|err| anyhow_site!("failed to open quick app package: {}", err))?;
+
+    // 根目录的 manifest 优先，其次才是子目录里的（部分打包工具会多包一层目录）
+    let mut best: Option<(usize, usize, usize)> = None;
+    let mut nested_rpks = Vec::new();
+    for index in 0..archive.len() {
+        let Ok(file) = archive.by_index(index) else {
+            continue;
+        };
+        let name = file.name();
+        if name.starts_with("__MACOSX/") || file.is_dir() {
+            continue;
+        }
+        let depth = name.matches('/').count();
+        let base = name.rsplit('/').next().unwrap_or(name);
+        if let Some(priority) = QUICKAPP_MANIFEST_NAMES.iter().position(|n| *n == base) {
+            let rank = (depth, priority, index);
+            if best.is_none_or(|cur| rank < cur) {
+                best = Some(rank);
+            }
+        } else if base.ends_with(".rpk") {
+            nested_rpks.push((base == "main.rpk" || base.contains(".main."), index));
+        }
+    }
+
+    if let Some((_, _, index)) = best {
+        let raw = read_zip_entry(&mut archive, index)?;
+        return parse_manifest_json(&raw);
+    }
+
+    if allow_nested && !nested_rpks.is_empty() {
+        // 主包优先，其余分包里一般没有 manifest
+        nested_rpks.sort_by_key(|(is_main, index)| (!is_main, *index));
+        for (_, index) in nested_rpks {
+            let inner = read_zip_entry(&mut archive, index)?;
+            if let Ok(manifest) = parse_quickapp_manifest_inner(&inner, false) {
+                return Ok(manifest);
+            }
+        }
+    }
+
+    bail_site!("manifest not found in quick app package")
+}
+
+fn read_zip_entry(archive: &mut zip::ZipArchive<Cursor<&[u8]>>, index: usize) -> Result<Vec<u8>> {
+    let file = archive
+        .by_index(index)
+        .map_err(|err| anyhow_site!("failed to read zip entry {}: {}", index, err))?;
+    let name = file.name().to_string();
+    let mut buf = Vec::with_capacity(file.size().min(MAX_QUICKAPP_MANIFEST_SIZE * 64) as usize);
+    // 内嵌 rpk 可以很大，这里只限制 manifest 本身
+    let limit = if name.ends_with(".rpk") {
+        u64::MAX
+    } else {
+        MAX_QUICKAPP_MANIFEST_SIZE + 1
+    };
+    file.take(limit)
+        .read_to_end(&mut buf)
+        .map_err(|err| anyhow_site!("failed to read {}: {}", name, err))?;
+    if !name.ends_with(".rpk") && buf.len() as u64 > MAX_QUICKAPP_MANIFEST_SIZE {
+        bail_site!("{} is too large", name);
+    }
+    Ok(buf)
+}
+
+fn parse_manifest_json(raw: &[u8]) -> Result<QuickAppManifest> {
+    // 有的打包工具会带 BOM
+    let raw = raw.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(raw);
+    let value: serde_json::Value = serde_json::from_slice(raw)
+        .map_err(|err| anyhow_site!("invalid quick app manifest: {}", err))?;
+
+    let package = value
+        .get("package")
+        .and_then(|v| v.as_str())
+        .map(str::trim)
+        .filter(|pkg| !pkg.is_empty())
+        .ok_or_else(|| anyhow_site!("quick app manifest has no package"))?
+        .to_string();
+
+    // versionCode 一般是数字，但也见过写成字符串的
+    let version_code = match value.get("versionCode") {
+        Some(serde_json::Value::Number(num)) => num.as_u64(),
+        Some(serde_json::Value::String(s)) => s.trim().parse::<u64>().ok(),
+        _ => None,
+    }
+    .and_then(|code| u32::try_from(code).ok())
+    .ok_or_else(|| anyhow_site!("quick app manifest has no valid versionCode"))?;
+
+    let version_name = value
+        .get("versionName")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+
+    Ok(QuickAppManifest {
+        package,
+        version_code,
+        version_name,
+    })
+}
+
+static LAST_FALLBACK_VERSION_CODE: AtomicU32 = AtomicU32::new(0);
+
+/// manifest 解析失败时用的兜底 versionCode：
+/// 以 2020-01-01 起的秒数为基础，保证同一进程内单调递增，手表至少会当成新版本覆盖安装。
+pub fn fallback_quickapp_version_code() -> u32 {
+    use web_time::{SystemTime, UNIX_EPOCH};
+    const EPOCH_2020: u64 = 1_577_836_800;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+        .saturating_sub(EPOCH_2020);
+    let now = u32::try_from(now).unwrap_or(u32::MAX);
+
+    let mut prev = LAST_FALLBACK_VERSION_CODE.load(Ordering::Relaxed);
+    loop {
+        let next = now.max(prev.saturating_add(1));
+        match LAST_FALLBACK_VERSION_CODE.compare_exchange_weak(
+            prev,
+            next,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => return next,
+            Err(actual) => prev = actual,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -186,6 +348,7 @@ mod tests {
         ResConfig {
             watchface_id_offset: 4,
             watchface_id_field_len: 24,
+            ..ResConfig::default()
         }
     }
 
@@ -365,6 +528,133 @@ mod tests {
         assert!(!is_xiaomi_firmware(&data, Some(data.len())));
     }
 
+    fn zip_with_files(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let cursor = Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(cursor);
+        let options = zip::write::FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        for (name, data) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    // toolkit 直接打出来的 rpk：根目录 manifest.json + 编译产物 + 签名
+    fn toolkit_rpk() -> Vec<u8> {
+        zip_with_files(&[
+            ("META-INF/CERT", b"cert"),
+            (
+                "manifest.json",
+                br#"{"package":"com.example.watch.todo","name":"Todo","versionName":"1.2.0","versionCode":12,"minPlatformVersion":1000,"features":[]}"#,
+            ),
+            ("app.js", b"!function(){}"),
+            ("pages/index/index.js", b"!function(){}"),
+            ("common/logo.png", b"\x89PNG"),
+        ])
+    }
+
+    #[test]
+    fn parses_toolkit_rpk_manifest() {
+        let manifest = parse_quickapp_manifest(&toolkit_rpk()).unwrap();
+
+        assert_eq!(manifest.package, "com.example.watch.todo");
+        assert_eq!(manifest.version_code, 12);
+        assert_eq!(manifest.version_name.as_deref(), Some("1.2.0"));
+    }
+
+    #[test]
+    fn parses_split_rpks_with_watch_manifest() {
+        // 分包布局：外层套 main.rpk + 分包，主包里是 manifest-watch.json，versionCode 写成了字符串
+        let main = zip_with_files(&[
+            ("META-INF/CERT", b"cert"),
+            (
+                "manifest-watch.json",
+                "\u{feff}{\"package\":\"com.example.watch.music\",\"versionName\":\"2.0\",\"versionCode\":\"2003\"}"
+                    .as_bytes(),
+            ),
+            ("app.js", b"!function(){}"),
+        ]);
+        let sub = zip_with_files(&[("pages/player/index.js", b"!function(){}")]);
+        let rpks = zip_with_files(&[
+            ("com.example.watch.music.sub.player.rpk", &sub),
+            ("main.rpk", &main),
+        ]);
+
+        let manifest = parse_quickapp_manifest(&rpks).unwrap();
+
+        assert_eq!(manifest.package, "com.example.watch.music");
+        assert_eq!(manifest.version_code, 2003);
+    }
+
+    #[test]
+    fn rejects_rpk_without_manifest() {
+        let data = zip_with_files(&[("app.js", b"!function(){}")]);
+
+        assert!(parse_quickapp_manifest(&data).is_err());
+    }
+
+    #[test]
+    fn fallback_version_code_is_monotonic() {
+        let a = fallback_quickapp_version_code();
+        let b = fallback_quickapp_version_code();
+
+        assert!(b > a);
+    }
+
+    #[test]
+    fn file_type_maps_to_mass_data_type() {
+        assert_eq!(
+            MassDataType::try_from(FileType::WatchFace).unwrap(),
+            MassDataType::Watchface
+        );
+        assert_eq!(
+            MassDataType::try_from(FileType::Firmware).unwrap(),
+            MassDataType::Firmware
+        );
+        assert_eq!(
+            MassDataType::try_from(FileType::ThirdPartyApp).unwrap(),
+            MassDataType::ThirdPartyApp
+        );
+        for ty in [
+            FileType::Text,
+            FileType::Zip,
+            FileType::Binary,
+            FileType::Null,
+            FileType::Abp,
+        ] {
+            assert!(MassDataType::try_from(ty).is_err());
+        }
+    }
+
+    #[test]
+    fn abp_package_is_unpacked_to_inner_resource() {
+        let rpk = toolkit_rpk();
+        let abp = zip_with_files(&[
+            ("abp.json", br#"{"file":"./app/todo.rpk"}"#),
+            ("app/todo.rpk", &rpk),
+        ]);
+
+        assert_eq!(get_file_type(&abp), FileType::Abp);
+        assert_eq!(
+            detect_mass_data_type(&abp).unwrap(),
+            MassDataType::ThirdPartyApp
+        );
+        let (ty, payload) = prepare_install_payload(abp).unwrap();
+        assert_eq!(ty, MassDataType::ThirdPartyApp);
+        assert_eq!(payload, rpk);
+
+        let declared = zip_with_files(&[
+            ("abp.json", br#"{"file":"face.bin","type":"watchface"}"#),
+            ("face.bin", b"\x5a\xa5\x34\x12face"),
+        ]);
+        let (ty, _) = prepare_install_payload(declared).unwrap();
+        assert_eq!(ty, MassDataType::Watchface);
+
+        let missing = zip_with_files(&[("abp.json", br#"{"file":"gone.bin"}"#)]);
+        assert!(unpack_abp_package(&missing).is_err());
+    }
+
     #[test]
     fn get_file_type_recognizes_miwear_ota_as_firmware() {
         let data = zip_with_entry("vela_ap.bin", MIN_FIRMWARE_SIZE);
@@ -378,6 +668,42 @@ mod tests {
 
         assert_eq!(get_file_type(&data), FileType::Firmware);
     }
+
+    #[test]
+    fn validate_package_reads_metadata() {
+        let config = test_config();
+        let mut face = data_with_field(b"123456789012");
+        face[..4].copy_from_slice(WATCHFACE_MAGIC);
+        let info = validate_package(&face, MassDataType::Watchface, &config).unwrap();
+        assert_eq!(info.id.as_deref(), Some("123456789012"));
+
+        let info = validate_package(&toolkit_rpk(), MassDataType::ThirdPartyApp, &config).unwrap();
+        assert_eq!(info.package_name.as_deref(), Some("com.example.watch.todo"));
+        assert_eq!(info.version_code, Some(12));
+        assert_eq!(info.version_name.as_deref(), Some("1.2.0"));
+    }
+
+    #[test]
+    fn validate_package_rejects_wrong_or_corrupt_files() {
+        let config = test_config();
+        let rpk = toolkit_rpk();
+        let err = validate_package(&rpk, MassDataType::Watchface, &config).unwrap_err();
+        assert!(err.to_string().contains("bad magic"));
+
+        // 条目内容坏了一个字节，zip 目录还在，解开时 CRC 对不上
+        let mut corrupt = zip_with_entry("app.js", 64);
+        corrupt[30 + "app.js".len() + 10] ^= 0xff;
+        let err = validate_package(&corrupt, MassDataType::ThirdPartyApp, &config).unwrap_err();
+        assert!(err.to_string().contains("corrupt zip entry app.js"));
+
+        let abp = zip_with_files(&[
+            ("abp.json", br#"{"file":"app.rpk","type":"quickapp"}"#),
+            ("app.rpk", &rpk),
+        ]);
+        let err = validate_package(&abp, MassDataType::Watchface, &config).unwrap_err();
+        assert!(err.to_string().contains("contains a ThirdPartyApp"));
+        assert!(validate_package(&[], MassDataType::Firmware, &config).is_err());
+    }
 }
 
 #[derive(Clone, Copy, Debug, Serialize_repr, PartialEq)]
@@ -390,7 +716,7 @@ pub enum FileType {
     // 又接暗广我服了。
     Abp = 91,
     WatchFace = MassDataType::Watchface as u8,
-    Firmware = MassDataType::Firmare as u8,
+    Firmware = MassDataType::Firmware as u8,
     ThirdPartyApp = MassDataType::ThirdPartyApp as u8,
 }
 pub fn get_file_type(data: &[u8]) -> FileType {
@@ -403,12 +729,10 @@ pub fn get_file_type(data: &[u8]) -> FileType {
     }
     // 1. 检查是不是 ZIP 格式
     if data.len() >= 4 && &data[..4] == [0x50, 0x4B, 0x03, 0x04] {
-        /* // 检查扩展名 abp
-        if let Some(ext) = filename.extension() {
-            if ext == "abp" {
-                return Ok("abp".to_string());
-            }
-        } */
+        // abp 是套了一层描述文件的资源包，拿不到扩展名就只能看里面有没有 abp.json
+        if is_abp_package(data) {
+            return FileType::Abp;
+        }
         // 检查尾部是否包含 quickapp 字样
         let tail = &data[..];
 
@@ -434,3 +758,237 @@ pub fn get_file_type(data: &[u8]) -> FileType {
     // 4. 其它都认为是二进制
     FileType::Binary
 }
+
+// abp 包根目录的描述文件。这个格式是本库自己定的（不是从官方/现成的 abp 包里扒来的），
+// 目前只认 `file` 和可选的 `type` 两个字段，其它字段一律忽略
+const ABP_DESCRIPTOR_NAME: &str = "abp.json";
+const MAX_ABP_DESCRIPTOR_SIZE: u64 = 64 * 1024;
+
+fn is_abp_package(data: &[u8]) -> bool {
+    let Ok(archive) = zip::ZipArchive::new(Cursor::new(data)) else {
+        return false;
+    };
+    archive.file_names().any(|name| name == ABP_DESCRIPTOR_NAME)
+}
+
+/// 拆开 abp 包，返回里面真正要装的文件和对应的 MassDataType。
+///
+/// `abp.json` 里 `file` 指向包内的资源文件，`type` 可选（`watchface` / `quickapp` / `firmware`），
+/// 没写就按资源内容自己猜。例如：
+///
+/// ```json
+/// { "file": "watchface.bin", "type": "watchface" }
+/// ```
+///
+/// 描述文件格式由本库定义，不对应任何官方规范。
+pub fn unpack_abp_package(data: &[u8]) -> Result<(MassDataType, Vec<u8>)> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|err| anyhow_site!("failed to open abp package: {}", err))?;
+
+    let descriptor = {
+        let file = archive
+            .by_name(ABP_DESCRIPTOR_NAME)
+            .map_err(|_| anyhow_site!("{} not found in abp package", ABP_DESCRIPTOR_NAME))?;
+        let mut buf = Vec::new();
+        file.take(MAX_ABP_DESCRIPTOR_SIZE + 1)
+            .read_to_end(&mut buf)
+            .map_err(|err| anyhow_site!("failed to read {}: {}", ABP_DESCRIPTOR_NAME, err))?;
+        if buf.len() as u64 > MAX_ABP_DESCRIPTOR_SIZE {
+            bail_site!("{} is too large", ABP_DESCRIPTOR_NAME);
+        }
+        buf
+    };
+    let descriptor = descriptor
+        .strip_prefix(b"\xEF\xBB\xBF")
+        .unwrap_or(&descriptor);
+    let value: serde_json::Value = serde_json::from_slice(descriptor)
+        .map_err(|err| anyhow_site!("invalid {}: {}", ABP_DESCRIPTOR_NAME, err))?;
+
+    let entry = value
+        .get("file")
+        .and_then(|v| v.as_str())
+        .map(|name| name.trim().trim_start_matches("./"))
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| anyhow_site!("{} has no file", ABP_DESCRIPTOR_NAME))?
+        .to_string();
+    let declared = match value.get("type").and_then(|v| v.as_str()) {
+        Some("watchface") => Some(MassDataType::Watchface),
+        Some("quickapp") => Some(MassDataType::ThirdPartyApp),
+        Some("firmware") => Some(MassDataType::Firmware),
+        Some(other) => bail_site!("unsupported abp content type: {}", other),
+        None => None,
+    };
+
+    let payload = {
+        let mut file = archive
+            .by_name(&entry)
+            .map_err(|_| anyhow_site!("{} not found in abp package", entry))?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf)
+            .map_err(|err| anyhow_site!("failed to read {}: {}", entry, err))?;
+        buf
+    };
+
+    let data_type = match declared {
+        Some(ty) => ty,
+        // 套娃的 abp 不认
+        None => match get_file_type(&payload) {
+            FileType::Abp => bail_site!("nested abp package is not supported"),
+            other => MassDataType::try_from(other)?,
+        },
+    };
+    Ok((data_type, payload))
+}
+
+impl TryFrom<FileType> for MassDataType {
+    type Error = anyhow::Error;
+
+    fn try_from(value: FileType) -> Result<Self> {
+        match value {
+            FileType::WatchFace => Ok(MassDataType::Watchface),
+            FileType::Firmware => Ok(MassDataType::Firmware),
+            FileType::ThirdPartyApp => Ok(MassDataType::ThirdPartyApp),
+            FileType::Text | FileType::Zip | FileType::Binary | FileType::Null | FileType::Abp => {
+                Err(anyhow_site!("{:?} file can not be installed via MASS", value))
+            }
+        }
+    }
+}
+
+/// 根据文件内容猜安装时该用的 MassDataType
+pub fn detect_mass_data_type(data: &[u8]) -> Result<MassDataType> {
+    match get_file_type(data) {
+        FileType::Abp => unpack_abp_package(data).map(|(ty, _)| ty),
+        other => MassDataType::try_from(other),
+    }
+}
+
+/// 安装前整理一下文件：abp 拆出里面的资源，其它原样返回
+pub fn prepare_install_payload(data: Vec<u8>) -> Result<(MassDataType, Vec<u8>)> {
+    match get_file_type(&data) {
+        FileType::Abp => unpack_abp_package(&data),
+        other => Ok((MassDataType::try_from(other)?, data)),
+    }
+}
+
+/// `validate_package` 从包里读出来的信息，读不到的字段是 None
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageInfo {
+    pub data_type: MassDataType,
+    /// 实际要传的大小，abp 包是拆出来的那个文件的大小
+    pub size: usize,
+    /// 表盘 id
+    pub id: Option<String>,
+    /// 快应用包名
+    pub package_name: Option<String>,
+    /// 快应用 manifest 里的 versionCode；表盘的跟固件版本有关，装的时候才算
+    pub version_code: Option<u32>,
+    pub version_name: Option<String>,
+}
+
+/// 传输前先把包检查一遍：魔数对不对、zip 能不能完整解开（每个条目都读一遍校验 CRC）、
+/// 表盘 id 在不在，能读到的元数据一起返回。错误里写明是哪一步不对，
+/// 用户拿错文件或文件下坏了的时候不用等传完才知道。
+///
+/// 快应用 manifest 解析不了不算错，安装时本来就会退回时间戳版本号。
+/// 图标、音乐这些不是“包”的类型只检查非空。
+pub fn validate_package(
+    data: &[u8],
+    expected: MassDataType,
+    config: &ResConfig,
+) -> Result<PackageInfo> {
+    if data.is_empty() {
+        bail_site!("{} package is empty", expected);
+    }
+
+    if get_file_type(data) == FileType::Abp {
+        let (inner_type, payload) = unpack_abp_package(data)?;
+        if inner_type != expected {
+            bail_site!(
+                "expected a {} package, but the abp package contains a {}",
+                expected,
+                inner_type
+            );
+        }
+        return validate_package(&payload, expected, config);
+    }
+
+    let mut info = PackageInfo {
+        data_type: expected,
+        size: data.len(),
+        id: None,
+        package_name: None,
+        version_code: None,
+        version_name: None,
+    };
+    match expected {
+        MassDataType::Watchface => {
+            if !data.starts_with(WATCHFACE_MAGIC) {
+                bail_site!(
+                    "not a watchface: bad magic {:02x?} (looks like {:?})",
+                    &data[..data.len().min(WATCHFACE_MAGIC.len())],
+                    get_file_type(data)
+                );
+            }
+            let id = get_watchface_id(data, config).ok_or_else(|| {
+                anyhow_site!(
+                    "watchface id not found at offset {} (file is {} bytes)",
+                    config.watchface_id_offset,
+                    data.len()
+                )
+            })?;
+            info.id = Some(id);
+        }
+        MassDataType::ThirdPartyApp => {
+            if !data.starts_with(ZIP_MAGIC) {
+                bail_site!(
+                    "not a quick app package: not a zip archive (looks like {:?})",
+                    get_file_type(data)
+                );
+            }
+            verify_zip_entries(data)?;
+            match parse_quickapp_manifest(data) {
+                Ok(manifest) => {
+                    info.package_name = Some(manifest.package);
+                    info.version_code = Some(manifest.version_code);
+                    info.version_name = manifest.version_name;
+                }
+                Err(err) => {
+                    log::warn!("[resutils] quick app package has no usable manifest: {err:?}")
+                }
+            }
+        }
+        MassDataType::Firmware => {
+            if !is_xiaomi_firmware(data, Some(data.len())) {
+                bail_site!(
+                    "not a Xiaomi wearable firmware (looks like {:?}, {} bytes)",
+                    get_file_type(data),
+                    data.len()
+                );
+            }
+            if data.starts_with(ZIP_MAGIC) {
+                verify_zip_entries(data)?;
+            }
+        }
+        MassDataType::WatchfaceImage
+        | MassDataType::NotificationIcon
+        | MassDataType::Music
+        | MassDataType::WatchfaceFont => {}
+    }
+    Ok(info)
+}
+
+/// 把每个条目都解压一遍，zip 库读完会校验 CRC，下载不完整的包在这里就能发现
+fn verify_zip_entries(data: &[u8]) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))
+        .map_err(|err| anyhow_site!("corrupt zip archive: {}", err))?;
+    for index in 0..archive.len() {
+        let mut file = archive
+            .by_index(index)
+            .map_err(|err| anyhow_site!("corrupt zip entry {}: {}", index, err))?;
+        let name = file.name().to_string();
+        std::io::copy(&mut file, &mut std::io::sink())
+            .map_err(|err| anyhow_site!("corrupt zip entry {}: {}", name, err))?;
+    }
+    Ok(())
+}
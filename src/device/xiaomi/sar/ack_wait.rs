@@ -0,0 +1,93 @@
+//! 按 seq 等 ACK。`record_acked` 确认一个 seq 就顺手划掉，等齐了直接叫醒，
+//! 上层不用再每隔一会儿进 runtime 查一遍 `is_acked`
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::sync::oneshot;
+
+struct Waiter {
+    remaining: Vec<u8>,
+    tx: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+pub(super) struct AckWaiters {
+    waiters: Vec<Waiter>,
+}
+
+impl AckWaiters {
+    /// `remaining` 里是还没确认的 seq，空的话直接就绪
+    pub(super) fn register(&mut self, remaining: Vec<u8>) -> AckWait {
+        if remaining.is_empty() {
+            return AckWait {
+                state: State::Done(true),
+            };
+        }
+        // 调用方中途不等了的顺手清掉
+        self.waiters.retain(|waiter| !waiter.tx.is_closed());
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push(Waiter { remaining, tx });
+        AckWait {
+            state: State::Pending(rx),
+        }
+    }
+
+    pub(super) fn on_acked(&mut self, seq: u8) {
+        if self.waiters.is_empty() {
+            return;
+        }
+        let mut i = 0;
+        while i < self.waiters.len() {
+            let waiter = &mut self.waiters[i];
+            waiter.remaining.retain(|s| *s != seq);
+            if waiter.remaining.is_empty() {
+                let _ = self.waiters.swap_remove(i).tx.send(());
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// 链路判死时调用，所有等待以 false 结束
+    pub(super) fn fail_all(&mut self) {
+        self.waiters.clear();
+    }
+
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.waiters.len()
+    }
+}
+
+enum State {
+    Pending(oneshot::Receiver<()>),
+    Done(bool),
+}
+
+/// `SarController::await_acks` 返回的 future：全部确认为 true，
+/// 链路判死或者 SarController 没了（设备被移除）为 false。完成后再 poll 还是同一个结果
+#[must_use = "AckWait does nothing unless awaited"]
+pub struct AckWait {
+    state: State,
+}
+
+impl Future for AckWait {
+    type Output = bool;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        let this = &mut *self;
+        match &mut this.state {
+            State::Done(acked) => Poll::Ready(*acked),
+            State::Pending(rx) => match Pin::new(rx).poll(cx) {
+                Poll::Ready(res) => {
+                    let acked = res.is_ok();
+                    this.state = State::Done(acked);
+                    Poll::Ready(acked)
+                }
+                Poll::Pending => Poll::Pending,
+            },
+        }
+    }
+}
@@ -56,6 +56,11 @@ impl CommandPool {
         self.cmd_queue.pop_front()
     }
 
+    /// 待发数据条数
+    pub fn data_len(&self) -> usize {
+        self.data_queue.len()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.cmd_queue.is_empty() && self.data_queue.is_empty()
     }
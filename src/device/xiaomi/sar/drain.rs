@@ -0,0 +1,272 @@
+use anyhow::Result;
+use serde::Serialize;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(target_arch = "wasm32")]
+use web_time::Duration;
+
+use super::{LinkState, SarController};
+use crate::{anyhow_site, device::xiaomi::XiaomiDevice};
+
+// 没等到 ACK 通知时多久自己看一眼
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// `SarController::drain` 的结果
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DrainReport {
+    /// 积压是否全部发完并被确认
+    pub drained: bool,
+    /// 还在 CommandPool 里没发出去的数据
+    pub remaining_queued: usize,
+    /// 已经发出去但还没等到 ACK 的数据
+    pub remaining_in_flight: usize,
+    /// drain 期间新入队、被压到之后才发的数据
+    pub deferred: usize,
+    /// 链路已经判死，提前放弃
+    pub link_failed: bool,
+    pub elapsed_ms: u64,
+}
+
+impl SarController {
+    /// 开始 drain：之后入队的数据先压着，不算进这次要清空的积压
+    pub fn begin_drain(&mut self) {
+        self.draining = true;
+        self.try_run_next();
+    }
+
+    /// 结束 drain，把期间压着的数据放回发送池，返回压了多少条
+    pub fn end_drain(&mut self) -> usize {
+        self.draining = false;
+        let mut deferred = 0;
+        while let Some(item) = self.held.pop_data() {
+            self.command_pool.push(item);
+            deferred += 1;
+        }
+        if deferred > 0 {
+            self.try_run_next();
+        }
+        deferred
+    }
+
+    #[inline]
+    pub fn is_draining(&self) -> bool {
+        self.draining
+    }
+
+    /// (还没发的, 等 ACK 的)
+    pub fn pending_counts(&self) -> (usize, usize) {
+        (self.command_pool.data_len(), self.tx_queue.len())
+    }
+
+    /// 等发送池和在途队列都清空，或者超时/链路判死。
+    /// 给宿主切后台前调用，避免 MASS 分片、时间同步之类的还没发完进程就被杀了
+    pub async fn drain(device_id: String, wait: Duration) -> Result<DrainReport> {
+        let (notify, clock) = crate::ecs::with_rt_mut_labeled("sar::drain_begin", {
+            let device_id = device_id.clone();
+            move |rt| {
+                rt.component_ref::<XiaomiDevice>(&device_id).map(|dev| {
+                    let mut sar = dev.sar.lock();
+                    sar.begin_drain();
+                    (sar.ack_notifier(), sar.clock.clone())
+                })
+            }
+        })
+        .await
+        .ok_or_else(|| anyhow_site!("Device {} not found when draining", device_id))?;
+        let started = clock.now();
+
+        let mut report = DrainReport::default();
+        loop {
+            let snapshot = crate::ecs::with_rt_read({
+                let device_id = device_id.clone();
+                move |rt| {
+                    rt.component_ref::<XiaomiDevice>(&device_id).map(|dev| {
+                        let sar = dev.sar.lock();
+                        (sar.pending_counts(), sar.link_state())
+                    })
+                }
+            })
+            .await;
+
+            // 设备中途被移除，等于链路没了
+            let Some(((queued, in_flight), link)) = snapshot else {
+                report.link_failed = true;
+                break;
+            };
+            report.remaining_queued = queued;
+            report.remaining_in_flight = in_flight;
+
+            if queued == 0 && in_flight == 0 {
+                report.drained = true;
+                break;
+            }
+            if link == LinkState::Failed {
+                report.link_failed = true;
+                break;
+            }
+            let elapsed = clock.elapsed(started);
+            if elapsed >= wait {
+                break;
+            }
+
+            let step = (wait - elapsed).min(DRAIN_POLL_INTERVAL);
+            let _ = clock.timeout(step, notify.notified()).await;
+        }
+
+        report.deferred = crate::ecs::with_rt_mut_labeled("sar::drain_end", {
+            let device_id = device_id.clone();
+            move |rt| {
+                rt.component_ref::<XiaomiDevice>(&device_id)
+                    .map(|dev| dev.sar.lock().end_drain())
+                    .unwrap_or_default()
+            }
+        })
+        .await;
+        report.elapsed_ms = clock
+            .elapsed(started)
+            .as_millis()
+            .try_into()
+            .unwrap_or(u64::MAX);
+
+        if !report.drained {
+            log::warn!(
+                "[SarController] drain for {} gave up: {} queued, {} in flight, link_failed={}",
+                device_id,
+                report.remaining_queued,
+                report.remaining_in_flight,
+                report.link_failed
+            );
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use crate::device::{
+        spawn_mock_xiaomi_with_sender,
+        xiaomi::{
+            SendError,
+            config::XiaomiDeviceConfig,
+            packet::v2::layer1::{L1DataType, L1Packet},
+        },
+    };
+    use bytes::Bytes;
+
+    // 慢吞吞的传输层，发出去但手表不回 ACK
+    async fn slow_sender(_frames: Vec<Vec<u8>>) -> Result<(), SendError> {
+        crate::asyncrt::sleep(Duration::from_millis(30)).await;
+        Ok(())
+    }
+
+    fn with_sar<R: Send + 'static>(
+        rt: &tokio::runtime::Runtime,
+        id: &str,
+        f: impl FnOnce(&mut SarController) -> R + Send + 'static,
+    ) -> R {
+        let id = id.to_string();
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| {
+            let dev = rt.component_ref::<XiaomiDevice>(&id).unwrap();
+            f(&mut dev.sar.lock())
+        }))
+    }
+
+    fn remove_device(rt: &tokio::runtime::Runtime, id: &str) {
+        let owner = id.to_string();
+        rt.block_on(crate::ecs::with_rt_mut(move |rt| {
+            rt.remove_device(&owner);
+        }));
+        crate::device::xiaomi::cleanup_cached_state(id);
+    }
+
+    #[test]
+    fn drain_reports_partial_progress_on_timeout() {
+        let _lock = super::super::tests::SAR_TEST_LOCK.lock();
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let id = "test:drain-partial";
+        rt.block_on(spawn_mock_xiaomi_with_sender(
+            id,
+            XiaomiDeviceConfig::default(),
+            slow_sender,
+        ));
+
+        with_sar(&rt, id, |sar| {
+            sar.enqueue_batch(vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+        });
+
+        let report = rt.block_on(async {
+            let late = tokio::spawn({
+                let id = id.to_string();
+                async move {
+                    crate::asyncrt::sleep(Duration::from_millis(50)).await;
+                    crate::ecs::with_rt_mut(move |rt| {
+                        let dev = rt.component_ref::<XiaomiDevice>(&id).unwrap();
+                        dev.sar.lock().enqueue(b"late".to_vec());
+                    })
+                    .await;
+                }
+            });
+            let report = SarController::drain(id.to_string(), Duration::from_millis(300))
+                .await
+                .unwrap();
+            late.await.unwrap();
+            report
+        });
+
+        assert!(!report.drained);
+        assert!(!report.link_failed);
+        assert_eq!(report.remaining_queued, 0);
+        assert_eq!(report.remaining_in_flight, 3);
+        assert_eq!(report.deferred, 1);
+        // drain 结束后压着的数据要照常发出去
+        assert_eq!(with_sar(&rt, id, |sar| sar.pending_counts()), (0, 4));
+        assert!(!with_sar(&rt, id, |sar| sar.is_draining()));
+
+        remove_device(&rt, id);
+    }
+
+    #[test]
+    fn drain_completes_once_everything_is_acked() {
+        let _lock = super::super::tests::SAR_TEST_LOCK.lock();
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let id = "test:drain-full";
+        rt.block_on(spawn_mock_xiaomi_with_sender(
+            id,
+            XiaomiDeviceConfig::default(),
+            slow_sender,
+        ));
+
+        let seqs = with_sar(&rt, id, |sar| {
+            sar.enqueue_batch(vec![b"a".to_vec(), b"b".to_vec()])
+        });
+        let last = *seqs.last().unwrap();
+
+        let report = rt.block_on(async {
+            tokio::spawn({
+                let id = id.to_string();
+                async move {
+                    crate::asyncrt::sleep(Duration::from_millis(100)).await;
+                    crate::ecs::with_rt_mut(move |rt| {
+                        let dev = rt.component_ref::<XiaomiDevice>(&id).unwrap();
+                        let ack = L1Packet::new(L1DataType::Ack, false, last, Bytes::new());
+                        dev.sar.lock().on_l1_packet(&ack);
+                    })
+                    .await;
+                }
+            });
+            SarController::drain(id.to_string(), Duration::from_secs(2))
+                .await
+                .unwrap()
+        });
+
+        assert!(report.drained);
+        assert_eq!(report.remaining_in_flight, 0);
+        assert!(report.elapsed_ms < 2_000);
+
+        remove_device(&rt, id);
+    }
+}
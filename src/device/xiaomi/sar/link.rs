@@ -0,0 +1,101 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+#[cfg(target_arch = "wasm32")]
+use web_time::{Duration, Instant};
+
+use crate::device::xiaomi::clock::SharedClock;
+
+/// SAR 视角下的链路状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LinkState {
+    Active,
+    /// 发送端报 Disconnected，处于宽限期内，队列原样保留
+    Paused {
+        paused_ms: u64,
+    },
+    /// 宽限期耗尽，等待方应当直接失败
+    Failed,
+}
+
+#[derive(Default)]
+struct LinkInner {
+    paused_since: Option<Instant>,
+    /// 链路已经恢复但 SarController 还没处理的暂停时长
+    resumed_after: Option<Duration>,
+    failed: bool,
+}
+
+/// 观察发送结果的链路监视器，发送任务和 SarController 共享
+#[derive(Default)]
+pub struct LinkMonitor {
+    inner: Mutex<LinkInner>,
+    clock: SharedClock,
+}
+
+impl LinkMonitor {
+    pub fn new(clock: SharedClock) -> Self {
+        Self {
+            inner: Mutex::default(),
+            clock,
+        }
+    }
+
+    pub fn on_disconnected(&self) {
+        let mut inner = self.inner.lock();
+        if inner.paused_since.is_none() && !inner.failed {
+            log::warn!("[SarController] sender reported Disconnected, pausing link");
+            inner.paused_since = Some(self.clock.now());
+        }
+    }
+
+    pub fn on_send_ok(&self) {
+        let mut inner = self.inner.lock();
+        if let Some(since) = inner.paused_since.take() {
+            let paused = self.clock.elapsed(since);
+            log::info!(
+                "[SarController] link recovered after {} ms",
+                paused.as_millis()
+            );
+            inner.resumed_after = Some(inner.resumed_after.unwrap_or_default() + paused);
+        }
+        inner.failed = false;
+    }
+
+    pub fn mark_failed(&self) {
+        let mut inner = self.inner.lock();
+        inner.paused_since = None;
+        inner.resumed_after = None;
+        inner.failed = true;
+    }
+
+    pub fn paused_since(&self) -> Option<Instant> {
+        self.inner.lock().paused_since
+    }
+
+    /// 取走一次“刚恢复”事件，返回暂停了多久
+    pub fn take_resumed(&self) -> Option<Duration> {
+        self.inner.lock().resumed_after.take()
+    }
+
+    pub fn state(&self) -> LinkState {
+        let inner = self.inner.lock();
+        if inner.failed {
+            LinkState::Failed
+        } else if let Some(since) = inner.paused_since {
+            LinkState::Paused {
+                paused_ms: self
+                    .clock
+                    .elapsed(since)
+                    .as_millis()
+                    .try_into()
+                    .unwrap_or(u64::MAX),
+            }
+        } else {
+            LinkState::Active
+        }
+    }
+}
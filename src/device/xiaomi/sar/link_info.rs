@@ -0,0 +1,73 @@
+use serde::Serialize;
+
+use crate::device::xiaomi::packet::v2::layer1cmd::L1CmdPacket;
+
+/// 握手时手表在 L1StartRsp 里报上来的参数，给诊断页面看
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceLinkInfo {
+    /// SAR 协议版本，形如 "1.0.0"
+    pub version: Option<String>,
+    pub mps: Option<u16>,
+    pub tx_win: Option<u16>,
+    pub send_timeout_ms: Option<u16>,
+    pub device_type: Option<u8>,
+    pub device_name: Option<String>,
+    pub os_version: Option<String>,
+}
+
+impl DeviceLinkInfo {
+    pub fn from_cmd(cmd: &L1CmdPacket) -> Self {
+        Self {
+            version: cmd.get_version().map(format_version),
+            mps: cmd.get_mps(),
+            tx_win: cmd.get_tx_win(),
+            send_timeout_ms: cmd.get_send_timeout(),
+            device_type: cmd.get_device_type(),
+            // 有的固件会在名字后面补 0
+            device_name: cmd
+                .get_device_name()
+                .map(|name| name.trim_end_matches('\0').to_string()),
+            os_version: cmd.get_os_version().map(format_version),
+        }
+    }
+}
+
+fn format_version((major, minor, patch): (u8, u8, u8)) -> String {
+    format!("{major}.{minor}.{patch}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::xiaomi::packet::v2::layer1cmd::{CmdCode, L1CmdBuilder};
+
+    #[test]
+    fn parses_full_start_rsp() {
+        let cmd = L1CmdBuilder::new()
+            .cmd(CmdCode::CmdL1startRsp)
+            .version(1, 2, 0)
+            .mps(244)
+            .tx_win(16)
+            .send_timeout(5000)
+            .device_type(2)
+            .device_name(b"Xiaomi Watch S4\0")
+            .os_version(3, 0, 12)
+            .build()
+            .unwrap();
+        // 走一遍序列化，和线上收到的包一样
+        let cmd = L1CmdPacket::from_payload_bytes(&cmd.to_payload_bytes()).unwrap();
+
+        assert_eq!(
+            DeviceLinkInfo::from_cmd(&cmd),
+            DeviceLinkInfo {
+                version: Some("1.2.0".to_string()),
+                mps: Some(244),
+                tx_win: Some(16),
+                send_timeout_ms: Some(5000),
+                device_type: Some(2),
+                device_name: Some("Xiaomi Watch S4".to_string()),
+                os_version: Some("3.0.12".to_string()),
+            }
+        );
+    }
+}
@@ -1,17 +1,20 @@
+use bytes::Bytes;
 use std::collections::{HashSet, VecDeque};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(not(target_arch = "wasm32"))]
 use std::time::{Duration, Instant};
 #[cfg(target_arch = "wasm32")]
 use web_time::{Duration, Instant};
 
-use crate::asyncrt::{TaskHandle, sleep, spawn_with_handle};
+use crate::asyncrt::{TaskHandle, spawn_with_handle};
 use tokio::runtime::Handle;
 use tokio::sync::Notify;
 
-use super::SendFn;
+use super::{SendError, SendFn, SendUnit};
 use crate::device::xiaomi::{
+    clock::SharedClock,
     config::SarConfig,
     packet::v2::{
         layer1::{L1DataType, L1Packet},
@@ -21,13 +24,34 @@ use crate::device::xiaomi::{
     transport_profiler::TransportProfilerHandle,
 };
 
+mod ack_wait;
 mod command_pool;
-pub use command_pool::CommandPool;
+mod drain;
+mod link;
+mod link_info;
+#[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+pub mod record;
+#[cfg(all(any(test, feature = "testing"), not(target_arch = "wasm32")))]
+pub mod replay;
+#[cfg(test)]
+pub(crate) mod test_support;
+pub use ack_wait::AckWait;
+use ack_wait::AckWaiters;
+pub(crate) use command_pool::CommandPool;
+pub use drain::DrainReport;
+use link::LinkMonitor;
+pub use link::LinkState;
+pub use link_info::DeviceLinkInfo;
+
+// acked 最多记这么多，seq 空间就 256，再多也没意义
+const MAX_ACKED: usize = 256;
+// CommandPool 里待发数据超过这个数就在巡检里喊一声
+const POOL_SOFT_LIMIT: usize = 4096;
 
 /// 待发送的数据（已分配 seq）
 pub struct QueuedData {
     pub seq: u8,
-    pub payload: Vec<u8>,
+    pub payload: Bytes,
 }
 
 #[derive(Clone)]
@@ -47,7 +71,7 @@ pub struct SarController {
     sender: SendFn,
     tk_handle: Handle,
     device_id: String,
-    pub command_pool: CommandPool,
+    pub(crate) command_pool: CommandPool,
     tx_queue: VecDeque<SendItem>,
     tx_next_seq: u8,
     tx_base: u8,
@@ -63,23 +87,65 @@ pub struct SarController {
     cmd_exchanged: bool,
     /// 记录已经确认的 seq，供上层查询（会在 seq 重用或消费后清理）。
     acked: HashSet<u8>,
+    /// acked 的插入顺序，超过 MAX_ACKED 按 FIFO 淘汰，防止上层一直不消费
+    acked_order: VecDeque<u8>,
     ack_notify: Arc<Notify>,
+    /// `await_acks` 注册的等待，确认时直接叫醒
+    ack_waiters: AckWaiters,
     profiler: TransportProfilerHandle,
+    /// 超时检查任务的退出信号，设备销毁时置位
+    timeout_shutdown: Arc<AtomicBool>,
+    timeout_checker: Option<TaskHandle>,
+    /// 链路短暂断开时的宽限期，期间只暂停不失败
+    reconnect_grace: Duration,
+    /// 接收缓冲上限，见 SarConfig::recv_buffer_limit
+    recv_buffer_limit: usize,
+    /// 见 SarConfig::ack_duplicate_data
+    ack_duplicate_data: bool,
+    /// 见 SarConfig::cum_ack_delay_ms
+    cum_ack_delay: Duration,
+    link: Arc<LinkMonitor>,
+    /// drain 期间新入队的数据先压在这，不算进本次要清空的积压
+    draining: bool,
+    held: CommandPool,
+    /// 最近一次 L1StartRsp 带来的设备参数
+    link_info: Option<DeviceLinkInfo>,
+    /// 最近一次收到设备任意 L1 包的时间，用来区分"设备忙"和"链路死了"
+    last_inbound_at: Option<Instant>,
+    /// 已经发过 L1StartReq
+    started: bool,
+    /// 所有 deadline 和定时器都按它算，见 SarConfig::clock
+    clock: SharedClock,
 }
 
 impl SarController {
     const LOCAL_TX_WIN: u8 = 32;
 
+    /// 构造完立刻发 L1StartReq。设备秒回的话 L1StartRsp 可能在实体还没进 runtime 时就到了，
+    /// 新代码用 `new_deferred` + `start`
     pub fn new(
         tk_handle: Handle,
         sender: SendFn,
         device_id: String,
         profiler: TransportProfilerHandle,
         config: SarConfig,
+    ) -> Self {
+        let mut ctrl = Self::new_deferred(tk_handle, sender, device_id, profiler, config);
+        ctrl.start();
+        ctrl
+    }
+
+    /// 只准备状态，什么都不发，等实体挂好了再调 `start`
+    pub fn new_deferred(
+        tk_handle: Handle,
+        sender: SendFn,
+        device_id: String,
+        profiler: TransportProfilerHandle,
+        config: SarConfig,
     ) -> Self {
         log::info!("Initializing SarController...");
 
-        let mut ctrl = Self {
+        Self {
             sender: sender.clone(),
             tk_handle,
             device_id: device_id.clone(),
@@ -99,12 +165,35 @@ impl SarController {
             rx_cum_ack_timer: None,
             cmd_exchanged: false,
             acked: HashSet::new(),
+            acked_order: VecDeque::new(),
             ack_notify: Arc::new(Notify::new()),
+            ack_waiters: AckWaiters::default(),
             profiler,
-        };
+            timeout_shutdown: Arc::new(AtomicBool::new(false)),
+            timeout_checker: None,
+            reconnect_grace: Duration::from_millis(config.reconnect_grace_ms),
+            recv_buffer_limit: config.recv_buffer_limit,
+            ack_duplicate_data: config.ack_duplicate_data,
+            cum_ack_delay: Duration::from_millis(config.cum_ack_delay_ms),
+            link: Arc::new(LinkMonitor::new(config.clock.clone())),
+            draining: false,
+            held: CommandPool::new(),
+            link_info: None,
+            last_inbound_at: None,
+            started: false,
+            clock: config.clock,
+        }
+    }
+
+    /// 启动超时巡检并发出 L1StartReq，重复调用无效果
+    pub fn start(&mut self) {
+        if self.started {
+            return;
+        }
+        self.started = true;
 
         // 启动定时检查超时任务
-        ctrl.start_timeout_checker(device_id.clone());
+        self.start_timeout_checker(self.device_id.clone());
 
         log::info!("Sending L1StartReq...");
 
@@ -117,19 +206,20 @@ impl SarController {
             .send_timeout(10_000)
             .build()
             .unwrap();
-        ctrl.command_pool
+        self.command_pool
             .push_cmd_front(start_req.to_payload_bytes());
-        ctrl.try_run_next();
+        self.try_run_next();
 
         log::info!("SarController initialization completed!");
-
-        ctrl
     }
 
     /// 将数据加入发送队列，返回分配的 seq
-    pub fn enqueue(&mut self, data: Vec<u8>) -> u8 {
+    pub fn enqueue(&mut self, data: impl Into<Bytes>) -> u8 {
         let seq = self.alloc_seq();
-        self.command_pool.push(QueuedData { seq, payload: data });
+        self.data_pool().push(QueuedData {
+            seq,
+            payload: data.into(),
+        });
         self.try_run_next();
         seq
     }
@@ -137,12 +227,16 @@ impl SarController {
     /// 批量入队，可减少多次 runtime 切换开销，返回每个 payload 对应的 seq。
     pub fn enqueue_batch<I>(&mut self, iter: I) -> Vec<u8>
     where
-        I: IntoIterator<Item = Vec<u8>>,
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
     {
         let mut seqs = Vec::new();
         for data in iter {
             let seq = self.alloc_seq();
-            self.command_pool.push(QueuedData { seq, payload: data });
+            self.data_pool().push(QueuedData {
+                seq,
+                payload: data.into(),
+            });
             seqs.push(seq);
         }
         self.try_run_next();
@@ -150,31 +244,49 @@ impl SarController {
     }
 
     /// 插队到队首
-    pub fn enqueue_front(&mut self, data: Vec<u8>) -> u8 {
+    pub fn enqueue_front(&mut self, data: impl Into<Bytes>) -> u8 {
         let seq = self.alloc_seq();
-        self.command_pool
-            .push_front(QueuedData { seq, payload: data });
+        self.data_pool().push_front(QueuedData {
+            seq,
+            payload: data.into(),
+        });
         self.try_run_next();
         seq
     }
 
-    /// 批量插队，返回按顺序分配的 seq 列表
+    /// 批量插队。
+    ///
+    /// 保证：`seqs[i]` 对应输入的第 i 个 payload，seq 按输入顺序递增分配，
+    /// 整批按输入顺序排在原有待发数据之前（批内不会倒序上线）。
     pub fn enqueue_front_batch<I>(&mut self, iter: I) -> Vec<u8>
     where
-        I: IntoIterator<Item = Vec<u8>>,
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
     {
-        let mut items: Vec<Vec<u8>> = iter.into_iter().collect();
-        let mut seqs = Vec::new();
-        while let Some(d) = items.pop() {
-            let seq = self.alloc_seq();
-            self.command_pool.push_front(QueuedData { seq, payload: d });
-            seqs.push(seq);
-        }
+        // 先按输入顺序分配 seq，再整批压到队首；
+        // 以前是倒着 pop 边分配边插，映射虽然对得上但批内 seq 在线上是倒序的
+        let items: Vec<QueuedData> = iter
+            .into_iter()
+            .map(|payload| QueuedData {
+                seq: self.alloc_seq(),
+                payload: payload.into(),
+            })
+            .collect();
+        let seqs = items.iter().map(|item| item.seq).collect();
+        self.data_pool().extend_front(items);
         self.try_run_next();
-        seqs.reverse();
         seqs
     }
 
+    /// 正常情况下进发送池，drain 期间先压着
+    fn data_pool(&mut self) -> &mut CommandPool {
+        if self.draining {
+            &mut self.held
+        } else {
+            &mut self.command_pool
+        }
+    }
+
     #[inline]
     pub fn runtime_handle(&self) -> Handle {
         self.tk_handle.clone()
@@ -195,6 +307,16 @@ impl SarController {
         self.send_timeout.as_millis().try_into().unwrap_or(u64::MAX)
     }
 
+    /// 握手拿到的设备参数，还没收到 L1StartRsp 时为 None
+    pub fn link_info(&self) -> Option<&DeviceLinkInfo> {
+        self.link_info.as_ref()
+    }
+
+    /// 距离上次收到设备的 L1 包过了多久，一个都没收到过时为 None
+    pub fn since_last_inbound(&self) -> Option<Duration> {
+        self.last_inbound_at.map(|at| self.clock.elapsed(at))
+    }
+
     /// 判断单个 seq 是否已被设备确认。
     pub fn is_acked(&self, seq: u8) -> bool {
         self.acked.contains(&seq)
@@ -210,9 +332,49 @@ impl SarController {
         self.ack_notify.clone()
     }
 
+    /// 等一组 seq 全部确认，已经确认过的不用再等。ACK 到了直接叫醒，不用轮询 `is_all_acked`。
+    /// 已经 `mark_ack_consumed` 掉的 seq 查不到确认记录，别拿来等
+    pub fn await_acks(&mut self, seqs: &[u8]) -> AckWait {
+        let remaining = seqs
+            .iter()
+            .copied()
+            .filter(|seq| !self.acked.contains(seq))
+            .collect();
+        self.ack_waiters.register(remaining)
+    }
+
+    /// 从头数连续已确认的 seq，标记为已消费并返回个数，碰到没确认的就停
+    pub fn consume_acked_prefix(&mut self, seqs: impl IntoIterator<Item = u8>) -> usize {
+        let mut consumed = 0;
+        for seq in seqs {
+            if !self.acked.contains(&seq) {
+                break;
+            }
+            self.mark_ack_consumed(seq);
+            consumed += 1;
+        }
+        consumed
+    }
+
     /// 在外部消费 ACK 后调用，避免陈旧的 ACK 记录影响后续判断。
     pub fn mark_ack_consumed(&mut self, seq: u8) {
-        self.acked.remove(&seq);
+        if self.acked.remove(&seq) {
+            self.acked_order.retain(|s| *s != seq);
+        }
+    }
+
+    fn record_acked(&mut self, seq: u8) {
+        if !self.acked.insert(seq) {
+            return;
+        }
+        self.ack_waiters.on_acked(seq);
+        self.acked_order.push_back(seq);
+        while self.acked_order.len() > MAX_ACKED {
+            if let Some(old) = self.acked_order.pop_front() {
+                self.acked.remove(&old);
+            }
+        }
+        debug_assert_eq!(self.acked.len(), self.acked_order.len());
     }
 
     fn alloc_seq(&mut self) -> u8 {
@@ -220,22 +382,173 @@ impl SarController {
         self.tx_next_seq = self.tx_next_seq.wrapping_add(1);
         if self.tx_next_seq == 0 {
             self.acked.clear();
+            self.acked_order.clear();
         }
         seq
     }
 
+    /// 巡检内部集合有没有超出预期上限，只打日志不修
+    fn check_integrity(&self) {
+        if self.acked.len() > MAX_ACKED || self.acked.len() != self.acked_order.len() {
+            log::error!(
+                "[SarController] acked bookkeeping out of sync for {}: set={} order={}",
+                self.device_id,
+                self.acked.len(),
+                self.acked_order.len()
+            );
+        }
+        if self.tx_queue.len() > usize::from(self.effective_tx_win()) {
+            log::error!(
+                "[SarController] in-flight queue for {} exceeds window: {} > {}",
+                self.device_id,
+                self.tx_queue.len(),
+                self.effective_tx_win()
+            );
+        }
+        let pooled = self.command_pool.data_len();
+        if pooled > POOL_SOFT_LIMIT {
+            log::warn!(
+                "[SarController] {} payloads waiting in CommandPool for {}, is anyone draining it?",
+                pooled,
+                self.device_id
+            );
+        }
+    }
+
     #[inline]
     fn effective_tx_win(&self) -> u8 {
         self.tx_win_effective.max(1)
     }
 
+    /// 会话中途换参数（比如切 QoS 预设），已经在飞的包不受影响
+    pub fn update_config(&mut self, config: &SarConfig) {
+        self.tx_win_effective = Self::compute_soft_cap_with_allowance(
+            Self::LOCAL_TX_WIN,
+            config.tx_win_overrun_allowance,
+        );
+        self.reconnect_grace = Duration::from_millis(config.reconnect_grace_ms);
+        self.recv_buffer_limit = config.recv_buffer_limit;
+        self.ack_duplicate_data = config.ack_duplicate_data;
+        self.cum_ack_delay = Duration::from_millis(config.cum_ack_delay_ms);
+        // 窗口可能变大了，排队的可以接着发
+        if self.started {
+            self.try_run_next();
+        }
+    }
+
     fn compute_soft_cap_with_allowance(win: u8, allowance: u8) -> u8 {
         let base = win.max(1);
         base.saturating_add(allowance).clamp(base, u8::MAX)
     }
 
+    /// 所有发送都走这里，顺便观察发送结果维护链路状态
+    fn spawn_send(&self, frames: Vec<Vec<u8>>) {
+        let send_fn = self.sender.clone();
+        let link = self.link.clone();
+        let units = frames
+            .into_iter()
+            .map(|frame| SendUnit::Frame(frame.into()))
+            .collect();
+        spawn_with_handle(
+            async move {
+                match (send_fn)(units).await {
+                    Ok(()) => link.on_send_ok(),
+                    Err(SendError::Disconnected) => link.on_disconnected(),
+                    Err(SendError::Io(err)) => {
+                        log::debug!("[SarController] send failed: {err}");
+                    }
+                }
+            },
+            self.tk_handle.clone(),
+        );
+    }
+
+    #[inline]
+    pub fn link_state(&self) -> LinkState {
+        self.link.state()
+    }
+
+    #[inline]
+    pub fn is_link_paused(&self) -> bool {
+        self.link.paused_since().is_some()
+    }
+
+    /// 宿主重新接上传输层后调用，立刻恢复而不用等下一次探测
+    pub fn resume_link(&mut self) {
+        self.link.on_send_ok();
+        self.poll_link();
+    }
+
+    /// 检查链路状态变化，返回链路当前是否可用
+    fn poll_link(&mut self) -> bool {
+        if let Some(paused) = self.link.take_resumed() {
+            self.profiler.record(
+                "sar",
+                "link_resumed",
+                Some(paused.as_millis().try_into().unwrap_or(u64::MAX)),
+                Some(self.tx_queue.len() as u32),
+                None,
+                None,
+                Some(true),
+                None,
+            );
+            self.resend_in_flight();
+            self.try_run_next();
+            return true;
+        }
+
+        let Some(paused_at) = self.link.paused_since() else {
+            return true;
+        };
+
+        if self.clock.elapsed(paused_at) >= self.reconnect_grace {
+            log::warn!(
+                "[SarController] link for {} did not recover within {} ms, failing waiters",
+                self.device_id,
+                self.reconnect_grace.as_millis()
+            );
+            self.link.mark_failed();
+            self.profiler.record(
+                "sar",
+                "link_failed",
+                None,
+                Some(self.tx_queue.len() as u32),
+                None,
+                None,
+                Some(false),
+                None,
+            );
+            // 叫醒等 ACK 的人，让他们看到 Failed 后自己退出
+            self.ack_notify.notify_waiters();
+            self.ack_waiters.fail_all();
+            return false;
+        }
+
+        // 宽限期内拿队头探测一下链路，发成功了下一轮就会恢复
+        if let Some(item) = self.tx_queue.front() {
+            self.spawn_send(vec![item.packet.to_bytes()]);
+        }
+        false
+    }
+
+    /// 链路恢复后把在途的包原样补发一遍，deadline 重新计算，暂停期间不算超时也不算重传
+    fn resend_in_flight(&mut self) {
+        if self.tx_queue.is_empty() {
+            return;
+        }
+        let deadline = self.clock.now() + self.send_timeout;
+        let mut frames = Vec::with_capacity(self.tx_queue.len());
+        for item in self.tx_queue.iter_mut() {
+            item.wait_ack = true;
+            item.need_retransmission = false;
+            item.deadline = deadline;
+            frames.push(item.packet.to_bytes());
+        }
+        self.spawn_send(frames);
+    }
+
     fn send_ack(&self, seq: u8) {
-        let pkt = L1Packet::new(L1DataType::Ack, false, seq, vec![]);
+        let pkt = L1Packet::new(L1DataType::Ack, false, seq, Bytes::new());
         self.profiler.record(
             "sar",
             "ack_send",
@@ -246,18 +559,11 @@ impl SarController {
             Some(true),
             None,
         );
-        let send_fn = self.sender.clone();
-        let handle = self.tk_handle.clone();
-        spawn_with_handle(
-            async move {
-                let _ = (send_fn)(vec![pkt.to_bytes()]).await;
-            },
-            handle,
-        );
+        self.spawn_send(vec![pkt.to_bytes()]);
     }
 
     fn send_nak(&self, seq: u8) {
-        let pkt = L1Packet::new(L1DataType::Nak, false, seq, vec![]);
+        let pkt = L1Packet::new(L1DataType::Nak, false, seq, Bytes::new());
         self.profiler.record(
             "sar",
             "nak_send",
@@ -268,27 +574,20 @@ impl SarController {
             Some(false),
             None,
         );
-        let send_fn = self.sender.clone();
-        let handle = self.tk_handle.clone();
-        spawn_with_handle(
-            async move {
-                let _ = (send_fn)(vec![pkt.to_bytes()]).await;
-            },
-            handle,
-        );
+        self.spawn_send(vec![pkt.to_bytes()]);
     }
 
     fn start_cum_ack_timer(&mut self, device: String) {
         if self.rx_cum_ack_timer.is_some() {
             return;
         }
-        let handle_outer = self.tk_handle.clone();
-        let handle_spawn = handle_outer.clone();
+        let handle_spawn = self.tk_handle.clone();
+        let delay = self.cum_ack_delay;
+        let clock = self.clock.clone();
         self.rx_cum_ack_timer = Some(spawn_with_handle(
             async move {
-                sleep(Duration::from_millis(500)).await;
-                let handle_inner = handle_outer.clone();
-                crate::ecs::with_rt_mut(move |rt| {
+                clock.sleep(delay).await;
+                crate::ecs::with_rt_mut_labeled("sar::cum_ack_timer", move |rt| {
                     let _ = rt.with_device_mut(&device, |world, entity| {
                         if let Some(dev) = world.get_mut::<super::XiaomiDevice>(entity) {
                             let mut sar = dev.sar.lock();
@@ -296,16 +595,8 @@ impl SarController {
                                 let seq = sar.rx_cum_ack_seq;
                                 sar.rx_cum_ack_index = 0;
                                 sar.rx_cum_ack_timer = None;
-                                let send_fn = sar.sender.clone();
-                                let handle_send = handle_inner.clone();
-                                spawn_with_handle(
-                                    async move {
-                                        let pkt =
-                                            L1Packet::new(L1DataType::Ack, false, seq, vec![]);
-                                        let _ = (send_fn)(vec![pkt.to_bytes()]).await;
-                                    },
-                                    handle_send,
-                                );
+                                let pkt = L1Packet::new(L1DataType::Ack, false, seq, Bytes::new());
+                                sar.spawn_send(vec![pkt.to_bytes()]);
                             }
                         }
                     });
@@ -323,29 +614,71 @@ impl SarController {
         self.rx_cum_ack_index = 0;
     }
 
-    fn start_timeout_checker(&self, device: String) {
+    fn start_timeout_checker(&mut self, device: String) {
         let handle = self.tk_handle.clone();
-        spawn_with_handle(
+        let shutdown = self.timeout_shutdown.clone();
+        let clock = self.clock.clone();
+        self.timeout_checker = Some(spawn_with_handle(
             async move {
                 loop {
-                    sleep(Duration::from_millis(500)).await;
+                    clock.sleep(Duration::from_millis(500)).await;
+                    if shutdown.load(Ordering::Acquire) {
+                        break;
+                    }
                     let dev_id = device.clone();
-                    crate::ecs::with_rt_mut(move |rt| {
-                        let _ = rt.with_device_mut(&dev_id, |world, entity| {
-                            if let Some(dev) = world.get_mut::<super::XiaomiDevice>(entity) {
-                                dev.sar.lock().check_timeouts_internal();
-                            }
-                        });
-                    })
-                    .await;
+                    let alive =
+                        crate::ecs::with_rt_mut_labeled("sar::timeout_checker", move |rt| {
+                            rt.with_device_mut(&dev_id, |world, entity| {
+                                if let Some(dev) = world.get_mut::<super::XiaomiDevice>(entity) {
+                                    dev.sar.lock().check_timeouts_internal();
+                                }
+                            })
+                            .is_some()
+                        })
+                        .await;
+                    // 设备已经没了就别空转了
+                    if !alive {
+                        break;
+                    }
                 }
+                log::debug!("[SarController] timeout checker for {device} exited");
             },
             handle,
-        );
+        ));
+    }
+
+    fn stop_timeout_checker(&mut self) {
+        self.timeout_shutdown.store(true, Ordering::Release);
+        if let Some(h) = self.timeout_checker.take() {
+            h.abort();
+        }
     }
 
     fn check_timeouts_internal(&mut self) {
-        let now = Instant::now();
+        self.check_integrity();
+        // 接收方已经放弃的读请求顺手清掉
+        crate::device::xiaomi::packet::read::drop_closed_reads_for(&self.device_id);
+        // 一直拼不出帧的接收缓冲清掉重新找帧头
+        if let Some(len) = crate::device::xiaomi::packet::dispatcher::guard_recv_buffer(
+            &self.device_id,
+            self.recv_buffer_limit,
+        ) {
+            self.profiler.record(
+                "sar",
+                "recv_buffer_reset",
+                None,
+                None,
+                Some(len as u64),
+                None,
+                Some(false),
+                None,
+            );
+        }
+        // 链路暂停期间冻结超时判定
+        if !self.poll_link() {
+            return;
+        }
+        let now = self.clock.now();
         let mut need = false;
         for item in self.tx_queue.iter_mut() {
             if item.wait_ack && now >= item.deadline {
@@ -360,6 +693,13 @@ impl SarController {
     }
 
     pub fn on_l1_packet(&mut self, l1: &L1Packet) -> bool {
+        self.last_inbound_at = Some(self.clock.now());
+        // 能收到包说明链路已经回来了
+        if self.link.paused_since().is_some() {
+            self.link.on_send_ok();
+        }
+        self.poll_link();
+
         match l1.pkt_type {
             L1DataType::Ack => {
                 self.handle_ack(l1.seq);
@@ -374,6 +714,7 @@ impl SarController {
                 if let Some(cmd) = L1CmdPacket::from_payload_bytes(&l1.payload) {
                     if cmd.cmd == CmdCode::CmdL1startRsp {
                         self.cmd_exchanged = true;
+                        self.link_info = Some(DeviceLinkInfo::from_cmd(&cmd));
                         if let Some(win) = cmd.get_tx_win() {
                             self.tx_win = win.clamp(1, u16::from(u8::MAX)) as u8;
                         }
@@ -421,17 +762,23 @@ impl SarController {
                 }
 
                 if l1.seq != self.rx_expect_seq {
-                    // 只有 seq 超前才回 NAK 请求重传 seq落后则静默丢弃，避免出现NAK DDOS
+                    // 只有 seq 超前才回 NAK 请求重传，seq 落后的不能 NAK，否则会出现 NAK DDOS
                     // onDataReceive：(seq - expect) 无符号 >= 128 视为你跑不过我我信了
                     let ahead = l1.seq.wrapping_sub(self.rx_expect_seq) < 128;
                     if ahead {
                         self.send_nak(self.rx_expect_seq);
+                    } else if self.ack_duplicate_data {
+                        // 落后的是收过的包在重传，说明对端没等到 ACK：把已收到的位置再确认一次，包本身丢掉。
+                        // 顺带把攒着的累积 ACK 一起确认了
+                        self.stop_cum_ack_timer();
+                        self.send_ack(self.rx_expect_seq.wrapping_sub(1));
                     }
                     return false;
                 }
 
-                let immediate = u32::from(self.rx_cum_ack_index)
-                    >= (u32::from(self.raw_tx_window_size()) * 2 / 3)
+                let immediate = self.cum_ack_delay.is_zero()
+                    || u32::from(self.rx_cum_ack_index)
+                        >= (u32::from(self.raw_tx_window_size()) * 2 / 3)
                     || matches!(channel, Some(L2Channel::Pb | L2Channel::Lyra));
                 if immediate {
                     self.stop_cum_ack_timer();
@@ -453,7 +800,7 @@ impl SarController {
         while let Some(item) = self.tx_queue.front() {
             if Self::seq_le(item.packet.seq, seq) {
                 let seq_val = item.packet.seq;
-                self.acked.insert(seq_val);
+                self.record_acked(seq_val);
                 self.tx_queue.pop_front();
                 self.tx_base = self.tx_base.wrapping_add(1);
                 advanced = true;
@@ -488,15 +835,36 @@ impl SarController {
             Some(false),
             None,
         );
-        if seq > 0 {
-            let ack_seq = seq.wrapping_sub(1);
-            self.handle_ack(ack_seq);
+        // NAK(seq) = 对端在等 seq：之前的都算收到，seq 起全部重传。
+        // 以队首为基准按回绕差算偏移，0 和 255→0 不需要特判
+        let Some(base) = self.tx_queue.front().map(|item| item.packet.seq) else {
+            // 会话刚开始（或刚重置）还没发过数据时对端可能直接 NAK(0)，没东西可重传
+            if seq != self.tx_next_seq {
+                log::warn!(
+                    "[SarController] ignore NAK seq={} with nothing in flight (next={})",
+                    seq,
+                    self.tx_next_seq
+                );
+            }
+            return;
+        };
+        let offset = usize::from(seq.wrapping_sub(base));
+        if offset > self.tx_queue.len() {
+            // 没发过的 seq，全标重传只会把窗口搅乱
+            log::warn!(
+                "[SarController] ignore NAK seq={} outside in-flight window (base={}, in_flight={})",
+                seq,
+                base,
+                self.tx_queue.len()
+            );
+            return;
+        }
+        if offset > 0 {
+            self.handle_ack(seq.wrapping_sub(1));
         }
         for item in self.tx_queue.iter_mut() {
-            if Self::seq_le(seq, item.packet.seq) {
-                item.need_retransmission = true;
-                item.wait_ack = false;
-            }
+            item.need_retransmission = true;
+            item.wait_ack = false;
         }
         self.try_run_next();
     }
@@ -506,12 +874,17 @@ impl SarController {
     }
 
     fn try_run_next(&mut self) {
+        // 链路暂停时不往死链路里灌包，数据留在 CommandPool 等恢复
+        if self.link.paused_since().is_some() {
+            return;
+        }
+
         // 优先重传，防止错错包
         if let Some(item) = self.tx_queue.iter_mut().find(|i| i.need_retransmission) {
             let pkt = item.packet.clone();
             item.need_retransmission = false;
             item.wait_ack = true;
-            item.deadline = Instant::now() + self.send_timeout;
+            item.deadline = self.clock.now() + self.send_timeout;
             self.profiler.record(
                 "sar",
                 "data_retransmit",
@@ -522,14 +895,7 @@ impl SarController {
                 None,
                 None,
             );
-            let send_fn = self.sender.clone();
-            let handle = self.tk_handle.clone();
-            spawn_with_handle(
-                async move {
-                    let _ = (send_fn)(vec![pkt.to_bytes()]).await;
-                },
-                handle,
-            );
+            self.spawn_send(vec![pkt.to_bytes()]);
             return;
         }
 
@@ -552,14 +918,7 @@ impl SarController {
                 Some(true),
                 None,
             );
-            let send_fn = self.sender.clone();
-            let handle = self.tk_handle.clone();
-            spawn_with_handle(
-                async move {
-                    let _ = (send_fn)(cmd_batch).await;
-                },
-                handle,
-            );
+            self.spawn_send(cmd_batch);
         }
 
         let mut data_batch = Vec::new();
@@ -569,7 +928,7 @@ impl SarController {
             };
             let pkt = L1Packet::new(L1DataType::Data, false, qd.seq, qd.payload);
             let bytes = pkt.to_bytes();
-            let deadline = Instant::now() + self.send_timeout;
+            let deadline = self.clock.now() + self.send_timeout;
             data_batch.push(bytes);
             self.tx_queue.push_back(SendItem {
                 packet: pkt,
@@ -595,14 +954,483 @@ impl SarController {
                     self.raw_tx_window_size()
                 )),
             );
-            let send_fn = self.sender.clone();
-            let handle = self.tk_handle.clone();
-            spawn_with_handle(
-                async move {
-                    let _ = (send_fn)(data_batch).await;
+            self.spawn_send(data_batch);
+        }
+    }
+}
+
+impl Drop for SarController {
+    fn drop(&mut self) {
+        self.stop_timeout_checker();
+        self.stop_cum_ack_timer();
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::asyncrt::sleep;
+    use crate::device::xiaomi::SendError;
+
+    // 会创建 SarController 的用例都会动全局的超时检查计数，串行跑
+    pub(crate) static SAR_TEST_LOCK: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+
+    fn noop_sender() -> SendFn {
+        Arc::new(|_frames: Vec<SendUnit>| {
+            Box::pin(async { Ok::<(), SendError>(()) })
+                as std::pin::Pin<
+                    Box<dyn std::future::Future<Output = Result<(), SendError>> + Send>,
+                >
+        })
+    }
+
+    #[test]
+    fn short_link_drop_resumes_without_data_loss() {
+        use parking_lot::Mutex;
+        use std::sync::atomic::AtomicBool;
+
+        let _lock = SAR_TEST_LOCK.lock();
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let link_down = Arc::new(AtomicBool::new(false));
+        let delivered: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+
+        let sender: SendFn = {
+            let link_down = link_down.clone();
+            let delivered = delivered.clone();
+            Arc::new(move |frames: Vec<SendUnit>| {
+                let link_down = link_down.clone();
+                let delivered = delivered.clone();
+                Box::pin(async move {
+                    if link_down.load(Ordering::SeqCst) {
+                        return Err(SendError::Disconnected);
+                    }
+                    delivered
+                        .lock()
+                        .extend(frames.into_iter().map(|unit| unit.into_bytes().to_vec()));
+                    Ok(())
+                })
+                    as std::pin::Pin<
+                        Box<dyn std::future::Future<Output = Result<(), SendError>> + Send>,
+                    >
+            })
+        };
+
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                sender,
+                "test:link-drop".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+
+        link_down.store(true, Ordering::SeqCst);
+        ctrl.enqueue(b"first".to_vec());
+        ctrl.enqueue(b"second".to_vec());
+        rt.block_on(async { sleep(Duration::from_millis(50)).await });
+        assert!(matches!(ctrl.link_state(), LinkState::Paused { .. }));
+
+        // 暂停期间继续入队的数据先留在 CommandPool
+        ctrl.enqueue(b"third".to_vec());
+        rt.block_on(async { sleep(Duration::from_millis(1_000)).await });
+        ctrl.check_timeouts_internal();
+        assert!(matches!(ctrl.link_state(), LinkState::Paused { .. }));
+
+        link_down.store(false, Ordering::SeqCst);
+        // 第一轮探测成功，第二轮处理恢复并补发
+        ctrl.check_timeouts_internal();
+        rt.block_on(async { sleep(Duration::from_millis(50)).await });
+        ctrl.check_timeouts_internal();
+        rt.block_on(async { sleep(Duration::from_millis(50)).await });
+        assert_eq!(ctrl.link_state(), LinkState::Active);
+
+        let mut received: Vec<(u8, Vec<u8>)> = delivered
+            .lock()
+            .iter()
+            .filter_map(|frame| L1Packet::from_bytes(frame).ok())
+            .filter(|pkt| pkt.pkt_type == L1DataType::Data)
+            .map(|pkt| (pkt.seq, pkt.payload.to_vec()))
+            .collect();
+        received.sort();
+        received.dedup();
+        assert_eq!(
+            received,
+            vec![
+                (0, b"first".to_vec()),
+                (1, b"second".to_vec()),
+                (2, b"third".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn enqueue_front_batch_keeps_input_order() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                noop_sender(),
+                "test:front-batch".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+
+        // 暂停链路让数据留在 CommandPool 里，方便看排队顺序
+        ctrl.link.on_disconnected();
+        let tail = ctrl.enqueue(b"tail".to_vec());
+        let input = vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()];
+        let seqs = ctrl.enqueue_front_batch(input.clone());
+
+        assert_eq!(seqs, vec![tail + 1, tail + 2, tail + 3]);
+
+        let mut queued = Vec::new();
+        while let Some(item) = ctrl.command_pool.pop_data() {
+            queued.push((item.seq, item.payload.to_vec()));
+        }
+        let mut expected: Vec<(u8, Vec<u8>)> = seqs.iter().copied().zip(input).collect();
+        expected.push((tail, b"tail".to_vec()));
+        assert_eq!(queued, expected);
+    }
+
+    #[test]
+    fn acked_stays_bounded_without_consumer() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                noop_sender(),
+                "test:acked-soak".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+
+        // 设备每包都 ACK，但上层从来不调 mark_ack_consumed
+        let mut peak_capacity = 0;
+        for i in 0..10_000u32 {
+            let seq = ctrl.enqueue(i.to_le_bytes().to_vec());
+            ctrl.handle_ack(seq);
+            assert!(ctrl.acked.len() <= MAX_ACKED);
+            assert_eq!(ctrl.acked.len(), ctrl.acked_order.len());
+            peak_capacity = peak_capacity.max(ctrl.acked_order.capacity());
+        }
+
+        assert!(ctrl.tx_queue.is_empty());
+        assert!(ctrl.command_pool.is_empty());
+        assert!(peak_capacity <= MAX_ACKED * 2);
+        assert!(ctrl.is_acked(ctrl.tx_next_seq.wrapping_sub(1)));
+    }
+
+    fn new_test_ctrl(rt: &tokio::runtime::Runtime, device_id: &str) -> SarController {
+        rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                noop_sender(),
+                device_id.to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        })
+    }
+
+    fn in_flight(ctrl: &SarController) -> Vec<u8> {
+        ctrl.tx_queue.iter().map(|item| item.packet.seq).collect()
+    }
+
+    fn pending_retransmit(ctrl: &SarController) -> usize {
+        ctrl.tx_queue
+            .iter()
+            .filter(|item| item.need_retransmission)
+            .count()
+    }
+
+    #[test]
+    fn nak_zero_at_session_start_retransmits_everything() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = new_test_ctrl(&rt, "test:nak-zero");
+
+        // 还没发数据时的 NAK(0) 什么都不做
+        ctrl.handle_nak(0);
+        assert!(ctrl.tx_queue.is_empty());
+
+        for payload in [b"a", b"b", b"c"] {
+            ctrl.enqueue(payload.to_vec());
+        }
+        ctrl.handle_nak(0);
+
+        assert_eq!(in_flight(&ctrl), vec![0, 1, 2]);
+        assert_eq!(ctrl.tx_base, 0);
+        assert!(!ctrl.is_acked(255));
+        // 第一个已经立刻重发出去了，剩下两个排着
+        assert_eq!(pending_retransmit(&ctrl), 2);
+    }
+
+    #[test]
+    fn nak_at_wrap_boundary_acks_before_wrap() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = new_test_ctrl(&rt, "test:nak-wrap");
+        ctrl.tx_next_seq = 254;
+        ctrl.tx_base = 254;
+
+        for payload in [b"w", b"x", b"y", b"z"] {
+            ctrl.enqueue(payload.to_vec());
+        }
+        assert_eq!(in_flight(&ctrl), vec![254, 255, 0, 1]);
+
+        ctrl.handle_nak(0);
+
+        assert_eq!(in_flight(&ctrl), vec![0, 1]);
+        assert_eq!(ctrl.tx_base, 0);
+        assert!(ctrl.is_acked(254));
+        assert!(ctrl.is_acked(255));
+        assert_eq!(pending_retransmit(&ctrl), 1);
+    }
+
+    #[test]
+    fn nak_for_unsent_seq_is_ignored() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = new_test_ctrl(&rt, "test:nak-unsent");
+
+        ctrl.enqueue(b"a".to_vec());
+        ctrl.enqueue(b"b".to_vec());
+        ctrl.handle_nak(100);
+
+        assert_eq!(in_flight(&ctrl), vec![0, 1]);
+        assert_eq!(pending_retransmit(&ctrl), 0);
+        assert!(ctrl.tx_queue.iter().all(|item| item.wait_ack));
+    }
+
+    #[test]
+    fn duplicate_data_is_acked_not_naked() {
+        use super::test_support::loopback_sender;
+        use crate::device::xiaomi::packet::v2::layer2::{L2OpCode, L2Packet};
+
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (sender, wire) = loopback_sender();
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                sender,
+                "test:dup-data".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+        ctrl.cmd_exchanged = true;
+
+        let data = |seq: u8| {
+            L2Packet::new(L2Channel::Pb, L2OpCode::Write, b"pb".to_vec()).into_l1(seq, false)
+        };
+        let replies = |ctrl: &mut SarController, pkt: L1Packet| {
+            let delivered = ctrl.on_l1_packet(&pkt);
+            rt.block_on(async { sleep(Duration::from_millis(20)).await });
+            let frames: Vec<(L1DataType, u8)> = wire
+                .lock()
+                .drain(..)
+                .filter_map(|frame| L1Packet::from_bytes(&frame).ok())
+                .map(|pkt| (pkt.pkt_type, pkt.seq))
+                .collect();
+            (delivered, frames)
+        };
+
+        assert_eq!(
+            replies(&mut ctrl, data(0)),
+            (true, vec![(L1DataType::Ack, 0)])
+        );
+        // 对端没收到 ACK 又重传了 0：再 ACK 一次并丢掉，不能 NAK
+        assert_eq!(
+            replies(&mut ctrl, data(0)),
+            (false, vec![(L1DataType::Ack, 0)])
+        );
+        assert_eq!(ctrl.rx_expect_seq, 1);
+        // 真正的空洞还是 NAK
+        assert_eq!(
+            replies(&mut ctrl, data(3)),
+            (false, vec![(L1DataType::Nak, 1)])
+        );
+
+        ctrl.ack_duplicate_data = false;
+        assert_eq!(replies(&mut ctrl, data(0)), (false, vec![]));
+    }
+
+    #[test]
+    fn data_is_retransmitted_once_send_timeout_passes() {
+        use super::test_support::loopback_sender;
+        use crate::device::xiaomi::clock::MockClock;
+
+        let _lock = SAR_TEST_LOCK.lock();
+        crate::ecs::init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let clock = MockClock::new();
+        let (sender, wire) = loopback_sender();
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                sender,
+                "test:retransmit-timeout".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig {
+                    clock: clock.shared(),
+                    ..SarConfig::default()
                 },
-                handle,
+            )
+        });
+        let sent_data = || {
+            rt.block_on(async { sleep(Duration::from_millis(20)).await });
+            wire.lock()
+                .drain(..)
+                .filter_map(|frame| L1Packet::from_bytes(&frame).ok())
+                .filter(|pkt| pkt.pkt_type == L1DataType::Data)
+                .map(|pkt| pkt.seq)
+                .collect::<Vec<_>>()
+        };
+
+        let seq = ctrl.enqueue(b"payload".to_vec());
+        assert_eq!(sent_data(), vec![seq]);
+
+        // 默认 10s 超时，差 1ms 都不重传
+        clock.advance(ctrl.send_timeout - Duration::from_millis(1));
+        ctrl.check_timeouts_internal();
+        assert_eq!(sent_data(), Vec::<u8>::new());
+
+        clock.advance(Duration::from_millis(1));
+        ctrl.check_timeouts_internal();
+        assert_eq!(sent_data(), vec![seq]);
+
+        // ACK 之后再久也不会重传
+        ctrl.handle_ack(seq);
+        clock.advance(ctrl.send_timeout * 3);
+        ctrl.check_timeouts_internal();
+        assert_eq!(sent_data(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn recovers_from_lossy_link_in_order() {
+        use super::test_support::{LoopbackPeer, LossyLink, loopback_sender};
+
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let (wire_sender, wire) = loopback_sender();
+        let sender = LossyLink::new(7)
+            .drop_rate(0.1)
+            .delay(Duration::ZERO, Duration::from_millis(3))
+            .wrap(wire_sender);
+        let mut ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                sender,
+                "test:lossy".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+        // 默认 10s 太长，按设备 L1StartRsp 里可能给的值调短
+        ctrl.send_timeout = Duration::from_millis(30);
+
+        // 超过 256 个，顺便把 seq 回绕也走一遍
+        let payloads: Vec<Vec<u8>> = (0..300u32).map(|i| i.to_le_bytes().to_vec()).collect();
+        ctrl.enqueue_batch(payloads.clone());
+
+        let mut peer = LoopbackPeer::default();
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while peer.delivered.len() < payloads.len() {
+            assert!(
+                Instant::now() < deadline,
+                "stalled after delivering {} of {}",
+                peer.delivered.len(),
+                payloads.len()
             );
+            rt.block_on(async { sleep(Duration::from_millis(2)).await });
+            let frames = std::mem::take(&mut *wire.lock());
+            for frame in frames {
+                if let Some(reply) = peer.on_frame(&frame) {
+                    ctrl.on_l1_packet(&reply);
+                }
+            }
+            ctrl.check_timeouts_internal();
+        }
+
+        let expected: Vec<(u8, Vec<u8>)> = payloads
+            .into_iter()
+            .enumerate()
+            .map(|(i, payload)| (i as u8, payload))
+            .collect();
+        assert_eq!(peer.delivered, expected);
+    }
+
+    #[test]
+    fn await_acks_wakes_without_polling() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut ctrl = new_test_ctrl(&rt, "test:await-acks");
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut poll = |wait: &mut AckWait| std::pin::Pin::new(wait).poll(&mut cx);
+
+        for payload in [b"a", b"b", b"c"] {
+            ctrl.enqueue(payload.to_vec());
         }
+        let mut first_two = ctrl.await_acks(&[0, 1]);
+        let mut all = ctrl.await_acks(&[0, 1, 2]);
+
+        ctrl.handle_ack(0);
+        assert_eq!(poll(&mut first_two), Poll::Pending);
+        ctrl.handle_ack(1);
+        assert_eq!(poll(&mut first_two), Poll::Ready(true));
+        // 完成后再 poll 也不会炸
+        assert_eq!(poll(&mut first_two), Poll::Ready(true));
+        assert_eq!(poll(&mut all), Poll::Pending);
+        ctrl.handle_ack(2);
+        assert_eq!(poll(&mut all), Poll::Ready(true));
+
+        // 早就确认过的直接就绪
+        assert_eq!(poll(&mut ctrl.await_acks(&[2])), Poll::Ready(true));
+
+        // 不等了的在下次注册时清掉
+        drop(ctrl.await_acks(&[5]));
+        let orphan = ctrl.await_acks(&[6]);
+        assert_eq!(ctrl.ack_waiters.len(), 1);
+
+        // 设备被移除，等待以 false 结束
+        drop(ctrl);
+        assert!(!rt.block_on(orphan));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn timeout_checker_exits_after_drop() {
+        let _lock = SAR_TEST_LOCK.lock();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let ctrl = rt.block_on(async {
+            SarController::new(
+                Handle::current(),
+                noop_sender(),
+                "test:timeout-checker".to_string(),
+                TransportProfilerHandle::new(),
+                SarConfig::default(),
+            )
+        });
+        let checker = ctrl
+            .timeout_checker
+            .as_ref()
+            .map(|handle| handle.abort_handle())
+            .expect("timeout checker not started");
+        assert!(!checker.is_finished());
+
+        drop(ctrl);
+        rt.block_on(async { sleep(Duration::from_millis(50)).await });
+        assert!(checker.is_finished());
     }
 }
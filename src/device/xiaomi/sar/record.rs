@@ -0,0 +1,201 @@
+//! 把某台设备收发的每个 L1 帧录成 JSONL，配合 [`super::replay`] 离线复现链路问题。
+//!
+//! 第一行是头 `{"version":1}`，后面一行一帧：`{"t_ms":12,"dir":"in","frame":"a5a5..."}`，
+//! `t_ms` 是相对开始录制的毫秒数。出方向录的是切块之前的整帧
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+use anyhow::Context;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+
+/// 录制格式的版本，字段有破坏性调整时加一
+pub const RECORD_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// 设备 -> 本端
+    In,
+    /// 本端 -> 设备
+    Out,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordHeader {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    pub t_ms: u64,
+    pub dir: Direction,
+    /// 整帧的 hex
+    pub frame: String,
+}
+
+struct Recorder {
+    started: Instant,
+    writer: Mutex<BufWriter<File>>,
+}
+
+static RECORDERS: OnceLock<RwLock<HashMap<String, Arc<Recorder>>>> = OnceLock::new();
+// 没在录的时候收发路径只读一个原子量
+static ANY_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+fn recorders() -> &'static RwLock<HashMap<String, Arc<Recorder>>> {
+    RECORDERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// 开始录 `device_id` 的帧，文件已存在会被覆盖；同一台设备重复调用会换成新文件。
+/// 想录到 L1StartReq 的话要在连设备之前调
+pub fn start(device_id: &str, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let file = File::create(path)
+        .with_context(|| format!("failed to create SAR record {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(
+        &mut writer,
+        &RecordHeader {
+            version: RECORD_VERSION,
+        },
+    )?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+
+    let mut guard = recorders().write();
+    guard.insert(
+        device_id.to_string(),
+        Arc::new(Recorder {
+            started: Instant::now(),
+            writer: Mutex::new(writer),
+        }),
+    );
+    ANY_ACTIVE.store(true, Ordering::Release);
+    log::info!("[SarRecord] recording {} to {}", device_id, path.display());
+    Ok(())
+}
+
+/// 停止录制并落盘，没在录返回 false
+pub fn stop(device_id: &str) -> anyhow::Result<bool> {
+    let removed = {
+        let mut guard = recorders().write();
+        let removed = guard.remove(device_id);
+        ANY_ACTIVE.store(!guard.is_empty(), Ordering::Release);
+        removed
+    };
+    match removed {
+        Some(recorder) => {
+            recorder.writer.lock().flush()?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+pub fn is_recording(device_id: &str) -> bool {
+    ANY_ACTIVE.load(Ordering::Acquire) && recorders().read().contains_key(device_id)
+}
+
+/// 收发路径上的钩子，写失败只打日志，不影响链路
+pub(crate) fn capture<F: AsRef<[u8]>>(device_id: &str, dir: Direction, frames: &[F]) {
+    if !ANY_ACTIVE.load(Ordering::Acquire) || frames.is_empty() {
+        return;
+    }
+    let Some(recorder) = recorders().read().get(device_id).cloned() else {
+        return;
+    };
+    let t_ms = recorder.started.elapsed().as_millis() as u64;
+    let mut writer = recorder.writer.lock();
+    let result = frames.iter().try_for_each(|frame| {
+        serde_json::to_writer(
+            &mut *writer,
+            &RecordedFrame {
+                t_ms,
+                dir,
+                frame: hex::encode(frame.as_ref()),
+            },
+        )?;
+        writer.write_all(b"\n")?;
+        anyhow::Ok(())
+    });
+    if let Err(err) = result.and_then(|_| writer.flush().map_err(Into::into)) {
+        log::warn!("[SarRecord] failed to write frames for {device_id}: {err:?}");
+    }
+}
+
+/// 读回一份录制，头的版本不认识直接报错
+pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Vec<RecordedFrame>> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read SAR record {}", path.display()))?;
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header: RecordHeader = serde_json::from_str(
+        lines
+            .next()
+            .ok_or_else(|| crate::anyhow_site!("SAR record {} is empty", path.display()))?,
+    )
+    .context("invalid SAR record header")?;
+    if header.version != RECORD_VERSION {
+        crate::bail_site!(
+            "unsupported SAR record version {} (expected {})",
+            header.version,
+            RECORD_VERSION
+        );
+    }
+    lines
+        .enumerate()
+        .map(|(idx, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid SAR record line {}", idx + 2))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recorded_frames_load_back_in_order() {
+        // 同时跑好几份测试（不同 target、CI 并行）时别写到同一个文件上
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let path = std::env::temp_dir().join(format!(
+            "corelib-sar-record-{}-{nanos}.jsonl",
+            std::process::id()
+        ));
+        let device_id = "test:sar-record";
+        start(device_id, &path).unwrap();
+        assert!(is_recording(device_id));
+
+        capture(device_id, Direction::Out, &[vec![0xa5, 0xa5, 0x01]]);
+        capture(device_id, Direction::In, &[vec![0x01], vec![0x02, 0x03]]);
+        // 别的设备不录
+        capture("test:sar-record-other", Direction::In, &[vec![0xff]]);
+        assert!(stop(device_id).unwrap());
+        assert!(!stop(device_id).unwrap());
+        capture(device_id, Direction::In, &[vec![0xee]]);
+
+        let frames = load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let summary: Vec<_> = frames.iter().map(|f| (f.dir, f.frame.as_str())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Direction::Out, "a5a501"),
+                (Direction::In, "01"),
+                (Direction::In, "0203"),
+            ]
+        );
+        assert!(frames.windows(2).all(|w| w[0].t_ms <= w[1].t_ms));
+    }
+}
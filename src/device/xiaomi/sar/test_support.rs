@@ -0,0 +1,118 @@
+//! 测 SAR 重传/累积 ACK 用的假链路：给 SendFn 套一层按概率丢包、延迟的壳，
+//! 再配一个按设备规则回 ACK/NAK 的对端，用例里自己泵消息
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use nanorand::{Rng, WyRand};
+use parking_lot::Mutex;
+
+use super::{Duration, SendFn};
+use crate::asyncrt::sleep;
+use crate::device::xiaomi::packet::v2::layer1::{L1DataType, L1Packet};
+use crate::device::xiaomi::{SendError, SendFuture, SendUnit};
+
+/// 丢包/延迟参数，同一个 seed 每次跑出来的丢包序列都一样
+#[derive(Debug, Clone)]
+pub(crate) struct LossyLink {
+    seed: u64,
+    /// 0.0 ~ 1.0，按帧算
+    drop_rate: f64,
+    delay: Option<(Duration, Duration)>,
+}
+
+impl LossyLink {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            drop_rate: 0.0,
+            delay: None,
+        }
+    }
+
+    pub fn drop_rate(mut self, rate: f64) -> Self {
+        self.drop_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// 每批帧在 [min, max] 之间随机延迟后再送达
+    pub fn delay(mut self, min: Duration, max: Duration) -> Self {
+        self.delay = Some((min, max.max(min)));
+        self
+    }
+
+    /// 包一层，丢掉的帧对发送方来说依然是发送成功（跟真实的空口一样）
+    pub fn wrap(self, inner: SendFn) -> SendFn {
+        let rng = Arc::new(Mutex::new(WyRand::new_seed(self.seed)));
+        let threshold = (self.drop_rate * f64::from(u32::MAX)) as u64;
+        Arc::new(move |frames: Vec<SendUnit>| {
+            let (kept, wait) = {
+                let mut rng = rng.lock();
+                let kept: Vec<SendUnit> = frames
+                    .into_iter()
+                    .filter(|_| u64::from(rng.generate::<u32>()) >= threshold)
+                    .collect();
+                let wait = self.delay.map(|(min, max)| {
+                    let span = (max - min).as_millis() as u64;
+                    min + Duration::from_millis(rng.generate_range(0..=span))
+                });
+                (kept, wait)
+            };
+            let inner = inner.clone();
+            Box::pin(async move {
+                if let Some(wait) = wait {
+                    sleep(wait).await;
+                }
+                if kept.is_empty() {
+                    return Ok(());
+                }
+                inner(kept).await
+            }) as SendFuture
+        })
+    }
+}
+
+/// 把发出去的帧原样收进一个队列，等用例去取
+pub(crate) fn loopback_sender() -> (SendFn, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let wire: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+    let sender: SendFn = {
+        let wire = wire.clone();
+        Arc::new(move |frames: Vec<SendUnit>| {
+            wire.lock()
+                .extend(frames.into_iter().map(|unit| unit.into_bytes().to_vec()));
+            Box::pin(async { Ok::<(), SendError>(()) }) as SendFuture
+        })
+    };
+    (sender, wire)
+}
+
+/// 模拟手表的接收端：按序收数据，乱序回 NAK，重复的回上一个 ACK
+#[derive(Default)]
+pub(crate) struct LoopbackPeer {
+    expect: u8,
+    pub delivered: Vec<(u8, Vec<u8>)>,
+}
+
+impl LoopbackPeer {
+    pub fn on_frame(&mut self, frame: &[u8]) -> Option<L1Packet> {
+        let pkt = L1Packet::from_bytes(frame).ok()?;
+        if pkt.pkt_type != L1DataType::Data {
+            return None;
+        }
+        if pkt.seq == self.expect {
+            self.delivered.push((pkt.seq, pkt.payload.to_vec()));
+            self.expect = self.expect.wrapping_add(1);
+            return Some(Self::reply(L1DataType::Ack, pkt.seq));
+        }
+        if pkt.seq.wrapping_sub(self.expect) < 128 {
+            Some(Self::reply(L1DataType::Nak, self.expect))
+        } else {
+            // 重传的老包，ACK 可能丢了，再确认一次
+            Some(Self::reply(L1DataType::Ack, self.expect.wrapping_sub(1)))
+        }
+    }
+
+    fn reply(pkt_type: L1DataType, seq: u8) -> L1Packet {
+        L1Packet::new(pkt_type, false, seq, Bytes::new())
+    }
+}
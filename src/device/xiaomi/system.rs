@@ -2,13 +2,15 @@ use pb::xiaomi::protocol::WearPacket;
 use prost::Message;
 use std::{
     any::TypeId,
-    collections::HashMap,
+    cell::Cell,
+    collections::{HashMap, HashSet},
     io::Cursor,
     sync::{OnceLock, RwLock},
 };
 
 use bevy_ecs::{component::Component, entity::Entity, world::World};
 
+use crate::bail_site;
 use crate::device::xiaomi::packet::v2::layer2::{L2Channel, L2OpCode};
 
 // 收L2包的System扩展trait
@@ -42,20 +44,57 @@ where
     }
 }
 
+thread_local! {
+    // 当前这一轮分发里有没有 System 认领了 PB 包，分发都在 ECS 线程上跑
+    static PB_CONSUMED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// 在 on_pb_packet 里调用，表示这个包已经有人处理了，不必转发给原始 WearPacket 订阅者
+pub fn mark_pb_consumed() {
+    PB_CONSUMED.with(|flag| flag.set(true));
+}
+
+/// 取出并清空本轮分发的认领标记
+pub fn take_pb_consumed() -> bool {
+    PB_CONSUMED.with(|flag| flag.replace(false))
+}
+
 type OnL2PacketDispatcher =
     fn(world: &mut World, entity: Entity, ch: L2Channel, op: L2OpCode, payload: &[u8]);
 
+/// 注册进分发表的 System 的元信息
+#[derive(Clone, Copy)]
+struct SysMeta {
+    /// 类型名去掉路径，比如 "NetworkSystem"，`set_system_enabled` 用它指定 System
+    id: &'static str,
+    dispatch: OnL2PacketDispatcher,
+}
+
 // 记录所有注册了该Ext的System
 // 唐比Rust不能动态类型。
 // TODO: 使用一些神秘第三方库并加上std的开盒功能也许可以替代这种傻逼写法，
-static ON_L2_PACKET_DISPATCHERS: OnceLock<RwLock<HashMap<TypeId, OnL2PacketDispatcher>>> =
-    OnceLock::new();
+static ON_L2_PACKET_DISPATCHERS: OnceLock<RwLock<HashMap<TypeId, SysMeta>>> = OnceLock::new();
+
+// 按设备地址记被关掉的 System id，不跟实体走，重连后照样生效
+static DISABLED_SYSTEMS: OnceLock<RwLock<HashMap<String, HashSet<String>>>> = OnceLock::new();
 
 #[inline]
-fn xiaomi_ext_on_l2packet_registry() -> &'static RwLock<HashMap<TypeId, OnL2PacketDispatcher>> {
+fn xiaomi_ext_on_l2packet_registry() -> &'static RwLock<HashMap<TypeId, SysMeta>> {
     ON_L2_PACKET_DISPATCHERS.get_or_init(|| RwLock::new(HashMap::new()))
 }
 
+#[inline]
+fn disabled_systems() -> &'static RwLock<HashMap<String, HashSet<String>>> {
+    DISABLED_SYSTEMS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+fn system_id<T: 'static>() -> &'static str {
+    std::any::type_name::<T>()
+        .rsplit("::")
+        .next()
+        .unwrap_or_default()
+}
+
 fn make_xiaomi_ext_on_l2packet_dispatcher<T>() -> OnL2PacketDispatcher
 where
     T: XiaomiSystemExt + Component + 'static,
@@ -74,35 +113,227 @@ where
     inner::<T>
 }
 
+/// 每建一次组件（`Default::default()`）都会调一次，同一个类型只有第一次真的登记
 pub fn register_xiaomi_system_ext_on_l2packet<T>()
 where
     T: XiaomiSystemExt + Component + 'static,
 {
+    let type_id = TypeId::of::<T>();
+    if xiaomi_ext_on_l2packet_registry()
+        .read()
+        .expect("poisoned XiaomiSystemExt registry")
+        .contains_key(&type_id)
+    {
+        return;
+    }
     let mut map = xiaomi_ext_on_l2packet_registry()
         .write()
         .expect("poisoned XiaomiSystemExt registry");
+    // 拿写锁之前可能被别的线程抢先登记了，已有的不覆盖
+    if map.contains_key(&type_id) {
+        return;
+    }
+    let id = system_id::<T>();
+    // 短名撞了的话 set_system_enabled 会一起开关，提醒一下
+    if map.values().any(|meta| meta.id == id) {
+        log::warn!(
+            "[System] `{}` shares system id `{}` with an already registered system",
+            std::any::type_name::<T>(),
+            id
+        );
+    }
     map.insert(
-        TypeId::of::<T>(),
-        make_xiaomi_ext_on_l2packet_dispatcher::<T>(),
+        type_id,
+        SysMeta {
+            id,
+            dispatch: make_xiaomi_ext_on_l2packet_dispatcher::<T>(),
+        },
     );
+    log::debug!(
+        "[System] registered L2 dispatcher for {} ({} total)",
+        std::any::type_name::<T>(),
+        map.len()
+    );
+}
+
+/// 已经登记了分发函数的 System 类型，排查"on_pb_packet 一直不触发"时先看这里有没有
+pub fn registered_ext_types() -> Vec<TypeId> {
+    xiaomi_ext_on_l2packet_registry()
+        .read()
+        .expect("poisoned XiaomiSystemExt registry")
+        .keys()
+        .copied()
+        .collect()
+}
+
+/// 已经注册过的 System id。System 在第一台设备建好时才注册，之前是空的
+pub fn registered_systems() -> Vec<&'static str> {
+    let map = xiaomi_ext_on_l2packet_registry()
+        .read()
+        .expect("poisoned XiaomiSystemExt registry");
+    let mut ids: Vec<_> = map.values().map(|meta| meta.id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// 开关某台设备上的某个 System（比如不要网络、不要三方应用自动握手），关掉后它收不到任何 L2 包，
+/// 组件本身还在。`system_id` 是类型名（"NetworkSystem"），得是 `registered_systems` 里有的，
+/// 拼错了直接报错，不然会以为关掉了。这台设备还没连上也可以先设，但 System 要在第一台设备建好后才注册
+pub fn set_system_enabled(device_id: &str, system_id: &str, enabled: bool) -> anyhow::Result<()> {
+    let known = registered_systems();
+    if !known.contains(&system_id) {
+        bail_site!("unknown system id `{system_id}`, registered systems: {known:?}");
+    }
+    let mut disabled = disabled_systems()
+        .write()
+        .expect("poisoned disabled systems registry");
+    if enabled {
+        if let Some(ids) = disabled.get_mut(device_id) {
+            ids.remove(system_id);
+            if ids.is_empty() {
+                disabled.remove(device_id);
+            }
+        }
+    } else {
+        disabled
+            .entry(device_id.to_string())
+            .or_default()
+            .insert(system_id.to_string());
+    }
+    Ok(())
+}
+
+pub fn is_system_enabled(device_id: &str, system_id: &str) -> bool {
+    !disabled_systems()
+        .read()
+        .expect("poisoned disabled systems registry")
+        .get(device_id)
+        .is_some_and(|ids| ids.contains(system_id))
 }
 
 pub fn dispatch_xiaomi_system_ext_on_l2packet(
     world: &mut World,
     entity: Entity,
+    device_id: &str,
     ch: L2Channel,
     op: L2OpCode,
     payload: &[u8],
 ) -> bool {
+    PB_CONSUMED.with(|flag| flag.set(false));
     let map = xiaomi_ext_on_l2packet_registry()
         .read()
         .expect("poisoned XiaomiSystemExt registry");
     if map.is_empty() {
         return false;
     }
-
-    for dispatch in map.values() {
-        dispatch(world, entity, ch, op, payload);
+    for meta in map.values() {
+        // 每个 System 单独短暂拿一下读锁查，不复制整个集合；也不跨 dispatch 持锁，
+        // System 在回调里开关别的 System 不会死锁
+        if !is_system_enabled(device_id, meta.id) {
+            continue;
+        }
+        (meta.dispatch)(world, entity, ch, op, payload);
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Component, Default)]
+    struct ToggleTestSystem {
+        received: usize,
+    }
+
+    impl XiaomiSystemExt for ToggleTestSystem {
+        fn on_layer2_packet(&mut self, _channel: L2Channel, _opcode: L2OpCode, _payload: &[u8]) {
+            self.received += 1;
+        }
+    }
+
+    #[derive(Component, Default)]
+    struct PickyPbSystem {
+        wanted_id: u32,
+    }
+
+    impl L2PbExt for PickyPbSystem {
+        fn on_pb_packet(&mut self, payload: WearPacket) {
+            if payload.id == self.wanted_id {
+                mark_pb_consumed();
+            }
+        }
+    }
+
+    #[test]
+    fn pb_packet_is_consumed_only_when_a_system_claims_it() {
+        let packet = |id| {
+            WearPacket {
+                r#type: pb::xiaomi::protocol::wear_packet::Type::System as i32,
+                id,
+                payload: Some(pb::xiaomi::protocol::wear_packet::Payload::System(
+                    pb::xiaomi::protocol::System { payload: None },
+                )),
+            }
+            .encode_to_vec()
+        };
+        let mut sys = PickyPbSystem { wanted_id: 3 };
+        take_pb_consumed();
+
+        // 有 payload 但没人认领，照样转给原始订阅者
+        sys.on_layer2_packet(L2Channel::Pb, L2OpCode::Write, &packet(9));
+        assert!(!take_pb_consumed());
+
+        sys.on_layer2_packet(L2Channel::Pb, L2OpCode::Write, &packet(3));
+        assert!(take_pb_consumed());
+    }
+
+    #[test]
+    fn disabled_system_is_skipped() {
+        let device_id = "system-toggle-test";
+        register_xiaomi_system_ext_on_l2packet::<ToggleTestSystem>();
+        // 重复登记不会多出一份，也不会让包被分发两次
+        register_xiaomi_system_ext_on_l2packet::<ToggleTestSystem>();
+        assert!(registered_systems().contains(&"ToggleTestSystem"));
+        let type_id = TypeId::of::<ToggleTestSystem>();
+        assert_eq!(
+            registered_ext_types()
+                .iter()
+                .filter(|id| **id == type_id)
+                .count(),
+            1
+        );
+
+        let mut world = World::new();
+        let entity = world.spawn(ToggleTestSystem::default()).id();
+        let received = |world: &World| world.get::<ToggleTestSystem>(entity).unwrap().received;
+        let dispatch = |world: &mut World| {
+            dispatch_xiaomi_system_ext_on_l2packet(
+                world,
+                entity,
+                device_id,
+                L2Channel::Network,
+                L2OpCode::Write,
+                &[],
+            )
+        };
+
+        dispatch(&mut world);
+        assert_eq!(received(&world), 1);
+
+        // 拼错的 id 直接报错，不会悄悄记下
+        assert!(set_system_enabled(device_id, "ToggleTestSystm", false).is_err());
+        assert!(is_system_enabled(device_id, "ToggleTestSystm"));
+
+        set_system_enabled(device_id, "ToggleTestSystem", false).unwrap();
+        assert!(!is_system_enabled(device_id, "ToggleTestSystem"));
+        dispatch(&mut world);
+        assert_eq!(received(&world), 1);
+        // 别的设备不受影响
+        assert!(is_system_enabled("system-toggle-other", "ToggleTestSystem"));
+
+        set_system_enabled(device_id, "ToggleTestSystem", true).unwrap();
+        dispatch(&mut world);
+        assert_eq!(received(&world), 2);
+    }
+}
@@ -1,10 +1,52 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive};
+use std::{fmt, str::FromStr};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, IntoPrimitive, TryFromPrimitive)]
 #[repr(u8)]
 pub enum ConnectType {
     SPP = 0,
     BLE = 1,
+    /// ADB 转发之类的 TCP 流，开发调试用，分包规则同 SPP 但不发 SPP Hello
+    TCP = 2,
+}
+
+impl ConnectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectType::SPP => "SPP",
+            ConnectType::BLE => "BLE",
+            ConnectType::TCP => "TCP",
+        }
+    }
+
+    /// 流式传输（SPP/TCP），可以合并大包发送
+    pub fn is_stream(&self) -> bool {
+        matches!(self, ConnectType::SPP | ConnectType::TCP)
+    }
+
+    /// 只有真 SPP 需要那个神秘 Hello
+    pub fn requires_spp_hello(&self) -> bool {
+        *self == ConnectType::SPP
+    }
+}
+
+impl fmt::Display for ConnectType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ConnectType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_uppercase().as_str() {
+            "SPP" => Ok(ConnectType::SPP),
+            "BLE" => Ok(ConnectType::BLE),
+            "TCP" | "ADB" | "USB" => Ok(ConnectType::TCP),
+            other => Err(format!("unknown connect type: {other}")),
+        }
+    }
 }
 
 impl serde::Serialize for ConnectType {
@@ -12,10 +54,46 @@ impl serde::Serialize for ConnectType {
     where
         S: serde::Serializer,
     {
-        let value = match self {
-            ConnectType::SPP => "SPP",
-            ConnectType::BLE => "BLE",
-        };
-        serializer.serialize_str(value)
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for ConnectType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        // 老版本存档里可能是数字
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Str(String),
+            Num(u8),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Str(value) => value.parse().map_err(serde::de::Error::custom),
+            Raw::Num(value) => ConnectType::try_from(value).map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connect_type_roundtrip() {
+        for ty in [ConnectType::SPP, ConnectType::BLE, ConnectType::TCP] {
+            let json = serde_json::to_string(&ty).unwrap();
+            assert_eq!(serde_json::from_str::<ConnectType>(&json).unwrap(), ty);
+            assert_eq!(ty.to_string().parse::<ConnectType>().unwrap(), ty);
+        }
+        assert_eq!("adb".parse::<ConnectType>().unwrap(), ConnectType::TCP);
+        assert_eq!(
+            serde_json::from_str::<ConnectType>("1").unwrap(),
+            ConnectType::BLE
+        );
+        assert!("usb-c".parse::<ConnectType>().is_err());
     }
 }
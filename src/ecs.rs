@@ -1,7 +1,10 @@
 pub mod access;
 pub mod graph;
+pub mod metrics;
 pub mod runtime;
 
+pub use metrics::{RuntimeMetrics, set_slow_job_threshold};
+
 pub use bevy_ecs::prelude::{Bundle, Component, Entity, World};
 
 // 非WASM平台支持多线程，采用默认初始化方式
@@ -9,14 +12,34 @@ pub use bevy_ecs::prelude::{Bundle, Component, Entity, World};
 mod native {
     use crate::ecs::runtime::Runtime;
     use once_cell::sync::OnceCell;
-    use std::{cell::Cell, ptr, thread};
+    use std::{
+        any::Any,
+        cell::Cell,
+        panic::{self, AssertUnwindSafe},
+        ptr,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Instant,
+    };
     use tokio::sync::oneshot;
 
-    type Job = Box<dyn FnOnce(&mut Runtime) + Send + 'static>;
+    type JobFn = Box<dyn FnOnce(&mut Runtime) + Send + 'static>;
+
+    // 带上投递点标签和入队时间，方便定位卡住 ECS 线程的任务
+    struct Job {
+        label: &'static str,
+        enqueued_at: Instant,
+        run: JobFn,
+    }
 
     // ECS Runtime 闭包任务发端
     static RT_TX: OnceCell<flume::Sender<Job>> = OnceCell::new();
 
+    const DEFAULT_JOB_QUEUE_CAPACITY: usize = 4096;
+
+    // 任务队列容量，满了投递方会等着，同时打 warning
+    static JOB_QUEUE_CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_JOB_QUEUE_CAPACITY);
+
     // 本地线程 ECS Runtime 指针数据，用于非跨线程状态下的零开销访问
     thread_local! {
         static IN_RT_THREAD: Cell<bool> = Cell::new(false);
@@ -28,7 +51,7 @@ mod native {
     where
         F: FnOnce() -> Runtime + Send + 'static,
     {
-        let (tx, rx) = flume::unbounded::<Job>();
+        let (tx, rx) = flume::bounded::<Job>(JOB_QUEUE_CAPACITY.load(Ordering::Relaxed));
         let _ = RT_TX.set(tx);
 
         // 包装初始化任务
@@ -39,7 +62,24 @@ mod native {
             RT_LOCAL_PTR.with(|cell| cell.set(&mut rt as *mut Runtime));
 
             while let Ok(job) = rx.recv() {
-                job(&mut rt);
+                let started_at = Instant::now();
+                let run = job.run;
+                // 某个组件的处理逻辑 panic 了也不能把所有设备共用的 ECS 线程带走
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| run(&mut rt))) {
+                    let device = rt.take_panicked_device();
+                    log::error!(
+                        "[ECS] job `{}` panicked (device: {}): {}",
+                        job.label,
+                        device.as_deref().unwrap_or("none"),
+                        panic_message(payload.as_ref())
+                    );
+                    crate::ecs::metrics::record_panic(job.label);
+                }
+                crate::ecs::metrics::record_job(
+                    job.label,
+                    started_at.duration_since(job.enqueued_at),
+                    started_at.elapsed(),
+                );
             }
         };
 
@@ -58,6 +98,26 @@ mod native {
         log::info!("ECS Runtime initialization completed!");
     }
 
+    /// 设置任务队列容量，要在 init_runtime_* 之前调用才生效
+    pub fn set_job_queue_capacity(capacity: usize) {
+        JOB_QUEUE_CAPACITY.store(capacity.max(1), Ordering::Relaxed);
+    }
+
+    /// 当前排队等 ECS 线程执行的任务数
+    pub fn pending_jobs() -> usize {
+        RT_TX.get().map(|tx| tx.len()).unwrap_or(0)
+    }
+
+    fn panic_message(payload: &(dyn Any + Send)) -> &str {
+        if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg
+        } else {
+            "<non-string panic payload>"
+        }
+    }
+
     pub fn init_runtime_with<F>(make_rt: F)
     where
         F: FnOnce() -> Runtime + Send + 'static,
@@ -91,6 +151,15 @@ mod native {
 
     /// 将闭包任务扔到ECS线程中执行
     pub async fn with_rt_mut<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut Runtime) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        with_rt_mut_labeled("unlabeled", f).await
+    }
+
+    /// 同 with_rt_mut，label 用于慢任务日志和运行指标
+    pub async fn with_rt_mut_labeled<F, R>(label: &'static str, f: F) -> R
     where
         F: FnOnce(&mut Runtime) -> R + Send + 'static,
         R: Send + 'static,
@@ -114,13 +183,81 @@ mod native {
 
         let (ret_tx, ret_rx) = oneshot::channel::<R>();
 
-        let job: Job = Box::new(move |rt: &mut Runtime| {
-            let out = f(rt);
-            let _ = ret_tx.send(out);
-        });
+        let job = Job {
+            label,
+            enqueued_at: Instant::now(),
+            run: Box::new(move |rt: &mut Runtime| {
+                let out = f(rt);
+                let _ = ret_tx.send(out);
+            }),
+        };
+
+        match tx.try_send(job) {
+            Ok(()) => {}
+            Err(flume::TrySendError::Full(job)) => {
+                // 队列满了就让调用方等着，相当于给上游施加背压
+                crate::ecs::metrics::record_queue_full(label, tx.len());
+                tx.send_async(job)
+                    .await
+                    .expect("runtime thread has stopped");
+            }
+            Err(flume::TrySendError::Disconnected(_)) => panic!("runtime thread has stopped"),
+        }
+        // 任务 panic 时 ret_tx 跟着被丢掉，ECS 线程还活着，只让这个调用方失败
+        ret_rx
+            .await
+            .unwrap_or_else(|_| panic!("ECS job `{label}` panicked on the runtime thread"))
+    }
+
+    /// 把任务投到 ECS 线程就返回，不等结果。队列是先进先出的，同一个调用方接连投的任务按投递顺序执行；
+    /// 队列满了就阻塞当前线程等空位，不另起任务去等，免得顺序乱掉。别在 ECS 线程里调
+    pub fn post_rt_job<F>(label: &'static str, f: F)
+    where
+        F: FnOnce(&mut Runtime) + Send + 'static,
+    {
+        debug_assert!(!in_rt_thread(), "post_rt_job called on the ECS thread");
+        let tx = RT_TX
+            .get()
+            .expect("RT not initialized. Call ecs::init_runtime_* first.");
+
+        let job = Job {
+            label,
+            enqueued_at: Instant::now(),
+            run: Box::new(f),
+        };
+
+        match tx.try_send(job) {
+            Ok(()) => {}
+            Err(flume::TrySendError::Full(job)) => {
+                crate::ecs::metrics::record_queue_full(label, tx.len());
+                tx.send(job).expect("runtime thread has stopped");
+            }
+            Err(flume::TrySendError::Disconnected(_)) => panic!("runtime thread has stopped"),
+        }
+    }
 
-        tx.send(job).expect("runtime thread has stopped");
-        ret_rx.await.expect("runtime thread dropped the response")
+    /// 闭包只能拿到 &Runtime，想在里面改东西编译都过不了
+    ///
+    /// ```compile_fail
+    /// let _ = corelib::ecs::with_rt_read(|rt| {
+    ///     rt.remove_device("00:00:00:00:00:00");
+    /// });
+    /// ```
+    ///
+    /// 只是签名上的约束：底下就是一个普通的 `with_rt_mut` 任务，照样排进同一个队列独占 ECS 线程，
+    /// 不会和别的任务并行，也不比 `with_rt_mut` 便宜
+    pub async fn with_rt_read<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Runtime) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        with_rt_mut_labeled("with_rt_read", move |rt: &mut Runtime| f(&*rt)).await
+    }
+
+    /// ECS 任务通道的运行指标：排队深度、耗时分位数、慢任务
+    pub fn runtime_metrics() -> crate::ecs::RuntimeMetrics {
+        let capacity = RT_TX.get().and_then(|tx| tx.capacity()).unwrap_or_default();
+        crate::ecs::metrics::snapshot(pending_jobs(), capacity)
     }
 
     pub fn in_rt_thread() -> bool {
@@ -173,6 +310,19 @@ mod wasm {
         init_runtime_with(Runtime::new);
     }
 
+    pub async fn with_rt_mut_labeled<F, R>(_label: &'static str, f: F) -> R
+    where
+        F: FnOnce(&mut Runtime) -> R + 'static,
+        R: 'static,
+    {
+        with_rt_mut(f).await
+    }
+
+    // WASM 下没有任务队列，只有空指标
+    pub fn runtime_metrics() -> crate::ecs::RuntimeMetrics {
+        crate::ecs::metrics::snapshot(0, 0)
+    }
+
     pub async fn with_rt_mut<F, R>(f: F) -> R
     where
         F: FnOnce(&mut Runtime) -> R + 'static,
@@ -191,6 +341,20 @@ mod wasm {
         })
     }
 
+    pub async fn with_rt_read<F, R>(f: F) -> R
+    where
+        F: FnOnce(&Runtime) -> R + 'static,
+        R: 'static,
+    {
+        RT.with(|cell| {
+            let rt_opt = cell.borrow();
+            let rt = rt_opt
+                .as_ref()
+                .expect("RT not initialized. Call ecs::init_runtime_* first.");
+            f(rt)
+        })
+    }
+
     pub fn in_rt_thread() -> bool {
         RT.with(|cell| cell.borrow().is_some())
     }
@@ -211,3 +375,44 @@ mod wasm {
 
 #[cfg(target_arch = "wasm32")]
 pub use wasm::*;
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_survives_panicking_job() {
+        init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let panicked = rt.block_on(async {
+            tokio::spawn(with_rt_mut_labeled("test_panicking_job", |_rt| -> u32 {
+                panic!("boom")
+            }))
+            .await
+        });
+        assert!(panicked.unwrap_err().is_panic());
+
+        // 后面的任务照常执行
+        let answer = rt.block_on(with_rt_mut(|_rt| 42));
+        assert_eq!(answer, 42);
+        assert!(runtime_metrics().panicked_jobs >= 1);
+    }
+
+    #[test]
+    fn posted_jobs_run_in_order() {
+        init_runtime_default();
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let seen = std::sync::Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        rt.block_on(async {
+            for i in 0..64 {
+                let seen = seen.clone();
+                post_rt_job("test_posted_job", move |_rt| seen.lock().push(i));
+            }
+            // 排在后面的任务跑完，前面投的肯定也跑完了
+            with_rt_mut(|_rt| ()).await;
+        });
+        assert_eq!(*seen.lock(), (0..64).collect::<Vec<_>>());
+    }
+}
@@ -41,3 +41,36 @@ where
         Ok(f(&mut comp))
     })
 }
+
+pub fn with_device_world_ref<R, F>(owner_id: String, f: F) -> Result<R, EcsAccessError>
+where
+    F: FnOnce(&World, Entity) -> Result<R, EcsAccessError> + Send + 'static,
+    R: Send + 'static,
+{
+    crate::asyncrt::universal_block_on(|| async move {
+        crate::ecs::with_rt_read(move |rt: &Runtime| {
+            rt.with_device_ref(&owner_id, |world, entity| f(world, entity))
+                .ok_or_else(|| EcsAccessError::DeviceNotFound {
+                    id: owner_id.clone(),
+                })?
+        })
+        .await
+    })
+}
+
+pub fn with_device_component_ref<T, R, F>(owner_id: String, f: F) -> Result<R, EcsAccessError>
+where
+    T: Component + 'static,
+    F: FnOnce(&T) -> R + Send + 'static,
+    R: Send + 'static,
+{
+    with_device_world_ref(owner_id.clone(), move |world, entity| {
+        let comp = world
+            .get::<T>(entity)
+            .ok_or_else(|| EcsAccessError::ComponentMissing {
+                id: owner_id.clone(),
+                component: std::any::type_name::<T>(),
+            })?;
+        Ok(f(comp))
+    })
+}
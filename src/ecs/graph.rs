@@ -9,6 +9,7 @@ use crate::{
             install::{InstallComponent, InstallSystem},
             mass::{MassComponent, MassSystem},
             media::{MediaComponent, MediaSystem},
+            notification::{NotificationComponent, NotificationSystem},
             resource::{ResourceComponent, ResourceSystem},
             sync::{SyncComponent, SyncSystem},
             thirdparty_app::{ThirdpartyAppComponent, ThirdpartyAppSystem},
@@ -16,7 +17,7 @@ use crate::{
         },
     },
     ecs::runtime::Runtime,
-    ecs::with_rt_mut,
+    ecs::with_rt_read,
 };
 use bevy_ecs::{component::Component, entity::Entity, world::World};
 use serde::Serialize;
@@ -31,8 +32,12 @@ const ENTITY_SPACING_Y: f64 = 380.0;
 const COMPONENT_SPACING_Y: f64 = 420.0;
 const SYSTEM_SPACING_Y: f64 = 320.0;
 
+/// 前端按这个版本号解析图结构。字段改名、kind 取值变化这类前端会感知到的改动都要加一
+pub const REACT_FLOW_SCHEMA_VERSION: u32 = 1;
+
 #[derive(Debug, Serialize)]
 pub struct ReactFlowGraph {
+    pub schema_version: u32,
     pub nodes: Vec<ReactFlowNode>,
     pub edges: Vec<ReactFlowEdge>,
 }
@@ -83,10 +88,10 @@ pub struct ReactFlowEdge {
 }
 
 pub async fn export_react_flow_graph() -> ReactFlowGraph {
-    with_rt_mut(|rt| build_graph(rt)).await
+    with_rt_read(|rt| build_graph(rt)).await
 }
 
-fn build_graph(rt: &mut Runtime) -> ReactFlowGraph {
+fn build_graph(rt: &Runtime) -> ReactFlowGraph {
     let world = rt.world();
     let mut nodes: Vec<ReactFlowNode> = Vec::new();
     let mut edges: Vec<ReactFlowEdge> = Vec::new();
@@ -126,7 +131,7 @@ fn build_graph(rt: &mut Runtime) -> ReactFlowGraph {
         };
         let entity_details = world
             .get::<XiaomiDevice>(entity)
-            .and_then(|dev| serde_json::to_value(dev).ok())
+            .map(snapshot_entity_details)
             .unwrap_or(Value::Null);
 
         let mut component_nodes: HashMap<String, String> = HashMap::new();
@@ -242,6 +247,18 @@ fn build_graph(rt: &mut Runtime) -> ReactFlowGraph {
             &mut nodes,
             &mut edges,
         );
+        add_component_node::<NotificationComponent>(
+            world,
+            entity,
+            device_id,
+            &node_id,
+            position,
+            &mut comp_idx,
+            &mut component_labels,
+            &mut component_nodes,
+            &mut nodes,
+            &mut edges,
+        );
         #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
         add_component_node::<NetworkComponent>(
             world,
@@ -355,6 +372,17 @@ fn build_graph(rt: &mut Runtime) -> ReactFlowGraph {
             &mut nodes,
             &mut edges,
         );
+        add_system_node::<NotificationSystem, NotificationComponent>(
+            world,
+            entity,
+            device_id,
+            &node_id,
+            position,
+            &component_nodes,
+            &mut system_labels,
+            &mut nodes,
+            &mut edges,
+        );
         #[cfg(all(not(target_arch = "wasm32"), feature = "xiaomi-network-stack"))]
         add_system_node::<NetworkSystem, NetworkComponent>(
             world,
@@ -394,7 +422,23 @@ fn build_graph(rt: &mut Runtime) -> ReactFlowGraph {
         });
     }
 
-    ReactFlowGraph { nodes, edges }
+    ReactFlowGraph {
+        schema_version: REACT_FLOW_SCHEMA_VERSION,
+        nodes,
+        edges,
+    }
+}
+
+/// 实体本身的数据，额外带上和默认配置不一样的字段，排查问题时一眼就能看出宿主改过哪些参数
+pub(crate) fn snapshot_entity_details(dev: &XiaomiDevice) -> Value {
+    let mut details = serde_json::to_value(dev).unwrap_or(Value::Null);
+    if let Value::Object(map) = &mut details {
+        map.insert(
+            "config_overrides".to_string(),
+            dev.config.diff_from_default(),
+        );
+    }
+    details
 }
 
 fn add_component_node<T: Component + Serialize>(
@@ -450,6 +494,7 @@ fn add_component_node<T: Component + Serialize>(
     *comp_idx += 1;
 }
 
+/// 系统节点不带数据，组件数据只在组件节点上序列化一次，这里连条边过去就行
 fn add_system_node<S: Component, C: Component>(
     world: &World,
     entity: Entity,
@@ -500,3 +545,85 @@ fn add_system_node<S: Component, C: Component>(
 
     labels.push(system_type.to_string());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 这里的形状就是和前端的约定，测试挂了说明改动会影响前端，记得同步改前端并升 schema_version
+    #[test]
+    fn minimal_graph_shape() {
+        let graph = ReactFlowGraph {
+            schema_version: REACT_FLOW_SCHEMA_VERSION,
+            nodes: vec![
+                ReactFlowNode {
+                    id: "runtime".to_string(),
+                    node_type: Some("input".to_string()),
+                    position: ReactFlowPosition { x: 0.0, y: 0.0 },
+                    data: ReactFlowNodeData {
+                        label: "ECS Runtime".to_string(),
+                        kind: ReactFlowNodeKind::Runtime,
+                        type_name: "Runtime".to_string(),
+                        owner: None,
+                        extra: None,
+                    },
+                },
+                ReactFlowNode {
+                    id: "system:dev".to_string(),
+                    node_type: None,
+                    position: ReactFlowPosition { x: 1.0, y: 2.0 },
+                    data: ReactFlowNodeData {
+                        label: "Mass".to_string(),
+                        kind: ReactFlowNodeKind::LogicSystem,
+                        type_name: "MassSystem".to_string(),
+                        owner: Some("dev".to_string()),
+                        extra: Some(json!({ "k": 1 })),
+                    },
+                },
+            ],
+            edges: vec![ReactFlowEdge {
+                id: "edge:runtime->system:dev".to_string(),
+                source: "runtime".to_string(),
+                target: "system:dev".to_string(),
+                label: None,
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&graph).unwrap(),
+            json!({
+                "schema_version": 1,
+                "nodes": [
+                    {
+                        "id": "runtime",
+                        "type": "input",
+                        "position": { "x": 0.0, "y": 0.0 },
+                        "data": {
+                            "label": "ECS Runtime",
+                            "kind": "runtime",
+                            "type_name": "Runtime",
+                        },
+                    },
+                    {
+                        "id": "system:dev",
+                        "position": { "x": 1.0, "y": 2.0 },
+                        "data": {
+                            "label": "Mass",
+                            "kind": "logic_system",
+                            "type_name": "MassSystem",
+                            "owner": "dev",
+                            "extra": { "k": 1 },
+                        },
+                    },
+                ],
+                "edges": [
+                    {
+                        "id": "edge:runtime->system:dev",
+                        "source": "runtime",
+                        "target": "system:dev",
+                    },
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+#[cfg(target_arch = "wasm32")]
+use web_time::Duration;
+
+// 滚动窗口里保留的最近任务耗时数量
+const LATENCY_WINDOW: usize = 512;
+
+// 慢任务阈值（微秒），超过就打 warning
+static SLOW_JOB_THRESHOLD_US: AtomicU64 = AtomicU64::new(100_000);
+
+static METRICS: Mutex<JobMetrics> = Mutex::new(JobMetrics::new());
+
+/// ECS 任务通道的运行指标快照
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RuntimeMetrics {
+    /// 当前排队中的任务数
+    pub queued_jobs: usize,
+    pub total_jobs: u64,
+    pub slow_jobs: u64,
+    /// 最近窗口内的执行耗时分位数（毫秒）
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub window_max_ms: f64,
+    /// 启动以来最慢的一次
+    pub max_ms: f64,
+    pub max_label: Option<&'static str>,
+    pub last_slow_label: Option<&'static str>,
+    /// 任务队列容量，0 表示没有队列（WASM）
+    pub queue_capacity: usize,
+    /// 投递时队列已满、只能等 ECS 线程腾位置的次数
+    pub queue_full_events: u64,
+    pub last_queue_full_label: Option<&'static str>,
+    /// panic 掉的任务数，ECS 线程本身会继续跑
+    pub panicked_jobs: u64,
+    pub last_panic_label: Option<&'static str>,
+}
+
+struct JobMetrics {
+    window: VecDeque<u64>,
+    total_jobs: u64,
+    slow_jobs: u64,
+    max_us: u64,
+    max_label: Option<&'static str>,
+    last_slow_label: Option<&'static str>,
+    queue_full_events: u64,
+    last_queue_full_label: Option<&'static str>,
+    panicked_jobs: u64,
+    last_panic_label: Option<&'static str>,
+}
+
+impl JobMetrics {
+    const fn new() -> Self {
+        Self {
+            window: VecDeque::new(),
+            total_jobs: 0,
+            slow_jobs: 0,
+            max_us: 0,
+            max_label: None,
+            last_slow_label: None,
+            queue_full_events: 0,
+            last_queue_full_label: None,
+            panicked_jobs: 0,
+            last_panic_label: None,
+        }
+    }
+}
+
+pub fn set_slow_job_threshold(threshold: Duration) {
+    SLOW_JOB_THRESHOLD_US.store(
+        threshold.as_micros().try_into().unwrap_or(u64::MAX),
+        Ordering::Relaxed,
+    );
+}
+
+pub fn slow_job_threshold() -> Duration {
+    Duration::from_micros(SLOW_JOB_THRESHOLD_US.load(Ordering::Relaxed))
+}
+
+/// 记录一次任务执行，由 ECS 线程在每个任务结束后调用
+pub(crate) fn record_job(label: &'static str, queued: Duration, elapsed: Duration) {
+    let elapsed_us: u64 = elapsed.as_micros().try_into().unwrap_or(u64::MAX);
+    let is_slow = elapsed_us >= SLOW_JOB_THRESHOLD_US.load(Ordering::Relaxed);
+
+    {
+        let mut metrics = METRICS.lock();
+        metrics.total_jobs += 1;
+        if metrics.window.len() >= LATENCY_WINDOW {
+            metrics.window.pop_front();
+        }
+        metrics.window.push_back(elapsed_us);
+        if elapsed_us >= metrics.max_us {
+            metrics.max_us = elapsed_us;
+            metrics.max_label = Some(label);
+        }
+        if is_slow {
+            metrics.slow_jobs += 1;
+            metrics.last_slow_label = Some(label);
+        }
+    }
+
+    if is_slow {
+        log::warn!(
+            "[ECS] slow job `{label}` blocked the runtime thread for {} ms (queued {} ms)",
+            elapsed.as_millis(),
+            queued.as_millis()
+        );
+    }
+}
+
+pub(crate) fn record_panic(label: &'static str) {
+    let mut metrics = METRICS.lock();
+    metrics.panicked_jobs += 1;
+    metrics.last_panic_label = Some(label);
+}
+
+/// 投递任务时发现队列满了，说明 ECS 线程成了瓶颈
+pub(crate) fn record_queue_full(label: &'static str, queued: usize) {
+    let events = {
+        let mut metrics = METRICS.lock();
+        metrics.queue_full_events += 1;
+        metrics.last_queue_full_label = Some(label);
+        metrics.queue_full_events
+    };
+
+    // 堵住的时候每个投递都会撞上，别刷屏
+    if events == 1 || events % 100 == 0 {
+        log::warn!(
+            "[ECS] job queue full ({queued} pending) when posting `{label}`, runtime thread is falling behind ({events} times so far)"
+        );
+    }
+}
+
+pub(crate) fn snapshot(queued_jobs: usize, queue_capacity: usize) -> RuntimeMetrics {
+    let metrics = METRICS.lock();
+    let mut sorted: Vec<u64> = metrics.window.iter().copied().collect();
+    sorted.sort_unstable();
+    let percentile = |p: f64| -> f64 {
+        if sorted.is_empty() {
+            return 0.0;
+        }
+        let idx = ((sorted.len() - 1) as f64 * p).round() as usize;
+        sorted[idx] as f64 / 1000.0
+    };
+
+    RuntimeMetrics {
+        queued_jobs,
+        total_jobs: metrics.total_jobs,
+        slow_jobs: metrics.slow_jobs,
+        p50_ms: percentile(0.5),
+        p95_ms: percentile(0.95),
+        window_max_ms: sorted.last().copied().unwrap_or_default() as f64 / 1000.0,
+        max_ms: metrics.max_us as f64 / 1000.0,
+        max_label: metrics.max_label,
+        last_slow_label: metrics.last_slow_label,
+        queue_capacity,
+        queue_full_events: metrics.queue_full_events,
+        last_queue_full_label: metrics.last_queue_full_label,
+        panicked_jobs: metrics.panicked_jobs,
+        last_panic_label: metrics.last_panic_label,
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    /// 阈值是全局的，测试里改了要在结束（包括 panic）时改回去
+    struct ThresholdGuard(Duration);
+
+    impl ThresholdGuard {
+        fn set(threshold: Duration) -> Self {
+            let prev = slow_job_threshold();
+            set_slow_job_threshold(threshold);
+            Self(prev)
+        }
+    }
+
+    impl Drop for ThresholdGuard {
+        fn drop(&mut self) {
+            set_slow_job_threshold(self.0);
+        }
+    }
+
+    #[test]
+    fn slow_job_is_reported() {
+        crate::ecs::init_runtime_default();
+        let _threshold = ThresholdGuard::set(Duration::from_millis(10));
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(crate::ecs::with_rt_mut_labeled("test_slow_job", |_rt| {
+            std::thread::sleep(Duration::from_millis(50));
+        }));
+
+        let metrics = crate::ecs::runtime_metrics();
+        assert!(metrics.slow_jobs >= 1);
+        assert!(metrics.max_ms >= 50.0);
+        assert!(metrics.total_jobs >= 1);
+    }
+
+    #[test]
+    fn queue_full_is_counted() {
+        let before = snapshot(0, 0).queue_full_events;
+        record_queue_full("test_queue_full", 8);
+
+        let metrics = snapshot(8, 8);
+        assert!(metrics.queue_full_events > before);
+        assert_eq!(metrics.queue_capacity, 8);
+    }
+}
@@ -2,9 +2,35 @@ use bevy_ecs::{
     bundle::Bundle,
     component::Component,
     entity::Entity,
-    world::{EntityWorldMut, World},
+    world::{EntityRef, EntityWorldMut, World},
 };
-use std::collections::HashMap;
+use std::{cell::Cell, collections::HashMap};
+
+thread_local! {
+    // 最近一次进入的设备实体，任务 panic 时拿来定位是哪个设备出的事
+    static CURRENT_ENTITY: Cell<Option<Entity>> = const { Cell::new(None) };
+}
+
+struct DeviceScope {
+    prev: Option<Entity>,
+}
+
+impl DeviceScope {
+    fn enter(entity: Entity) -> Self {
+        Self {
+            prev: CURRENT_ENTITY.with(|cell| cell.replace(Some(entity))),
+        }
+    }
+}
+
+impl Drop for DeviceScope {
+    fn drop(&mut self) {
+        // panic 展开时保留现场，留给 ECS 线程打日志
+        if !std::thread::panicking() {
+            CURRENT_ENTITY.with(|cell| cell.set(self.prev));
+        }
+    }
+}
 
 #[derive(Default)]
 struct DeviceIndex {
@@ -58,6 +84,12 @@ impl Runtime {
         self.devices.map.get(id).copied()
     }
 
+    /// 只读定位设备实体，拿不到可变引用，适合只读任务
+    pub fn find_entity_by_id(&self, id: &str) -> Option<EntityRef<'_>> {
+        let entity = self.device_entity(id)?;
+        self.world.get_entity(entity)
+    }
+
     pub fn device_entity_mut(&mut self, id: &str) -> Option<EntityWorldMut<'_>> {
         let entity = self.device_entity(id)?;
         Some(self.world.entity_mut(entity))
@@ -79,9 +111,26 @@ impl Runtime {
         f: impl FnOnce(&mut World, Entity) -> R,
     ) -> Option<R> {
         let entity = self.device_entity(id)?;
+        let _scope = DeviceScope::enter(entity);
         Some(f(&mut self.world, entity))
     }
 
+    pub fn with_device_ref<R>(&self, id: &str, f: impl FnOnce(&World, Entity) -> R) -> Option<R> {
+        let entity = self.device_entity(id)?;
+        let _scope = DeviceScope::enter(entity);
+        Some(f(&self.world, entity))
+    }
+
+    /// 取出 panic 时正在处理的设备 ID（没有就是 None），顺便清掉现场
+    pub(crate) fn take_panicked_device(&self) -> Option<String> {
+        let entity = CURRENT_ENTITY.with(|cell| cell.take())?;
+        self.devices
+            .map
+            .iter()
+            .find(|(_, e)| **e == entity)
+            .map(|(id, _)| id.clone())
+    }
+
     pub fn with_world_mut<R>(&mut self, f: impl FnOnce(&mut World) -> R) -> R {
         f(&mut self.world)
     }
@@ -1,6 +1,8 @@
 use once_cell::sync::OnceCell;
 use tokio::sync::broadcast;
 
+use crate::device::post_connect::PostConnectStep;
+
 #[derive(Debug, Clone)]
 pub struct InterconnectMessage {
     pub device_addr: String,
@@ -13,10 +15,27 @@ pub struct DeviceStateChanged {
     pub device_addr: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct WatchfaceChanged {
+    pub device_addr: String,
+    pub watchface_id: String,
+}
+
+/// `post_connect_sync` 每跑完一步发一次
+#[derive(Debug, Clone)]
+pub struct PostConnectStepFinished {
+    pub device_addr: String,
+    pub step: PostConnectStep,
+    /// None 表示成功
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub enum CoreEvent {
     InterconnectMessage(InterconnectMessage),
     DeviceStateChanged(DeviceStateChanged),
+    WatchfaceChanged(WatchfaceChanged),
+    PostConnectStep(PostConnectStepFinished),
 }
 
 const EVENT_CHANNEL_CAPACITY: usize = 64;
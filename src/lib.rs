@@ -8,7 +8,10 @@ pub mod events;
 pub mod error;
 pub mod logger;
 pub mod models;
+pub mod prelude;
 pub mod tools;
+#[cfg(all(target_arch = "wasm32", feature = "wasm-api"))]
+pub mod wasm_api;
 
 // 默认初始化函数，使用默认配置初始化ECS系统
 pub fn init() {
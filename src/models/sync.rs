@@ -23,7 +23,139 @@ pub struct Time {
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct TimeZone {
+    /// 小米的写法：以 15 分钟为单位再加 32，UTC 是 32，UTC+8 是 64
     pub offset: i32,
     pub dst_offset: i32,
     pub id: String,
 }
+
+// UTC-12:00 到 UTC+14:00，换成 offset 的写法
+const MIN_TIMEZONE_OFFSET: i32 = 32 - 12 * 4;
+const MAX_TIMEZONE_OFFSET: i32 = 32 + 14 * 4;
+
+/// `TimeSyncProps::validate` 发现的越界字段
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeSyncError {
+    Month(u32),
+    Day { year: u32, month: u32, day: u32 },
+    Time { hour: u32, minute: u32, second: u32 },
+    Millisecond(u32),
+    TimezoneOffset(i32),
+}
+
+impl std::fmt::Display for TimeSyncError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Month(month) => write!(f, "invalid month {month}, expected 1..=12"),
+            Self::Day { year, month, day } => {
+                write!(f, "invalid day {day} for {year}-{month:02}")
+            }
+            Self::Time {
+                hour,
+                minute,
+                second,
+            } => write!(f, "invalid time {hour:02}:{minute:02}:{second:02}"),
+            Self::Millisecond(ms) => write!(f, "invalid millisecond {ms}, expected 0..=999"),
+            Self::TimezoneOffset(offset) => write!(
+                f,
+                "invalid timezone offset {offset}, expected {MIN_TIMEZONE_OFFSET}..={MAX_TIMEZONE_OFFSET} (quarter hours + 32)"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TimeSyncError {}
+
+impl TimeSyncProps {
+    /// 发给手表之前查一遍，越界的值手表那边只会莫名其妙地拒掉
+    pub fn validate(&self) -> Result<(), TimeSyncError> {
+        let Date { year, month, day } = self.date;
+        if !(1..=12).contains(&month) {
+            return Err(TimeSyncError::Month(month));
+        }
+        if day == 0 || day > days_in_month(year, month) {
+            return Err(TimeSyncError::Day { year, month, day });
+        }
+        let Time {
+            hour,
+            minute,
+            second,
+            millisecond,
+        } = self.time;
+        if hour > 23 || minute > 59 || second > 59 {
+            return Err(TimeSyncError::Time {
+                hour,
+                minute,
+                second,
+            });
+        }
+        if millisecond > 999 {
+            return Err(TimeSyncError::Millisecond(millisecond));
+        }
+        if !(MIN_TIMEZONE_OFFSET..=MAX_TIMEZONE_OFFSET).contains(&self.timezone.offset) {
+            return Err(TimeSyncError::TimezoneOffset(self.timezone.offset));
+        }
+        Ok(())
+    }
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if (year % 4 == 0 && year % 100 != 0) || year % 400 == 0 => 29,
+        2 => 28,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(month: u32, day: u32, hour: u32, offset: i32) -> TimeSyncProps {
+        TimeSyncProps {
+            date: Date {
+                year: 2024,
+                month,
+                day,
+            },
+            time: Time {
+                hour,
+                minute: 30,
+                second: 0,
+                millisecond: 0,
+            },
+            timezone: TimeZone {
+                offset,
+                dst_offset: 0,
+                id: "Asia/Shanghai".to_string(),
+            },
+            is_12_hour_format: false,
+        }
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_fields() {
+        assert_eq!(props(2, 29, 23, 64).validate(), Ok(()));
+        assert_eq!(
+            props(13, 1, 0, 64).validate(),
+            Err(TimeSyncError::Month(13))
+        );
+        assert!(matches!(
+            props(2, 30, 0, 64).validate(),
+            Err(TimeSyncError::Day { day: 30, .. })
+        ));
+        assert!(matches!(
+            props(1, 1, 24, 64).validate(),
+            Err(TimeSyncError::Time { hour: 24, .. })
+        ));
+        // 直接传秒数是最常见的错
+        assert_eq!(
+            props(1, 1, 0, 8 * 3600).validate(),
+            Err(TimeSyncError::TimezoneOffset(8 * 3600))
+        );
+        assert_eq!(props(1, 1, 0, 88).validate(), Ok(()));
+        assert_eq!(props(1, 1, 0, -16).validate(), Ok(()));
+    }
+}
@@ -0,0 +1,81 @@
+//! 下游常用的类型和入口，`use corelib::prelude::*;` 一行拿全。
+//!
+//! 内部模块会随重构挪位置，深路径（`device::xiaomi::components::...`）不保证稳定；
+//! 这里导出的名字按 semver 对待：删改算破坏性变更，要跟着升主版本号。
+//!
+//! 只用 prelude 就能把一台小米设备连起来：
+//!
+//! ```no_run
+//! use corelib::prelude::*;
+//!
+//! async fn connect(handle: tokio::runtime::Handle) -> anyhow::Result<DeviceConnectionInfo> {
+//!     corelib::init();
+//!     let info = create_device(
+//!         handle,
+//!         DeviceKind::Xiaomi,
+//!         "Xiaomi Watch S4".to_string(),
+//!         "AA:BB:CC:DD:EE:FF".to_string(),
+//!         "0123456789abcdef0123456789abcdef".to_string(),
+//!         2,
+//!         ConnectType::SPP,
+//!         None,
+//!         None,
+//!         None,
+//!         false,
+//!         XiaomiDeviceConfig::default(),
+//!         |_frames: Vec<Vec<u8>>| async { Ok::<(), SendError>(()) },
+//!     )
+//!     .await?;
+//!
+//!     let mut events = subscribe_events();
+//!     if let Ok(CoreEvent::DeviceStateChanged(changed)) = events.try_recv() {
+//!         println!("{} changed", changed.device_addr);
+//!     }
+//!     Ok(info)
+//! }
+//! ```
+
+pub use crate::device::{
+    Device, DeviceConnectionInfo, DeviceError, DeviceHandle, DeviceKind, DeviceSetup,
+    DeviceSummary, PostConnectOptions, PostConnectStep, RetryPolicy, SetupError, SetupReport,
+    SyncReport, XiaomiConnectParams, cleanup_device_state, connect_with_retry, create_device,
+    create_vivo_device, install::FileInstallError, list_connected, post_connect_sync,
+};
+
+pub use crate::device::xiaomi::{
+    SendError, XiaomiDevice,
+    clock::{Clock, SharedClock, SystemClock},
+    components::{
+        auth::AuthError,
+        capability::DeviceCapabilities,
+        install::{InstallError, InstallOptions, InstallOutcome},
+        mass::{
+            IncomingTransferDecision, IncomingTransferRequest, MassError, SendMassCallbackData,
+            set_incoming_transfer_policy,
+        },
+        notification::{NotificationError, NotificationFilter, NotificationImportance},
+    },
+    config::{
+        BlePacing, CaptureLinkType, ChannelCrypto, ChannelCryptoPolicy, InfoConfig, MassConfig,
+        NetworkConfig, OversizedPacketPolicy, QosProfile, ResConfig, SarConfig, TransportConfig,
+        XiaomiDeviceConfig,
+    },
+    packet::{
+        L2Channel, L2OpCode,
+        mass::{
+            MassDataType,
+            codec::{CompressionCodec, register_codec},
+        },
+    },
+    sar::{AckWait, DeviceLinkInfo, DrainReport, LinkState},
+    r#type::ConnectType,
+};
+
+pub use crate::error::format_anyhow;
+pub use crate::events::{
+    CoreEvent, DeviceStateChanged, InterconnectMessage, PostConnectStepFinished, WatchfaceChanged,
+    subscribe as subscribe_events,
+};
+
+/// 对外的接口都返回 `anyhow::Error`，具体原因用 `downcast_ref` 取上面那些 `*Error`
+pub use anyhow::Error as CoreError;
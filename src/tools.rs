@@ -1,4 +1,4 @@
-use nanorand::Rng;
+pub mod rng;
 
 pub fn vec_to_array_16_opt(v: &Vec<u8>) -> Option<[u8; 16]> {
     v.as_slice().try_into().ok()
@@ -19,16 +19,10 @@ pub fn hex_stream_to_bytes(hex: &str) -> Result<Vec<u8>, String> {
         .collect()
 }
 
+/// 走系统 CSPRNG（测试里可以用 `rng::set_test_rng` 换成固定种子）
 pub fn generate_random_bytes(size: usize) -> Vec<u8> {
-    #[cfg(not(any(target_os = "espidf", target_arch = "wasm32")))]
-    let mut rng = nanorand::tls_rng();
-
-    // 实际上没人在意这该死的随机数安全性
-    #[cfg(any(target_os = "espidf", target_arch = "wasm32"))]
-    let mut rng = nanorand::WyRand::new_seed(114514);
-
     let mut buffer = vec![0u8; size];
-    rng.fill_bytes(&mut buffer);
+    rng::fill_bytes(&mut buffer);
     buffer
 }
 
@@ -44,3 +38,46 @@ pub fn calc_crc32_bytes(data: &[u8]) -> [u8; 4] {
     let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
     crc.checksum(data).to_be_bytes()
 }
+
+/// 语音备忘录之类的音频文件容器类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum AudioContainer {
+    OggOpus,
+    Ogg,
+    WavAdpcm,
+    WavPcm,
+    Wav,
+    Unknown,
+}
+
+/// 根据文件头猜音频容器，只需要前几十个字节，流式接收时拿第一个分片就够了
+pub fn sniff_audio_container(data: &[u8]) -> AudioContainer {
+    if data.starts_with(b"OggS") {
+        // 第一页的 payload 从 27 + segment 数开始，Opus 的话就是 OpusHead
+        let segments = data.get(26).copied().unwrap_or(0) as usize;
+        let payload_start = 27 + segments;
+        if data
+            .get(payload_start..payload_start + 8)
+            .is_some_and(|magic| magic == b"OpusHead")
+        {
+            return AudioContainer::OggOpus;
+        }
+        return AudioContainer::Ogg;
+    }
+
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WAVE" {
+        // fmt chunk 一般紧跟在 WAVE 后面，audio format 在 chunk 数据的头两个字节
+        if data.get(12..16).is_some_and(|id| id == b"fmt ") {
+            if let Some(fmt) = data.get(20..22) {
+                return match u16::from_le_bytes([fmt[0], fmt[1]]) {
+                    0x0001 => AudioContainer::WavPcm,
+                    0x0002 | 0x0011 => AudioContainer::WavAdpcm,
+                    _ => AudioContainer::Wav,
+                };
+            }
+        }
+        return AudioContainer::Wav;
+    }
+
+    AudioContainer::Unknown
+}
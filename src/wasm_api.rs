@@ -0,0 +1,98 @@
+//! 给 Web 前端直接用的几个 wasm-bindgen 导出，省得每个宿主自己写一遍胶水。
+//! 都返回 JSON 字符串，序列化失败在 JS 那边抛 Error
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::device::list_connected;
+use crate::ecs::graph::export_react_flow_graph;
+
+fn to_json<T: Serialize>(value: &T) -> Result<String, JsError> {
+    serde_json::to_string(value).map_err(|err| JsError::new(&format!("serialize failed: {err}")))
+}
+
+/// 同 `ecs::graph::export_react_flow_graph`
+#[wasm_bindgen(js_name = exportGraphJson)]
+pub async fn export_graph_json() -> Result<String, JsError> {
+    to_json(&export_react_flow_graph().await)
+}
+
+/// 同 `device::list_connected`，按地址排好序
+#[wasm_bindgen(js_name = listDevicesJson)]
+pub async fn list_devices_json() -> Result<String, JsError> {
+    to_json(&list_connected().await)
+}
+
+/// 设备不在时返回 undefined
+#[wasm_bindgen(js_name = deviceSummaryJson)]
+pub async fn device_summary_json(addr: String) -> Result<Option<String>, JsError> {
+    list_connected()
+        .await
+        .into_iter()
+        .find(|summary| summary.addr == addr)
+        .map(|summary| to_json(&summary))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::{Device, DeviceKind};
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    async fn with_dummy_device(addr: &'static str) {
+        crate::ecs::init_runtime_default();
+        crate::ecs::with_rt_mut(move |rt| {
+            rt.spawn_device(
+                addr.to_string(),
+                Device::new("mock".to_string(), addr.to_string(), DeviceKind::Xiaomi),
+            );
+        })
+        .await;
+    }
+
+    #[wasm_bindgen_test]
+    async fn summaries_are_json() {
+        with_dummy_device("test:wasm-api").await;
+
+        // runtime 是全局的，别的测试挂的设备也会列出来，只认自己这台
+        let list: serde_json::Value =
+            serde_json::from_str(&list_devices_json().await.unwrap()).unwrap();
+        let ours = list
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|summary| summary["addr"] == "test:wasm-api")
+            .expect("spawned device should be listed");
+        assert_eq!(ours["authed"], false);
+
+        let summary = device_summary_json("test:wasm-api".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let summary: serde_json::Value = serde_json::from_str(&summary).unwrap();
+        assert_eq!(summary["name"], "mock");
+        assert!(
+            device_summary_json("test:missing".to_string())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    async fn graph_has_runtime_and_entity_nodes() {
+        with_dummy_device("test:wasm-graph").await;
+
+        let graph: serde_json::Value =
+            serde_json::from_str(&export_graph_json().await.unwrap()).unwrap();
+        let ids: Vec<&str> = graph["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|node| node["id"].as_str())
+            .collect();
+        assert!(ids.contains(&"runtime"));
+        assert!(ids.contains(&"entity:test:wasm-graph"));
+    }
+}